@@ -0,0 +1,202 @@
+// A scene with hundreds of small meshes needs hundreds of vertex and index buffers if every mesh
+// gets its own, and every one of them is an allocation in the driver. The arena puts the data of
+// many meshes into a few large buffers (the pages) instead. A mesh gets a range of a page, the
+// renderers bind the whole page and find the mesh in it with the base vertex and the first
+// index of the draw call.
+//
+// The pages are never resized: a larger buffer would have to be copied and every mesh would
+// have to be pointed to the new one. When no page has enough room for a mesh, the arena grows
+// by another page. Inside of a page the free space is a list of ranges, new ranges are taken
+// from the first one that is large enough (the last free range of a page is the untouched rest,
+// so on a fresh page this is a bump allocator). A range that is dropped goes back to the list
+// of its page and is merged with its free neighbours.
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use crate::model::RawVertex;
+use crate::stats::RenderStats;
+
+// the size of a page, a mesh that doesn't fit into it gets a page of its own size
+const DEFAULT_PAGE_SIZE: wgpu::BufferAddress = 4 * 1024 * 1024;
+
+struct Page {
+    buffer: Arc<wgpu::Buffer>,
+    // the free ranges of the page, sorted by their offset and never touching each other
+    free: Mutex<Vec<Range<wgpu::BufferAddress>>>,
+}
+
+impl Page {
+    fn allocate(&self, size: wgpu::BufferAddress, alignment: wgpu::BufferAddress) -> Option<wgpu::BufferAddress> {
+        let mut free = self.free.lock().unwrap();
+        let (i, offset) = free.iter().enumerate().find_map(|(i, range)| {
+            let offset = range.start.div_ceil(alignment) * alignment;
+            (offset + size <= range.end).then_some((i, offset))
+        })?;
+        // what is left on both sides of the new range stays free
+        let range = free.remove(i);
+        if offset + size < range.end {
+            free.insert(i, offset + size..range.end);
+        }
+        if range.start < offset {
+            free.insert(i, range.start..offset);
+        }
+        Some(offset)
+    }
+
+    fn free(&self, range: Range<wgpu::BufferAddress>) {
+        let mut free = self.free.lock().unwrap();
+        let i = free.partition_point(|r| r.start < range.start);
+        free.insert(i, range);
+        // merge with the following and then with the previous range
+        if i + 1 < free.len() && free[i].end == free[i + 1].start {
+            free[i].end = free.remove(i + 1).end;
+        }
+        if i > 0 && free[i - 1].end == free[i].start {
+            free[i - 1].end = free.remove(i).end;
+        }
+    }
+
+    fn used_bytes(&self) -> wgpu::BufferAddress {
+        let free: wgpu::BufferAddress = self.free.lock().unwrap().iter().map(|r| r.end - r.start).sum();
+        self.buffer.size() - free
+    }
+}
+
+/// The part of a GPU buffer that holds the data of one mesh. This is either a buffer of its own
+/// or a range of a page of a `BufferArena`, the range goes back to the arena when it is dropped.
+pub struct BufferRange {
+    pub buffer: Arc<wgpu::Buffer>,
+    /// where the data starts in the buffer, in bytes
+    pub offset: wgpu::BufferAddress,
+    pub size: wgpu::BufferAddress,
+    // the page the range was taken from, None for a buffer of its own
+    page: Option<Arc<Page>>,
+}
+
+impl BufferRange {
+    /// a buffer of its own that holds `contents`
+    pub fn dedicated(label: &str, contents: &[u8], usage: wgpu::BufferUsages, device: &wgpu::Device) -> Self {
+        use wgpu::util::DeviceExt;
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage,
+        });
+        Self { buffer: Arc::new(buffer), offset: 0, size: contents.len() as wgpu::BufferAddress, page: None }
+    }
+
+    /// true if the range shares its buffer with other ranges
+    pub fn is_shared(&self) -> bool {
+        self.page.is_some()
+    }
+
+    /// Write `data` to the start of the range. Data that doesn't fit would end up in the range
+    /// of another mesh, so it's not written at all.
+    #[allow(dead_code)]
+    pub fn write(&self, data: &[u8], queue: &wgpu::Queue) {
+        if data.len() as wgpu::BufferAddress > self.size {
+            log::error!("{} bytes don't fit into a buffer range of {} bytes, nothing is written", data.len(), self.size);
+            return;
+        }
+        queue.write_buffer(&self.buffer, self.offset, data);
+        RenderStats::record_buffer_write();
+    }
+}
+
+impl Drop for BufferRange {
+    fn drop(&mut self) {
+        if let Some(page) = &self.page {
+            if self.size > 0 {
+                page.free(self.offset..self.offset + self.size);
+            }
+        }
+    }
+}
+
+/// Sub-allocates ranges of a few large buffers, see the top of this file
+pub struct BufferArena {
+    label: String,
+    usage: wgpu::BufferUsages,
+    page_size: wgpu::BufferAddress,
+    // the offset of every range is a multiple of this
+    alignment: wgpu::BufferAddress,
+    pages: Vec<Arc<Page>>,
+}
+
+impl BufferArena {
+    /// An arena for buffers with `usage`, the data is written with the queue so COPY_DST is
+    /// added. `alignment` has to be a multiple of 4 (`wgpu::COPY_BUFFER_ALIGNMENT`).
+    pub fn new(label: &str, usage: wgpu::BufferUsages, page_size: wgpu::BufferAddress, alignment: wgpu::BufferAddress) -> Self {
+        assert!(alignment > 0 && alignment.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT), "the alignment of an arena has to be a multiple of 4");
+        Self {
+            label: label.to_string(),
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            page_size,
+            alignment,
+            pages: Vec::new(),
+        }
+    }
+
+    /// Put `contents` into the arena, a new page is created if none of them has enough room.
+    /// The length of `contents` has to be a multiple of 4, like for `queue.write_buffer`.
+    pub fn allocate(&mut self, contents: &[u8], device: &wgpu::Device, queue: &wgpu::Queue) -> BufferRange {
+        let size = contents.len() as wgpu::BufferAddress;
+        if size == 0 {
+            // an empty range doesn't take up space, but it still needs a buffer to point to
+            let page = self.pages.first().cloned().unwrap_or_else(|| self.add_page(self.page_size, device));
+            return BufferRange { buffer: page.buffer.clone(), offset: 0, size: 0, page: None };
+        }
+        let found = self.pages.iter().find_map(|page| Some((page.clone(), page.allocate(size, self.alignment)?)));
+        let (page, offset) = match found {
+            Some(found) => found,
+            None => {
+                let page = self.add_page(self.page_size.max(size), device);
+                let offset = page.allocate(size, self.alignment).expect("a new page has room for the range");
+                (page, offset)
+            }
+        };
+        queue.write_buffer(&page.buffer, offset, contents);
+        RenderStats::record_buffer_write();
+        BufferRange { buffer: page.buffer.clone(), offset, size, page: Some(page) }
+    }
+
+    fn add_page(&mut self, size: wgpu::BufferAddress, device: &wgpu::Device) -> Arc<Page> {
+        let size = size.div_ceil(self.alignment) * self.alignment;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{} page {}", self.label, self.pages.len())),
+            size,
+            usage: self.usage,
+            mapped_at_creation: false,
+        });
+        let page = Arc::new(Page { buffer: Arc::new(buffer), free: Mutex::new(std::iter::once(0..size).collect()) });
+        self.pages.push(page.clone());
+        page
+    }
+
+    /// the number of buffers the arena created
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// the bytes of all pages that are taken by ranges
+    pub fn used_bytes(&self) -> wgpu::BufferAddress {
+        self.pages.iter().map(|page| page.used_bytes()).sum()
+    }
+}
+
+/// The arenas for the vertices and the indices of the surfaces, see `Surface::new_with_lods`
+pub struct MeshArena {
+    pub vertices: BufferArena,
+    pub indices: BufferArena,
+}
+
+impl Default for MeshArena {
+    fn default() -> Self {
+        Self {
+            // the ranges start at whole vertices so the base vertex of a mesh is its offset
+            // divided by the size of a vertex
+            vertices: BufferArena::new("Mesh arena vertices", wgpu::BufferUsages::VERTEX, DEFAULT_PAGE_SIZE, std::mem::size_of::<RawVertex>() as wgpu::BufferAddress),
+            indices: BufferArena::new("Mesh arena indices", wgpu::BufferUsages::INDEX, DEFAULT_PAGE_SIZE, std::mem::size_of::<u32>() as wgpu::BufferAddress),
+        }
+    }
+}
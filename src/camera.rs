@@ -1,7 +1,10 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::f32::consts::FRAC_PI_2;
 
+use crate::stats::RenderStats;
+
 // This import allows us to use the useful definitions from cgmath
 // The e.g. define a function to construct the view transformation
 // matrix
@@ -22,6 +25,20 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 );
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+// the range of the field of view in the camera ui, in degrees. Wider than this the picture is
+// mostly distortion, narrower and the camera barely moves the view when it turns
+const MIN_FIELD_OF_VIEW: f32 = 10.0;
+const MAX_FIELD_OF_VIEW: f32 = 120.0;
+// how long the switch between the perspective and the orthographic projection takes, in seconds
+const PROJECTION_BLEND_DURATION: f32 = 0.3;
+// below this the blended projection is so close to orthographic that the orthographic matrix is
+// used, the camera of the blend would be so far away that the depth runs out of precision
+const MIN_PERSPECTIVE_BLEND: f32 = 0.01;
+// the closest an orbiting camera gets to its target, closer and it would pass through it and
+// end up looking at it from the other side
+const MIN_ORBIT_DISTANCE: f32 = 0.2;
+// how long the camera takes to fly to a saved view that is picked in the ui
+const PRESET_FLIGHT_DURATION: std::time::Duration = std::time::Duration::from_millis(1000);
 
 /// The ObserverControlls are the user interface to an observer it allows the user to
 /// move the observer around and look at different objects in the scene/world
@@ -33,16 +50,35 @@ pub struct CameraControlls {
     amount_backward: f32,
     amount_up: f32,
     amount_down: f32,
+    amount_roll_left: f32,
+    amount_roll_right: f32,
     rotate_horizontal: f32,
     rotate_vertical: f32,
+    pan_horizontal: f32,
+    pan_vertical: f32,
     scroll: f32,
     speed: f32,
     sensitivity: f32,
+    pan_speed: f32,
+    // radians per second
+    roll_speed: f32,
     mouse_pressed: bool,
+    pan_pressed: bool,
+    // how fast the camera moves (in world units per second) and turns (in radians per second
+    // for the yaw, the pitch and the roll). They follow what the keys and the mouse ask for with a delay,
+    // so the camera speeds up and slows down instead of starting and stopping at once
+    velocity: Vector3<f32>,
+    turn_velocity: Vector3<f32>,
+    // the time in seconds the velocities take to get most of the way (63%) to the velocity the
+    // input asks for, 0 follows the input right away
+    pub smoothing: f32,
 }
 
+// a velocity below this counts as standing still, the exponential decay never reaches zero
+const REST_VELOCITY: f32 = 1e-4;
+
 impl CameraControlls {
-    pub fn new(speed: f32, sensitivity: f32) -> Self {
+    pub fn new(speed: f32, sensitivity: f32, smoothing: f32) -> Self {
         Self {
             amount_left: 0.0,
             amount_right: 0.0,
@@ -50,51 +86,104 @@ impl CameraControlls {
             amount_backward: 0.0,
             amount_up: 0.0,
             amount_down: 0.0,
+            amount_roll_left: 0.0,
+            amount_roll_right: 0.0,
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
+            pan_horizontal: 0.0,
+            pan_vertical: 0.0,
             scroll: 0.0,
             speed,
             sensitivity,
+            pan_speed: 0.5,
+            roll_speed: 1.0,
             mouse_pressed: false,
+            pan_pressed: false,
+            velocity: Vector3::zero(),
+            turn_velocity: Vector3::zero(),
+            smoothing,
         }
     }
+
+    /// forget all the input that has not been applied to the camera yet, as well as the keys
+    /// and buttons that are held down. The camera stops right away.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.speed, self.sensitivity, self.smoothing);
+    }
+
+    /// the velocity of the camera in world units per second
+    #[allow(dead_code)]
+    pub fn velocity(&self) -> Vector3<f32> {
+        self.velocity
+    }
+
+    // Move the velocities towards the ones the input asks for. This is exponential smoothing,
+    // in every update the velocity covers the same share of the way that is left, the share
+    // comes from the time step so the smoothing doesn't depend on the frame rate. A short tap
+    // or a flick of the mouse moves the camera as far as without smoothing, only spread over
+    // several frames.
+    fn smooth_velocities(&mut self, target_velocity: Vector3<f32>, target_turn_velocity: Vector3<f32>, dt: f32) {
+        let share = if self.smoothing > 0.0 { 1.0 - (-dt / self.smoothing).exp() } else { 1.0 };
+        self.velocity += (target_velocity - self.velocity) * share;
+        self.turn_velocity += (target_turn_velocity - self.turn_velocity) * share;
+        if self.velocity.magnitude() < REST_VELOCITY {
+            self.velocity = Vector3::zero();
+        }
+        if self.turn_velocity.magnitude() < REST_VELOCITY {
+            self.turn_velocity = Vector3::zero();
+        }
+    }
+
     pub fn on_keyboard_input(&mut self, input: &winit::event::KeyEvent) -> bool {
-        let amount: f32 = if input.state == ElementState::Pressed {
+        match input.physical_key {
+            PhysicalKey::Code(code) => self.on_key(code, input.state),
+            PhysicalKey::Unidentified(_) => false,
+        }
+    }
+
+    /// Process a key by its code. Key events from winit can't be created by hand, so the
+    /// replayed input (see input_recording.rs) comes in through here.
+    pub fn on_key(&mut self, code: KeyCode, state: ElementState) -> bool {
+        let amount: f32 = if state == ElementState::Pressed {
             1.0
         } else {
             0.0
         };
-        match input.physical_key {
-            PhysicalKey::Code(code) => {
-                match code {
-                    KeyCode::KeyR | KeyCode::ArrowUp => {
-                        self.amount_forward = amount;
-                        true
-                    }
-                    KeyCode::KeyH | KeyCode::ArrowDown => {
-                        self.amount_backward = amount;
-                        true
-                    }
-                    KeyCode::KeyS | KeyCode::ArrowLeft => {
-                        self.amount_left = amount;
-                        true
-                    }
-                    KeyCode::KeyT | KeyCode::ArrowRight => {
-                        self.amount_right = amount;
-                        true
-                    }
-                    KeyCode::Space => {
-                        self.amount_up = amount;
-                        true
-                    }
-                    KeyCode::ShiftLeft => {
-                        self.amount_down = amount;
-                        true
-                    }
-                    _ => false,
-                }
+        match code {
+            KeyCode::KeyR | KeyCode::ArrowUp => {
+                self.amount_forward = amount;
+                true
             }
-            PhysicalKey::Unidentified(_) => false,
+            KeyCode::KeyH | KeyCode::ArrowDown => {
+                self.amount_backward = amount;
+                true
+            }
+            KeyCode::KeyS | KeyCode::ArrowLeft => {
+                self.amount_left = amount;
+                true
+            }
+            KeyCode::KeyT | KeyCode::ArrowRight => {
+                self.amount_right = amount;
+                true
+            }
+            KeyCode::Space => {
+                self.amount_up = amount;
+                true
+            }
+            KeyCode::ShiftLeft => {
+                self.amount_down = amount;
+                true
+            }
+            // bank the camera, the horizon tilts the other way
+            KeyCode::KeyQ => {
+                self.amount_roll_left = amount;
+                true
+            }
+            KeyCode::KeyE => {
+                self.amount_roll_right = amount;
+                true
+            }
+            _ => false,
         }
     }
 
@@ -103,6 +192,12 @@ impl CameraControlls {
             self.rotate_horizontal = delta.0 as f32;
             self.rotate_vertical = delta.1 as f32;
             true
+        } else if self.pan_pressed {
+            // there may be multiple motion events between two frames, so the pan is accumulated
+            // until the camera is updated
+            self.pan_horizontal += delta.0 as f32;
+            self.pan_vertical += delta.1 as f32;
+            true
         } else {
             false
         }
@@ -120,9 +215,9 @@ impl CameraControlls {
         true
     }
 
-    /// Process the mouse button input. Currently we want to pan the camera if
-    /// the left mouse button is pressed, we don't care about anything else for now
-    /// also indicate if it has been processed
+    /// Process the mouse button input. We want to rotate the camera if the left mouse
+    /// button is pressed and move it sideways/up and down (truck/pedestal) if the middle
+    /// button is pressed. Also indicate if it has been processed
     pub fn on_mouse_button_input(
         &mut self,
         state: &winit::event::ElementState,
@@ -133,6 +228,10 @@ impl CameraControlls {
                 self.mouse_pressed = *state == winit::event::ElementState::Pressed;
                 true
             }
+            winit::event::MouseButton::Middle => {
+                self.pan_pressed = *state == winit::event::ElementState::Pressed;
+                true
+            }
             _ => false,
         }
     }
@@ -156,6 +255,99 @@ impl CameraControlls {
     }
 }
 
+/// A constraint on where the camera can go, like a wall or a building that the camera should not
+/// fly through. After the camera moved in `Camera::update` the collider gets the new position and
+/// returns the position where the camera should end up instead.
+pub trait CameraCollider: std::fmt::Debug {
+    fn resolve(&self, proposed_position: Point3<f32>) -> Point3<f32>;
+}
+
+/// Keeps the camera out of a box, a camera that ends up inside is pushed out through the
+/// closest side
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct BoxCollider {
+    pub aabb: crate::model::Aabb,
+}
+
+impl CameraCollider for BoxCollider {
+    fn resolve(&self, p: Point3<f32>) -> Point3<f32> {
+        let (min, max) = (self.aabb.min, self.aabb.max);
+        let inside = (0..3).all(|axis| p[axis] > min[axis] && p[axis] < max[axis]);
+        if !inside {
+            return p;
+        }
+        // the distance to every side of the box, the camera leaves through the closest one
+        let mut resolved = p;
+        let mut closest = f32::INFINITY;
+        for axis in 0..3 {
+            for side in [min[axis], max[axis]] {
+                let distance = (p[axis] - side).abs();
+                if distance < closest {
+                    closest = distance;
+                    resolved = p;
+                    resolved[axis] = side;
+                }
+            }
+        }
+        resolved
+    }
+}
+
+/// How the controls move the camera
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    /// fly through the scene, dragging the mouse turns the view in place and the wheel moves
+    /// the camera forward
+    #[default]
+    Fly,
+    /// circle around `Camera::target`, dragging the mouse moves the camera around it and the
+    /// wheel changes the distance. The keys and panning move the target, the camera follows it.
+    Orbit,
+}
+
+/// Where a camera is and where it looks, to jump back to a view later. The angles are in
+/// radians, the fields are plain numbers so the pose can be written to a file.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CameraPose {
+    pub position: [f32; 3],
+    pub pitch: f32,
+    pub yaw: f32,
+    pub roll: f32,
+    pub field_of_view: f32,
+}
+
+/// How a camera animation speeds up and slows down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Easing {
+    /// the same speed from start to end
+    Linear,
+    /// starts slowly, is fastest in the middle and slows down at the end
+    #[default]
+    Smoothstep,
+}
+
+impl Easing {
+    /// how far the animation is at the share `t` (0 to 1) of its duration
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::Smoothstep => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+// an animation of the camera from one pose to another, see `Camera::lerp_to`
+#[derive(Debug, Clone)]
+struct PoseAnimation {
+    from: CameraPose,
+    to: CameraPose,
+    elapsed: std::time::Duration,
+    duration: std::time::Duration,
+    easing: Easing,
+}
+
 #[derive(Debug)]
 pub struct Camera {
     // This is the position of the camera in world space
@@ -164,6 +356,10 @@ pub struct Camera {
     // as angles relative to the world coordinate frame
     pub pitch: Rad<f32>,
     pub yaw: Rad<f32>,
+    // the rotation around the view direction, positive values tilt the top of the camera to
+    // the right. It only turns the picture, the controls keep moving and turning the camera
+    // relative to the horizon
+    pub roll: Rad<f32>,
     // field of view of the camera (something like the difference between
     // a zoom lense and a ultra wide lens)
     pub field_of_view: Rad<f32>,
@@ -175,12 +371,37 @@ pub struct Camera {
     pub znear: f32,
     pub zfar: f32,
 
-    // this is the perspective matrix. We only need to compute this very
-    // seldomly so we store it instead of recomputing it each time we
-    // update the GPU uniform
+    pub mode: CameraMode,
+    // the point the camera circles around and its distance to it in the orbit mode, the
+    // position is derived from them (and the pitch and yaw) in every update
+    pub target: Point3<f32>,
+    pub distance: f32,
+
+    // the distance of the plane that the orthographic projection shows at the same size as the
+    // perspective one, so this plane stays put when switching between the two
+    pub focus_distance: f32,
+
+    // this is the projection matrix, during the switch to orthographic a blend of the two. We
+    // only need to compute this very seldomly so we store it instead of recomputing it each
+    // time we update the GPU uniform
     perspective: Matrix4<f32>,
+    // 0 is orthographic, 1 perspective and the values between are the animation from one to the
+    // other, see `compute_blended_projection`
+    projection_blend: f32,
+    // the value the projection blend is animated towards
+    projection_blend_target: f32,
     pub uniform: Arc<Mutex<CameraUniform>>,
     pub controls: CameraControlls,
+    // the lowest height the camera can go down to, like the eye height above the ground when
+    // walking through a building
+    pub min_height: Option<f32>,
+    // a custom constraint on the position of the camera, it's applied before the minimal height
+    pub collider: Option<Box<dyn CameraCollider>>,
+    // the saved views of this camera by their name, see `save_preset`. Sorted so the ui lists
+    // them in a stable order
+    pub presets: BTreeMap<String, CameraPose>,
+    // the flight to a pose that is running, the controls are ignored until it ends
+    animation: Option<PoseAnimation>,
 }
 
 // This is the struct that contains all the information to define
@@ -216,27 +437,69 @@ impl Camera {
             position: position.into(),
             pitch: pitch.into(),
             yaw: yaw.into(),
+            roll: Rad(0.0),
             field_of_view: field_of_view.clone().into(),
             aspect_ratio: screen_width as f32 / screen_height as f32,
             zfar,
             znear,
+            mode: CameraMode::Fly,
+            target: Point3::origin(),
+            distance: 5.0,
+            focus_distance: 5.0,
             perspective: Self::compute_projection_matrix(
                 field_of_view,
                 screen_width as f32 / screen_height as f32,
-                zfar,
                 znear,
+                zfar,
             ),
+            projection_blend: 1.0,
+            projection_blend_target: 1.0,
             uniform,
-            controls: CameraControlls::new(4.0, 0.4),
+            controls: CameraControlls::new(4.0, 0.4, 0.08),
+            min_height: None,
+            collider: None,
+            presets: BTreeMap::new(),
+            animation: None,
         };
         // the data in the GPU needs to actually be initialized, so we compute the matrix here and
         // then send it to the GPU
         {
             let mut uniform = cam.uniform.lock().unwrap();
-            uniform.update(cam.compute_full_camera_transform(), queue);
+            uniform.update(cam.compute_full_camera_transform(), cam.compute_view_matrix().into(), queue);
         }
         cam
     }
+
+    /// A new camera with the same view, lens and projection as this one and a uniform of its
+    /// own. The controls start at rest and the running flight to a pose is not copied. The
+    /// collider can't be copied either, the new camera only keeps the minimal height.
+    pub fn duplicate(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let cam = Camera {
+            position: self.position,
+            pitch: self.pitch,
+            yaw: self.yaw,
+            roll: self.roll,
+            field_of_view: self.field_of_view,
+            aspect_ratio: self.aspect_ratio,
+            znear: self.znear,
+            zfar: self.zfar,
+            mode: self.mode,
+            target: self.target,
+            distance: self.distance,
+            focus_distance: self.focus_distance,
+            perspective: self.perspective,
+            projection_blend: self.projection_blend,
+            projection_blend_target: self.projection_blend_target,
+            uniform: Arc::new(Mutex::new(CameraUniform::new(device))),
+            controls: CameraControlls::new(self.controls.speed, self.controls.sensitivity, self.controls.smoothing),
+            min_height: self.min_height,
+            collider: None,
+            presets: self.presets.clone(),
+            animation: None,
+        };
+        cam.update_uniform(queue);
+        cam
+    }
     // This is the matrix that distorts the world to emulate the 'lens' of the camera
     // When the result is projected onto a 2D plane it will look like a picture taken
     // with this virtual camera
@@ -247,23 +510,147 @@ impl Camera {
         OPENGL_TO_WGPU_MATRIX * perspective(fov.into(), aspect, znear, zfar)
     }
 
+    // The orthographic projection that shows the plane at `focus_distance` at the same size as
+    // the perspective projection with the field of view `fov`
+    fn compute_orthographic_matrix(fov: Rad<f32>, aspect: f32, znear: f32, zfar: f32, focus_distance: f32) -> Matrix4<f32> {
+        let half_height = focus_distance * (fov / 2.0).tan();
+        let half_width = half_height * aspect;
+        OPENGL_TO_WGPU_MATRIX * ortho(-half_width, half_width, -half_height, half_height, znear, zfar)
+    }
+
+    // The projection between orthographic (blend 0) and perspective (blend 1).
+    //
+    // Lerping the two matrices doesn't work well: the result is a projection, but the w of the
+    // perspective matrix grows with the distance while the one of the orthographic matrix is 1,
+    // so the perspective wins almost right away and the picture jumps at the end of the switch.
+    // Instead this does a dolly zoom: the field of view gets narrower while the camera moves back
+    // along the view direction, so the plane at the focus distance keeps its size on the screen
+    // and everything in front of and behind it slowly flattens out. The tangent of the half field
+    // of view shrinks linearly with the blend (after easing it in and out), and the camera is
+    // moved back just far enough to keep the focus plane in view. The near and far planes move
+    // with the camera, so the same part of the world is clipped as by the original camera. In
+    // the limit the camera is infinitely far away, which is the orthographic projection.
+    fn compute_blended_projection(fov: Rad<f32>, aspect: f32, znear: f32, zfar: f32, focus_distance: f32, blend: f32) -> Matrix4<f32> {
+        // smoothstep, so the animation starts and ends slowly
+        let eased = blend * blend * (3.0 - 2.0 * blend);
+        if eased >= 1.0 {
+            return Self::compute_projection_matrix(fov, aspect, znear, zfar);
+        }
+        if eased < MIN_PERSPECTIVE_BLEND {
+            return Self::compute_orthographic_matrix(fov, aspect, znear, zfar, focus_distance);
+        }
+        let blended_fov = Rad(2.0 * (eased * (fov / 2.0).tan()).atan());
+        // the focus plane is at focus_distance / eased from the camera that was moved back
+        let dolly = focus_distance / eased - focus_distance;
+        Self::compute_projection_matrix(blended_fov, aspect, znear + dolly, zfar + dolly)
+            * Matrix4::from_translation(Vector3::new(0.0, 0.0, -dolly))
+    }
+
+    fn update_projection(&mut self) {
+        self.perspective = Self::compute_blended_projection(
+            self.field_of_view,
+            self.aspect_ratio,
+            self.znear,
+            self.zfar,
+            self.focus_distance,
+            self.projection_blend,
+        );
+    }
+
+    /// The pure perspective projection of the camera, whatever projection it currently uses
+    #[allow(dead_code)]
+    pub fn perspective_matrix(&self) -> Matrix4<f32> {
+        Self::compute_projection_matrix(self.field_of_view, self.aspect_ratio, self.znear, self.zfar)
+    }
+
+    /// The pure orthographic projection of the camera, it shows the plane at the focus distance
+    /// at the same size as the perspective projection
+    #[allow(dead_code)]
+    pub fn orthographic_matrix(&self) -> Matrix4<f32> {
+        Self::compute_orthographic_matrix(self.field_of_view, self.aspect_ratio, self.znear, self.zfar, self.focus_distance)
+    }
+
+    /// The projection the camera currently uses, this is the blend of the perspective and the
+    /// orthographic projection while the camera switches between them
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        self.perspective
+    }
+
+    /// Where the camera is between orthographic (0) and perspective (1)
+    #[allow(dead_code)]
+    pub fn projection_blend(&self) -> f32 {
+        self.projection_blend
+    }
+
+    /// Jump straight to a blend of the projections, 0 is orthographic and 1 perspective. This
+    /// also stops the animation of the projection.
+    #[allow(dead_code)]
+    pub fn set_projection_blend(&mut self, blend: f32) {
+        self.projection_blend = blend.clamp(0.0, 1.0);
+        self.projection_blend_target = self.projection_blend;
+        self.update_projection();
+    }
+
+    /// true if the camera is orthographic or on its way there
+    pub fn is_orthographic(&self) -> bool {
+        self.projection_blend_target == 0.0
+    }
+
+    /// Switch to the orthographic or the perspective projection, the switch is animated by
+    /// `animate_projection`
+    pub fn set_orthographic(&mut self, orthographic: bool) {
+        self.projection_blend_target = if orthographic { 0.0 } else { 1.0 };
+    }
+
+    /// Advance the animation between the projections. Returns true if the projection changed and
+    /// the uniform needs to be written.
+    pub fn animate_projection(&mut self, dt: std::time::Duration) -> bool {
+        if self.projection_blend == self.projection_blend_target {
+            return false;
+        }
+        let step = dt.as_secs_f32() / PROJECTION_BLEND_DURATION;
+        self.projection_blend = if self.projection_blend < self.projection_blend_target {
+            (self.projection_blend + step).min(self.projection_blend_target)
+        } else {
+            (self.projection_blend - step).max(self.projection_blend_target)
+        };
+        self.update_projection();
+        true
+    }
+
     // This is the matrix that moves all the vertices around such that it appears as
     // if we are looking at the world from the direction and position of our camera
     // we update this every time we move so
-    fn compute_view_matrix(&self) -> Matrix4<f32> {
-        // get the angles that we are looking at from the pitch and yaw
-        // of the camera
-        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
-        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+    pub fn compute_view_matrix(&self) -> Matrix4<f32> {
+        Self::compute_view_matrix_at(self.position, self.direction(), self.roll)
+    }
+
+    fn compute_view_matrix_at(position: Point3<f32>, direction: Vector3<f32>, roll: Rad<f32>) -> Matrix4<f32> {
         // this `;ook to riht handed constructor builds the transform matrix
         // that let's us see the world from the point of view of the camera
-        Matrix4::look_to_rh(
-            self.position,
-            // here we construct the vector, that points in the direction we
-            // are pointing the camera
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vector3::unit_y(),
-        )
+        Matrix4::look_to_rh(position, direction, Self::up_for(direction, roll))
+    }
+
+    // The up direction of the picture. Without roll this is the direction in the plane of the
+    // view direction and the world up axis that is at a right angle to the view direction, the
+    // roll turns it around the view direction. The pitch never reaches 90°, so the view
+    // direction is never parallel to the world up axis.
+    fn up_for(direction: Vector3<f32>, roll: Rad<f32>) -> Vector3<f32> {
+        let right = direction.cross(Vector3::unit_y()).normalize();
+        let up = right.cross(direction).normalize();
+        Quaternion::from_axis_angle(direction.normalize(), roll).rotate_vector(up)
+    }
+
+    /// The unit vector pointing in the direction the camera is looking
+    pub fn direction(&self) -> Vector3<f32> {
+        Self::direction_for(self.pitch, self.yaw)
+    }
+
+    // the view direction of a camera with the pitch and the yaw
+    fn direction_for(pitch: Rad<f32>, yaw: Rad<f32>) -> Vector3<f32> {
+        let (sin_pitch, cos_pitch) = pitch.sin_cos();
+        let (sin_yaw, cos_yaw) = yaw.sin_cos();
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
     }
 
     // This is the matrix that distorts the world to emulate the 'lens' of the camera
@@ -277,17 +664,294 @@ impl Camera {
         self.field_of_view = field_of_view.into();
         self.znear = znear;
         self.zfar = zfar;
-        self.perspective =
-            Self::compute_projection_matrix(field_of_view, aspect_ratio, znear, zfar);
+        self.update_projection();
     }
 
-    pub fn resize(&mut self, screen_width: u32, screen_height: u32) {
+    /// Adapt the aspect ratio to the new size of the screen. Only the matrix on the CPU side is
+    /// updated, the uniform is not written: with several cameras only the active one needs to
+    /// reach the GPU, which is done with `update_uniform` once all of them are resized.
+    pub fn resize_cpu_only(&mut self, screen_width: u32, screen_height: u32) {
         self.aspect_ratio = screen_width as f32 / screen_height as f32;
         self.set_perspective(self.field_of_view, self.aspect_ratio, self.znear, self.zfar)
     }
 
+    /// Widgets for the position, the direction and the lens of the camera. The changes are
+    /// applied to the camera right away, but the uniform is only written by the caller, so it
+    /// returns if anything changed.
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Position");
+            for (value, axis) in [(&mut self.position.x, "x "), (&mut self.position.y, "y "), (&mut self.position.z, "z ")] {
+                changed |= ui.add(egui::DragValue::new(value).speed(0.1).prefix(axis)).changed();
+            }
+        });
+        let position_changed = changed;
+        // the angles are shown in degrees, the pitch stays in the same range as in `update`
+        let max_pitch = Deg::from(Rad(SAFE_FRAC_PI_2)).0;
+        let mut pitch = Deg::from(self.pitch).0;
+        if ui.add(egui::Slider::new(&mut pitch, -max_pitch..=max_pitch).text("pitch (°)")).changed() {
+            self.pitch = Deg(pitch).into();
+            changed = true;
+        }
+        let mut yaw = Deg::from(self.yaw).0;
+        if ui.add(egui::DragValue::new(&mut yaw).speed(1.0).prefix("yaw (°) ")).changed() {
+            self.yaw = Deg(yaw).into();
+            changed = true;
+        }
+        let mut roll = Deg::from(self.roll).0;
+        if ui.add(egui::Slider::new(&mut roll, -180.0..=180.0).text("roll (°) (Q/E)")).changed() {
+            self.roll = Deg(roll).into();
+            changed = true;
+        }
+
+        ui.horizontal(|ui| {
+            if ui.radio(self.mode == CameraMode::Fly, "Fly").clicked() {
+                self.mode = CameraMode::Fly;
+            }
+            // the camera starts to circle around the point it is looking at
+            if ui.radio(self.mode == CameraMode::Orbit, "Orbit").clicked() && self.mode != CameraMode::Orbit {
+                self.set_orbit_target(self.position + self.direction() * self.distance);
+                changed = true;
+            }
+        });
+        if self.mode == CameraMode::Orbit {
+            ui.horizontal(|ui| {
+                ui.label("Target");
+                for (value, axis) in [(&mut self.target.x, "x "), (&mut self.target.y, "y "), (&mut self.target.z, "z ")] {
+                    changed |= ui.add(egui::DragValue::new(value).speed(0.1).prefix(axis)).changed();
+                }
+            });
+            changed |= ui.add(egui::DragValue::new(&mut self.distance).speed(0.1).clamp_range(MIN_ORBIT_DISTANCE..=10_000.0).prefix("distance ")).changed();
+            // a camera that was moved by hand turns to the target, otherwise the position follows
+            // the target, the distance and the angles
+            if position_changed {
+                self.set_orbit_target(self.target);
+            } else if changed {
+                self.position = self.target - self.direction() * self.distance;
+            }
+        }
+
+        // the lens changes the projection matrix
+        let mut field_of_view = Deg::from(self.field_of_view).0;
+        let mut znear = self.znear;
+        let mut zfar = self.zfar;
+        let mut lens_changed = ui.add(egui::Slider::new(&mut field_of_view, MIN_FIELD_OF_VIEW..=MAX_FIELD_OF_VIEW).text("field of view (°)")).changed();
+        ui.horizontal(|ui| {
+            // the near plane has to stay in front of the camera and the far plane behind the
+            // near plane, otherwise the projection turns the world inside out
+            lens_changed |= ui.add(egui::DragValue::new(&mut znear).speed(0.01).clamp_range(0.001..=zfar * 0.5).prefix("near ")).changed();
+            lens_changed |= ui.add(egui::DragValue::new(&mut zfar).speed(1.0).clamp_range(znear * 2.0..=100_000.0).prefix("far ")).changed();
+        });
+        if lens_changed {
+            self.set_perspective(Deg(field_of_view), self.aspect_ratio, znear, zfar);
+            changed = true;
+        }
+
+        ui.add(egui::Slider::new(&mut self.controls.smoothing, 0.0..=0.5).text("movement smoothing (s)"));
+
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Views");
+            let mut goto = None;
+            for name in self.presets.keys() {
+                if ui.button(name).clicked() {
+                    goto = Some(name.clone());
+                }
+            }
+            // the camera flies to the view instead of jumping there
+            if let Some(pose) = goto.and_then(|name| self.presets.get(&name).copied()) {
+                self.lerp_to(pose, PRESET_FLIGHT_DURATION, Easing::Smoothstep);
+            }
+            if ui.button("Save view").clicked() {
+                // the first name that is not taken yet
+                let name = (1..).map(|i| format!("view {}", i)).find(|name| !self.presets.contains_key(name)).unwrap();
+                self.save_preset(&name);
+            }
+        });
+
+        let mut orthographic = self.is_orthographic();
+        if ui.checkbox(&mut orthographic, "Orthographic (O)").changed() {
+            self.set_orthographic(orthographic);
+        }
+        if ui.add(egui::DragValue::new(&mut self.focus_distance).speed(0.1).clamp_range(0.01..=10_000.0).prefix("focus distance ")).changed() {
+            self.update_projection();
+            changed = true;
+        }
+        changed
+    }
+
+    /// the current view of the camera
+    pub fn snapshot(&self) -> CameraPose {
+        CameraPose {
+            position: self.position.into(),
+            pitch: self.pitch.0,
+            yaw: self.yaw.0,
+            roll: self.roll.0,
+            field_of_view: self.field_of_view.0,
+        }
+    }
+
+    /// Jump to the view of the pose. The input that was not applied yet and the keys that are
+    /// held are forgotten, so the camera doesn't move away from the pose right after the jump.
+    /// An orbiting camera keeps its distance and circles around the point in front of it.
+    pub fn apply_pose(&mut self, pose: &CameraPose) {
+        self.position = pose.position.into();
+        self.pitch = Rad(pose.pitch.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        self.yaw = Rad(pose.yaw);
+        self.roll = Rad(pose.roll);
+        if self.field_of_view != Rad(pose.field_of_view) {
+            self.set_perspective(Rad(pose.field_of_view), self.aspect_ratio, self.znear, self.zfar);
+        }
+        if self.mode == CameraMode::Orbit {
+            self.target = self.position + self.direction() * self.distance;
+        }
+        self.controls.reset();
+    }
+
+    /// remember the current view under the name, a preset with the same name is replaced
+    pub fn save_preset(&mut self, name: &str) {
+        self.presets.insert(name.to_string(), self.snapshot());
+    }
+
+    /// Jump to the view that was saved under the name, false if there is no such preset
+    #[allow(dead_code)]
+    pub fn goto_preset(&mut self, name: &str) -> bool {
+        match self.presets.get(name).copied() {
+            Some(pose) => {
+                self.apply_pose(&pose);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fly from the current view to the pose over the duration, `update` moves the camera
+    /// along. The position and the field of view are interpolated linearly and the orientation
+    /// with a slerp, so the camera turns at an even speed. The controls are ignored until the
+    /// camera arrives, after that the camera is exactly at the pose. A flight that is already
+    /// running is replaced, the new one starts where the camera is right now.
+    pub fn lerp_to(&mut self, target: CameraPose, duration: std::time::Duration, easing: Easing) {
+        self.controls.reset();
+        self.animation = Some(PoseAnimation {
+            from: self.snapshot(),
+            to: target,
+            elapsed: std::time::Duration::ZERO,
+            duration,
+            easing,
+        });
+    }
+
+    /// true while the camera flies to a pose, see `lerp_to`
+    pub fn is_animating(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    // The rotation that turns the axes of the camera space (x right, y up and z pointing back,
+    // like in the view matrix) into the camera's right, up and back in world space
+    fn orientation(pose: &CameraPose) -> Quaternion<f32> {
+        let (sin_pitch, cos_pitch) = pose.pitch.sin_cos();
+        let (sin_yaw, cos_yaw) = pose.yaw.sin_cos();
+        let direction = Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize();
+        let up = Self::up_for(direction, Rad(pose.roll));
+        Quaternion::from(Matrix3::from_cols(direction.cross(up), up, -direction)).normalize()
+    }
+
+    // Move the camera along the running flight, returns false if there is none
+    fn advance_animation(&mut self, dt: std::time::Duration) -> bool {
+        let Some(animation) = &mut self.animation else {
+            return false;
+        };
+        // the input that came in during the flight is thrown away
+        self.controls.reset();
+        animation.elapsed += dt;
+        if animation.elapsed >= animation.duration {
+            let to = animation.to;
+            self.animation = None;
+            self.apply_pose(&to);
+            return true;
+        }
+        let (from, to) = (animation.from, animation.to);
+        let t = animation.easing.apply(animation.elapsed.as_secs_f32() / animation.duration.as_secs_f32());
+
+        let from_position = Point3::from(from.position);
+        self.position = from_position + (Point3::from(to.position) - from_position) * t;
+        // a quaternion and its negation are the same rotation, the slerp takes the short way
+        // only if they point into the same half
+        let from_orientation = Self::orientation(&from);
+        let mut to_orientation = Self::orientation(&to);
+        if from_orientation.dot(to_orientation) < 0.0 {
+            to_orientation = -to_orientation;
+        }
+        let orientation = from_orientation.slerp(to_orientation, t);
+        // and back to the angles, like in `set_orbit_target` and `up_for`
+        let direction = orientation.rotate_vector(-Vector3::unit_z());
+        let up = orientation.rotate_vector(Vector3::unit_y());
+        self.pitch = Rad(direction.y.clamp(-1.0, 1.0).asin().clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        self.yaw = Rad(direction.z.atan2(direction.x));
+        let level_up = Self::up_for(direction, Rad(0.0));
+        self.roll = Rad(level_up.cross(up).dot(direction).atan2(level_up.dot(up)));
+
+        let field_of_view = Rad(from.field_of_view + (to.field_of_view - from.field_of_view) * t);
+        if self.field_of_view != field_of_view {
+            self.set_perspective(field_of_view, self.aspect_ratio, self.znear, self.zfar);
+        }
+        if self.mode == CameraMode::Orbit {
+            self.target = self.position + self.direction() * self.distance;
+        }
+        true
+    }
+
+    /// Circle around `target` from now on. The camera stays where it is and turns to look at
+    /// the target, so the view doesn't jump.
+    pub fn set_orbit_target(&mut self, target: Point3<f32>) {
+        let offset = target - self.position;
+        let distance = offset.magnitude();
+        if distance > 0.0 {
+            // the inverse of `direction`
+            let direction = offset / distance;
+            self.pitch = Rad(direction.y.clamp(-1.0, 1.0).asin().clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+            self.yaw = Rad(direction.z.atan2(direction.x));
+        }
+        self.mode = CameraMode::Orbit;
+        self.target = target;
+        self.distance = distance.max(MIN_ORBIT_DISTANCE);
+        self.position = self.target - self.direction() * self.distance;
+    }
+
+    /// Move the camera along its view direction so that the box from `bbox_min` to `bbox_max`
+    /// fills the picture, looking at the center of the box. The box is wrapped in a sphere,
+    /// which fits into the picture at the distance `radius / sin(fov / 2)` for the narrower of
+    /// the horizontal and the vertical field of view. The far plane is pushed out if it would
+    /// cut off the back of the sphere. The uniform is written by the caller.
+    pub fn frame_object(&mut self, bbox_min: Point3<f32>, bbox_max: Point3<f32>) {
+        let center = bbox_min.midpoint(bbox_max);
+        let radius = (bbox_max - bbox_min).magnitude() / 2.0;
+        let half_vertical = self.field_of_view / 2.0;
+        let half_horizontal = Rad((half_vertical.tan() * self.aspect_ratio).atan());
+        let half_fov = if half_horizontal < half_vertical { half_horizontal } else { half_vertical };
+        // the front of the sphere has to stay behind the near plane, which only matters for
+        // tiny boxes (a single point has a radius of 0)
+        let mut distance = (radius / half_fov.sin()).max(radius + 2.0 * self.znear);
+        if self.mode == CameraMode::Orbit {
+            distance = distance.max(MIN_ORBIT_DISTANCE);
+            self.target = center;
+            self.distance = distance;
+        }
+        // a flight to a pose that is still running would take the camera away again
+        self.animation = None;
+        self.position = center - self.direction() * distance;
+        // the orthographic projection shows the plane at the focus distance at the same size
+        self.focus_distance = distance;
+        let zfar = self.zfar.max((distance + radius) * 1.1);
+        self.set_perspective(self.field_of_view, self.aspect_ratio, self.znear, zfar);
+    }
+
     /// Take the input of the controls and update the state of the camera transform matrix
     pub fn update(&mut self, dt: std::time::Duration) {
+        // a flight to a pose moves the camera on its own
+        if self.advance_animation(dt) {
+            return;
+        }
         let dt = dt.as_secs_f32();
 
         // process the moving around part of the camera
@@ -295,21 +959,48 @@ impl Camera {
         let (pitch_sin, pitch_cos) = self.pitch.sin_cos();
         let forward = Vector3::new(yaw_cos, pitch_sin, yaw_sin).normalize();
         let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        self.position += forward * (self.controls.amount_forward - self.controls.amount_backward) * self.controls.speed * dt;
-        self.position += right * (self.controls.amount_right - self.controls.amount_left) * self.controls.speed * dt;
-        self.position += Vector3::unit_y() * (self.controls.amount_up - self.controls.amount_down) * self.controls.speed * dt;
+        let target_velocity = (forward * (self.controls.amount_forward - self.controls.amount_backward)
+            + right * (self.controls.amount_right - self.controls.amount_left)
+            + Vector3::unit_y() * (self.controls.amount_up - self.controls.amount_down)) * self.controls.speed;
+        // the mouse movement of this frame turns into a turning speed
+        let target_turn_velocity = Vector3::new(
+            self.controls.rotate_horizontal * self.controls.sensitivity,
+            -self.controls.rotate_vertical * self.controls.sensitivity,
+            (self.controls.amount_roll_right - self.controls.amount_roll_left) * self.controls.roll_speed,
+        );
+        self.controls.rotate_horizontal = 0.0;
+        self.controls.rotate_vertical = 0.0;
+        self.controls.smooth_velocities(target_velocity, target_turn_velocity, dt);
+        let mut translation = self.controls.velocity * dt;
+
+        // process the panning, the camera is moved in the plane of the screen without changing
+        // the direction it is looking in. Dragging to the right moves the world to the right, so
+        // the camera to the left.
+        let view_direction = Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
+        let screen_up = right.cross(view_direction).normalize();
+        translation -= right * self.controls.pan_horizontal * self.controls.pan_speed * dt;
+        translation += screen_up * self.controls.pan_vertical * self.controls.pan_speed * dt;
+        self.controls.pan_horizontal = 0.0;
+        self.controls.pan_vertical = 0.0;
 
         // process the scrolling motion and then reset it so that we don't scroll to
         // infinity
-        let scrollward = Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        self.position += scrollward * self.controls.scroll * self.controls.speed * self.controls.sensitivity * dt;
+        let zoom = self.controls.scroll * self.controls.speed * self.controls.sensitivity * dt;
         self.controls.scroll = 0.;
+        match self.mode {
+            CameraMode::Fly => self.position += translation + view_direction * zoom,
+            // the camera moves with the target, scrolling towards the target gets closer to it
+            CameraMode::Orbit => {
+                self.target += translation;
+                self.distance = (self.distance - zoom).max(MIN_ORBIT_DISTANCE);
+            }
+        }
 
-        // update the view direction and then reset the control amount;
-        self.yaw += Rad(self.controls.rotate_horizontal) * self.controls.sensitivity * dt;
-        self.pitch += Rad(-self.controls.rotate_vertical) * self.controls.sensitivity * dt;
-        self.controls.rotate_horizontal = 0.0;
-        self.controls.rotate_vertical = 0.0;
+        // turn the view direction with the smoothed turning speed
+        self.yaw += Rad(self.controls.turn_velocity.x * dt);
+        self.pitch += Rad(self.controls.turn_velocity.y * dt);
+        // the roll stays between -180° and 180° so the slider in the ui can show it
+        self.roll = (self.roll + Rad(self.controls.turn_velocity.z * dt)).normalize_signed();
 
         // limit the maximum and minimum pitch so we dont get gimball lock
         if self.pitch < -Rad(SAFE_FRAC_PI_2) {
@@ -317,16 +1008,202 @@ impl Camera {
         } else if self.pitch > Rad(SAFE_FRAC_PI_2) {
             self.pitch = Rad(SAFE_FRAC_PI_2);
         }
+
+        // in the orbit mode the pitch and yaw turn the camera around the target, it stays on the
+        // far side of the target from where it is looking
+        if self.mode == CameraMode::Orbit {
+            self.position = self.target - self.direction() * self.distance;
+        }
+
+        // all the movement is done, now the camera is put back where it's allowed to be. This
+        // happens before the uniform is uploaded so the constrained position is never visible
+        self.position = self.constrain(self.position);
+    }
+
+    /// the position the collider and the minimal height allow that is closest to `position`
+    pub fn constrain(&self, position: Point3<f32>) -> Point3<f32> {
+        let mut position = match &self.collider {
+            Some(collider) => collider.resolve(position),
+            None => position,
+        };
+        if let Some(min_height) = self.min_height {
+            position.y = position.y.max(min_height);
+        }
+        position
+    }
+
+    /// The matrix from world space to the clip space of wgpu, the projection (during the switch
+    /// to orthographic the blended one) times the view matrix
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        self.perspective * self.compute_view_matrix()
+    }
+
+    /// The matrix from the clip space of wgpu back to world space, None if the view projection
+    /// can't be inverted. Divide the result by its w to get a point.
+    pub fn inverse_view_projection(&self) -> Option<Matrix4<f32>> {
+        Self::finite_inverse(self.view_projection())
+    }
+
+    fn finite_inverse(view_projection: Matrix4<f32>) -> Option<Matrix4<f32>> {
+        // a broken lens (like the near plane on the far plane) fills the matrix with infinities,
+        // its determinant isn't 0 but the inverse is useless
+        let inverse = view_projection.invert()?;
+        AsRef::<[f32; 16]>::as_ref(&inverse).iter().all(|v| v.is_finite()).then_some(inverse)
     }
 
     /// Compute the transform matrix that goes into the CameraUniform
     pub fn compute_full_camera_transform(&self) -> [[f32; 4]; 4] {
-        (self.perspective * self.compute_view_matrix()).into()
+        self.view_projection().into()
+    }
+
+    /// The transform for the CameraUniform if this camera was at `position` looking in
+    /// `direction` with the `roll`, the perspective of this camera is used
+    pub fn compute_camera_transform_at(&self, position: Point3<f32>, direction: Vector3<f32>, roll: Rad<f32>) -> [[f32; 4]; 4] {
+        (self.perspective * Self::compute_view_matrix_at(position, direction, roll)).into()
+    }
+
+    /// The point in world space that ends up at `ndc` on the screen. x and y go from -1 to 1
+    /// (left to right and bottom to top), z is the value in the depth buffer from 0 (near plane)
+    /// to 1 (far plane). None if the projection can't be inverted.
+    pub fn unproject(&self, ndc: Point3<f32>) -> Option<Point3<f32>> {
+        Self::unproject_with(self.inverse_view_projection()?, ndc)
+    }
+
+    // `unproject` with the inverse of the view projection
+    fn unproject_with(inverse: Matrix4<f32>, ndc: Point3<f32>) -> Option<Point3<f32>> {
+        let world = inverse * ndc.to_homogeneous();
+        (world.w != 0.0).then(|| Point3::from_homogeneous(world))
+    }
+
+    /// The ray from the camera through the point of the screen, for picking with the mouse.
+    /// `screen_x` and `screen_y` are in pixels from the top left corner of a screen of `width`
+    /// x `height` pixels (add 0.5 to hit the center of a pixel). Returns the origin of the ray
+    /// on the near plane and its normalized direction.
+    ///
+    /// The screen point is unprojected twice, at the depth of the near plane and of the far
+    /// plane. The projection contains `OPENGL_TO_WGPU_MATRIX`, so those depths are 0 and 1 (not
+    /// -1 and 1 like in OpenGL), the inverse of the matrix undoes the remap. With an orthographic
+    /// projection all rays are parallel and start at different points of the near plane. If the
+    /// matrix can't be inverted the ray starts at the camera and goes straight ahead.
+    #[allow(dead_code)]
+    pub fn screen_ray(&self, screen_x: f32, screen_y: f32, width: u32, height: u32) -> (Point3<f32>, Vector3<f32>) {
+        // y points up in normalized device coordinates
+        let x = screen_x / width.max(1) as f32 * 2.0 - 1.0;
+        let y = 1.0 - screen_y / height.max(1) as f32 * 2.0;
+        Self::ray_through(self.view_projection(), self.direction(), x, y)
+            .unwrap_or((self.position, self.direction()))
+    }
+
+    // The ray of `screen_ray` through the point (x, y) in normalized device coordinates, for a
+    // camera with the view projection that looks in the direction `forward`. None if the
+    // matrix doesn't give a ray.
+    fn ray_through(view_projection: Matrix4<f32>, forward: Vector3<f32>, x: f32, y: f32) -> Option<(Point3<f32>, Vector3<f32>)> {
+        let inverse = Self::finite_inverse(view_projection)?;
+        let near = Self::unproject_with(inverse, Point3::new(x, y, 0.0))?;
+        let far = Self::unproject_with(inverse, Point3::new(x, y, 1.0))?;
+        let direction = far - near;
+        if direction.magnitude2() == 0.0 || !direction.magnitude2().is_finite() {
+            return None;
+        }
+        // a projection with the near and far plane swapped maps the far plane to depth 0, the
+        // ray still has to start at the camera and point away from it
+        if direction.dot(forward) < 0.0 {
+            return Some((far, -direction.normalize()));
+        }
+        Some((near, direction.normalize()))
+    }
+
+    /// The volume this camera sees in world space, to skip what is out of view. Use
+    /// `Frustum::contains_sphere` to test what is visible.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(self.view_projection())
     }
 
-    
     pub fn update_uniform(&self, queue: &wgpu::Queue) {
-        self.uniform.lock().unwrap().update(self.compute_full_camera_transform(), queue)
+        self.uniform.lock().unwrap().update(self.compute_full_camera_transform(), self.compute_view_matrix().into(), queue)
+    }
+}
+
+/// The volume a camera sees, a pyramid with its tip cut off (or a box for an orthographic
+/// camera), see `Camera::frustum`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// The six planes that bound the frustum in world space, in the order left, right, bottom,
+    /// top, near, far. Each plane (a, b, c, d) has a normalized normal that points into the
+    /// frustum, so a*x + b*y + c*z + d is the distance of a point from the plane and is positive
+    /// on the inside.
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Read the planes off the rows of a view projection matrix (the Gribb-Hartmann method).
+    /// The matrix has to include `OPENGL_TO_WGPU_MATRIX`: the depth of wgpu goes from 0 to 1
+    /// (not from -1 to 1 like in OpenGL), which is why the near plane is just the third row.
+    pub fn from_matrix(m: Matrix4<f32>) -> Self {
+        let (x, y, z, w) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            let length = plane.truncate().magnitude();
+            if length > 0.0 { plane / length } else { plane }
+        });
+        Self { planes }
+    }
+
+    /// true if at least a part of the sphere may be inside. The sphere is only rejected if it
+    /// is completely on the outside of one of the planes, near a corner of the frustum a sphere
+    /// that is just outside can still count as inside, which is fine for culling. A point is a
+    /// sphere with the radius 0, points exactly on one of the planes count as inside.
+    pub fn contains_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        let center = center.to_homogeneous();
+        self.planes.iter().all(|plane| plane.dot(center) >= -radius)
+    }
+}
+
+/// An animated switch from one camera to another. The view moves from the pose of the previous
+/// camera to the pose of the new camera over the duration, the position is interpolated linearly
+/// and the view direction is rotated with a slerp so it turns at a constant speed.
+/// The new camera itself doesn't move during the transition, only what is written to its uniform.
+#[derive(Debug, Clone)]
+pub struct CameraTransition {
+    from_position: Point3<f32>,
+    from_direction: Vector3<f32>,
+    from_roll: Rad<f32>,
+    elapsed: std::time::Duration,
+    duration: std::time::Duration,
+}
+
+impl CameraTransition {
+    /// start a transition that begins at the current pose of `from`
+    pub fn new(from: &Camera, duration: std::time::Duration) -> Self {
+        Self {
+            from_position: from.position,
+            from_direction: from.direction(),
+            from_roll: from.roll,
+            elapsed: std::time::Duration::ZERO,
+            duration,
+        }
+    }
+
+    /// Advance the transition by dt and write the intermediate view of the target camera into
+    /// its uniform. Returns false once the transition has ended, the uniform then holds the
+    /// exact pose of the target camera.
+    pub fn step(&mut self, dt: std::time::Duration, target: &Camera, queue: &wgpu::Queue) -> bool {
+        self.elapsed += dt;
+        if self.elapsed >= self.duration {
+            target.update_uniform(queue);
+            return false;
+        }
+        let t = self.elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        let position = self.from_position + (target.position - self.from_position) * t;
+        // the rotation that turns the old direction into the new one, for opposite directions
+        // we turn around the up axis
+        let rotation = Quaternion::from_arc(self.from_direction, target.direction(), Some(Vector3::unit_y()));
+        let direction = Quaternion::one().slerp(rotation, t).rotate_vector(self.from_direction);
+        // the roll takes the short way, from 170° to -170° it turns by 20°
+        let roll = self.from_roll + (target.roll - self.from_roll).normalize_signed() * t;
+        let transform = target.compute_camera_transform_at(position, direction, roll);
+        let view = Camera::compute_view_matrix_at(position, direction, roll);
+        target.uniform.lock().unwrap().update(transform, view.into(), queue);
+        true
     }
 }
 
@@ -335,12 +1212,15 @@ impl Camera {
 /// up when this struct is instantiated.
 #[derive(Debug)]
 pub struct CameraUniform {
-    gpu_buffer: wgpu::Buffer,
+    pub gpu_buffer: wgpu::Buffer,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
 }
 
 impl CameraUniform {
+    /// the full transform and the view matrix
+    pub const SIZE: wgpu::BufferAddress = 2 * 16 * 4;
+
     pub fn new(device: &wgpu::Device) -> Self {
         let gpu_buffer = Self::create_gpu_buffer(device);
         let bind_group_layout = Self::create_gpu_bind_group_layout(device);
@@ -352,8 +1232,11 @@ impl CameraUniform {
         }
     }
 
-    // when a new view transform is computed, this sends that new data to the buffer on the GPU
-    pub fn update(&mut self, camera_transform: [[f32; 4]; 4], queue: &wgpu::Queue) {
+    // when a new view transform is computed, this sends that new data to the buffer on the GPU.
+    // The view matrix alone comes after the full transform, the shaders that need the distance
+    // from the camera (like the fog) can't get it from the projection: it's 1 for an
+    // orthographic one
+    pub fn update(&mut self, camera_transform: [[f32; 4]; 4], view: [[f32; 4]; 4], queue: &wgpu::Queue) {
         // This hides complexity that would otherwise
         // be our responsibility. It essentially creates a 'staging buffer'
         // to which it writes the data and then adds a buffertobuffer copy operation to
@@ -361,8 +1244,9 @@ impl CameraUniform {
         queue.write_buffer(
             &self.gpu_buffer,
             0,
-            bytemuck::cast_slice(&[camera_transform]),
+            bytemuck::cast_slice(&[camera_transform, view]),
         );
+        RenderStats::record_buffer_write();
     }
 
     pub fn describe() -> wgpu::BindGroupLayoutDescriptor<'static> {
@@ -410,10 +1294,11 @@ impl CameraUniform {
     fn create_gpu_buffer(device: &wgpu::Device) -> wgpu::Buffer {
         device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Observer projection uniform buffer"),
-            size: 16 * 4,
+            size: Self::SIZE,
             // This buffer is the place that the view projection is placed in, so
             // we don't need the
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            // (COPY_SRC is only there so the golden checks can read the bound transform back)
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
             // a buffer that is mapped at creation will be available as
             // a memory map on the CPU side to write into. This
             // means that
@@ -421,3 +1306,99 @@ impl CameraUniform {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The view projection of a camera at the position with the angles on a 640 x 360 screen,
+    // between orthographic (blend 0) and perspective (blend 1), and the direction it looks in
+    fn view_projection(position: Point3<f32>, pitch: Deg<f32>, yaw: Deg<f32>, roll: Deg<f32>, blend: f32) -> (Matrix4<f32>, Vector3<f32>) {
+        let direction = Camera::direction_for(pitch.into(), yaw.into());
+        let projection = Camera::compute_blended_projection(Deg(60.0).into(), 640.0 / 360.0, 0.1, 100.0, 5.0, blend);
+        (projection * Camera::compute_view_matrix_at(position, direction, roll.into()), direction)
+    }
+
+    // for a tilted and rolled camera on a screen that isn't square, with the perspective and
+    // with the orthographic projection
+    #[test]
+    fn screen_ray_through_the_center_goes_straight_ahead() {
+        for blend in [1.0, 0.0] {
+            let (view_projection, forward) = view_projection(Point3::new(1.0, 2.0, 3.0), Deg(-25.0), Deg(130.0), Deg(20.0), blend);
+            let (_, direction) = Camera::ray_through(view_projection, forward, 0.0, 0.0).unwrap();
+            assert!(direction.cross(forward).magnitude() <= 1e-5, "the ray goes {:?} instead of {:?}", direction, forward);
+            assert!(direction.dot(forward) > 0.0);
+        }
+    }
+
+    // the eight corners of the screen at the near and the far plane are unprojected into the
+    // world and projected back, they have to end up where they started
+    #[test]
+    fn unprojected_corners_project_back() {
+        let (view_projection, _) = view_projection(Point3::new(2.0, 3.0, -4.0), Deg(-30.0), Deg(45.0), Deg(0.0), 1.0);
+        let inverse = Camera::finite_inverse(view_projection).unwrap();
+        for corner in 0..8 {
+            let ndc = Point3::new(
+                if corner & 1 == 0 { -1.0 } else { 1.0 },
+                if corner & 2 == 0 { -1.0 } else { 1.0 },
+                if corner & 4 == 0 { 0.0 } else { 1.0 },
+            );
+            let world = Camera::unproject_with(inverse, ndc).unwrap();
+            let back = Point3::from_homogeneous(view_projection * world.to_homogeneous());
+            assert!((back - ndc).magnitude() <= 1e-4, "the corner {:?} came back at {:?}", ndc, back);
+        }
+    }
+
+    // a camera that looks down the negative z axis with the near plane 1 in front of it
+    #[test]
+    fn frustum_rejects_spheres_out_of_view() {
+        let projection = Camera::compute_projection_matrix(Deg(60.0), 1.0, 1.0, 100.0);
+        let view = Camera::compute_view_matrix_at(Point3::new(0.0, 0.0, 0.0), Camera::direction_for(Deg(0.0).into(), Deg(-90.0).into()), Rad(0.0));
+        let frustum = Frustum::from_matrix(projection * view);
+        let spheres = [
+            ("before the near plane", Point3::new(0.0, 0.0, -0.5), 0.25, false),
+            ("behind the camera", Point3::new(0.0, 0.0, 3.0), 1.0, false),
+            ("in view", Point3::new(0.0, 0.0, -10.0), 1.0, true),
+            ("straddling the near plane", Point3::new(0.0, 0.0, -0.75), 0.5, true),
+            ("left of the view", Point3::new(-20.0, 0.0, -10.0), 1.0, false),
+            ("the point on the near plane", Point3::new(0.0, 0.0, -1.0), 0.0, true),
+            ("the point behind the camera", Point3::new(0.0, 0.0, 1.0), 0.0, false),
+        ];
+        for (name, center, radius, expected) in spheres {
+            assert_eq!(frustum.contains_sphere(center, radius), expected, "the sphere {}", name);
+        }
+    }
+
+    // rolling by 90 degrees turns the picture, what was on the right of the screen is at the
+    // top of it now
+    #[test]
+    fn rolling_a_quarter_turn_moves_up_to_the_old_right() {
+        let position = Point3::new(1.0, 2.0, 3.0);
+        let direction = Camera::direction_for(Deg(-20.0).into(), Deg(30.0).into());
+        let right = direction.cross(Camera::up_for(direction, Rad(0.0)));
+        let up = Camera::up_for(direction, Deg(90.0).into());
+        assert!((up - right).magnitude() <= 1e-5, "up is {:?} instead of {:?}", up, right);
+        // a point on the old right is straight above the center of the screen
+        let view = Camera::compute_view_matrix_at(position, direction, Deg(90.0).into());
+        let seen = view.transform_point(position + direction + right);
+        assert!(seen.x.abs() <= 1e-5 && seen.y > 0.0, "the point on the right is seen at {:?}", seen);
+    }
+
+    // ten frames of 10 ms get as close to the velocity the input asks for as one frame of
+    // 100 ms: a share of 1 - e^(-0.1 / 0.1) of the way for a smoothing of 0.1 s
+    #[test]
+    fn smoothing_does_not_depend_on_the_frame_rate() {
+        let (target, turn) = (Vector3::new(4.0, 0.0, -2.0), Vector3::new(0.0, 1.5, 0.0));
+        let mut fast = CameraControlls::new(4.0, 1.0, 0.1);
+        for _ in 0..10 {
+            fast.smooth_velocities(target, turn, 0.01);
+        }
+        let mut slow = CameraControlls::new(4.0, 1.0, 0.1);
+        slow.smooth_velocities(target, turn, 0.1);
+        let share = 1.0 - (-1.0f32).exp();
+        for controls in [&fast, &slow] {
+            assert!((controls.velocity - target * share).magnitude() <= 1e-5, "the velocity is {:?} instead of {:?}", controls.velocity, target * share);
+            assert!((controls.turn_velocity - turn * share).magnitude() <= 1e-5, "the turn velocity is {:?} instead of {:?}", controls.turn_velocity, turn * share);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// Clip planes cut away the geometry on one side of a plane, like a section in a CAD program, so
+// the inside of an object can be inspected. The planes are kept on the CPU side and sent to the
+// GPU as a small uniform, the fragment shader discards every fragment in front of an enabled plane.
+use crate::stats::RenderStats;
+
+/// the number of planes that can cut the scene at the same time
+pub const MAX_CLIP_PLANES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipPlane {
+    pub enabled: bool,
+    /// the side the normal points to is cut away, it doesn't need to be normalized
+    pub normal: [f32; 3],
+    /// the distance of the plane from the origin along the normal
+    pub distance: f32,
+}
+
+impl Default for ClipPlane {
+    fn default() -> Self {
+        Self { enabled: false, normal: [1.0, 0.0, 0.0], distance: 0.0 }
+    }
+}
+
+impl ClipPlane {
+    /// The plane equation (a, b, c, d) for the shader, a point p is clipped if
+    /// a*p.x + b*p.y + c*p.z + d > 0. A disabled plane is (0, 0, 0, -1) which never clips.
+    fn equation(&self) -> [f32; 4] {
+        let [x, y, z] = self.normal;
+        let length = (x * x + y * y + z * z).sqrt();
+        if !self.enabled || length == 0.0 {
+            return [0.0, 0.0, 0.0, -1.0];
+        }
+        [x / length, y / length, z / length, -self.distance]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ClipSettings {
+    pub planes: [ClipPlane; MAX_CLIP_PLANES],
+}
+
+impl ClipSettings {
+    fn to_raw(self) -> RawClipPlanes {
+        RawClipPlanes { planes: self.planes.map(|p| p.equation()) }
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Section planes");
+        for (i, plane) in self.planes.iter_mut().enumerate() {
+            ui.checkbox(&mut plane.enabled, format!("plane {}", i));
+            if plane.enabled {
+                ui.horizontal(|ui| {
+                    ui.label("normal");
+                    ui.add(egui::DragValue::new(&mut plane.normal[0]).speed(0.01).clamp_range(-1.0..=1.0));
+                    ui.add(egui::DragValue::new(&mut plane.normal[1]).speed(0.01).clamp_range(-1.0..=1.0));
+                    ui.add(egui::DragValue::new(&mut plane.normal[2]).speed(0.01).clamp_range(-1.0..=1.0));
+                });
+                ui.add(egui::Slider::new(&mut plane.distance, -10.0..=10.0).text("distance"));
+            }
+        }
+    }
+}
+
+// this needs to match the ClipPlanes struct in the shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawClipPlanes {
+    planes: [[f32; 4]; MAX_CLIP_PLANES],
+}
+
+/// Struct that holds all the data related to the representation of the clip planes on the GPU,
+/// it is set up the same way as the FogUniform
+#[derive(Debug)]
+pub struct ClipUniform {
+    gpu_buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ClipUniform {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let gpu_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Clip plane uniform buffer"),
+            size: std::mem::size_of::<RawClipPlanes>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&ClipUniform::describe());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            label: Some("Clip plane bind group"),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: gpu_buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            gpu_buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// send the new planes to the GPU
+    pub fn update(&mut self, settings: &ClipSettings, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.gpu_buffer, 0, bytemuck::cast_slice(&[settings.to_raw()]));
+        RenderStats::record_buffer_write();
+    }
+
+    pub fn describe() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Clip plane bind group"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        }
+    }
+}
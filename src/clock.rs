@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+// The time that drives the camera and the animations. The redraws don't happen at a fixed rate
+// (vsync, window in the background ...) so the time between two frames is measured and fed into
+// the clock. The clock can be paused and run faster or slower than real time, everything that
+// moves takes its time step from here so that it all stays in sync.
+
+/// the time step of `Clock::step`, one frame at 60 frames per second
+pub const STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    /// the simulated time that has passed since the start
+    elapsed: Duration,
+    paused: bool,
+    /// a paused clock advances by this in the next tick, see `step`
+    pending_step: Option<Duration>,
+    /// how fast the simulated time runs compared to the real time (1 is real time)
+    pub time_scale: f32,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            elapsed: Duration::ZERO,
+            paused: false,
+            pending_step: None,
+            time_scale: 1.0,
+        }
+    }
+
+    /// advance the clock by the real time that has passed and return the simulated time step.
+    /// When paused, no simulated time passes (unless a step was asked for).
+    pub fn tick(&mut self, real_dt: Duration) -> Duration {
+        let dt = if self.paused {
+            self.pending_step.take().unwrap_or(Duration::ZERO)
+        } else {
+            real_dt.mul_f32(self.time_scale.max(0.0))
+        };
+        self.elapsed += dt;
+        dt
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.pending_step = None;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Let a paused clock advance by exactly `STEP` in the next tick, to go through an animation
+    /// frame by frame. The time scale doesn't apply. A running clock ignores this.
+    pub fn step(&mut self) {
+        if self.paused {
+            self.pending_step = Some(STEP);
+        }
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label(format!("Simulated time: {:.2} s", self.elapsed.as_secs_f32()));
+        let mut paused = self.paused;
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut paused, "Paused (F6)").changed() {
+                if paused { self.pause() } else { self.resume() }
+            }
+            if ui.add_enabled(self.paused, egui::Button::new("Step (F7)")).clicked() {
+                self.step();
+            }
+        });
+        ui.add(egui::Slider::new(&mut self.time_scale, 0.0..=4.).text("time scale"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_and_paused_time_steps() {
+        let mut clock = Clock::new();
+        clock.time_scale = 0.5;
+        // times that are exact in the f32 of `mul_f32`
+        assert_eq!(clock.tick(Duration::from_secs(1)), Duration::from_millis(500));
+        clock.pause();
+        assert_eq!(clock.tick(Duration::from_millis(100)), Duration::ZERO);
+        // a step while paused is a whole frame, the time scale doesn't apply to it
+        clock.step();
+        assert_eq!(clock.tick(Duration::from_millis(100)), STEP);
+        assert_eq!(clock.tick(Duration::from_millis(100)), Duration::ZERO);
+        clock.resume();
+        assert_eq!(clock.tick(Duration::from_millis(250)), Duration::from_millis(125));
+        assert_eq!(clock.elapsed(), Duration::from_millis(625) + STEP);
+    }
+}
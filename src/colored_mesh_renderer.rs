@@ -2,29 +2,33 @@
 use wgpu::RenderPipelineDescriptor;
 
 // This renderer depends on the data structures as defined in the model and instance 
-use crate::{renderer, model, instance};
+use crate::{renderer, model, instance, camera, fog, clip, object_constants};
+use crate::stats::RenderStats;
 use std::mem;
+use std::sync::Arc;
 
 impl renderer::DescribeRenderPipeline for ColoredMeshRenderer {
-    fn describe_color_attachment(view: Option<&wgpu::TextureView>) -> Option<wgpu::RenderPassColorAttachment> {
+    fn describe_color_attachment<'a>(view: Option<&'a wgpu::TextureView>, resolve_target: Option<&'a wgpu::TextureView>, clear_color: wgpu::Color) -> Option<wgpu::RenderPassColorAttachment<'a>> {
         match view {
             Some(view) => Some(wgpu::RenderPassColorAttachment {
                 view,
-                resolve_target: None,
+                // when multisampling, the samples are averaged into the resolve target
+                resolve_target,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color { r: 0.001, g: 0.001, b: 0.001, a: 1.0 }),
+                    // every frame starts on the background
+                    load: wgpu::LoadOp::Clear(clear_color),
                     store: wgpu::StoreOp::Store }
             }),
             None => None
         }
     }
 
-    fn describe_depth_stencil(view: Option<&wgpu::TextureView>) -> Option<wgpu::RenderPassDepthStencilAttachment> {
+    fn describe_depth_stencil<'a>(view: Option<&'a wgpu::TextureView>, load: wgpu::LoadOp<f32>) -> Option<wgpu::RenderPassDepthStencilAttachment<'a>> {
         match view {
             Some(view) => Some(wgpu::RenderPassDepthStencilAttachment {
                 view,
                 depth_ops: Some(wgpu::Operations { 
-                load: wgpu::LoadOp::Clear(1.0),
+                load,
                 store: wgpu::StoreOp::Store }),
                 stencil_ops: None,
             }),
@@ -52,7 +56,7 @@ impl <'a, 'b, 'c> model::DrawMesh<'a, 'b, 'c> for ColoredMeshRenderer {
         mesh: &'c model::Surface,
         camera_bind_group: &'c wgpu::BindGroup,
     ) where 'b: 'a, 'c: 'b {
-        ColoredMeshRenderer::draw_mesh_instanced(render_pass, mesh, 0..1, camera_bind_group); 
+        ColoredMeshRenderer::draw_mesh_instanced(render_pass, mesh, mesh.instance_buffer.all_instances(), camera_bind_group); 
     }
 
     fn draw_mesh_instanced(
@@ -60,56 +64,224 @@ impl <'a, 'b, 'c> model::DrawMesh<'a, 'b, 'c> for ColoredMeshRenderer {
         mesh: &'c model::Surface,
         instances: std::ops::Range<u32>,
         camera_bind_group: &'c wgpu::BindGroup,
+    ) where 'b: 'a, 'c: 'b {
+        // far away meshes are drawn with a simplified version of them, see `model::Lod`
+        let (indices, base_vertex) = mesh.lod_range();
+        ColoredMeshRenderer::draw_submesh(render_pass, mesh, indices, base_vertex, instances, camera_bind_group);
+    }
+
+    fn draw_submesh(
+        render_pass: &'a mut wgpu::RenderPass<'b>,
+        mesh: &'c model::Surface,
+        indices: std::ops::Range<u32>,
+        base_vertex: i32,
+        instances: std::ops::Range<u32>,
+        camera_bind_group: &'c wgpu::BindGroup,
     ) where 'b: 'a, 'c: 'b {
         let occ_slots = mesh.instance_buffer.occupied_slots;
-        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        // all instances are hidden (or gone), an empty buffer slice is not allowed
+        if occ_slots == 0 {
+            return;
+        }
+        // only the instances that are in the buffer can be drawn
+        let instances = instances.start.min(occ_slots as u32)..instances.end.min(occ_slots as u32);
+        if instances.is_empty() {
+            return;
+        }
+        // the buffers may be shared with other meshes, the whole buffers are bound and the mesh
+        // is found in them with its first index and base vertex
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.buffer.slice(..));
         render_pass.set_vertex_buffer(1, mesh.instance_buffer.gpu_buffer.slice(..occ_slots * mem::size_of::<instance::RawInstance>() as u64));
-        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_index_buffer(mesh.index_buffer.buffer.slice(..), wgpu::IndexFormat::Uint32);
+        // the colored mesh renderer does not sample the material, so only the camera is bound
+        // here. The fog and clip plane bind groups are the same for all meshes and are bound by
+        // the caller
         render_pass.set_bind_group(0, camera_bind_group, &[]);
-        if let Some(material) = &mesh.material {
-            let mesh_texture_bind_group = material.bind_group.as_ref().unwrap();
-            render_pass.set_bind_group(1, mesh_texture_bind_group, &[]);
-        }
-        render_pass.draw_indexed(0..mesh.num_elements, 0, 0..occ_slots as u32);
+        RenderStats::record_bind_group_switch();
+        let instance_count = instances.len() as u64;
+        // the share of the triangles of the mesh that are in the index range
+        let triangle_count = mesh.triangle_count() * indices.len() as u64 / mesh.num_elements.max(1) as u64;
+        let first_index = mesh.first_index();
+        render_pass.draw_indexed(indices.start + first_index..indices.end + first_index, base_vertex + mesh.base_vertex(), instances);
+        RenderStats::record_draw(triangle_count * instance_count);
     }
 }
 
 pub struct ColoredMeshRenderer {
     pub pipeline: wgpu::RenderPipeline,
+    pub config: renderer::PipelineConfig,
+    /// the pipeline that only writes the depth of the meshes, if the renderer was created for
+    /// a depth prepass
+    pub prepass_pipeline: Option<wgpu::RenderPipeline>,
+    /// the pipelines for the wireframe with hidden line removal, if the wireframe is drawn with
+    /// lines (without the line mode the triangles are filled and hide each other anyway)
+    pub hidden_line: Option<HiddenLinePipelines>,
+    /// one pipeline for every decal level, they pull the surface towards the camera with the
+    /// bias of `decal_depth_bias`. Empty without a depth buffer
+    decal_pipelines: Vec<wgpu::RenderPipeline>,
+    /// blends the instances with an alpha below 1 over what is already drawn, see
+    /// `model::Surface::sort_transparent_instances`
+    pub transparent_pipeline: wgpu::RenderPipeline,
+    // kept to build the pipelines again when the shader is reloaded
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    layout: Arc<wgpu::PipelineLayout>,
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    depth_prepass: bool,
+}
+
+/// The shader in the source tree. In debug builds it is read from here when it is reloaded, the
+/// pipelines are first created (and always in release builds) with the copy in the binary.
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+pub const SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/color_shader.wgsl");
+
+/// The depth bias of the surfaces with the decal level (see `model::Surface::decal_level`).
+/// It is in units of the depth precision plus a part that grows with the slope of the triangle,
+/// negative values move the surface closer. Every level has to win against the one below it,
+/// so the bias grows with the level.
+pub fn decal_depth_bias(level: u8) -> wgpu::DepthBiasState {
+    let level = level.min(model::DECAL_LEVELS);
+    wgpu::DepthBiasState { constant: -4 * level as i32, slope_scale: -(level as f32), clamp: 0.0 }
+}
+
+/// A wireframe only shows the edges that are not hidden behind the surface of a mesh, when it
+/// is drawn in two passes. The first pass fills the triangles, but only into the depth buffer,
+/// the second one draws the lines and tests them against that depth. The lines on the visible
+/// side have the same depth as the filled triangles, so the surface is pushed back a little with
+/// a depth bias and the lines pass with `LessEqual`.
+pub struct HiddenLinePipelines {
+    /// fills the triangles into the depth buffer, there is no color target
+    pub surface_pipeline: wgpu::RenderPipeline,
+    /// draws the lines that are in front of the surface
+    pub line_pipeline: wgpu::RenderPipeline,
 }
 
 impl ColoredMeshRenderer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         // The device on which we create the render pipeline
         device: &wgpu::Device,
         // this is the camera that we are going to use for this pipeline
         camera_bind_group_layout: &wgpu::BindGroupLayout,
+        // the layout of the fog uniform the fragment shader reads
+        fog_bind_group_layout: &wgpu::BindGroupLayout,
+        // the layout of the clip planes the fragment shader cuts the geometry with
+        clip_bind_group_layout: &wgpu::BindGroupLayout,
+        // decides if the per object constants are push constants or a uniform at group 3
+        object_constants: &object_constants::ObjectConstantBinding,
         // the configuration of the surface that the resulting texture is going to be rendered to.
         surface_config: & wgpu::SurfaceConfiguration,
         // the properties of the depth buffer if we have one, the depth buffer that needs to be
         // used is set during the render pass. Here we declare how the buffer is used by the render
         // pipeline
-        depth_format: Option<wgpu::TextureFormat>
+        depth_format: Option<wgpu::TextureFormat>,
+        // the number of samples per pixel, needs to match the color and depth targets
+        sample_count: u32,
+        // how the indices of the meshes form triangles, a list or strips. Only meshes with this
+        // topology can be drawn with the renderer (see `model::Surface::topology`)
+        topology: wgpu::PrimitiveTopology,
+        // Render the depth of the meshes in a separate pass first. The color pass then only
+        // shades the fragments that are visible, because it tests for depth *equal* to the
+        // depth of the prepass and doesn't write depth itself. This only works because both
+        // passes compute bit for bit the same depth, which is why the position output of the
+        // vertex shader is marked @invariant and both pipelines use the same vertex shader and
+        // primitive state. Any difference (a depth bias, another vertex shader) makes the Equal
+        // test fail randomly. It only pays off when the fragment shader is expensive.
+        depth_prepass: bool,
+        // `Line` draws the wireframe, `Fill` solid triangles. Fill works on every device, the
+        // line mode needs the POLYGON_MODE_LINE feature (and `Point` POLYGON_MODE_POINT), which
+        // App::new asks for. Without the feature the renderer falls back to filling.
+        polygon_mode: wgpu::PolygonMode,
+        // the side of the triangles that is not drawn, None draws both. In the wireframe the
+        // culled triangles only take away the lines they don't share with a visible triangle,
+        // so it looks much the same. The solid meshes show it: with the back faces culled an
+        // open mesh can be seen into, with the front faces culled only its inside is left.
+        cull_mode: Option<wgpu::Face>,
     ) -> ColoredMeshRenderer {
         // The shader is hard coded into the program binary. Here it is loaded from
         // the binary and compiled into a shader module for the specific GPU that we have.
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Normal Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/color_shader.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(object_constants.shader_source(include_str!("./shaders/color_shader.wgsl")).into()),
         });
 
         // The layout for the pipeline. We only have an observer for this simple pipeline, that
-        // means no light and only the camera bind group that we need to care about in the layout.
+        // means no light and only the camera, the fog and the clip plane bind group that we need
+        // to care about in the layout. The per object constants are either push constants or one
+        // more bind group.
+        let mut bind_group_layouts = vec![camera_bind_group_layout, fog_bind_group_layout, clip_bind_group_layout];
+        bind_group_layouts.extend(object_constants.bind_group_layout());
         let layout = device.create_pipeline_layout(& wgpu::PipelineLayoutDescriptor {
             label: Some("Layout of the Colored Mesh Renderer Bind Group"),
-            bind_group_layouts: &[camera_bind_group_layout],
-            push_constant_ranges: &[],
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &object_constants.push_constant_ranges(),
         });
 
+        // All the fixed function state of the pipeline is collected in the config first so
+        // that the pipeline can report what it actually does (see `describe_config`)
+        let config = renderer::PipelineConfig {
+            label: "Colored Mesh Renderer",
+            // describes how the individual vertices form triangles (or if they form points or
+            // lines
+            topology,
+            // strips need the format of the indices to know the primitive restart index, which
+            // ends a strip and starts the next one
+            strip_index_format: topology.is_strip().then_some(wgpu::IndexFormat::Uint32),
+            // the list of triangle vertices is given in counter clockwise order, which determins
+            // which side the normal (and thus the 'front face' of the triangle lies on
+            front_face: wgpu::FrontFace::Ccw,
+            // we can decide here that we either want the hardware to pass all triangles to the
+            // rasterization stage, or only the ones with the front face facing 'the camear' or
+            // those with the back face 'facing the camera', If a primitive is 'culled' it is not
+            // sent to the fragment stage
+            cull_mode,
+            // to render objects as wiremeshes in a particular color this is set to the line mode,
+            // as then it does not fill the triangles, but only draws lines around the triangles.
+            // Some backends (WebGL) don't support the line mode, there we fill the triangles.
+            polygon_mode: match polygon_mode {
+                wgpu::PolygonMode::Line if !device.features().contains(wgpu::Features::POLYGON_MODE_LINE) => wgpu::PolygonMode::Fill,
+                wgpu::PolygonMode::Point if !device.features().contains(wgpu::Features::POLYGON_MODE_POINT) => wgpu::PolygonMode::Fill,
+                polygon_mode => polygon_mode,
+            },
+            depth_format,
+            // with a prepass the depth buffer already holds the closest depth
+            depth_write_enabled: !depth_prepass,
+            depth_compare: if depth_prepass { wgpu::CompareFunction::Equal } else { wgpu::CompareFunction::Less },
+            color_format: surface_config.format,
+            blend: Some(wgpu::BlendState {
+                color: wgpu::BlendComponent::REPLACE,
+                alpha: wgpu::BlendComponent::REPLACE,
+            }),
+            // this determins if and how multisampling is performed (in multisampling each pixel is
+            // split into multiple subpixels that are computed indipendently, the resulting color is a
+            // mixture of the supersampled pixels
+            sample_count,
+            bind_group_layout_entries: [
+                camera::CameraUniform::describe().entries.to_vec(),
+                fog::FogUniform::describe().entries.to_vec(),
+                clip::ClipUniform::describe().entries.to_vec(),
+            ].into_iter()
+                .chain(object_constants.bind_group_layout().map(|_| object_constants::ObjectConstantBinding::describe().entries.to_vec()))
+                .collect(),
+            push_constant_ranges: object_constants.push_constant_ranges(),
+        };
+        Self::with_shader(device, &shader, Arc::new(layout), config, depth_prepass)
+    }
+
+    /// Create all the pipelines of the renderer with the shader module. The fixed function state
+    /// comes from `config`, the variants (prepass, hidden lines, decals and transparency) are
+    /// derived from it.
+    fn with_shader(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        layout: Arc<wgpu::PipelineLayout>,
+        config: renderer::PipelineConfig,
+        depth_prepass: bool,
+    ) -> ColoredMeshRenderer {
+        let depth_format = config.depth_format;
         // the stuff that concerns the Vertex shader, 
         let vertex_state = wgpu::VertexState {
             // a reference to the compiled shader
-            module: &shader,
+            module: shader,
             // entry point for the vertex shader (the function that should is defined in the shader
             // source code that should be executed as the vertex shader).
             entry_point: "vs_main",
@@ -121,69 +293,29 @@ impl ColoredMeshRenderer {
         // hardware can make the right choices in sending data to the fragment shader 
         // this is the stage where the 'rendering primitives' are generated from the list of
         // vertices, hence the name.
-        let primitive = wgpu::PrimitiveState {
-            // describes how the individual vertices form triangles (or if they form points or
-            // lines
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            // the list of triangle vertices is given in counter clockwise order, which determins
-            // which side the normal (and thus the 'front face' of the triangle lies on
-            front_face: wgpu::FrontFace::Ccw,
-            // we can decide here that we either want the hardware to pass all triangles to the
-            // rasterization stage, or only the ones with the front face facing 'the camear' or
-            // those with the back face 'facing the camera', If a primitive is 'culled' it is not
-            // sent to the fragment stage
-            cull_mode: None,
-            // if this is set to false, the triangles that are rendered need to be inside the [0-1]
-            // x,y and range.
-            unclipped_depth: false,
-            // this pipeline should render objects as wiremeshes in a particular color. for this to
-            // this is why we need to set this to polygon line mode, as then it does not fill the
-            // triangles, but only draws lines around the triangles.
-            polygon_mode: wgpu::PolygonMode::Line,
-            // determins if every pixel touched by the triangle will be passed to the fragment
-            // shader.
-            conservative: false,
-        };
+        let primitive = config.primitive_state();
 
         let fragent_state = wgpu::FragmentState {
             // here the same shader module (compiled binary) contains both the fragment and the
             // vertex shader code
-            module: &shader,
+            module: shader,
             // the fragment shader has a different entry point than the vertex shader of course
             entry_point: "fs_main",
             targets: &[Some(wgpu::ColorTargetState {
-                format: surface_config.format,
-                blend: Some(wgpu::BlendState {
-                    color: wgpu::BlendComponent::REPLACE,
-                    alpha: wgpu::BlendComponent::REPLACE,
-                }),
+                format: config.color_format,
+                blend: config.blend,
                 write_mask: wgpu::ColorWrites::ALL,
             })],
 
         };
 
         // This determins if and how a Depth buffer will be used in the pipeline.
-        let depth_stencil = depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        });
-
-        // this determins if and how multisampling is performed (in multisampling each pixel is
-        // split into multiple subpixels that are computed indipendently, the resulting color is a
-        // mixture of the supersampled pixels
-        let multisample_state = wgpu::MultisampleState {
-            count: 1,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
-        };
+        let depth_stencil = config.depth_stencil_state();
+        let multisample_state = config.multisample_state();
 
         let descriptor = RenderPipelineDescriptor{
-            label: Some("Colored Mesh Renderer"),
-            layout: Some(&layout),
+            label: Some(config.label),
+            layout: Some(&*layout),
             vertex: vertex_state, 
             primitive,
             depth_stencil,
@@ -191,6 +323,283 @@ impl ColoredMeshRenderer {
             fragment: Some(fragent_state),
             multiview: None,
         };
-        ColoredMeshRenderer{ pipeline: device.create_render_pipeline(&descriptor)}
+        let pipeline = device.create_render_pipeline(&descriptor);
+
+        // the prepass pipeline is the same, but it writes the depth and has no color target.
+        // The fragment shader only discards the fragments that are cut away by the clip planes,
+        // so they don't end up in the depth buffer
+        let prepass_pipeline = depth_prepass.then(|| {
+            let prepass_config = renderer::PipelineConfig {
+                label: "Colored Mesh Depth Prepass",
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                ..config.clone()
+            };
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(prepass_config.label),
+                layout: Some(&*layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::Vertex::desc(), instance::Instance::desc()],
+                },
+                primitive: prepass_config.primitive_state(),
+                depth_stencil: prepass_config.depth_stencil_state(),
+                multisample: prepass_config.multisample_state(),
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_depth",
+                    targets: &[],
+                }),
+                multiview: None,
+            })
+        });
+
+        let hidden_line = (config.polygon_mode == wgpu::PolygonMode::Line && depth_format.is_some()).then(|| {
+            let surface_config = renderer::PipelineConfig {
+                label: "Colored Mesh Hidden Line Surface",
+                polygon_mode: wgpu::PolygonMode::Fill,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                ..config.clone()
+            };
+            // the bias is in units of the depth precision plus a part that grows with the slope
+            // of the triangle, the lines along steep triangles are the ones that would flicker
+            let depth_stencil = surface_config.depth_stencil_state().map(|state| wgpu::DepthStencilState {
+                bias: wgpu::DepthBiasState { constant: 2, slope_scale: 1.0, clamp: 0.0 },
+                ..state
+            });
+            let surface_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(surface_config.label),
+                layout: Some(&*layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::Vertex::desc(), instance::Instance::desc()],
+                },
+                primitive: surface_config.primitive_state(),
+                depth_stencil,
+                multisample: surface_config.multisample_state(),
+                // the clipped parts are discarded so they don't hide the lines behind them
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_depth",
+                    targets: &[],
+                }),
+                multiview: None,
+            });
+            let line_config = renderer::PipelineConfig {
+                label: "Colored Mesh Hidden Line Wireframe",
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                ..config.clone()
+            };
+            let line_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(line_config.label),
+                layout: Some(&*layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::Vertex::desc(), instance::Instance::desc()],
+                },
+                primitive: line_config.primitive_state(),
+                depth_stencil: line_config.depth_stencil_state(),
+                multisample: line_config.multisample_state(),
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: line_config.color_format,
+                        blend: line_config.blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+            });
+            HiddenLinePipelines { surface_pipeline, line_pipeline }
+        });
+
+        // The decals only test the depth, they are drawn after the surfaces they are on and
+        // don't write depth so nothing else depends on how much they are pulled forward. The
+        // depth of a decal is never in the prepass either, so `LessEqual` instead of `Equal`.
+        let decal_pipelines = depth_format.map(|_| (1..=model::DECAL_LEVELS).map(|level| {
+            let decal_config = renderer::PipelineConfig {
+                label: "Colored Mesh Decal",
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                ..config.clone()
+            };
+            let depth_stencil = decal_config.depth_stencil_state().map(|state| wgpu::DepthStencilState {
+                bias: decal_depth_bias(level),
+                ..state
+            });
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(decal_config.label),
+                layout: Some(&*layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[model::Vertex::desc(), instance::Instance::desc()],
+                },
+                primitive: decal_config.primitive_state(),
+                depth_stencil,
+                multisample: decal_config.multisample_state(),
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: decal_config.color_format,
+                        blend: decal_config.blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+            })
+        }).collect()).unwrap_or_default();
+
+        // The transparent instances are drawn after all the opaque ones, from back to front so
+        // every one is blended over the ones behind it. They test the depth so the opaque
+        // geometry hides them, but they don't write it: a transparent surface must not hide
+        // what is behind it. `Less` works with a prepass as well, the prepass only has the
+        // depth of the opaque instances.
+        let transparent_config = renderer::PipelineConfig {
+            label: "Colored Mesh Transparent",
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            ..config.clone()
+        };
+        let transparent_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(transparent_config.label),
+            layout: Some(&*layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[model::Vertex::desc(), instance::Instance::desc()],
+            },
+            primitive: transparent_config.primitive_state(),
+            depth_stencil: transparent_config.depth_stencil_state(),
+            multisample: transparent_config.multisample_state(),
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_transparent",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: transparent_config.color_format,
+                    blend: transparent_config.blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        ColoredMeshRenderer{ pipeline, config, prepass_pipeline, hidden_line, decal_pipelines, transparent_pipeline, layout, depth_prepass }
+    }
+
+    /// Read the shader from the source tree again (see `SHADER_PATH`) and build all pipelines
+    /// with it, for the surface and depth format that are given. If the shader doesn't compile
+    /// the error is returned and the renderer keeps the pipelines it had, so a typo in the
+    /// shader doesn't take the app down.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    pub fn reload_shader(
+        &mut self,
+        device: &wgpu::Device,
+        object_constants: &object_constants::ObjectConstantBinding,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+    ) -> anyhow::Result<()> {
+        let source = std::fs::read_to_string(SHADER_PATH)
+            .map_err(|e| anyhow::anyhow!("could not read {}: {}", SHADER_PATH, e))?;
+        self.rebuild_with_source(device, object_constants, &source, surface_config, depth_format)
+    }
+
+    /// `reload_shader` with the source of the shader
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    pub fn rebuild_with_source(
+        &mut self,
+        device: &wgpu::Device,
+        object_constants: &object_constants::ObjectConstantBinding,
+        source: &str,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+    ) -> anyhow::Result<()> {
+        // the errors of the shader and the pipelines that use it end up in the scope instead of
+        // the handler that panics
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Normal Shader"),
+            source: wgpu::ShaderSource::Wgsl(object_constants.shader_source(source).into()),
+        });
+        let config = renderer::PipelineConfig {
+            color_format: surface_config.format,
+            depth_format,
+            ..self.config.clone()
+        };
+        let reloaded = Self::with_shader(device, &shader, self.layout.clone(), config, self.depth_prepass);
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            anyhow::bail!("the reloaded shader has errors, keeping the old one: {}", error);
+        }
+        *self = reloaded;
+        Ok(())
+    }
+
+
+    /// the pipeline for the surfaces with the decal level, None for level 0 (no decal) or if
+    /// the renderer has no depth buffer
+    pub fn decal_pipeline(&self, level: u8) -> Option<&wgpu::RenderPipeline> {
+        let level = level.min(model::DECAL_LEVELS) as usize;
+        level.checked_sub(1).and_then(|i| self.decal_pipelines.get(i))
+    }
+
+    /// Describe the effective state of the pipeline, this is side effect free and can be used
+    /// to debug why a pipeline is behaving unexpectedly
+    pub fn describe_config(&self) -> String {
+        self.config.describe()
+    }
+}
+
+impl renderer::ScenePipeline for ColoredMeshRenderer {
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    fn prepass_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        self.prepass_pipeline.as_ref()
+    }
+
+    fn decal_pipeline(&self, level: u8) -> Option<&wgpu::RenderPipeline> {
+        ColoredMeshRenderer::decal_pipeline(self, level)
+    }
+
+    fn transparent_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        Some(&self.transparent_pipeline)
+    }
+
+    fn draw_mesh<'a>(&self, render_pass: &mut wgpu::RenderPass<'a>, mesh: &'a model::Surface, camera_bind_group: &'a wgpu::BindGroup) {
+        <ColoredMeshRenderer as model::DrawMesh>::draw_mesh(render_pass, mesh, camera_bind_group);
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every decal level is pulled closer than the one below it, level 0 not at all, and the
+    // levels above the last one are drawn like it
+    #[test]
+    fn decal_levels_are_pulled_closer_one_after_the_other() {
+        let surface = decal_depth_bias(0);
+        assert!(surface.constant == 0 && surface.slope_scale == 0.0, "level 0 has the bias {:?}", surface);
+        for level in 1..=model::DECAL_LEVELS {
+            let (below, bias) = (decal_depth_bias(level - 1), decal_depth_bias(level));
+            assert!(bias.constant < below.constant && bias.slope_scale < below.slope_scale, "level {} has the bias {:?} after {:?}", level, bias, below);
+        }
+        assert_eq!(decal_depth_bias(u8::MAX), decal_depth_bias(model::DECAL_LEVELS));
     }
 }
@@ -0,0 +1,71 @@
+// The settings of the app that are given on the command line when it starts.
+//
+// `--trace <dir>` records a wgpu API trace into the directory: every call the app makes to the
+// device and the queue, with the contents of the buffers and the shaders. The trace can be
+// replayed without the app, which helps with bugs that only show up with one driver. Attach
+// the whole directory to the bug report.
+//
+// Writing the trace needs the `trace` feature of wgpu, which is behind the `trace` feature of
+// this crate:
+//
+//     cargo run --features trace -- --trace /tmp/wgpu-trace
+//
+// To replay it, check out the wgpu repository at the release this crate uses (0.18) and run
+// its player with the directory:
+//
+//     cd player
+//     cargo run --features winit --bin play -- /tmp/wgpu-trace
+//
+// `--msaa <samples>` starts with MSAA at the number of samples per pixel instead of without
+// anti aliasing. The count has to be one the adapter supports for the formats of the scene
+// (the anti aliasing menu lists them), otherwise the app starts without MSAA.
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppConfig {
+    /// the directory the wgpu API trace is written to, no trace is written if this is None
+    pub trace_path: Option<PathBuf>,
+    /// the samples per pixel of the scene at the start, 1 renders without MSAA
+    pub msaa_samples: u32,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self { trace_path: None, msaa_samples: 1 }
+    }
+}
+
+impl AppConfig {
+    pub fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        for (i, arg) in args.iter().enumerate() {
+            if arg == "--trace" {
+                let path = args.get(i + 1).ok_or_else(|| anyhow::anyhow!("--trace needs the directory of the trace"))?;
+                config.trace_path = Some(PathBuf::from(path));
+            }
+            if arg == "--msaa" {
+                let samples = args.get(i + 1).ok_or_else(|| anyhow::anyhow!("--msaa needs the number of samples"))?;
+                config.msaa_samples = samples.parse().map_err(|e| anyhow::anyhow!("--msaa {}: {}", samples, e))?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// The trace path to hand to `request_device`. wgpu silently ignores the path when it is
+    /// built without tracing, so the missing feature is reported here instead.
+    pub fn device_trace_path(&self) -> Option<&Path> {
+        let path = self.trace_path.as_deref()?;
+        if cfg!(not(feature = "trace")) {
+            log::warn!("a trace into {:?} was requested, but wgpu is built without tracing. \
+                Run with `cargo run --features trace` to write it", path);
+            return None;
+        }
+        // wgpu writes into the directory, but doesn't create it
+        if let Err(e) = std::fs::create_dir_all(path) {
+            log::warn!("could not create the trace directory {:?}, no trace is written: {}", path, e);
+            return None;
+        }
+        log::info!("writing a wgpu trace into {:?}", path);
+        Some(path)
+    }
+}
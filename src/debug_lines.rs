@@ -0,0 +1,158 @@
+// Renderer for debug geometry. Lines are collected during the frame with `line` and `aabb`,
+// uploaded with `prepare` and drawn inside the scene render pass with `draw`. After drawing the
+// lines are cleared, so everything that should stay visible has to be added every frame.
+use cgmath::Point3;
+use std::mem;
+
+use crate::model;
+use crate::stats::RenderStats;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl LineVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+pub struct DebugLineRenderer {
+    pub pipeline: wgpu::RenderPipeline,
+    vertices: Vec<LineVertex>,
+    gpu_buffer: wgpu::Buffer,
+    // the number of vertices that fit into the gpu buffer
+    capacity: usize,
+    // the number of vertices uploaded by the last call to prepare
+    uploaded: u32,
+}
+
+impl DebugLineRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        // has to match the sample count of the scene render pass the lines are drawn in
+        sample_count: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug Line Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/debug_lines.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug line pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Line Renderer"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[LineVertex::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // the lines are hidden behind the geometry in front of them, but they don't write
+            // the depth so they don't hide anything themselves
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let capacity = 64;
+        let gpu_buffer = Self::create_gpu_buffer(device, capacity);
+        Self { pipeline, vertices: Vec::new(), gpu_buffer, capacity, uploaded: 0 }
+    }
+
+    fn create_gpu_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug line vertex buffer"),
+            size: (capacity * mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// add a line from `start` to `end` in world coordinates for the next frame
+    pub fn line(&mut self, start: Point3<f32>, end: Point3<f32>, color: [f32; 4]) {
+        self.vertices.push(LineVertex { position: start.into(), color });
+        self.vertices.push(LineVertex { position: end.into(), color });
+    }
+
+    /// add the 12 edges of a box in world coordinates for the next frame
+    pub fn aabb(&mut self, aabb: &model::Aabb, color: [f32; 4]) {
+        for (start, end) in aabb.edges() {
+            self.line(start, end, color);
+        }
+    }
+
+    /// upload the lines collected since the last frame, the buffer grows if they don't fit
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.vertices.len() > self.capacity {
+            self.capacity = self.vertices.len().next_power_of_two();
+            self.gpu_buffer = Self::create_gpu_buffer(device, self.capacity);
+        }
+        if !self.vertices.is_empty() {
+            queue.write_buffer(&self.gpu_buffer, 0, bytemuck::cast_slice(&self.vertices));
+            RenderStats::record_buffer_write();
+        }
+        self.uploaded = self.vertices.len() as u32;
+        self.vertices.clear();
+    }
+
+    /// draw the uploaded lines, this has to be called inside the scene render pass as it shares
+    /// the depth buffer with the meshes
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if self.uploaded == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+        render_pass.set_vertex_buffer(0, self.gpu_buffer.slice(..));
+        render_pass.draw(0..self.uploaded, 0..1);
+        // lines are no triangles
+        RenderStats::record_draw(0);
+    }
+}
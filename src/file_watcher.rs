@@ -0,0 +1,64 @@
+// Tells when files on disk change, so that whatever was made from them can be rebuilt while the
+// app is running (like the shaders, see `App::reload_shaders`). The operating system reports the
+// changes on a thread of its own, they are collected in a channel and picked up once per frame.
+//
+// The folders of the files are watched and not the files themselves: many editors save by
+// writing a new file and renaming it over the old one, and the watch of the old file is gone
+// after the first save.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use notify::Watcher;
+
+pub struct FileWatcher {
+    // the watching stops when this is dropped
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    files: HashSet<PathBuf>,
+}
+
+impl FileWatcher {
+    /// watch the files, they have to exist
+    pub fn new(files: &[&Path]) -> anyhow::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // the app may be gone already, then nobody is interested in the change
+            let _ = sender.send(event);
+        })?;
+        // the events name the files by the path of the folder that is watched, so both are
+        // made absolute to compare them
+        let files = files.iter()
+            .map(|file| file.canonicalize().map_err(|e| anyhow::anyhow!("could not watch {:?}: {}", file, e)))
+            .collect::<anyhow::Result<HashSet<_>>>()?;
+        let folders = files.iter().filter_map(|file| file.parent()).collect::<HashSet<_>>();
+        for folder in folders {
+            watcher.watch(folder, notify::RecursiveMode::NonRecursive)?;
+        }
+        Ok(Self { _watcher: watcher, events, files })
+    }
+
+    /// the watched files that were written, created or renamed since the last call, every file
+    /// is in the list only once
+    pub fn changed(&self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for event in self.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    log::warn!("error while watching files: {}", e);
+                    continue;
+                }
+            };
+            if !(event.kind.is_modify() || event.kind.is_create()) {
+                continue;
+            }
+            for path in event.paths {
+                if self.files.contains(&path) && !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,144 @@
+// Distance fog fades the geometry into a fog color the further away it is from the camera.
+// The settings are kept on the CPU side and sent to the GPU as a small uniform that the fragment
+// shader reads.
+use crate::stats::RenderStats;
+
+/// The formula used to compute how much fog there is at a given distance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FogMode {
+    /// the fog increases linearly from the start to the end distance
+    Linear,
+    /// the fog increases with exp(-(density * distance)^2), there is no hard end
+    Exp2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FogSettings {
+    pub enabled: bool,
+    pub mode: FogMode,
+    pub color: [f32; 3],
+    pub start: f32,
+    pub end: f32,
+    pub density: f32,
+}
+
+impl FogSettings {
+    /// the fog should blend into the background, so it takes the clear color by default
+    pub fn new(color: [f32; 3]) -> Self {
+        Self {
+            enabled: false,
+            mode: FogMode::Linear,
+            color,
+            start: 5.0,
+            end: 50.0,
+            density: 0.05,
+        }
+    }
+
+    /// the layout of the struct as it is expected by the shader. The mode 0 switches the fog off
+    fn to_raw(self) -> RawFog {
+        let mode = match (self.enabled, self.mode) {
+            (false, _) => 0,
+            (true, FogMode::Linear) => 1,
+            (true, FogMode::Exp2) => 2,
+        };
+        RawFog {
+            color: [self.color[0], self.color[1], self.color[2], 1.0],
+            start: self.start,
+            end: self.end,
+            density: self.density,
+            mode,
+        }
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Fog");
+        egui::ComboBox::from_label("Fog mode")
+            .selected_text(format!("{:?}", self.mode))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.mode, FogMode::Linear, "Linear");
+                ui.selectable_value(&mut self.mode, FogMode::Exp2, "Exp2");
+            });
+        ui.horizontal(|ui| {
+            ui.label("Fog color");
+            ui.color_edit_button_rgb(&mut self.color);
+        });
+        match self.mode {
+            FogMode::Linear => {
+                ui.add(egui::Slider::new(&mut self.start, 0.0..=100.).text("fog start"));
+                ui.add(egui::Slider::new(&mut self.end, self.start..=200.).text("fog end"));
+            }
+            FogMode::Exp2 => {
+                ui.add(egui::Slider::new(&mut self.density, 0.0..=1.).text("fog density"));
+            }
+        }
+    }
+}
+
+// this needs to match the Fog struct in the shader, the color is a vec4 so that the
+// following floats are 16 byte aligned
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawFog {
+    color: [f32; 4],
+    start: f32,
+    end: f32,
+    density: f32,
+    mode: u32,
+}
+
+/// Struct that holds all the data related to the representation of the fog on the GPU, it is
+/// set up the same way as the CameraUniform
+#[derive(Debug)]
+pub struct FogUniform {
+    gpu_buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl FogUniform {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let gpu_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fog uniform buffer"),
+            size: std::mem::size_of::<RawFog>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&FogUniform::describe());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            label: Some("Fog bind group"),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: gpu_buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            gpu_buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// send the new fog settings to the GPU
+    pub fn update(&mut self, settings: &FogSettings, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.gpu_buffer, 0, bytemuck::cast_slice(&[settings.to_raw()]));
+        RenderStats::record_buffer_write();
+    }
+
+    pub fn describe() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Fog bind group"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        }
+    }
+}
@@ -0,0 +1,2487 @@
+// Golden image regression checks. A fixed scene is rendered without a window at a fixed size with
+// a fixed camera, and the result is compared against an image that is stored in the repository.
+// If the images differ by more than a small tolerance something changed in the way we render,
+// which is either a bug or an intended change that needs a new golden image.
+//
+// Run the check with `cargo run -- --golden`. After an intended change the golden images are
+// written again with `cargo run -- --update-golden`. The checks here need a GPU, the ones that
+// don't (the math of the camera, the clock, ...) are unit tests next to the code and run with
+// `cargo test`.
+//
+// The rendering needs to be deterministic, so there is no timing involved: the camera is not
+// updated by the clock, the fog and the clip planes are switched off and the target is cleared with the fixed clear
+// color of the renderer. Different GPUs (and drivers) rasterize slightly differently, which is
+// what the tolerance is for.
+use std::path::{Path, PathBuf};
+
+use cgmath::SquareMatrix;
+use image::RgbaImage;
+
+use crate::colored_mesh_renderer::ColoredMeshRenderer;
+use crate::wireframe_renderer::WireframeRenderer;
+use crate::model::DrawMesh;
+use crate::renderer::{self, DescribeRenderPipeline};
+use crate::{buffer_arena, camera, clip, fog, hud_renderer, instance, light, lit_mesh_renderer, matcap_renderer, model, object_constants, point_cloud_renderer, primitives, resources, scene, skybox_renderer, stats};
+
+/// the size of the rendered golden images in pixels
+pub const GOLDEN_SIZE: u32 = 256;
+/// the largest mean squared error (of the 0-255 channel values) that still counts as equal
+pub const MSE_THRESHOLD: f64 = 4.0;
+
+const GOLDEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// compare the rendered images with the stored ones
+    Check,
+    /// overwrite the stored images with the rendered ones
+    Update,
+}
+
+impl Mode {
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        if args.iter().any(|a| a == "--update-golden") {
+            Some(Mode::Update)
+        } else if args.iter().any(|a| a == "--golden") {
+            Some(Mode::Check)
+        } else {
+            None
+        }
+    }
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("res").join("golden")
+}
+
+/// A device that is not connected to any window, this is enough to render into textures. The
+/// adapter comes along to find out what the device can do.
+pub async fn headless_device() -> anyhow::Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }).await.ok_or_else(|| anyhow::anyhow!("no adapter found to render the golden images"))?;
+    log::info!("rendering golden images with {:?}", adapter.get_info());
+    let mut features = adapter.features() & wgpu::Features::POLYGON_MODE_LINE;
+    let mut limits = wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits());
+    // the same choice between push constants and the uniform fallback as in the app
+    if object_constants::push_constants_supported(&adapter) {
+        features |= wgpu::Features::PUSH_CONSTANTS;
+        limits.max_push_constant_size = object_constants::PUSH_CONSTANT_SIZE;
+    }
+    let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
+        label: Some("Golden image device"),
+        features,
+        limits,
+    }, None).await?;
+    Ok((adapter, device, queue))
+}
+
+/// Render the objects as seen by the camera into an image of `GOLDEN_SIZE` x `GOLDEN_SIZE`
+/// pixels. The camera should have been created with a square aspect ratio. All meshes need to
+/// have the `topology`.
+pub fn render_golden(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    objects: &[model::Object],
+    camera: &camera::Camera,
+    topology: wgpu::PrimitiveTopology,
+) -> RgbaImage {
+    render_with_depth(device, queue, objects, camera, topology, wgpu::PolygonMode::Line).0
+}
+
+/// `render_golden` with the polygon mode of the colored mesh renderer, the depth texture the
+/// objects were drawn with comes along with the image
+pub fn render_with_depth(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    objects: &[model::Object],
+    camera: &camera::Camera,
+    topology: wgpu::PrimitiveTopology,
+    polygon_mode: wgpu::PolygonMode,
+) -> (RgbaImage, model::Texture) {
+    render_with_fog(device, queue, objects, camera, topology, polygon_mode, &fog::FogSettings::new([0.0, 0.0, 0.0]))
+}
+
+/// `render_with_depth` in the fog, the golden images are rendered without it
+pub fn render_with_fog(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    objects: &[model::Object],
+    camera: &camera::Camera,
+    topology: wgpu::PrimitiveTopology,
+    polygon_mode: wgpu::PolygonMode,
+    fog_settings: &fog::FogSettings,
+) -> (RgbaImage, model::Texture) {
+    let config = golden_config();
+    let target = golden_target(device);
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_texture = model::Texture::create_depth_texture(device, &config, 1, "golden depth texture");
+
+    let mut fog_uniform = fog::FogUniform::new(device);
+    fog_uniform.update(fog_settings, queue);
+    let mut clip_uniform = clip::ClipUniform::new(device);
+    clip_uniform.update(&clip::ClipSettings::default(), queue);
+    let mut object_constants = object_constants::ObjectConstantBinding::new(device);
+    let constants = objects.iter()
+        .flat_map(|obj| obj.meshes.iter())
+        .map(|mesh| mesh.object_constants())
+        .collect();
+    object_constants.prepare(device, queue, constants);
+    camera.update_uniform(queue);
+    let camera_uniform = camera.uniform.lock().unwrap();
+    let renderer = ColoredMeshRenderer::new(
+        device,
+        &camera_uniform.bind_group_layout,
+        &fog_uniform.bind_group_layout,
+        &clip_uniform.bind_group_layout,
+        &object_constants,
+        &config,
+        Some(model::Texture::DEPTH_FORMAT),
+        1,
+        topology,
+        false,
+        polygon_mode,
+        None,
+    );
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Golden image encoder"),
+    });
+    {
+        let color_attachment = [ColoredMeshRenderer::describe_color_attachment(Some(&target_view), None, renderer::DEFAULT_CLEAR_COLOR)];
+        let depth_stencil_attachment = ColoredMeshRenderer::describe_depth_stencil(Some(&depth_texture.view), renderer::DEPTH_CLEAR);
+        let mut render_pass = encoder.begin_render_pass(&ColoredMeshRenderer::describe_render_pass(&color_attachment, depth_stencil_attachment));
+        render_pass.set_pipeline(&renderer.pipeline);
+        render_pass.set_bind_group(1, &fog_uniform.bind_group, &[]);
+        render_pass.set_bind_group(2, &clip_uniform.bind_group, &[]);
+        let meshes = || objects.iter().flat_map(|obj| obj.meshes.iter()).enumerate();
+        for (i, mesh) in meshes().filter(|(_, mesh)| !mesh.is_decal()) {
+            object_constants.apply(&mut render_pass, i);
+            ColoredMeshRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group);
+        }
+        // the decals like in the app, after the surfaces and the lowest level first
+        for level in 1..=model::DECAL_LEVELS {
+            let Some(decal_pipeline) = renderer.decal_pipeline(level) else { break };
+            render_pass.set_pipeline(decal_pipeline);
+            for (i, mesh) in meshes().filter(|(_, mesh)| mesh.decal_level.min(model::DECAL_LEVELS) == level) {
+                object_constants.apply(&mut render_pass, i);
+                ColoredMeshRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group);
+            }
+        }
+    }
+    (read_target(device, queue, encoder, &target), depth_texture)
+}
+
+/// Draw the edges of the objects with lines `line_width` pixels wide (see `WireframeRenderer`),
+/// like `render_golden`
+pub fn render_thick_lines(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    objects: &[model::Object],
+    camera: &camera::Camera,
+    line_width: f32,
+) -> RgbaImage {
+    let config = golden_config();
+    let target = golden_target(device);
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_texture = model::Texture::create_depth_texture(device, &config, 1, "golden depth texture");
+    camera.update_uniform(queue);
+    let camera_uniform = camera.uniform.lock().unwrap();
+    let mut wireframe = WireframeRenderer::new(device, &camera_uniform.bind_group_layout, &config, Some(model::Texture::DEPTH_FORMAT), 1);
+    wireframe.set_line_width(line_width);
+    wireframe.prepare(GOLDEN_SIZE, GOLDEN_SIZE, queue);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Golden image encoder"),
+    });
+    {
+        let color_attachment = [ColoredMeshRenderer::describe_color_attachment(Some(&target_view), None, renderer::DEFAULT_CLEAR_COLOR)];
+        let depth_stencil_attachment = ColoredMeshRenderer::describe_depth_stencil(Some(&depth_texture.view), renderer::DEPTH_CLEAR);
+        let mut render_pass = encoder.begin_render_pass(&ColoredMeshRenderer::describe_render_pass(&color_attachment, depth_stencil_attachment));
+        wireframe.bind(&mut render_pass);
+        for mesh in objects.iter().flat_map(|obj| obj.meshes.iter()) {
+            WireframeRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group);
+        }
+    }
+    read_target(device, queue, encoder, &target)
+}
+
+/// Draw the quads of the HUD renderer, which has to be prepared already, on top of the clear
+/// color of the renderer
+pub fn render_hud(device: &wgpu::Device, queue: &wgpu::Queue, hud: &hud_renderer::HudRenderer) -> RgbaImage {
+    let target = golden_target(device);
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Golden image encoder"),
+    });
+    {
+        // the HUD loads what is in the target, so it is cleared first
+        let color_attachment = [ColoredMeshRenderer::describe_color_attachment(Some(&target_view), None, renderer::DEFAULT_CLEAR_COLOR)];
+        let _ = encoder.begin_render_pass(&ColoredMeshRenderer::describe_render_pass(&color_attachment, None));
+    }
+    hud.render(&mut encoder, &target_view);
+    read_target(device, queue, encoder, &target)
+}
+
+/// Draw the mesh with the lit renderer, lit from the side so the highlight doesn't whiten it.
+/// With a texture the surfaces get its colors (`fs_textured`), otherwise the colors of their
+/// instances. With `submesh` only that range of the indices is drawn, with that base vertex
+/// (`DrawMesh::draw_submesh`), otherwise the whole mesh.
+pub fn render_lit(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mesh: &model::Surface,
+    camera: &camera::Camera,
+    texture: Option<&model::Texture>,
+    submesh: Option<(std::ops::Range<u32>, i32)>,
+) -> anyhow::Result<RgbaImage> {
+    let texture = match texture {
+        Some(texture) => match (texture.bind_group_layout.as_ref(), texture.bind_group.as_ref()) {
+            (Some(layout), Some(bind_group)) => Some((layout, bind_group)),
+            _ => anyhow::bail!("the texture {} has no bind group", texture.name),
+        },
+        None => None,
+    };
+    let mut light_uniform = light::LightUniform::new(device);
+    light_uniform.update([1.0, -1.0, 0.0], [1.0, 1.0, 1.0], queue);
+    camera.update_uniform(queue);
+    let camera_uniform = camera.uniform.lock().unwrap();
+    let lit = lit_mesh_renderer::LitMeshRenderer::new(device, &camera_uniform.bind_group_layout, &light_uniform.bind_group_layout, texture.map(|(layout, _)| layout), &golden_config(), None, 1);
+    lit.update_eye(camera, queue);
+    let target = golden_target(device);
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Golden image encoder"),
+    });
+    {
+        let color_attachment = [ColoredMeshRenderer::describe_color_attachment(Some(&target_view), None, renderer::DEFAULT_CLEAR_COLOR)];
+        let mut render_pass = encoder.begin_render_pass(&ColoredMeshRenderer::describe_render_pass(&color_attachment, None));
+        lit.bind(&mut render_pass, &light_uniform);
+        if let Some((_, bind_group)) = texture {
+            render_pass.set_bind_group(3, bind_group, &[]);
+        }
+        match submesh {
+            Some((indices, base_vertex)) => lit_mesh_renderer::LitMeshRenderer::draw_submesh(&mut render_pass, mesh, indices, base_vertex, mesh.instance_buffer.all_instances(), &camera_uniform.bind_group),
+            None => lit_mesh_renderer::LitMeshRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group),
+        }
+    }
+    Ok(read_target(device, queue, encoder, &target))
+}
+
+// the renderers take the surface configuration to know what they render to
+fn golden_config() -> wgpu::SurfaceConfiguration {
+    wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: GOLDEN_FORMAT,
+        width: GOLDEN_SIZE,
+        height: GOLDEN_SIZE,
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+    }
+}
+
+fn golden_target(device: &wgpu::Device) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Golden image target"),
+        size: wgpu::Extent3d { width: GOLDEN_SIZE, height: GOLDEN_SIZE, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: GOLDEN_FORMAT,
+        // the image is copied out of the texture after rendering
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// submit the encoder with the rendering and copy the target out once the GPU is done
+fn read_target(device: &wgpu::Device, queue: &wgpu::Queue, mut encoder: wgpu::CommandEncoder, target: &wgpu::Texture) -> RgbaImage {
+    // the rows of a texture copy have to be aligned to 256 bytes
+    let unpadded_row = GOLDEN_SIZE * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_row = unpadded_row.div_ceil(align) * align;
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Golden image readback"),
+        size: (padded_row * GOLDEN_SIZE) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &readback,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: Some(GOLDEN_SIZE),
+            },
+        },
+        wgpu::Extent3d { width: GOLDEN_SIZE, height: GOLDEN_SIZE, depth_or_array_layers: 1 },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    // wait for the GPU to finish, there is nothing else to do in the meantime
+    let slice = readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| result.expect("could not read the golden image back"));
+    device.poll(wgpu::Maintain::Wait);
+    let data = slice.get_mapped_range();
+    let mut pixels = Vec::with_capacity((unpadded_row * GOLDEN_SIZE) as usize);
+    for row in data.chunks(padded_row as usize) {
+        pixels.extend_from_slice(&row[..unpadded_row as usize]);
+    }
+    drop(data);
+    readback.unmap();
+    RgbaImage::from_raw(GOLDEN_SIZE, GOLDEN_SIZE, pixels).expect("the readback has the size of the image")
+}
+
+/// The mean squared difference of all channels of all pixels, 0 means the images are equal and
+/// 65025 (255²) is the largest possible value
+pub fn mean_squared_error(a: &RgbaImage, b: &RgbaImage) -> anyhow::Result<f64> {
+    if a.dimensions() != b.dimensions() {
+        anyhow::bail!("the images have different sizes: {:?} and {:?}", a.dimensions(), b.dimensions());
+    }
+    let sum: f64 = a.as_raw().iter().zip(b.as_raw().iter())
+        .map(|(&x, &y)| {
+            let d = x as f64 - y as f64;
+            d * d
+        })
+        .sum();
+    Ok(sum / a.as_raw().len().max(1) as f64)
+}
+
+/// compare an image with the stored golden image of the same name, or store it in update mode
+pub fn check_golden(name: &str, image: &RgbaImage, mode: Mode) -> anyhow::Result<()> {
+    let path = golden_dir().join(format!("{}.png", name));
+    match mode {
+        Mode::Update => {
+            std::fs::create_dir_all(golden_dir())?;
+            image.save(&path)?;
+            println!("golden {}: written to {:?}", name, path);
+        }
+        Mode::Check => {
+            let golden = image::open(&path)
+                .map_err(|e| anyhow::anyhow!("could not open the golden image {:?}: {}", path, e))?
+                .to_rgba8();
+            let mse = mean_squared_error(image, &golden)?;
+            if mse > MSE_THRESHOLD {
+                // keep the failed image around so it can be compared by hand
+                let failed = std::env::temp_dir().join(format!("{}.failed.png", name));
+                image.save(&failed)?;
+                anyhow::bail!("golden {}: mean squared error {:.3} is above {}, the rendered image is at {:?}", name, mse, MSE_THRESHOLD, failed);
+            }
+            println!("golden {}: ok (mean squared error {:.3})", name, mse);
+        }
+    }
+    Ok(())
+}
+
+/// render all the golden scenes and check (or update) them
+pub async fn run(mode: Mode) -> anyhow::Result<()> {
+    // The app creates its own wgpu instance. With GL two instances share the EGL display, and
+    // whichever is dropped first takes it down with it, so the other one aborts the process
+    // when it is dropped. The app is done and dropped before the device of the other checks
+    // is created.
+    check_headless_app().await?;
+    let (adapter, device, queue) = headless_device().await?;
+    let teapot = resources::load_model("teapot.obj", &device, &queue).await?;
+    // looking at the teapot from the front so that all of it is in the image
+    let camera = camera::Camera::new(
+        (0.0, -1.5, 8.0),
+        cgmath::Deg(0.0),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        &device,
+        &queue,
+    );
+    let image = render_golden(&device, &queue, &[teapot], &camera, wgpu::PrimitiveTopology::TriangleList);
+    check_golden("teapot", &image, mode)?;
+    check_mesh_arena(&device, &queue, &camera, &image).await?;
+    check_lod_selection(&device, &queue)?;
+    check_render_stats(&device, &queue)?;
+    check_decal_levels(&device, &queue)?;
+    check_strip_plane(&device, &queue)?;
+    check_line_width(&device, &queue)?;
+    check_fog_endpoints(&device, &queue)?;
+    check_hidden_instance(&device, &queue)?;
+    check_alpha_partition(&device, &queue)?;
+    check_matrix_instance(&device, &queue)?;
+    check_pose_flight(&device, &queue)?;
+    check_scene_chain(&device, &queue)?;
+    check_dirty_upload(&device, &queue)?;
+    check_instance_removal(&device, &queue)?;
+    check_buffer_shrink(&device, &queue)?;
+    check_instance_limit(&device, &queue)?;
+    check_instance_panel(&device, &queue)?;
+    check_camera_window(&device, &queue)?;
+    check_frame_object(&device, &queue)?;
+    check_flipbook_frame_rate(&device, &queue)?;
+    check_flipbook_layers(&device, &queue)?;
+    check_crosshair(&device, &queue)?;
+    check_sampler_lod(&device, &queue)?;
+    check_instance_layers(&device, &queue)?;
+    check_submesh(&device, &queue)?;
+    check_premultiplied_alpha(&device, &queue)?;
+    check_last_mip_level(&device, &queue)?;
+    check_linear_texture(&device, &queue)?;
+    check_matcap_texture(&device, &queue)?;
+    check_cube_faces(&device, &queue)?;
+    check_point_cloud(&device, &queue)?;
+    check_model_from_path(&device, &queue).await?;
+    check_embedded_model(&device, &queue).await?;
+    check_stl_cube(&device, &queue).await?;
+    check_obj_normals(&device, &queue).await?;
+    check_metadata_sidecar(&device, &queue)?;
+    check_light_uniform(&device, &queue)?;
+    // GL can't copy depth textures into buffers
+    if adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::DEPTH_TEXTURE_AND_BUFFER_COPIES) {
+        check_depth_readback(&device, &queue)
+    } else {
+        println!("depth readback: skipped, the device can't copy depth textures");
+        Ok(())
+    }
+}
+
+/// A generated plane has to look the same with triangle strips as with a triangle list. This
+/// compares the two images with each other, so there is no stored image for it.
+fn check_strip_plane(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    // looking down at the whole plane at an angle, so the rows have different sizes on the screen
+    let camera = camera::Camera::new(
+        (0.0, 4.0, 5.0),
+        cgmath::Deg(-40.0),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        device,
+        queue,
+    );
+    let render = |layout: primitives::IndexLayout| {
+        let (vertices, indices) = primitives::grid_plane(12, 8, 3.0, layout);
+        let mut plane = model::Object::new(format!("{:?} plane", layout));
+        plane.meshes.push(model::Surface::new_with_topology(plane.name.clone(), &vertices, &indices, layout.topology(), None, device, queue));
+        render_golden(device, queue, &[plane], &camera, layout.topology())
+    };
+    let list = render(primitives::IndexLayout::TriangleList);
+    let strip = render(primitives::IndexLayout::TriangleStrip);
+    let mse = mean_squared_error(&list, &strip)?;
+    if mse > MSE_THRESHOLD {
+        let failed = std::env::temp_dir().join("plane_strip.failed.png");
+        strip.save(&failed)?;
+        anyhow::bail!("plane strip: mean squared error {:.3} to the triangle list is above {}, the strip image is at {:?}", mse, MSE_THRESHOLD, failed);
+    }
+    println!("plane strip: ok (mean squared error {:.3} to the triangle list)", mse);
+    Ok(())
+}
+
+/// The thick lines have to get wider in proportion to their width. The edges of a plane are
+/// drawn with lines of a few widths, the number of pixels they cover has to grow with the same
+/// factor as the width. Where the lines meet they overlap, which is why the factor only has to
+/// be close.
+fn check_line_width(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    // looking straight down at a plane with a few long edges
+    let camera = camera::Camera::new(
+        (0.0, 5.0, 0.001),
+        cgmath::Deg(-89.9),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        device,
+        queue,
+    );
+    let (vertices, indices) = primitives::grid_plane(2, 2, 3.0, primitives::IndexLayout::TriangleList);
+    let mut plane = model::Object::new("line width plane".to_string());
+    plane.meshes.push(model::Surface::new(plane.name.clone(), &vertices, &indices, None, device, queue));
+    let objects = [plane];
+    let background = render_thick_lines(device, queue, &[], &camera, 1.0);
+    let covered = |width: f32| {
+        let image = render_thick_lines(device, queue, &objects, &camera, width);
+        image.pixels().zip(background.pixels()).filter(|(a, b)| a != b).count()
+    };
+    let widths = [2.0, 4.0, 8.0];
+    let counts = widths.map(covered);
+    for i in 1..widths.len() {
+        let expected = widths[i] / widths[i - 1];
+        let factor = counts[i] as f32 / counts[i - 1].max(1) as f32;
+        if (factor - expected).abs() > 0.15 * expected {
+            anyhow::bail!("line width: {} px lines cover {} pixels, {} px lines {}, that is {:.2} times as many instead of {:.2}",
+                widths[i - 1], counts[i - 1], widths[i], counts[i], factor, expected);
+        }
+    }
+    println!("line width: ok (covered pixels {:?} for the widths {:?})", counts, widths);
+    Ok(())
+}
+
+/// The linear fog starts at the start distance and covers everything from the end distance on,
+/// whatever the projection. A plane is seen straight from above at exactly these distances with
+/// the perspective, the orthographic projection and halfway between them.
+fn check_fog_endpoints(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let (vertices, indices) = primitives::grid_plane(1, 1, 100.0, primitives::IndexLayout::TriangleList);
+    let mut plane = model::Object::new("fog plane".to_string());
+    plane.meshes.push(model::Surface::new(plane.name.clone(), &vertices, &indices, None, device, queue));
+    let objects = [plane];
+    let mut fog_settings = fog::FogSettings::new([1.0, 0.0, 1.0]);
+    fog_settings.start = 4.0;
+    fog_settings.end = 12.0;
+    let fog_color = image::Rgba([255, 0, 255, 255]);
+    let center = |height: f32, blend: f32, fog_settings: &fog::FogSettings| {
+        let mut camera = camera::Camera::new(
+            (0.0, height, 0.001),
+            cgmath::Deg(-89.9),
+            cgmath::Deg(-90.0),
+            cgmath::Deg(45.0),
+            GOLDEN_SIZE,
+            GOLDEN_SIZE,
+            0.1,
+            100.0,
+            device,
+            queue,
+        );
+        camera.set_projection_blend(blend);
+        let (image, _) = render_with_fog(device, queue, &objects, &camera, wgpu::PrimitiveTopology::TriangleList, wgpu::PolygonMode::Fill, fog_settings);
+        *image.get_pixel(GOLDEN_SIZE / 2, GOLDEN_SIZE / 2)
+    };
+    let close = |a: image::Rgba<u8>, b: image::Rgba<u8>| a.0.iter().zip(b.0.iter()).all(|(&a, &b)| a.abs_diff(b) <= 2);
+    let clear = center(fog_settings.start, 1.0, &fog::FogSettings::new([1.0, 0.0, 1.0]));
+    if close(clear, fog_color) {
+        anyhow::bail!("fog endpoints: the plane has the color of the fog without fog");
+    }
+    fog_settings.enabled = true;
+    for (blend, projection) in [(1.0, "perspective"), (0.0, "orthographic"), (0.5, "blended")] {
+        let at_start = center(fog_settings.start, blend, &fog_settings);
+        if !close(at_start, clear) {
+            anyhow::bail!("fog endpoints: the {} plane at the start of the fog is {:?} instead of {:?}", projection, at_start, clear);
+        }
+        let at_end = center(fog_settings.end, blend, &fog_settings);
+        if !close(at_end, fog_color) {
+            anyhow::bail!("fog endpoints: the {} plane at the end of the fog is {:?} instead of the fog color", projection, at_end);
+        }
+    }
+    println!("fog endpoints: ok (no fog at {} and only fog at {} for all projections)", fog_settings.start, fog_settings.end);
+    Ok(())
+}
+
+/// The depth that is read back from a pixel has to be the depth the camera projects the surface
+/// in that pixel to. The plane is seen (almost) straight from above, so it has (almost) the same
+/// depth everywhere and the pixel in the middle of the image has the depth of the center of the
+/// plane.
+fn check_depth_readback(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let camera = camera::Camera::new(
+        (0.0, 5.0, 0.001),
+        cgmath::Deg(-89.9),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        device,
+        queue,
+    );
+    let (vertices, indices) = primitives::grid_plane(1, 1, 3.0, primitives::IndexLayout::TriangleList);
+    let mut plane = model::Object::new("depth plane".to_string());
+    plane.meshes.push(model::Surface::new(plane.name.clone(), &vertices, &indices, None, device, queue));
+    let (_, depth_texture) = render_with_depth(device, queue, &[plane], &camera, wgpu::PrimitiveTopology::TriangleList, wgpu::PolygonMode::Fill);
+    let clip = camera.view_projection() * cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let expected = clip.z / clip.w;
+    let depth = depth_texture.read_depth_at(device, queue, GOLDEN_SIZE / 2, GOLDEN_SIZE / 2)?;
+    let corner = depth_texture.read_depth_at(device, queue, 0, 0)?;
+    if (depth - expected).abs() > 1e-4 || corner != 1.0 {
+        anyhow::bail!("depth readback: the plane has the depth {} instead of {} and the empty corner {} instead of 1", depth, expected, corner);
+    }
+    println!("depth readback: ok (depth {} where {} is expected)", depth, expected);
+    Ok(())
+}
+
+/// A hidden instance keeps its slot but is left out of the instances that are drawn. Three
+/// instances with the one in the middle hidden have to draw two instances and look the same as
+/// the two outer instances on their own. The hidden instance isn't in the drawn part of the GPU
+/// buffer, and showing it again draws all three with the data it had.
+fn check_hidden_instance(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let camera = camera::Camera::new(
+        (0.0, 5.0, 0.001),
+        cgmath::Deg(-89.9),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        device,
+        queue,
+    );
+    let row = |xs: &[f32], hidden: Option<usize>| -> anyhow::Result<model::Object> {
+        let (vertices, indices) = primitives::grid_plane(1, 1, 1.0, primitives::IndexLayout::TriangleList);
+        let mut object = model::Object::new("instance row".to_string());
+        let mut mesh = model::Surface::new(object.name.clone(), &vertices, &indices, None, device, queue);
+        // the surface comes with an instance at the origin
+        mesh.instances.clear();
+        for &x in xs {
+            mesh.create_instance(cgmath::Vector3::new(x, 0.0, 0.0), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(0.0, 1.0, 0.0, 1.0))?;
+        }
+        if let Some(hidden) = hidden {
+            mesh.instances[hidden].set_visible(false);
+        }
+        for instance in mesh.instances.iter_mut() {
+            instance.update(&mut mesh.instance_buffer);
+        }
+        mesh.instance_buffer.flush(device, queue);
+        object.meshes.push(mesh);
+        Ok(object)
+    };
+    let mut with_hidden = row(&[-1.5, 0.0, 1.5], Some(1))?;
+    let drawn = with_hidden.meshes[0].instance_buffer.all_instances().len();
+    if drawn != 2 {
+        anyhow::bail!("hidden instance: {} of the three instances are drawn instead of 2", drawn);
+    }
+    let raw_size = std::mem::size_of::<instance::RawInstance>();
+    let hidden = with_hidden.meshes[0].instances[1].compute_instance_matrix();
+    let uploaded = read_buffer(device, queue, &with_hidden.meshes[0].instance_buffer.gpu_buffer, (drawn * raw_size) as wgpu::BufferAddress);
+    if uploaded.chunks(raw_size).any(|raw| raw == bytemuck::bytes_of(&hidden)) {
+        anyhow::bail!("hidden instance: the hidden instance is in the drawn part of the GPU buffer");
+    }
+    let outer = row(&[-1.5, 1.5], None)?;
+    let (hidden_image, _) = render_with_depth(device, queue, std::slice::from_ref(&with_hidden), &camera, wgpu::PrimitiveTopology::TriangleList, wgpu::PolygonMode::Fill);
+    let (outer_image, _) = render_with_depth(device, queue, &[outer], &camera, wgpu::PrimitiveTopology::TriangleList, wgpu::PolygonMode::Fill);
+    let mse = mean_squared_error(&hidden_image, &outer_image)?;
+    if mse > MSE_THRESHOLD {
+        let failed = std::env::temp_dir().join("hidden_instance.failed.png");
+        hidden_image.save(&failed)?;
+        anyhow::bail!("hidden instance: mean squared error {:.3} to the outer instances is above {}, the image is at {:?}", mse, MSE_THRESHOLD, failed);
+    }
+
+    let mesh = &mut with_hidden.meshes[0];
+    mesh.instances[1].set_visible(true);
+    for instance in mesh.instances.iter_mut() {
+        instance.update(&mut mesh.instance_buffer);
+    }
+    mesh.instance_buffer.flush(device, queue);
+    let shown = mesh.instance_buffer.all_instances().len();
+    let uploaded = read_buffer(device, queue, &mesh.instance_buffer.gpu_buffer, (shown * raw_size) as wgpu::BufferAddress);
+    if shown != 3 {
+        anyhow::bail!("hidden instance: after showing the hidden instance again {} instances are drawn instead of 3", shown);
+    }
+    if !uploaded.chunks(raw_size).any(|raw| raw == bytemuck::bytes_of(&hidden)) {
+        anyhow::bail!("hidden instance: the instance that is shown again lost its data");
+    }
+    println!("hidden instance: ok ({} of 3 instances drawn, mean squared error {:.3} to the outer instances, all 3 when it is shown again)", drawn, mse);
+    Ok(())
+}
+
+/// Changing the alpha of an instance moves it between the opaque and the transparent part of
+/// the instance buffer: of three opaque instances the middle one is made half transparent, it
+/// has to be the last one in the buffer, and made opaque again all three are opaque.
+fn check_alpha_partition(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let (vertices, indices) = primitives::grid_plane(1, 1, 1.0, primitives::IndexLayout::TriangleList);
+    let mut mesh = model::Surface::new("alpha row".to_string(), &vertices, &indices, None, device, queue);
+    mesh.instances.clear();
+    for x in [-1.5, 0.0, 1.5] {
+        mesh.create_instance(cgmath::Vector3::new(x, 0.0, 0.0), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(0.0, 1.0, 0.0, 1.0))?;
+    }
+    let raw_size = std::mem::size_of::<instance::RawInstance>();
+    for (alpha, opaque, transparent) in [(1.0, 0..3, 3..3), (0.5, 0..2, 2..3), (1.0, 0..3, 3..3)] {
+        mesh.instances[1].color.w = alpha;
+        for instance in mesh.instances.iter_mut() {
+            instance.update(&mut mesh.instance_buffer);
+        }
+        mesh.instance_buffer.flush(device, queue);
+        let buffer = &mesh.instance_buffer;
+        if buffer.opaque_instances() != opaque || buffer.transparent_instances() != transparent {
+            anyhow::bail!("alpha partition: with the alpha {} the opaque instances are {:?} and the transparent ones {:?} instead of {:?} and {:?}",
+                alpha, buffer.opaque_instances(), buffer.transparent_instances(), opaque, transparent);
+        }
+        if alpha < 1.0 {
+            let uploaded = read_buffer(device, queue, &buffer.gpu_buffer, (3 * raw_size) as wgpu::BufferAddress);
+            if &uploaded[2 * raw_size..] != bytemuck::bytes_of(&mesh.instances[1].compute_instance_matrix()) {
+                anyhow::bail!("alpha partition: the half transparent instance isn't in the transparent part of the GPU buffer");
+            }
+        }
+    }
+    println!("alpha partition: ok (the instance moves to the transparent part at the alpha 0.5 and back at 1)");
+    Ok(())
+}
+
+/// copy the first `size` bytes of a GPU buffer (an instance buffer or a uniform) back to the CPU
+fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer, size: wgpu::BufferAddress) -> Vec<u8> {
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Buffer readback"),
+        size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Buffer readback encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, size);
+    queue.submit(std::iter::once(encoder.finish()));
+    let slice = readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| result.expect("could not read the buffer back"));
+    device.poll(wgpu::Maintain::Wait);
+    let bytes = slice.get_mapped_range().to_vec();
+    readback.unmap();
+    bytes
+}
+
+/// An instance created from a matrix has to put exactly that matrix into the instance buffer,
+/// even if it has shear that the position, rotation and scale can't express. The buffer is read
+/// back from the GPU and compared bit for bit.
+fn check_matrix_instance(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let matrix = cgmath::Matrix4::new(
+        1.5, 0.0, 0.0, 0.0,
+        0.5, 2.0, 0.0, 0.0,
+        0.0, 0.25, 0.75, 0.0,
+        1.0, 2.0, 3.0, 1.0,
+    );
+    let mut buffer = instance::InstanceBuffer::new(device, 1);
+    let mut instance = instance::Instance::from_matrix(matrix, cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0), buffer.get_instance_buffer_slot()?);
+    instance.update(&mut buffer);
+    buffer.flush(device, queue);
+
+    let size = std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress;
+    let uploaded: [[f32; 4]; 4] = bytemuck::pod_read_unaligned(&read_buffer(device, queue, &buffer.gpu_buffer, size));
+
+    let expected: [[f32; 4]; 4] = matrix.into();
+    if bytemuck::bytes_of(&uploaded) != bytemuck::bytes_of(&expected) {
+        anyhow::bail!("matrix instance: the instance buffer holds {:?} instead of {:?}", uploaded, expected);
+    }
+    println!("matrix instance: ok (the sheared matrix is in the instance buffer bit for bit)");
+    Ok(())
+}
+
+/// A grandchild is placed by the transforms of its parent and grandparent. Only the nodes that
+/// moved (and their children) are placed again, and an attached instance is still found after
+/// an instance before it in the mesh was removed; once it's removed itself it's dropped.
+fn check_scene_chain(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    use cgmath::{Deg, Matrix4, Vector3};
+
+    let (vertices, indices) = primitives::grid_plane(1, 1, 1.0, primitives::IndexLayout::TriangleList);
+    let mut object = model::Object::new("scene chain".to_string());
+    let mut mesh = model::Surface::new(object.name.clone(), &vertices, &indices, None, device, queue);
+    // the first instance is only there to be removed, the other three go down the chain
+    for i in 1..4 {
+        mesh.create_instance(Vector3::new(0.0, 0.0, i as f32), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0))?;
+    }
+    object.meshes.push(mesh);
+    let mut objects = vec![object];
+
+    let mut scene = scene::SceneGraph::new();
+    let root = scene.add_node("root", Matrix4::from_translation(Vector3::new(1.0, 0.0, 0.0)), None);
+    let child = scene.add_node("child", Matrix4::from_angle_y(Deg(90.0)), Some(root));
+    let grandchild = scene.add_node("grandchild", Matrix4::from_scale(2.0), Some(child));
+    for (node, instance) in [(root, 1), (child, 2), (grandchild, 3)] {
+        if !scene.attach_instance(node, scene::InstanceRef { object: 0, mesh: 0, instance }, &objects) {
+            anyhow::bail!("scene chain: instance {} could not be attached", instance);
+        }
+    }
+    // the world matrix of the instance that was created at z = distance on the node
+    let expected = |chain: &[Matrix4<f32>], distance: f32| {
+        chain.iter().fold(Matrix4::identity(), |world, local| world * local) * Matrix4::from_translation(Vector3::new(0.0, 0.0, distance))
+    };
+    let compare = |objects: &[model::Object], index: usize, expected: Matrix4<f32>, name: &str| -> anyhow::Result<()> {
+        let world = objects[0].meshes[0].instances[index].world_matrix();
+        let difference = AsRef::<[f32; 16]>::as_ref(&(world - expected)).iter().fold(0.0f32, |max, v| max.max(v.abs()));
+        if difference > 1e-5 {
+            anyhow::bail!("scene chain: the {} instance is at {:?} instead of {:?}", name, world, expected);
+        }
+        Ok(())
+    };
+
+    let placed_first = scene.resolve_world_transforms(&mut objects);
+    let (root_local, child_local, grandchild_local) = (scene.node(root).local(), scene.node(child).local(), scene.node(grandchild).local());
+    compare(&objects, 1, expected(&[root_local], 1.0), "root")?;
+    compare(&objects, 2, expected(&[root_local, child_local], 2.0), "child")?;
+    compare(&objects, 3, expected(&[root_local, child_local, grandchild_local], 3.0), "grandchild")?;
+    if scene.world_transform(grandchild) != root_local * child_local * grandchild_local {
+        anyhow::bail!("scene chain: the world transform of the grandchild isn't the product of the chain");
+    }
+    let placed_unchanged = scene.resolve_world_transforms(&mut objects);
+
+    // the instances move down by one, the scene still knows which one is which
+    objects[0].meshes[0].remove_instance(0);
+    let child_local = Matrix4::from_angle_y(Deg(-45.0));
+    scene.set_local(child, child_local);
+    let placed_moved = scene.resolve_world_transforms(&mut objects);
+    compare(&objects, 0, expected(&[root_local], 1.0), "root")?;
+    compare(&objects, 1, expected(&[root_local, child_local], 2.0), "child")?;
+    compare(&objects, 2, expected(&[root_local, child_local, grandchild_local], 3.0), "grandchild")?;
+    if (placed_first, placed_unchanged, placed_moved) != (3, 0, 2) {
+        anyhow::bail!("scene chain: {}, {} and {} instances were placed, expected all 3, none and the 2 below the moved child",
+            placed_first, placed_unchanged, placed_moved);
+    }
+
+    objects[0].meshes[0].remove_instance(2);
+    scene.set_local(grandchild, Matrix4::from_scale(3.0));
+    let placed_removed = scene.resolve_world_transforms(&mut objects);
+    if placed_removed != 0 || !scene.node(grandchild).instances.is_empty() {
+        anyhow::bail!("scene chain: the removed grandchild instance is still attached");
+    }
+    println!("scene chain: ok (3 levels, {} / {} / {} instances placed for the first, an unchanged and a moved frame)", placed_first, placed_unchanged, placed_moved);
+    Ok(())
+}
+
+/// The cube and the teapot loaded into one arena share a single vertex and a single index
+/// buffer, which holds exactly their data. The teapot comes after the cube in the buffers, drawn
+/// from there it has to look the same as the teapot with buffers of its own.
+async fn check_mesh_arena(device: &wgpu::Device, queue: &wgpu::Queue, camera: &camera::Camera, teapot_image: &RgbaImage) -> anyhow::Result<()> {
+    let mut arena = buffer_arena::MeshArena::default();
+    let options = resources::LoadOptions::default();
+    let cube = resources::load_model_from(&resources::resource_location("cube.obj"), device, queue, None, Some(&mut arena), options).await?;
+    let teapot = resources::load_model_from(&resources::resource_location("teapot.obj"), device, queue, None, Some(&mut arena), options).await?;
+    let meshes = cube.meshes.iter().chain(teapot.meshes.iter()).collect::<Vec<_>>();
+    let vertex_bytes: wgpu::BufferAddress = meshes.iter().map(|mesh| mesh.vertex_buffer.size).sum();
+    let index_bytes: wgpu::BufferAddress = meshes.iter().map(|mesh| mesh.index_buffer.size).sum();
+    if arena.vertices.page_count() != 1 || arena.indices.page_count() != 1 {
+        anyhow::bail!("mesh arena: {} vertex and {} index pages for {} meshes instead of one each",
+            arena.vertices.page_count(), arena.indices.page_count(), meshes.len());
+    }
+    if arena.vertices.used_bytes() != vertex_bytes || arena.indices.used_bytes() != index_bytes {
+        anyhow::bail!("mesh arena: {} vertex and {} index bytes are used instead of {} and {}",
+            arena.vertices.used_bytes(), arena.indices.used_bytes(), vertex_bytes, index_bytes);
+    }
+    let first = &meshes[0].vertex_buffer.buffer;
+    if meshes.iter().any(|mesh| !mesh.vertex_buffer.is_shared() || !std::sync::Arc::ptr_eq(&mesh.vertex_buffer.buffer, first)) {
+        anyhow::bail!("mesh arena: not all meshes are in the same vertex buffer");
+    }
+    if teapot.meshes.iter().any(|mesh| mesh.base_vertex() == 0 || mesh.first_index() == 0) {
+        anyhow::bail!("mesh arena: the teapot starts at the beginning of the buffers, before the cube");
+    }
+    let mesh_count = meshes.len();
+    let image = render_golden(device, queue, &[teapot], camera, wgpu::PrimitiveTopology::TriangleList);
+    let mse = mean_squared_error(&image, teapot_image)?;
+    if mse > MSE_THRESHOLD {
+        let failed = std::env::temp_dir().join("mesh_arena.failed.png");
+        image.save(&failed)?;
+        anyhow::bail!("mesh arena: mean squared error {:.3} to the teapot in its own buffers is above {}, the image is at {:?}", mse, MSE_THRESHOLD, failed);
+    }
+    println!("mesh arena: ok ({} meshes in one page of {} vertex bytes, mean squared error {:.3})", mesh_count, vertex_bytes, mse);
+    Ok(())
+}
+
+/// The objects of the app are loaded into its arena, all their meshes share one vertex buffer
+fn check_app_arena(app: &crate::App) -> anyhow::Result<()> {
+    let meshes = app.objects.iter().flat_map(|object| object.meshes.iter()).collect::<Vec<_>>();
+    if app.mesh_arena.vertices.page_count() != 1 || meshes.iter().any(|mesh| !mesh.vertex_buffer.is_shared()) {
+        anyhow::bail!("app arena: the {} meshes of the app are spread over {} vertex pages",
+            meshes.len(), app.mesh_arena.vertices.page_count());
+    }
+    println!("app arena: ok ({} meshes in one page, {} bytes used)", meshes.len(), app.mesh_arena.vertices.used_bytes());
+    Ok(())
+}
+
+/// A mesh with two levels of detail is drawn with fewer triangles the further away it is: the
+/// full 128 triangles of the plane up close, half of them from the distance of the first level
+/// and a quarter from the second one on.
+fn check_lod_selection(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let (vertices, indices) = primitives::grid_plane(8, 8, 2.0, primitives::IndexLayout::TriangleList);
+    let mesh = model::Surface::new_with_lods("lod plane".to_string(), &vertices, &indices, 2, None, None, device, queue);
+    let distances = mesh.lods.iter().map(|lod| lod.distance).collect::<Vec<_>>();
+    let mut plane = model::Object::new("lod plane".to_string());
+    plane.meshes.push(mesh);
+    let mut objects = vec![plane];
+    for (height, triangles) in [(distances[0] * 0.5, 128), (distances[0] * 1.5, 64), (distances[1] * 1.5, 32)] {
+        let camera = camera::Camera::new(
+            (0.0, height, 0.001),
+            cgmath::Deg(-89.0),
+            cgmath::Deg(-90.0),
+            cgmath::Deg(45.0),
+            GOLDEN_SIZE,
+            GOLDEN_SIZE,
+            0.1,
+            2.0 * height,
+            device,
+            queue,
+        );
+        objects[0].meshes[0].select_lod(camera.position);
+        stats::RenderStats::take();
+        render_golden(device, queue, &objects, &camera, wgpu::PrimitiveTopology::TriangleList);
+        let drawn = stats::RenderStats::take().triangles;
+        if drawn != triangles {
+            anyhow::bail!("lod selection: {} triangles were drawn from {} away instead of {}", drawn, height, triangles);
+        }
+    }
+    println!("lod selection: ok (128, 64 and 32 triangles, the levels start at {:.1} and {:.1})", distances[0], distances[1]);
+    Ok(())
+}
+
+/// Decals lie on the surface they are stuck to and are drawn over it with their depth bias: a
+/// green level 1 decal on a red ground, seen at a slant, with a blue level 2 decal on top of it
+/// that comes before it in the scene. The decals are triangulated differently than the ground,
+/// so without the bias they would flicker through it. A yellow decal 0.2 below the ground is
+/// pulled forward by much less than that and stays hidden.
+fn check_decal_levels(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    // looking at the origin from above and in front
+    let camera = camera::Camera::new(
+        (0.0, 3.0, 4.0),
+        cgmath::Deg(-36.87),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        device,
+        queue,
+    );
+    let plane = |cells: u32, size: f32, center: (f32, f32, f32), color: [f32; 3], decal_level: u8| -> anyhow::Result<model::Object> {
+        let (vertices, indices) = primitives::grid_plane(cells, cells, size, primitives::IndexLayout::TriangleList);
+        let mut object = model::Object::new(format!("decal level {}", decal_level));
+        let mut mesh = model::Surface::new(object.name.clone(), &vertices, &indices, None, device, queue);
+        mesh.decal_level = decal_level;
+        mesh.instances.clear();
+        mesh.create_instance(center.into(), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(color[0], color[1], color[2], 1.0))?;
+        for instance in mesh.instances.iter_mut() {
+            instance.update(&mut mesh.instance_buffer);
+        }
+        mesh.instance_buffer.flush(device, queue);
+        object.meshes.push(mesh);
+        Ok(object)
+    };
+    let objects = [
+        plane(1, 4.0, (0.0, 0.0, 0.0), [1.0, 0.0, 0.0], 0)?,
+        plane(2, 1.0, (0.0, 0.0, 0.0), [0.0, 0.0, 1.0], 2)?,
+        plane(3, 2.0, (0.0, 0.0, 0.0), [0.0, 1.0, 0.0], 1)?,
+        plane(1, 0.6, (1.5, -0.2, 0.0), [1.0, 1.0, 0.0], 1)?,
+    ];
+    let (image, _) = render_with_depth(device, queue, &objects, &camera, wgpu::PrimitiveTopology::TriangleList, wgpu::PolygonMode::Fill);
+
+    // the color that is expected on the ground at x, z, points close to an edge are skipped
+    let expected = |x: f32, z: f32| -> Option<[u8; 4]> {
+        let inside = |half: f32| x.abs() < half - 0.05 && z.abs() < half - 0.05;
+        let outside = |half: f32| x.abs() > half + 0.05 || z.abs() > half + 0.05;
+        if inside(0.5) {
+            Some([0, 0, 255, 255])
+        } else if inside(1.0) && outside(0.5) {
+            Some([0, 255, 0, 255])
+        } else if outside(1.0) && x.abs() < 1.95 && z.abs() < 1.95 {
+            Some([255, 0, 0, 255])
+        } else {
+            None
+        }
+    };
+    let mut checked = 0;
+    for i in 0..40 {
+        for j in 0..40 {
+            let (x, z) = (-1.95 + i as f32 * 0.1, -1.95 + j as f32 * 0.1);
+            let Some(color) = expected(x, z) else { continue };
+            let ndc = cgmath::Point3::from_homogeneous(camera.view_projection() * cgmath::Point3::new(x, 0.0, z).to_homogeneous());
+            let (px, py) = (((ndc.x + 1.0) * 0.5 * GOLDEN_SIZE as f32) as u32, ((1.0 - ndc.y) * 0.5 * GOLDEN_SIZE as f32) as u32);
+            if px >= GOLDEN_SIZE || py >= GOLDEN_SIZE {
+                continue;
+            }
+            let pixel = image.get_pixel(px, py).0;
+            if pixel != color {
+                anyhow::bail!("decal levels: the ground at ({}, {}) is {:?} instead of {:?}", x, z, pixel, color);
+            }
+            checked += 1;
+        }
+    }
+    println!("decal levels: ok ({} points of the ground have the color of the highest decal on them)", checked);
+    Ok(())
+}
+
+/// The render statistics of a known scene: a 2x2 plane (8 triangles) with three instances, a
+/// 3x1 plane (6 triangles) with one and a plane whose only instance is hidden. That is two draw
+/// calls, the hidden plane isn't drawn at all, and 3 * 8 + 6 = 30 triangles.
+fn check_render_stats(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let camera = camera::Camera::new(
+        (0.0, 5.0, 0.001),
+        cgmath::Deg(-89.0),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        device,
+        queue,
+    );
+    let plane = |columns: u32, rows: u32, xs: &[f32], hidden: bool| -> anyhow::Result<model::Object> {
+        let (vertices, indices) = primitives::grid_plane(columns, rows, 1.0, primitives::IndexLayout::TriangleList);
+        let mut object = model::Object::new(format!("{}x{} plane", columns, rows));
+        let mut mesh = model::Surface::new(object.name.clone(), &vertices, &indices, None, device, queue);
+        // the surface comes with an instance at the origin
+        mesh.instances.clear();
+        for &x in xs {
+            mesh.create_instance(cgmath::Vector3::new(x, 0.0, 0.0), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(0.0, 1.0, 0.0, 1.0))?;
+        }
+        for instance in mesh.instances.iter_mut() {
+            instance.set_visible(!hidden);
+            instance.update(&mut mesh.instance_buffer);
+        }
+        mesh.instance_buffer.flush(device, queue);
+        object.meshes.push(mesh);
+        Ok(object)
+    };
+    let objects = [plane(2, 2, &[-1.5, 0.0, 1.5], false)?, plane(3, 1, &[0.0], false)?, plane(1, 1, &[0.0], true)?];
+    stats::RenderStats::take();
+    render_golden(device, queue, &objects, &camera, wgpu::PrimitiveTopology::TriangleList);
+    let stats = stats::RenderStats::take();
+    if stats.draw_calls != 2 || stats.triangles != 30 {
+        anyhow::bail!("render stats: {} draw calls with {} triangles instead of 2 with 30", stats.draw_calls, stats.triangles);
+    }
+    println!("render stats: ok (2 draw calls with 30 triangles)");
+    Ok(())
+}
+
+/// A flight to a pose is still on its way one frame before the end and lands exactly on the pose
+/// (bit for bit, not just close) in the frame that reaches the duration, with both easings.
+fn check_pose_flight(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    let target = camera::CameraPose {
+        position: [-3.0, 1.5, 7.25],
+        pitch: -0.4,
+        yaw: 2.1,
+        roll: 0.3,
+        field_of_view: 0.9,
+    };
+    for easing in [camera::Easing::Linear, camera::Easing::Smoothstep] {
+        let mut camera = camera::Camera::new(
+            (0.0, 2.0, 5.0),
+            cgmath::Deg(-20.0),
+            cgmath::Deg(-90.0),
+            cgmath::Deg(45.0),
+            GOLDEN_SIZE,
+            GOLDEN_SIZE,
+            0.1,
+            100.0,
+            device,
+            queue,
+        );
+        camera.lerp_to(target, Duration::from_secs(1), easing);
+        for _ in 0..9 {
+            camera.update(Duration::from_millis(100));
+        }
+        if !camera.is_animating() || camera.snapshot() == target {
+            anyhow::bail!("pose flight: with {:?} the camera arrived before the end of the flight", easing);
+        }
+        camera.update(Duration::from_millis(100));
+        if camera.is_animating() || camera.snapshot() != target {
+            anyhow::bail!("pose flight: with {:?} the camera ended at {:?} instead of {:?}", easing, camera.snapshot(), target);
+        }
+    }
+    println!("pose flight: ok (linear and smoothstep land exactly on the pose after 10 frames of 100 ms)");
+    Ok(())
+}
+
+/// Moving one instance out of many only writes that one instance to the GPU, and the buffer still
+/// holds all the instances packed together afterwards (read back and compared bit for bit).
+fn check_dirty_upload(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    const COUNT: usize = 64;
+    let mut buffer = instance::InstanceBuffer::new(device, 1);
+    let mut instances = Vec::new();
+    for i in 0..COUNT {
+        let position = cgmath::Vector3::new(i as f32, 0.0, 0.0);
+        instances.push(instance::Instance::init(position, cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0), buffer.get_instance_buffer_slot()?));
+    }
+    for instance in instances.iter_mut() {
+        instance.update(&mut buffer);
+    }
+    buffer.flush(device, queue);
+    let full = buffer.uploaded_bytes();
+    if full != COUNT * std::mem::size_of::<instance::RawInstance>() {
+        anyhow::bail!("dirty upload: the first flush wrote {} bytes instead of all {} instances", full, COUNT);
+    }
+
+    // the app updates every instance every frame, only the moved one is different
+    instances[COUNT / 2].translate(cgmath::Vector3::new(0.0, 1.0, 0.0));
+    for instance in instances.iter_mut() {
+        instance.update(&mut buffer);
+    }
+    buffer.flush(device, queue);
+    let moved = buffer.uploaded_bytes();
+    if moved != std::mem::size_of::<instance::RawInstance>() {
+        anyhow::bail!("dirty upload: moving one instance wrote {} bytes instead of one instance", moved);
+    }
+
+    let size = (COUNT * std::mem::size_of::<instance::RawInstance>()) as wgpu::BufferAddress;
+    let uploaded = read_buffer(device, queue, &buffer.gpu_buffer, size);
+    let expected = instances.iter().map(|i| i.compute_instance_matrix()).collect::<Vec<_>>();
+    if uploaded != bytemuck::cast_slice::<_, u8>(&expected) {
+        anyhow::bail!("dirty upload: the instance buffer doesn't hold the packed instances after the partial write");
+    }
+    println!("dirty upload: ok ({} bytes for {} instances, {} bytes after moving one)", full, COUNT, moved);
+    Ok(())
+}
+
+/// Removing an instance from the middle and adding a new one leaves the three live instances
+/// packed together at the start of the GPU buffer, with nothing left of the removed one.
+fn check_instance_removal(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let (vertices, indices) = primitives::grid_plane(1, 1, 1.0, primitives::IndexLayout::TriangleList);
+    let mut mesh = model::Surface::new("instance removal".to_string(), &vertices, &indices, None, device, queue);
+    // the surface comes with an instance at the origin
+    mesh.instances.clear();
+    let create = |mesh: &mut model::Surface, x: f32| {
+        mesh.create_instance(cgmath::Vector3::new(x, 0.0, 0.0), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(0.0, 1.0, 0.0, 1.0))
+    };
+    let flush = |mesh: &mut model::Surface| {
+        for instance in mesh.instances.iter_mut() {
+            instance.update(&mut mesh.instance_buffer);
+        }
+        mesh.instance_buffer.flush(device, queue);
+    };
+    for x in [1.0, 2.0, 3.0] {
+        create(&mut mesh, x)?;
+    }
+    flush(&mut mesh);
+    let removed = mesh.instances[1].compute_instance_matrix();
+    if !mesh.remove_instance(1) {
+        anyhow::bail!("instance removal: the middle instance could not be removed");
+    }
+    create(&mut mesh, 4.0)?;
+    flush(&mut mesh);
+
+    let drawn = mesh.instance_buffer.all_instances();
+    if drawn != (0..3) {
+        anyhow::bail!("instance removal: the instances {:?} are drawn instead of 0..3", drawn);
+    }
+    // the packed buffer holds the instances in the order of their slots
+    let mut live = mesh.instances.iter().collect::<Vec<_>>();
+    live.sort_by_key(|instance| *instance.buffer_index);
+    let expected = live.iter().map(|instance| instance.compute_instance_matrix()).collect::<Vec<_>>();
+    let size = (3 * std::mem::size_of::<instance::RawInstance>()) as wgpu::BufferAddress;
+    let uploaded = read_buffer(device, queue, &mesh.instance_buffer.gpu_buffer, size);
+    if uploaded != bytemuck::cast_slice::<_, u8>(&expected) {
+        anyhow::bail!("instance removal: the first 3 slots of the GPU buffer don't hold the live instances");
+    }
+    if uploaded.chunks(std::mem::size_of::<instance::RawInstance>()).any(|raw| raw == bytemuck::bytes_of(&removed)) {
+        anyhow::bail!("instance removal: the removed instance is still in the GPU buffer");
+    }
+    println!("instance removal: ok (3 live instances packed at the start of the buffer after removing one and adding one)");
+    Ok(())
+}
+
+/// The upload thread may be late with changed data, but when the buffer is packed again the
+/// draw calls count the new slots in the same frame. The buffer has to hold the packed instances
+/// without waiting for the thread, also when older uploads for it are still on their way.
+fn check_upload_order(app: &crate::App) -> anyhow::Result<()> {
+    const COUNT: usize = 32;
+    let mut buffer = instance::InstanceBuffer::new(&app.device, 1);
+    let mut instances = Vec::new();
+    for i in 0..COUNT {
+        let position = cgmath::Vector3::new(i as f32, 0.0, 0.0);
+        instances.push(instance::Instance::init(position, cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0), buffer.get_instance_buffer_slot()?));
+    }
+    let expected_buffer = |instances: &[instance::Instance], buffer: &instance::InstanceBuffer| {
+        let size = (instances.len() * std::mem::size_of::<instance::RawInstance>()) as wgpu::BufferAddress;
+        let uploaded = read_buffer(&app.device, &app.queue, &buffer.gpu_buffer, size);
+        let expected = instances.iter().map(|i| i.compute_instance_matrix()).collect::<Vec<_>>();
+        uploaded == bytemuck::cast_slice::<_, u8>(&expected)
+    };
+    for instance in instances.iter_mut() {
+        instance.update(&mut buffer);
+    }
+    // the buffer grows from 1 slot, so this is a new buffer
+    buffer.flush_async(&app.device, &app.uploader);
+    if !expected_buffer(&instances, &buffer) {
+        anyhow::bail!("upload order: the grown buffer doesn't hold the instances in the same frame");
+    }
+
+    for round in 0..20 {
+        // a few frames of moved instances go through the thread, then one is removed
+        for _ in 0..5 {
+            for instance in instances.iter_mut() {
+                instance.translate(cgmath::Vector3::new(0.0, 1.0, 0.0));
+                instance.update(&mut buffer);
+            }
+            buffer.flush_async(&app.device, &app.uploader);
+            // this is asked every frame by the UI, it must not count below zero
+            let _ = app.uploader.pending();
+        }
+        let removed = instances.remove(round % instances.len());
+        buffer.release_slot(removed.buffer_index);
+        for instance in instances.iter_mut() {
+            instance.update(&mut buffer);
+        }
+        buffer.flush_async(&app.device, &app.uploader);
+        if buffer.all_instances().len() != instances.len() {
+            anyhow::bail!("upload order: {} slots are drawn for {} instances", buffer.all_instances().len(), instances.len());
+        }
+        if !expected_buffer(&instances, &buffer) {
+            anyhow::bail!("upload order: an older upload overwrote the packed buffer in round {}", round);
+        }
+    }
+    println!("upload order: ok (20 removals after uploads on the thread)");
+    Ok(())
+}
+
+/// A buffer limited to 4 instances refuses the fifth one with an error instead of growing, and
+/// takes one again once a slot is free.
+fn check_instance_limit(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    const LIMIT: usize = 4;
+    let mut buffer = instance::InstanceBuffer::new(device, 2 * LIMIT);
+    buffer.set_max_instances(Some(LIMIT));
+    let refused = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counter = refused.clone();
+    buffer.on_limit_reached(move |_| counter.set(counter.get() + 1));
+    let mut slots = (0..LIMIT).map(|_| buffer.get_instance_buffer_slot()).collect::<anyhow::Result<Vec<_>>>()?;
+    buffer.flush(device, queue);
+    let capacity = buffer.gpu_capacity();
+
+    let error = match buffer.get_instance_buffer_slot() {
+        Ok(slot) => anyhow::bail!("instance limit: slot {} was handed out past the limit of {}", slot, LIMIT),
+        Err(e) => e,
+    };
+    buffer.flush(device, queue);
+    if buffer.live_instances() != LIMIT || buffer.gpu_capacity() != capacity || refused.get() != 1 {
+        anyhow::bail!("instance limit: after the refused slot there are {} instances, room for {} (was {}) and {} refusals",
+            buffer.live_instances(), buffer.gpu_capacity(), capacity, refused.get());
+    }
+    buffer.release_slot(slots.remove(1));
+    slots.push(buffer.get_instance_buffer_slot()
+        .map_err(|e| anyhow::anyhow!("instance limit: a released slot can't be taken again: {}", e))?);
+    println!("instance limit: ok (the slot past {} was refused with \"{}\")", LIMIT, error);
+    Ok(())
+}
+
+/// A spike of instances grows the buffer, when most of them are gone again it shrinks once after
+/// a while, and not again while the usage stays the same. The instances that are left keep their
+/// slots and are still all in the buffer afterwards.
+fn check_buffer_shrink(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let mut buffer = instance::InstanceBuffer::new(device, 8);
+    let mut instances = Vec::new();
+    for i in 0..40 {
+        let position = cgmath::Vector3::new(i as f32, 0.0, 0.0);
+        instances.push(instance::Instance::init(position, cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0), buffer.get_instance_buffer_slot()?));
+    }
+    for instance in instances.iter_mut() {
+        instance.update(&mut buffer);
+    }
+    buffer.flush(device, queue);
+    let grown = buffer.gpu_capacity();
+
+    // 10 of the 64 slots are used from now on, that is below a quarter of 64 but not of 32
+    for instance in instances.drain(10..) {
+        buffer.release_slot(instance.buffer_index);
+    }
+    let mut shrinks = Vec::new();
+    for frame in 0..1000 {
+        instances[3].translate(cgmath::Vector3::new(0.0, 0.01, 0.0));
+        for instance in instances.iter_mut() {
+            instance.update(&mut buffer);
+        }
+        let before = buffer.gpu_capacity();
+        buffer.flush(device, queue);
+        if buffer.gpu_capacity() != before {
+            shrinks.push((frame, before, buffer.gpu_capacity()));
+        }
+    }
+    match shrinks[..] {
+        [(frame, 64, 32)] if grown == 64 && frame > 1 => (),
+        _ => anyhow::bail!("buffer shrink: the buffer grew to {} and then changed its size {:?} (frame, from, to) instead of shrinking once", grown, shrinks),
+    }
+
+    let size = (instances.len() * std::mem::size_of::<instance::RawInstance>()) as wgpu::BufferAddress;
+    let uploaded = read_buffer(device, queue, &buffer.gpu_buffer, size);
+    let expected = instances.iter().map(|i| i.compute_instance_matrix()).collect::<Vec<_>>();
+    if uploaded != bytemuck::cast_slice::<_, u8>(&expected) {
+        anyhow::bail!("buffer shrink: the instance buffer doesn't hold the remaining instances after the shrink");
+    }
+    println!("buffer shrink: ok (grew to {} instances, shrank to {} after {} flushes with {} instances)", grown, shrinks[0].2, shrinks[0].0 + 1, instances.len());
+    Ok(())
+}
+
+/// The whole app without a window: it renders a frame of the default scene into its offscreen
+/// target, and after a resize the frames have the new size.
+async fn check_headless_app() -> anyhow::Result<()> {
+    let mut app = crate::App::new_headless(320, 240).await?;
+    let mut sizes = Vec::new();
+    for (width, height) in [(320, 240), (200, 300)] {
+        app.resize(winit::dpi::PhysicalSize::new(width, height));
+        app.render().map_err(|e| anyhow::anyhow!("headless app: {}", e))?;
+        let frame = app.read_frame()?;
+        if frame.dimensions() != (width, height) {
+            anyhow::bail!("headless app: the frame is {:?} after resizing to {:?}", frame.dimensions(), (width, height));
+        }
+        // the teapot is in front of the camera, so not every pixel has the background color
+        let background = *frame.get_pixel(0, 0);
+        let scene_pixels = frame.pixels().filter(|&&p| p != background).count();
+        if scene_pixels == 0 {
+            anyhow::bail!("headless app: the {}x{} frame only shows the background", width, height);
+        }
+        sizes.push(format!("{}x{} with {} scene pixels", width, height, scene_pixels));
+    }
+    // the ui windows show up in the second frame, egui only measures them in the first one
+    let without_ui = app.read_frame()?;
+    app.ui.visible = true;
+    app.render().map_err(|e| anyhow::anyhow!("headless app: {}", e))?;
+    app.render().map_err(|e| anyhow::anyhow!("headless app: {}", e))?;
+    let with_ui = app.read_frame()?;
+    let ui_pixels = with_ui.pixels().zip(without_ui.pixels()).filter(|(a, b)| a != b).count();
+    if ui_pixels == 0 {
+        anyhow::bail!("headless app: the ui doesn't change the frame");
+    }
+    sizes.push(format!("the ui changes {} pixels", ui_pixels));
+    println!("headless app: ok ({})", sizes.join(", "));
+    app.ui.visible = false;
+    check_app_arena(&app)?;
+    check_camera_resize(&mut app)?;
+    check_camera_switch(&mut app)?;
+    check_camera_transition(&mut app)?;
+    check_depth_prepass(&mut app)?;
+    check_auto_rotate(&app)?;
+    #[cfg(debug_assertions)]
+    check_shader_reload(&mut app)?;
+    check_object_hot_reload(&mut app).await?;
+    check_reload_selection(&mut app).await?;
+    check_pause(&mut app)?;
+    check_ground_clamp(&mut app)?;
+    check_input_replay(&mut app)?;
+    check_upload_order(&app)?;
+    // the golden device only has the WebGL2 limits, without storage buffers
+    check_light_set(&app.device, &app.queue)
+}
+
+/// With the depth prepass the scene pass loads the depth of the prepass (`LoadOp::Load`) and
+/// only draws the surfaces that are at exactly that depth. The frame has to come out the same as
+/// without the prepass, a scene pass that cleared the depth would only show the background.
+fn check_depth_prepass(app: &mut crate::App) -> anyhow::Result<()> {
+    app.render().map_err(|e| anyhow::anyhow!("depth prepass: {}", e))?;
+    let without_prepass = app.read_frame()?;
+    app.set_depth_prepass(true);
+    app.render().map_err(|e| anyhow::anyhow!("depth prepass: {}", e))?;
+    let with_prepass = app.read_frame()?;
+    app.set_depth_prepass(false);
+    let background = *without_prepass.get_pixel(0, 0);
+    let scene_pixels = with_prepass.pixels().filter(|&&p| p != background).count();
+    let changed = with_prepass.pixels().zip(without_prepass.pixels()).filter(|(a, b)| a != b).count();
+    if scene_pixels == 0 || changed != 0 {
+        anyhow::bail!("depth prepass: {} pixels differ from the frame without the prepass, {} pixels show the scene", changed, scene_pixels);
+    }
+    println!("depth prepass: ok (the frame with the prepass is the same, {} pixels show the scene)", scene_pixels);
+    Ok(())
+}
+
+/// While the clock is paused the camera holds still, even with a key held down, and the input
+/// isn't saved up for later. A step advances the clock by exactly one frame.
+fn check_pause(app: &mut crate::App) -> anyhow::Result<()> {
+    use winit::{event::ElementState, keyboard::KeyCode};
+    let frame = std::time::Duration::from_millis(16);
+    app.set_paused(true);
+    let position = app.cameras[app.active_camera].position;
+    let elapsed = app.clock.elapsed();
+    for _ in 0..5 {
+        app.cameras[app.active_camera].controls.on_key(KeyCode::ArrowUp, ElementState::Pressed);
+        app.update(frame);
+    }
+    if app.cameras[app.active_camera].position != position {
+        anyhow::bail!("pause: the camera moved from {:?} to {:?} while paused", position, app.cameras[app.active_camera].position);
+    }
+    if app.clock.elapsed() != elapsed {
+        anyhow::bail!("pause: the clock ran while paused");
+    }
+    app.clock.step();
+    app.update(frame);
+    app.update(frame);
+    let stepped = app.clock.elapsed() - elapsed;
+    if stepped != crate::clock::STEP {
+        anyhow::bail!("pause: a step advanced the clock by {:?} instead of {:?}", stepped, crate::clock::STEP);
+    }
+    app.set_paused(false);
+    if app.cameras[app.active_camera].controls.velocity() != cgmath::Vector3::new(0.0, 0.0, 0.0) {
+        anyhow::bail!("pause: the camera kept the input from the pause");
+    }
+    println!("pause: ok (5 paused frames with a key held, one step of {:?})", stepped);
+    Ok(())
+}
+
+/// With the ground clamp at a height of 1 the camera can fly down and forward while looking at
+/// the ground as long as it likes, it stops at 1 and never goes below it.
+fn check_ground_clamp(app: &mut crate::App) -> anyhow::Result<()> {
+    use winit::{event::ElementState, keyboard::KeyCode};
+    let frame = std::time::Duration::from_millis(16);
+    let camera = &mut app.cameras[app.active_camera];
+    let saved = (camera.position, camera.pitch, camera.min_height, camera.mode);
+    camera.position.y = 3.0;
+    camera.pitch = cgmath::Deg(-60.0).into();
+    camera.min_height = Some(1.0);
+    camera.mode = crate::camera::CameraMode::Fly;
+    camera.controls.on_key(KeyCode::ShiftLeft, ElementState::Pressed);
+    camera.controls.on_key(KeyCode::ArrowUp, ElementState::Pressed);
+    let mut lowest = f32::INFINITY;
+    for _ in 0..120 {
+        app.update(frame);
+        lowest = lowest.min(app.cameras[app.active_camera].position.y);
+    }
+    let camera = &mut app.cameras[app.active_camera];
+    camera.controls.reset();
+    let last = camera.position.y;
+    (camera.position, camera.pitch, camera.min_height, camera.mode) = saved;
+    app.update(frame);
+    if lowest < 1.0 {
+        anyhow::bail!("ground clamp: the camera went down to {} below the clamp at 1", lowest);
+    }
+    if last > 1.0 + 1e-4 {
+        anyhow::bail!("ground clamp: the camera only came down to {}, the clamp wasn't reached", last);
+    }
+    println!("ground clamp: ok (120 frames down and forward, the lowest height was {})", lowest);
+    Ok(())
+}
+
+/// Input that is recorded to a file and replayed from it moves the camera through the same
+/// positions, frame for frame.
+fn check_input_replay(app: &mut crate::App) -> anyhow::Result<()> {
+    use crate::input_recording::{InputRecorder, InputReplay, RecordedEvent, TimedEvent};
+    use winit::{event::{ElementState, MouseButton}, keyboard::KeyCode};
+    const FRAMES: u32 = 40;
+    let frame = std::time::Duration::from_millis(16);
+    // forward for a while, looking around with the mouse on the way
+    let script = |index: u32| -> Vec<RecordedEvent> {
+        match index {
+            0 => vec![RecordedEvent::Key { code: KeyCode::ArrowUp, state: ElementState::Pressed }],
+            10 => vec![RecordedEvent::MouseButton { button: MouseButton::Left, state: ElementState::Pressed }],
+            11..=15 => vec![RecordedEvent::MouseMotion { dx: 12.0, dy: -3.0 }],
+            20 => vec![
+                RecordedEvent::MouseButton { button: MouseButton::Left, state: ElementState::Released },
+                RecordedEvent::Key { code: KeyCode::Space, state: ElementState::Pressed },
+            ],
+            30 => vec![
+                RecordedEvent::Key { code: KeyCode::ArrowUp, state: ElementState::Released },
+                RecordedEvent::Key { code: KeyCode::Space, state: ElementState::Released },
+            ],
+            _ => Vec::new(),
+        }
+    };
+    let camera = &app.cameras[app.active_camera];
+    let start = (camera.position, camera.pitch, camera.yaw);
+    let restart = |app: &mut crate::App| {
+        let camera = &mut app.cameras[app.active_camera];
+        (camera.position, camera.pitch, camera.yaw) = start;
+        camera.controls.reset();
+    };
+    let path = std::env::temp_dir().join(format!("input-replay-{}.jsonl", std::process::id()));
+
+    restart(app);
+    let mut recorder = InputRecorder::create(&path)?;
+    let mut recorded = Vec::new();
+    for index in 0..FRAMES {
+        for event in script(index) {
+            recorder.write(TimedEvent { time: frame * index, event })?;
+            app.apply_recorded_input(&event);
+        }
+        app.update(frame);
+        recorded.push(app.cameras[app.active_camera].position);
+    }
+    drop(recorder);
+
+    restart(app);
+    let mut replay = InputReplay::load(&path)?;
+    let mut replayed = Vec::new();
+    for index in 0..FRAMES {
+        for event in replay.due_at(frame * index) {
+            app.apply_recorded_input(&event);
+        }
+        app.update(frame);
+        replayed.push(app.cameras[app.active_camera].position);
+    }
+    restart(app);
+    std::fs::remove_file(&path)?;
+    if !replay.is_finished() {
+        anyhow::bail!("input replay: not all recorded events were replayed");
+    }
+    if let Some(index) = (0..FRAMES as usize).find(|&i| recorded[i] != replayed[i]) {
+        anyhow::bail!("input replay: in frame {} the camera is at {:?} instead of {:?}", index, replayed[index], recorded[index]);
+    }
+    if recorded[0] == recorded[FRAMES as usize - 1] {
+        anyhow::bail!("input replay: the recorded input didn't move the camera");
+    }
+    println!("input replay: ok ({} frames at the same positions, the camera moved from {:?} to {:?})", FRAMES, recorded[0], recorded[FRAMES as usize - 1]);
+    Ok(())
+}
+
+/// Saving the file of an object reloads it in the next update after the change is reported. The
+/// instance keeps the place it was moved to, only the mesh changes.
+async fn check_object_hot_reload(app: &mut crate::App) -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("object-hot-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("cube.obj");
+    let original = std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("res").join("cube.obj"))?;
+    std::fs::write(&path, &original)?;
+    let object = resources::load_model_from_path(&path, &app.device, &app.queue).await?;
+    app.objects.push(object);
+    let index = app.objects.len() - 1;
+    app.watch_object_files();
+    let moved = cgmath::Vector3::new(3.0, 0.0, 0.0);
+    app.objects[index].meshes[0].instances[0].position = moved;
+    let size_before = app.objects[index].meshes[0].aabb.max.x - app.objects[index].meshes[0].aabb.min.x;
+
+    // the same cube at twice the size
+    let doubled = original.lines().map(|line| match line.strip_prefix("v ") {
+        Some(coordinates) => format!("v {}", coordinates.split_whitespace()
+            .map(|c| c.parse::<f32>().map(|c| (c * 2.0).to_string()).unwrap_or_else(|_| c.to_string()))
+            .collect::<Vec<_>>().join(" ")),
+        None => line.to_string(),
+    }).collect::<Vec<_>>().join("\n");
+    std::fs::write(&path, doubled)?;
+    // the change comes in from another thread, so it can take a moment
+    let mut size_after = size_before;
+    for _ in 0..100 {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        app.update(std::time::Duration::ZERO);
+        let mesh = &app.objects[index].meshes[0];
+        size_after = mesh.aabb.max.x - mesh.aabb.min.x;
+        if size_after != size_before {
+            break;
+        }
+    }
+    let position = app.objects[index].meshes[0].instances[0].position;
+    app.objects.pop();
+    app.watch_object_files();
+    std::fs::remove_dir_all(&dir)?;
+    if size_after != size_before * 2.0 {
+        anyhow::bail!("object hot reload: the cube is {} wide after the file was saved, expected {}", size_after, size_before * 2.0);
+    }
+    if position != moved {
+        anyhow::bail!("object hot reload: the instance moved to {:?} with the reload", position);
+    }
+    println!("object hot reload: ok (the cube grew from {} to {} and the instance stayed in place)", size_before, size_after);
+    Ok(())
+}
+
+/// When a reload changes the number of meshes the instances start over, so a selection or a scene
+/// node that points into the object is dropped. The ui doesn't fall over a selection that points
+/// at something that isn't there anymore.
+async fn check_reload_selection(app: &mut crate::App) -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("reload-selection-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("triangles.obj");
+    let triangle = |name: &str, x: f32| format!("o {}\nv {} 0 0\nv {} 0 0\nv {} 1 0\n", name, x, x + 1.0, x);
+    std::fs::write(&path, format!("{}f 1 2 3\n{}f 4 5 6\n", triangle("first", 0.0), triangle("second", 2.0)))?;
+    let object = resources::load_model_from_path(&path, &app.device, &app.queue).await?;
+    if object.meshes.len() != 2 {
+        anyhow::bail!("reload selection: the file has 2 meshes, {} were loaded", object.meshes.len());
+    }
+    app.objects.push(object);
+    let index = app.objects.len() - 1;
+    let stale = scene::InstanceRef { object: index, mesh: 1, instance: 0 };
+    let node = app.scene.add_node("reload selection", cgmath::Matrix4::identity(), None);
+    app.scene.attach_instance(node, stale, &app.objects);
+    app.selected = Some(stale);
+
+    std::fs::write(&path, format!("{}f 1 2 3\n", triangle("first", 0.0)))?;
+    let reloaded = app.reload_object(index, true);
+    let selected = app.selected;
+    let attached = app.scene.node(node).instances.len();
+    // the ui has to cope with a selection like that too, it is shown for two frames (see
+    // `check_headless_app`)
+    app.selected = Some(stale);
+    app.ui.visible = true;
+    let rendered = app.render().and_then(|_| app.render());
+    app.ui.visible = false;
+    app.selected = None;
+    app.objects.pop();
+    std::fs::remove_dir_all(&dir)?;
+    reloaded?;
+    rendered.map_err(|e| anyhow::anyhow!("reload selection: {}", e))?;
+    if selected.is_some() {
+        anyhow::bail!("reload selection: {:?} is still selected after the mesh is gone", selected);
+    }
+    if attached != 0 {
+        anyhow::bail!("reload selection: the scene node still moves {} instances of the old meshes", attached);
+    }
+    println!("reload selection: ok (2 meshes reloaded as 1, the selection and the scene node were cleared)");
+    Ok(())
+}
+
+/// A shader with an error must not take the app down, the pipeline keeps the shader it had. The
+/// shader read from the source tree draws the same frame as the one in the binary.
+#[cfg(debug_assertions)]
+fn check_shader_reload(app: &mut crate::App) -> anyhow::Result<()> {
+    let render = |app: &mut crate::App| -> anyhow::Result<image::RgbaImage> {
+        app.render().map_err(|e| anyhow::anyhow!("shader reload: {}", e))?;
+        app.read_frame()
+    };
+    let before = render(app)?;
+    let name = renderer::culled_pipeline_name(renderer::WIREFRAME_PIPELINE, app.cull_mode);
+    let scene_config = app.scene_config();
+    let pipeline = app.pipelines.get_as_mut::<ColoredMeshRenderer>(&name)
+        .ok_or_else(|| anyhow::anyhow!("shader reload: there is no pipeline {}", name))?;
+    let broken = format!("{}\nthis is not wgsl", include_str!("./shaders/color_shader.wgsl"));
+    let error = match pipeline.rebuild_with_source(&app.device, &app.object_constants, &broken, &scene_config, Some(model::Texture::DEPTH_FORMAT)) {
+        Ok(()) => anyhow::bail!("shader reload: the broken shader was accepted"),
+        Err(e) => e,
+    };
+    if render(app)? != before {
+        anyhow::bail!("shader reload: the frame changed after the broken shader was rejected");
+    }
+    app.reload_shaders();
+    if render(app)? != before {
+        anyhow::bail!("shader reload: the shader from the source tree draws another frame than the one in the binary");
+    }
+    println!("shader reload: ok (the broken shader was rejected with \"{}\")", error.to_string().lines().next().unwrap_or_default());
+    Ok(())
+}
+
+/// The uniform that the render pass binds (the one of the active camera) has to hold the
+/// transform of the active camera, it's read back from the GPU and compared bit for bit.
+fn check_bound_transform(app: &crate::App) -> anyhow::Result<()> {
+    let camera = &app.cameras[app.active_camera];
+    let size = std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress;
+    let bound: [[f32; 4]; 4] = bytemuck::pod_read_unaligned(&read_buffer(&app.device, &app.queue, &camera.uniform.lock().unwrap().gpu_buffer, size));
+    if bound != camera.compute_full_camera_transform() {
+        anyhow::bail!("camera switch: the bound uniform doesn't hold the transform of camera {}", app.active_camera);
+    }
+    Ok(())
+}
+
+/// An object on the turntable turns by `radians_per_sec * dt` in every update: two instances on
+/// either side of the center go around it by that angle, and turn by it themselves.
+fn check_auto_rotate(app: &crate::App) -> anyhow::Result<()> {
+    use cgmath::{InnerSpace, Rotation, Rotation3};
+
+    let (vertices, indices) = primitives::grid_plane(1, 1, 1.0, primitives::IndexLayout::TriangleList);
+    let mut mesh = model::Surface::new("turntable".to_string(), &vertices, &indices, None, &app.device, &app.queue);
+    mesh.instances.clear();
+    for x in [1.0, 3.0] {
+        mesh.create_instance(cgmath::Vector3::new(x, 0.5, 0.0), cgmath::Quaternion::from_angle_y(cgmath::Rad(0.0)), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0))?;
+    }
+    let mut object = model::Object::new("turntable".to_string());
+    object.meshes.push(mesh);
+    object.set_auto_rotate(cgmath::Vector3::new(0.0, 2.0, 0.0), 0.8);
+    object.update(std::time::Duration::from_millis(250), &app.device, &app.uploader);
+    // 0.8 rad/s for 0.25 s about the y axis through the center at (2, 0.5, 0)
+    let angle = cgmath::Rad(0.2);
+    let turn = cgmath::Quaternion::from_angle_y(angle);
+    for (instance, x) in object.meshes[0].instances.iter().zip([-1.0, 1.0]) {
+        let expected = cgmath::Vector3::new(2.0, 0.5, 0.0) + turn.rotate_vector(cgmath::Vector3::new(x, 0.0, 0.0));
+        if (instance.position - expected).magnitude() > 1e-5 || (instance.rotation - turn).magnitude() > 1e-5 {
+            anyhow::bail!("auto rotate: an instance is at {:?} turned by {:?} instead of {:?} turned by {:?}", instance.position, instance.rotation, expected, turn);
+        }
+    }
+    println!("auto rotate: ok (0.8 rad/s for 250 ms turned the instances by {:?})", angle);
+    Ok(())
+}
+
+/// A resize gives every camera the aspect ratio of the new size, not only the active one: the
+/// app is resized to 200x300 and 320x240 and the projections of all cameras are checked.
+fn check_camera_resize(app: &mut crate::App) -> anyhow::Result<()> {
+    let previous = winit::dpi::PhysicalSize::new(app.surface_config.width, app.surface_config.height);
+    for (width, height) in [(200, 300), (320, 240)] {
+        app.resize(winit::dpi::PhysicalSize::new(width, height));
+        let aspect = width as f32 / height as f32;
+        for (i, camera) in app.cameras.iter().enumerate() {
+            // the projection scales x by the aspect ratio less than y
+            let projection = camera.projection_matrix();
+            let projected = projection.y.y / projection.x.x;
+            if camera.aspect_ratio != aspect || (projected - aspect).abs() > 1e-5 {
+                anyhow::bail!("camera resize: after resizing to {}x{} camera {} (active {}) has the aspect ratio {} and its projection {} instead of {}",
+                    width, height, i, app.active_camera, camera.aspect_ratio, projected, aspect);
+            }
+        }
+    }
+    app.resize(previous);
+    println!("camera resize: ok (all {} cameras follow the size, the active one is {})", app.cameras.len(), app.active_camera);
+    Ok(())
+}
+
+/// Switching the camera must show the view of the new camera in the very next frame. The
+/// overview camera was never uploaded since the resize, so its uniform still has the old aspect
+/// ratio until the switch writes it. A new camera at the current view shows the same frame.
+fn check_camera_switch(app: &mut crate::App) -> anyhow::Result<()> {
+    let render = |app: &mut crate::App| -> anyhow::Result<image::RgbaImage> {
+        app.render().map_err(|e| anyhow::anyhow!("camera switch: {}", e))?;
+        app.read_frame()
+    };
+    app.camera_transition_duration = std::time::Duration::ZERO;
+    let first_view = render(app)?;
+    app.set_active_camera(1);
+    check_bound_transform(app)?;
+    let switched = render(app)?;
+    app.cameras[1].update_uniform(&app.queue);
+    let overview = render(app)?;
+    if switched != overview {
+        anyhow::bail!("camera switch: the first frame after the switch doesn't show the view of the new camera");
+    }
+    if overview == first_view {
+        anyhow::bail!("camera switch: both cameras show the same frame");
+    }
+    let index = app.add_camera_at_view();
+    if index != 2 || app.active_camera != 2 {
+        anyhow::bail!("camera switch: the new camera is camera {} and the active one {}", index, app.active_camera);
+    }
+    if render(app)? != overview {
+        anyhow::bail!("camera switch: the new camera doesn't show the view it was made at");
+    }
+    app.set_active_camera(0);
+    check_bound_transform(app)?;
+    if render(app)? != first_view {
+        anyhow::bail!("camera switch: switching back doesn't show the first view again");
+    }
+    println!("camera switch: ok (3 cameras, every switch binds the new transform and shows the new view in the next frame)");
+    Ok(())
+}
+
+/// A camera switch with a transition moves the view over from the old camera: partway through,
+/// the eye of the bound view is on the way between the two cameras, and once the transition is
+/// over the bound uniform holds the exact transform of the new camera.
+fn check_camera_transition(app: &mut crate::App) -> anyhow::Result<()> {
+    use cgmath::{InnerSpace, Transform};
+
+    let previous_duration = app.camera_transition_duration;
+    app.camera_transition_duration = std::time::Duration::from_secs(1);
+    let from = app.cameras[app.active_camera].position;
+    app.set_active_camera(1);
+    let to = app.cameras[1].position;
+    app.update(std::time::Duration::from_millis(400));
+    if app.camera_transition.is_none() {
+        anyhow::bail!("camera transition: the transition ended after 400 ms of 1 s");
+    }
+    // the view matrix is stored behind the full transform
+    let size = std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress;
+    let bound: [[[f32; 4]; 4]; 2] = bytemuck::pod_read_unaligned(&read_buffer(&app.device, &app.queue, &app.cameras[1].uniform.lock().unwrap().gpu_buffer, 2 * size));
+    let view = cgmath::Matrix4::from(bound[1]);
+    let eye = view.invert()
+        .ok_or_else(|| anyhow::anyhow!("camera transition: the bound view can't be inverted"))?
+        .transform_point(cgmath::Point3::new(0.0, 0.0, 0.0));
+    let path = to - from;
+    let t = (eye - from).dot(path) / path.magnitude2();
+    let off_path = (eye - (from + path * t)).magnitude();
+    if t <= 0.0 || t >= 1.0 || off_path > 1e-3 * path.magnitude() {
+        anyhow::bail!("camera transition: after 400 ms the eye is at {:?}, which is not between {:?} and {:?}", eye, from, to);
+    }
+    app.update(std::time::Duration::from_millis(700));
+    if app.camera_transition.is_some() {
+        anyhow::bail!("camera transition: the transition is still running after 1.1 s of 1 s");
+    }
+    check_bound_transform(app)?;
+    app.camera_transition_duration = std::time::Duration::ZERO;
+    app.set_active_camera(0);
+    app.camera_transition_duration = previous_duration;
+    println!("camera transition: ok (the eye is {:.0}% of the way after 400 ms, then exactly at the new camera)", 100.0 * t);
+    Ok(())
+}
+
+/// A flipbook texture runs at the frame rate of its options, and a frame rate that can't be
+/// played is refused.
+fn check_flipbook_frame_rate(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let frames = [[255, 0, 0, 255], [0, 0, 255, 255]]
+        .map(|color| image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(4, 4, image::Rgba(color))));
+    let options = model::TextureOptions { frame_rate: 12.0, ..Default::default() };
+    let flipbook = model::Texture::from_images_with_options(device, queue, &frames, "flipbook", &options)?;
+    if flipbook.frame_rate != 12.0 {
+        anyhow::bail!("flipbook frame rate: the texture runs at {} frames per second instead of 12", flipbook.frame_rate);
+    }
+    let options = model::TextureOptions { frame_rate: -1.0, ..Default::default() };
+    if model::Texture::from_images_with_options(device, queue, &frames, "flipbook", &options).is_ok() {
+        anyhow::bail!("flipbook frame rate: a texture with a negative frame rate was created");
+    }
+    println!("flipbook frame rate: ok (12 frames per second from the options, a negative rate is refused)");
+    Ok(())
+}
+
+/// Every frame of a flipbook shows its own layer of the texture array: a quad with the flipbook
+/// is drawn at two frames and the center pixel has the color of the layer of the frame.
+fn check_flipbook_layers(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+    let frames = colors.map(|color| image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(4, 4, image::Rgba(color))));
+    let flipbook = std::sync::Arc::new(model::Texture::from_images(device, queue, &frames, "flipbook")?);
+    let mut hud = hud_renderer::HudRenderer::new(device, &golden_config());
+    let center = (GOLDEN_SIZE / 2) as f32;
+    for frame in [2, 1] {
+        flipbook.set_frame(frame, queue);
+        hud.add_quad(hud_renderer::HudRect::centered([center, center], [64.0, 64.0]), &flipbook, [1.0, 1.0, 1.0, 1.0]);
+        hud.prepare(device, queue);
+        let image = render_hud(device, queue, &hud);
+        let pixel = image.get_pixel(GOLDEN_SIZE / 2, GOLDEN_SIZE / 2).0;
+        if pixel != colors[frame as usize] {
+            anyhow::bail!("flipbook layers: frame {} shows {:?} instead of the layer {:?}", frame, pixel, colors[frame as usize]);
+        }
+    }
+    println!("flipbook layers: ok (frames 2 and 1 sample their layers)");
+    Ok(())
+}
+
+/// The lod options of a texture reach its sampler and its frame uniform. The texture is red on
+/// the left three quarters and blue on the right, its smallest mip level is a mix of both:
+/// drawn 1:1 the red part stays red unless `lod_min_clamp` or `lod_bias` push the sampler to
+/// the smallest level, and drawn a single pixel large the mix shows up unless `lod_max_clamp`
+/// keeps the sampler at the full size.
+fn check_sampler_lod(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(64, 64, |x, _| {
+        if x < 48 { image::Rgba([255, 0, 0, 255]) } else { image::Rgba([0, 0, 255, 255]) }
+    }));
+    let center = (GOLDEN_SIZE / 2) as f32;
+    // the red quarter of a 64 pixel quad, and a quad of a single pixel (the texture shrunk 64 times)
+    let full_size = (hud_renderer::HudRect::centered([center, center], [64.0, 64.0]), (GOLDEN_SIZE / 2 - 16, GOLDEN_SIZE / 2));
+    let one_pixel = (hud_renderer::HudRect::new(center, center, center + 1.0, center + 1.0), (GOLDEN_SIZE / 2, GOLDEN_SIZE / 2));
+    let default = model::SamplerOptions::default();
+    let cases = [
+        ("no options at full size", default, full_size, false),
+        ("lod_min_clamp 6", model::SamplerOptions { lod_min_clamp: 6.0, ..default }, full_size, true),
+        ("lod_bias 6", model::SamplerOptions { lod_bias: 6.0, ..default }, full_size, true),
+        ("no options at one pixel", default, one_pixel, true),
+        ("lod_max_clamp 0", model::SamplerOptions { lod_max_clamp: 0.0, ..default }, one_pixel, false),
+    ];
+    for (name, sampler, (rect, (x, y)), mixed) in cases {
+        let options = model::TextureOptions { sampler, ..Default::default() };
+        let texture = std::sync::Arc::new(model::Texture::from_images_with_options(device, queue, std::slice::from_ref(&image), "half red", &options)?);
+        let mut hud = hud_renderer::HudRenderer::new(device, &golden_config());
+        hud.add_quad(rect, &texture, [1.0, 1.0, 1.0, 1.0]);
+        hud.prepare(device, queue);
+        let pixel = render_hud(device, queue, &hud).get_pixel(x, y).0;
+        let shows_blue = pixel[2] > 32;
+        if pixel[0] < 128 || shows_blue != mixed {
+            anyhow::bail!("sampler lod: with {} the pixel is {:?}, {}", name, pixel, if mixed { "the smallest mip level should mix in the blue" } else { "it should be red from the full size level" });
+        }
+    }
+    println!("sampler lod: ok (the clamps and the bias move the sampler to the level they ask for)");
+    Ok(())
+}
+
+/// The crosshair of the HUD drawn like the app draws it, 32 pixels large in the middle of the
+/// screen: the very center is in the gap between the lines and keeps the clear color, a pixel
+/// on the arm above it is (almost) white.
+fn check_crosshair(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let crosshair = hud_renderer::crosshair_texture(device, queue)?;
+    let mut hud = hud_renderer::HudRenderer::new(device, &golden_config());
+    let center = GOLDEN_SIZE / 2;
+    hud.add_quad(hud_renderer::HudRect::centered([center as f32, center as f32], [32.0, 32.0]), &crosshair, [1.0, 1.0, 1.0, 0.8]);
+    hud.prepare(device, queue);
+    let image = render_hud(device, queue, &hud);
+    let background = *image.get_pixel(0, 0);
+    let middle = *image.get_pixel(center, center);
+    if middle != background {
+        anyhow::bail!("crosshair: the center is {:?} instead of the clear color {:?}", middle.0, background.0);
+    }
+    // 12 pixels above the center, well outside of the gap and inside of the quad
+    let arm = *image.get_pixel(center, center - 12);
+    if arm.0[..3].iter().any(|&c| c < 200) {
+        anyhow::bail!("crosshair: the arm above the center is {:?} instead of white", arm.0);
+    }
+    println!("crosshair: ok (the center has the clear color {:?}, the arm is {:?})", middle.0, arm.0);
+    Ok(())
+}
+
+/// The texture layer of an instance (`Instance::tex_layer`, location 10) reaches the textured
+/// lit shader: two instances of a plane with a red, green and blue texture array are drawn with
+/// the layers 2 and 1, the left one is blue and the right one green.
+fn check_instance_layers(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+    let frames = colors.map(|color| image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(4, 4, image::Rgba(color))));
+    let texture = model::Texture::from_images(device, queue, &frames, "layers")?;
+    // looking down at the planes, x goes to the right
+    let camera = camera::Camera::new(
+        (0.0, 5.0, 0.001),
+        cgmath::Deg(-89.0),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        device,
+        queue,
+    );
+    let (vertices, indices) = primitives::grid_plane(1, 1, 1.0, primitives::IndexLayout::TriangleList);
+    let mut mesh = model::Surface::new("layer planes".to_string(), &vertices, &indices, None, device, queue);
+    mesh.instances.clear();
+    for (x, layer) in [(-1.0, 2), (1.0, 1)] {
+        mesh.create_instance(cgmath::Vector3::new(x, 0.0, 0.0), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0))?;
+        mesh.instances.last_mut().unwrap().tex_layer = layer;
+    }
+    for instance in mesh.instances.iter_mut() {
+        instance.update(&mut mesh.instance_buffer);
+    }
+    mesh.instance_buffer.flush(device, queue);
+
+    let image = render_lit(device, queue, &mesh, &camera, Some(&texture), None)?;
+
+    // the planes are 1 wide and 1 apart from the center, half the view is 5 * tan(22.5°) wide
+    let offset = (GOLDEN_SIZE as f32 / 2.0 / (5.0 * 22.5f32.to_radians().tan())) as u32;
+    for (x, layer, side) in [(GOLDEN_SIZE / 2 - offset, 2, "left"), (GOLDEN_SIZE / 2 + offset, 1, "right")] {
+        let pixel = image.get_pixel(x, GOLDEN_SIZE / 2).0;
+        let others_dark = (0..3).filter(|&c| c != layer).all(|c| pixel[c] < 32);
+        if pixel[layer] < 128 || !others_dark {
+            anyhow::bail!("instance layers: the {} plane with layer {} is {:?} instead of the color of the layer {:?}", side, layer, pixel, colors[layer]);
+        }
+    }
+    println!("instance layers: ok (the planes with the layers 2 and 1 are blue and green)");
+    Ok(())
+}
+
+/// `DrawMesh::draw_submesh` only draws the index range it is given, shifted by the base vertex.
+/// The plane has a left and a right quad (indices 0..6 and 6..12): drawing the first range
+/// shows the left quad, the second range the right one, and the first range with a base vertex
+/// of 1 also the right one, as every vertex of it is one column further right.
+fn check_submesh(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    // looking down at the plane, x goes to the right
+    let camera = camera::Camera::new(
+        (0.0, 5.0, 0.001),
+        cgmath::Deg(-89.0),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        device,
+        queue,
+    );
+    let (vertices, indices) = primitives::grid_plane(2, 1, 2.0, primitives::IndexLayout::TriangleList);
+    let mut mesh = model::Surface::new("two quads".to_string(), &vertices, &indices, None, device, queue);
+    mesh.instances.clear();
+    mesh.create_instance(cgmath::Vector3::new(0.0, 0.0, 0.0), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0))?;
+    for instance in mesh.instances.iter_mut() {
+        instance.update(&mut mesh.instance_buffer);
+    }
+    mesh.instance_buffer.flush(device, queue);
+    // the centers of the quads are half a unit from the middle, half the view is 5 * tan(22.5°) wide
+    let offset = (0.5 * GOLDEN_SIZE as f32 / 2.0 / (5.0 * 22.5f32.to_radians().tan())) as u32;
+    let (left, right) = ((GOLDEN_SIZE / 2 - offset, GOLDEN_SIZE / 2), (GOLDEN_SIZE / 2 + offset, GOLDEN_SIZE / 2));
+    for (indices, base_vertex, drawn, empty) in [(0..6, 0, left, right), (6..12, 0, right, left), (0..6, 1, right, left)] {
+        stats::RenderStats::take();
+        let image = render_lit(device, queue, &mesh, &camera, None, Some((indices.clone(), base_vertex)))?;
+        let triangles = stats::RenderStats::take().triangles;
+        let background = *image.get_pixel(0, 0);
+        let (drawn_pixel, empty_pixel) = (*image.get_pixel(drawn.0, drawn.1), *image.get_pixel(empty.0, empty.1));
+        if drawn_pixel == background || empty_pixel != background || triangles != 2 {
+            anyhow::bail!("submesh: the indices {:?} with the base vertex {} drew {} triangles, the pixel at {:?} is {:?} and the one at {:?} is {:?}",
+                indices, base_vertex, triangles, drawn, drawn_pixel.0, empty, empty_pixel.0);
+        }
+    }
+    println!("submesh: ok (the index ranges and the base vertex select the quad that is drawn)");
+    Ok(())
+}
+
+/// A half transparent texel loaded with `premultiply_alpha` reaches the GPU with its color
+/// multiplied by the alpha. In an sRGB texture the color is multiplied in linear space, so it
+/// ends up brighter than half of the stored value.
+fn check_premultiplied_alpha(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let texel = image::Rgba([200, 100, 50, 128]);
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(4, 4, texel));
+    // worked out by hand: 200 * 128 / 255 is 100.4 and so on, in sRGB 200 decodes to 0.578,
+    // times 128 / 255 is 0.290 which encodes to 147
+    let expected = [(model::ColorSpace::Linear, [100, 50, 25, 128]), (model::ColorSpace::Srgb, [147, 72, 34, 128])];
+    for (color_space, expected) in expected {
+        let options = model::TextureOptions { premultiply_alpha: true, mipmaps: false, color_space, ..Default::default() };
+        let texture = model::Texture::from_images_with_options(device, queue, std::slice::from_ref(&img), "half transparent", &options)?;
+        let uploaded = texture.read_rgba(device, queue)?;
+        if uploaded.get_pixel(0, 0).0 != expected {
+            anyhow::bail!("premultiplied alpha: in {:?} the texel {:?} was uploaded as {:?} instead of {:?}", color_space, texel.0, uploaded.get_pixel(0, 0).0, expected);
+        }
+    }
+    println!("premultiplied alpha: ok ({:?} is uploaded as [100, 50, 25, 128] in linear and [147, 72, 34, 128] in sRGB)", texel.0);
+    Ok(())
+}
+
+/// The mip levels of an image texture reach the GPU down to the last one: a 64x32 image with a
+/// red left and a green right half has 7 levels. The level with 2x1 pixels still has a red and a
+/// green one, the last level has a single pixel with half of each, averaged in linear space:
+/// 0.5 encodes to 188 in sRGB.
+fn check_last_mip_level(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let img = image::RgbaImage::from_fn(64, 32, |x, _| if x < 32 { image::Rgba([255, 0, 0, 255]) } else { image::Rgba([0, 255, 0, 255]) });
+    let texture = model::Texture::from_images(device, queue, &[image::DynamicImage::ImageRgba8(img)], "half red half green")?;
+    let levels = texture.texture.mip_level_count();
+    if levels != 7 {
+        anyhow::bail!("last mip level: the 64x32 texture has {} mip levels instead of 7", levels);
+    }
+    let two_pixels = texture.read_rgba_level(device, queue, 5)?;
+    let last = texture.read_rgba_level(device, queue, 6)?;
+    let (left, right, mixed) = (two_pixels.get_pixel(0, 0).0, two_pixels.get_pixel(1, 0).0, last.get_pixel(0, 0).0);
+    let close = |a: [u8; 4], b: [u8; 4]| a.iter().zip(b).all(|(&a, b)| a.abs_diff(b) <= 1);
+    if two_pixels.dimensions() != (2, 1) || last.dimensions() != (1, 1) || left != [255, 0, 0, 255] || right != [0, 255, 0, 255] || !close(mixed, [188, 188, 0, 255]) {
+        anyhow::bail!("last mip level: the level with 2x1 pixels is {:?} and {:?}, the last one {:?}", left, right, mixed);
+    }
+    println!("last mip level: ok (the last of the 7 levels is {:?})", mixed);
+    Ok(())
+}
+
+/// A normal map is data and not a color: loaded as `ColorSpace::Linear` it stays `Rgba8Unorm`
+/// and the shader gets the stored values. The flat normal (128, 128, 255) is sampled as 0.5,
+/// which the sRGB target encodes to 188. Loaded as a color it would be decoded to 0.22 and
+/// come out as the 128 it went in as.
+fn check_linear_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let texel = image::Rgba([128, 128, 255, 255]);
+    let normal_map = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(8, 8, texel));
+    let center = GOLDEN_SIZE / 2;
+    let cases = [
+        (model::ColorSpace::Linear, wgpu::TextureFormat::Rgba8Unorm, [188, 188, 255, 255]),
+        (model::ColorSpace::Srgb, wgpu::TextureFormat::Rgba8UnormSrgb, [128, 128, 255, 255]),
+    ];
+    for (color_space, format, expected) in cases {
+        let options = model::TextureOptions { color_space, ..Default::default() };
+        let texture = std::sync::Arc::new(model::Texture::from_images_with_options(device, queue, std::slice::from_ref(&normal_map), "flat normal map", &options)?);
+        let stored = texture.read_rgba(device, queue)?.get_pixel(0, 0).0;
+        if texture.texture.format() != format || stored != texel.0 {
+            anyhow::bail!("linear texture: in {:?} the normal map is {:?} with the texel {:?} instead of {:?} with {:?}", color_space, texture.texture.format(), stored, format, texel.0);
+        }
+        let mut hud = hud_renderer::HudRenderer::new(device, &golden_config());
+        hud.add_quad(hud_renderer::HudRect::centered([center as f32, center as f32], [32.0, 32.0]), &texture, [1.0, 1.0, 1.0, 1.0]);
+        hud.prepare(device, queue);
+        let pixel = render_hud(device, queue, &hud).get_pixel(center, center).0;
+        if pixel.iter().zip(expected).any(|(&a, b)| a.abs_diff(b) > 1) {
+            anyhow::bail!("linear texture: in {:?} the normal map is drawn as {:?} instead of {:?}", color_space, pixel, expected);
+        }
+    }
+    println!("linear texture: ok (the normal map is Rgba8Unorm and its values reach the shader unchanged)");
+    Ok(())
+}
+
+/// The built in matcaps are made from their pixels in memory (`Texture::from_raw_rgba`), the
+/// texture read back from the GPU has the pixels of the image.
+fn check_matcap_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let camera_uniform = camera::CameraUniform::new(device);
+    let mut matcap = matcap_renderer::MatcapRenderer::new(device, queue, &camera_uniform.bind_group_layout, &golden_config(), Some(model::Texture::DEPTH_FORMAT), 1)?;
+    matcap.set_builtin(device, queue, matcap_renderer::BuiltinMatcap::RedWax)?;
+    let texture = matcap.texture();
+    let uploaded = texture.read_rgba(device, queue)?;
+    let expected = matcap_renderer::BuiltinMatcap::RedWax.image(texture.size.width);
+    let center = texture.size.width / 2;
+    if uploaded != expected {
+        let wrong = uploaded.pixels().zip(expected.pixels()).filter(|(a, b)| a != b).count();
+        anyhow::bail!("matcap texture: {} texels differ, the center one is {:?} instead of {:?}", wrong, uploaded.get_pixel(center, center), expected.get_pixel(center, center));
+    }
+    println!("matcap texture: ok (the center texel is {:?} like in the image)", uploaded.get_pixel(center, center).0);
+    Ok(())
+}
+
+/// A cubemap loaded from six encoded images (`Texture::from_cube_faces`) shows every face in its
+/// direction: the sky is drawn looking along each axis and the center pixel has the color of the
+/// face on that axis.
+fn check_cube_faces(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    // in the order of the layers: +X, -X, +Y, -Y, +Z, -Z
+    let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 0, 255], [255, 0, 255, 255], [0, 255, 255, 255]];
+    let mut encoded = Vec::new();
+    for color in colors {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::from_pixel(4, 4, image::Rgba(color)).write_to(&mut png, image::ImageOutputFormat::Png)?;
+        encoded.push(png.into_inner());
+    }
+    let faces = [0, 1, 2, 3, 4, 5].map(|i| encoded[i].as_slice());
+    let sky = model::Texture::from_cube_faces(device, queue, faces, "solid faces")?;
+    let skybox = skybox_renderer::SkyboxRenderer::new(device, sky, &golden_config(), None, 1);
+    // the pitch and yaw that look along the axes, straight up and down is left out of the camera
+    let views = [(0.0, 0.0), (0.0, 180.0), (89.0, 0.0), (-89.0, 0.0), (0.0, 90.0), (0.0, -90.0)];
+    for ((pitch, yaw), color) in views.into_iter().zip(colors) {
+        let camera = camera::Camera::new((0.0, 0.0, 0.0), cgmath::Deg(pitch), cgmath::Deg(yaw), cgmath::Deg(45.0), GOLDEN_SIZE, GOLDEN_SIZE, 0.1, 10.0, device, queue);
+        skybox.prepare(&camera, queue);
+        let target = golden_target(device);
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Golden image encoder"),
+        });
+        {
+            let color_attachment = [ColoredMeshRenderer::describe_color_attachment(Some(&target_view), None, renderer::DEFAULT_CLEAR_COLOR)];
+            let mut render_pass = encoder.begin_render_pass(&ColoredMeshRenderer::describe_render_pass(&color_attachment, None));
+            skybox.draw(&mut render_pass);
+        }
+        let image = read_target(device, queue, encoder, &target);
+        let pixel = image.get_pixel(GOLDEN_SIZE / 2, GOLDEN_SIZE / 2).0;
+        if pixel != color {
+            anyhow::bail!("cube faces: looking along {:?} shows {:?} instead of the face color {:?}", camera.direction(), pixel, color);
+        }
+    }
+    println!("cube faces: ok (the six faces are seen along the six axes)");
+    Ok(())
+}
+
+/// The points of a cloud are round splats of the given size in pixels: a red point in the middle
+/// and a green one a unit to the right, seen from 5 units above. Without attenuation the red splat
+/// is 32 pixels wide and leaves the corners of its quad empty, with an attenuation of 1 it shrinks
+/// to 32 / (1 + 5) pixels.
+fn check_point_cloud(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    // looking down at the points, x goes to the right
+    let camera = camera::Camera::new(
+        (0.0, 5.0, 0.001),
+        cgmath::Deg(-89.0),
+        cgmath::Deg(-90.0),
+        cgmath::Deg(45.0),
+        GOLDEN_SIZE,
+        GOLDEN_SIZE,
+        0.1,
+        100.0,
+        device,
+        queue,
+    );
+    camera.update_uniform(queue);
+    let camera_uniform = camera.uniform.lock().unwrap();
+    let points = [
+        point_cloud_renderer::Point::new(cgmath::Point3::new(0.0, 0.0, 0.0), [1.0, 0.0, 0.0, 1.0]),
+        point_cloud_renderer::Point::new(cgmath::Point3::new(1.0, 0.0, 0.0), [0.0, 1.0, 0.0, 1.0]),
+    ];
+    let cloud = point_cloud_renderer::PointCloud::new("golden points".to_string(), &points, device);
+    let mut renderer = point_cloud_renderer::PointCloudRenderer::new(device, &camera_uniform.bind_group_layout, &golden_config(), None, 1);
+    let mut render = |settings: point_cloud_renderer::PointSettings| {
+        renderer.prepare(&settings, GOLDEN_SIZE, GOLDEN_SIZE, queue);
+        let target = golden_target(device);
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Golden image encoder"),
+        });
+        {
+            let color_attachment = [ColoredMeshRenderer::describe_color_attachment(Some(&target_view), None, renderer::DEFAULT_CLEAR_COLOR)];
+            let mut render_pass = encoder.begin_render_pass(&ColoredMeshRenderer::describe_render_pass(&color_attachment, None));
+            renderer.draw(&mut render_pass, &cloud, &camera_uniform.bind_group);
+        }
+        read_target(device, queue, encoder, &target)
+    };
+
+    // the pixels the points are projected to, the camera looks a bit past the middle point
+    let pixel_of = |point: &point_cloud_renderer::Point| {
+        let ndc = cgmath::Point3::from_homogeneous(camera.view_projection() * cgmath::Point3::from(point.position).to_homogeneous());
+        (((ndc.x + 1.0) * 0.5 * GOLDEN_SIZE as f32) as u32, ((1.0 - ndc.y) * 0.5 * GOLDEN_SIZE as f32) as u32)
+    };
+    let (red, green) = (pixel_of(&points[0]), pixel_of(&points[1]));
+    let flat = render(point_cloud_renderer::PointSettings { size: 32.0, attenuation: 0.0, min_size: 1.0 });
+    let background = *flat.get_pixel(0, 0);
+    let expected = [
+        (red, [255, 0, 0, 255], "the middle of the red point"),
+        ((red.0 + 14, red.1), [255, 0, 0, 255], "the edge of the red point"),
+        // the quad reaches a pixel beyond the 16 pixels of the radius
+        ((red.0 + 14, red.1 + 14), background.0, "the corner of the quad of the red point"),
+        (green, [0, 255, 0, 255], "the green point"),
+    ];
+    for ((x, y), color, what) in expected {
+        let pixel = flat.get_pixel(x, y).0;
+        if pixel != color {
+            anyhow::bail!("point cloud: {} at ({}, {}) is {:?} instead of {:?}", what, x, y, pixel, color);
+        }
+    }
+    let attenuated = render(point_cloud_renderer::PointSettings { size: 32.0, attenuation: 1.0, min_size: 1.0 });
+    let (middle, edge) = (attenuated.get_pixel(red.0, red.1).0, attenuated.get_pixel(red.0 + 6, red.1).0);
+    if middle != [255, 0, 0, 255] || edge != background.0 {
+        anyhow::bail!("point cloud: the attenuated red point is {:?} in the middle and {:?} 6 pixels to the right", middle, edge);
+    }
+    println!("point cloud: ok (round splats of 32 pixels, about 5 pixels 5 units away with an attenuation of 1)");
+    Ok(())
+}
+
+/// Showing the transform panel of an instance without touching it must leave the instance alone:
+/// the angles of the rotation are shown as euler angles, but the quaternion is not rebuilt from
+/// them, and nothing is written to the instance buffer.
+fn check_instance_panel(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let matrix = cgmath::Matrix4::from_translation(cgmath::Vector3::new(1.0, 2.0, 3.0))
+        * cgmath::Matrix4::from(cgmath::Euler::new(cgmath::Deg(30.0), cgmath::Deg(89.0), cgmath::Deg(-45.0)))
+        * cgmath::Matrix4::from_nonuniform_scale(1.0, 2.0, 0.5);
+    let mut buffer = instance::InstanceBuffer::new(device, 1);
+    let mut instance = instance::Instance::from_matrix(matrix, cgmath::Vector4::new(1.0, 0.5, 0.25, 1.0), buffer.get_instance_buffer_slot()?);
+    instance.update(&mut buffer);
+    buffer.flush(device, queue);
+    let before = instance.compute_instance_matrix();
+
+    let context = egui::Context::default();
+    let mut written = 0;
+    for _ in 0..3 {
+        let _ = context.run(egui::RawInput::default(), |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| instance.build_ui(ui, &instance::GridSnap::default(), &mut buffer));
+        });
+        buffer.flush(device, queue);
+        written += buffer.uploaded_bytes();
+    }
+    let after = instance.compute_instance_matrix();
+    if bytemuck::bytes_of(&before) != bytemuck::bytes_of(&after) || written != 0 {
+        anyhow::bail!("instance panel: the untouched panel changed the instance ({} bytes written)", written);
+    }
+    println!("instance panel: ok (3 frames of the panel left the instance as it was)");
+    Ok(())
+}
+
+/// An edit in the Camera window reaches the uniform like in the app: tab focuses the first field
+/// (the x of the position), three presses of the up arrow move it by its speed of 0.1 each, and
+/// after `build_ui` reports the change the bound view matrix has the eye at the new position.
+fn check_camera_window(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    use cgmath::{EuclideanSpace, InnerSpace, SquareMatrix, Transform};
+
+    let mut camera = camera::Camera::new((1.0, 2.0, 3.0), cgmath::Deg(-20.0), cgmath::Deg(30.0), cgmath::Deg(45.0), GOLDEN_SIZE, GOLDEN_SIZE, 0.1, 100.0, device, queue);
+    camera.update_uniform(queue);
+    let press = |key| egui::Event::Key { key, physical_key: None, pressed: true, repeat: false, modifiers: egui::Modifiers::NONE };
+    let frames = [vec![press(egui::Key::Tab)], vec![press(egui::Key::ArrowUp); 3], vec![]];
+    let context = egui::Context::default();
+    let mut changes = 0;
+    for events in frames {
+        let _ = context.run(egui::RawInput { events, ..Default::default() }, |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                // the app sends the camera to the GPU when the window changed it
+                if camera.build_ui(ui) {
+                    camera.update_uniform(queue);
+                    changes += 1;
+                }
+            });
+        });
+    }
+    let expected = cgmath::Point3::new(1.3, 2.0, 3.0);
+    if changes != 1 || (camera.position - expected).magnitude() > 1e-5 {
+        anyhow::bail!("camera window: {} edits moved the camera to {:?} instead of {:?}", changes, camera.position, expected);
+    }
+    let size = std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress;
+    let bound: [[[f32; 4]; 4]; 2] = bytemuck::pod_read_unaligned(&read_buffer(device, queue, &camera.uniform.lock().unwrap().gpu_buffer, 2 * size));
+    let eye = cgmath::Matrix4::from(bound[1])
+        .invert()
+        .ok_or_else(|| anyhow::anyhow!("camera window: the bound view can't be inverted"))?
+        .transform_point(cgmath::Point3::origin());
+    if bound[0] != camera.compute_full_camera_transform() || (eye - expected).magnitude() > 1e-4 {
+        anyhow::bail!("camera window: the bound view has the eye at {:?} instead of {:?}", eye, expected);
+    }
+    println!("camera window: ok (the edited position is in the bound view, the eye is at {:?})", eye);
+    Ok(())
+}
+
+/// A box framed with `frame_object` fits the frustum of the camera, on a wide and on a tall
+/// picture: the corners of the box are inside and the sphere around it just fits between the
+/// sides of the narrower field of view, a little further out from the center is outside.
+fn check_frame_object(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    use cgmath::{Angle, EuclideanSpace, InnerSpace};
+
+    let (min, max) = (cgmath::Point3::new(1.0, -2.0, 3.0), cgmath::Point3::new(4.0, 0.0, 5.0));
+    let center = min.midpoint(max);
+    let radius = (max - min).magnitude() / 2.0;
+    for (width, height) in [(320, 180), (180, 320)] {
+        let mut camera = camera::Camera::new((0.0, 0.0, 0.0), cgmath::Deg(-20.0), cgmath::Deg(30.0), cgmath::Deg(45.0), width, height, 0.1, 10.0, device, queue);
+        camera.frame_object(min, max);
+        let frustum = camera.frustum();
+        let corners = (0..8).map(|i| cgmath::Point3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        ));
+        if let Some(corner) = corners.into_iter().find(|&corner| !frustum.contains_sphere(corner, 0.0)) {
+            anyhow::bail!("frame object: the corner {:?} of the box is outside of the {}x{} picture", corner, width, height);
+        }
+        // the sides of the narrower field of view touch the sphere, at the depth of the center
+        // they are radius / cos(fov / 2) away from it
+        let direction = camera.direction();
+        let right = direction.cross(cgmath::Vector3::unit_y()).normalize();
+        let half_vertical = cgmath::Rad::from(cgmath::Deg(22.5f32));
+        let (side, half_fov) = if width >= height {
+            (right.cross(direction), half_vertical)
+        } else {
+            (right, cgmath::Rad((half_vertical.tan() * width as f32 / height as f32).atan()))
+        };
+        let reach = radius / half_fov.cos();
+        if !frustum.contains_sphere(center + side * reach * 0.95, 0.0) || frustum.contains_sphere(center + side * reach * 1.05, 0.0) {
+            anyhow::bail!("frame object: the sphere around the box doesn't fill the {}x{} picture, the camera is at {:?}", width, height, camera.position);
+        }
+    }
+    println!("frame object: ok (the framed box fits the wide and the tall picture)");
+    Ok(())
+}
+
+/// A model from a folder outside of the resources finds its MTL and texture next to it. Without
+/// the texture it still loads, the mesh just has no material.
+async fn check_model_from_path(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("model-from-path-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let res = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res");
+    for file in ["cube.obj", "cube.mtl"] {
+        std::fs::copy(res.join(file), dir.join(file))?;
+    }
+    let without_texture = resources::load_model_from_path(&dir.join("cube.obj"), device, queue).await?;
+    std::fs::copy(res.join("cube-diffuse.jpg"), dir.join("cube-diffuse.jpg"))?;
+    let with_texture = resources::load_model_from_path(&dir.join("cube.obj"), device, queue).await?;
+    std::fs::remove_dir_all(&dir)?;
+    if without_texture.meshes.iter().any(|mesh| mesh.material.is_some()) {
+        anyhow::bail!("model from path: the cube has a material without its texture");
+    }
+    if with_texture.meshes.iter().any(|mesh| mesh.material.is_none()) {
+        anyhow::bail!("model from path: the texture next to the cube wasn't loaded");
+    }
+    println!("model from path: ok ({} meshes, the texture is found next to the model and its absence falls back to the color)", with_texture.meshes.len());
+    Ok(())
+}
+
+/// The metadata of an object and its instances is written to the sidecar format and read back
+/// onto an object of the same shape, the maps have to come out the same.
+fn check_metadata_sidecar(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let (vertices, indices) = primitives::grid_plane(1, 1, 1.0, primitives::IndexLayout::TriangleList);
+    let object_with_instances = |instances: [usize; 2]| -> anyhow::Result<model::Object> {
+        let mut object = model::Object::new("sidecar".to_string());
+        for count in instances {
+            let mut mesh = model::Surface::new(object.name.clone(), &vertices, &indices, None, device, queue);
+            for _ in 1..count {
+                mesh.create_instance(cgmath::Vector3::new(0.0, 0.0, 0.0), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 1.0, 1.0), cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0))?;
+            }
+            object.meshes.push(mesh);
+        }
+        Ok(object)
+    };
+    let entry = |key: &str, value: &str| (key.to_string(), value.to_string());
+    let mut original = object_with_instances([1, 2])?;
+    original.metadata.extend([entry("sensor", "A-17"), entry("building", "north \"wing\"")]);
+    original.meshes[0].instances[0].metadata.extend([entry("temperature", "21.5")]);
+    original.meshes[1].instances[1].metadata.extend([entry("temperature", "-3"), entry("unit", "°C")]);
+
+    let json = serde_json::to_string_pretty(&resources::MetadataSidecar::from_object(&original))?;
+    let sidecar: resources::MetadataSidecar = serde_json::from_str(&json)
+        .map_err(|e| anyhow::anyhow!("metadata sidecar: the written sidecar can't be read back: {}\n{}", e, json))?;
+    let mut restored = object_with_instances([1, 2])?;
+    sidecar.apply(&mut restored);
+    if restored.metadata != original.metadata {
+        anyhow::bail!("metadata sidecar: the object has {:?} instead of {:?}", restored.metadata, original.metadata);
+    }
+    for (mesh_idx, (restored, original)) in restored.meshes.iter().zip(original.meshes.iter()).enumerate() {
+        for (instance_idx, (restored, original)) in restored.instances.iter().zip(original.instances.iter()).enumerate() {
+            if restored.metadata != original.metadata {
+                anyhow::bail!("metadata sidecar: instance {} of mesh {} has {:?} instead of {:?}", instance_idx, mesh_idx, restored.metadata, original.metadata);
+            }
+        }
+    }
+    println!("metadata sidecar: ok (the object and 3 instances came back from {} bytes of json)", json.len());
+    Ok(())
+}
+
+/// Three lights are uploaded to the storage buffer of a `LightSet` and read back. Every light
+/// takes 64 bytes in the layout of the Light struct in shader.wgsl: position and direction as
+/// vec4, the color as a vec3 with the intensity in its last 4 bytes, then the kind and the range
+/// and 8 bytes of padding. The count uniform says 3.
+fn check_light_set(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let mut lights = match light::LightSet::new(device, &device.limits(), light::DEFAULT_MAX_LIGHTS) {
+        Ok(lights) => lights,
+        Err(e) => {
+            println!("light set: skipped ({})", e);
+            return Ok(());
+        }
+    };
+    lights.add_point_light(cgmath::Point3::new(1.0, 2.0, 3.0), [0.25, 0.5, 0.75], 2.0, 10.0)?;
+    lights.add_directional_light(cgmath::Vector3::new(0.0, -2.0, 0.0), [1.0, 0.5, 0.0], 0.5)?;
+    lights.add_point_light(cgmath::Point3::new(-4.0, 0.0, 4.0), [0.0, 1.0, 0.0], 1.0, 5.0)?;
+    lights.upload(queue);
+
+    const LIGHT_SIZE: usize = 64;
+    let data = read_buffer(device, queue, &lights.gpu_buffer, (3 * LIGHT_SIZE) as wgpu::BufferAddress);
+    let word = |light: usize, offset: usize| -> [u8; 4] {
+        let start = light * LIGHT_SIZE + offset;
+        data[start..start + 4].try_into().unwrap()
+    };
+    let float = |light: usize, offset: usize| f32::from_le_bytes(word(light, offset));
+    let uint = |light: usize, offset: usize| u32::from_le_bytes(word(light, offset));
+    // (light, byte offset, expected value, what it is)
+    let floats = [
+        (0, 0, 1.0, "position x"), (0, 8, 3.0, "position z"), (0, 12, 1.0, "position w"),
+        (0, 32, 0.25, "color r"), (0, 40, 0.75, "color b"), (0, 44, 2.0, "intensity"), (0, 52, 10.0, "range"),
+        (1, 20, -1.0, "normalized direction y"), (1, 28, 0.0, "direction w"), (1, 32, 1.0, "color r"), (1, 44, 0.5, "intensity"),
+        (2, 0, -4.0, "position x"), (2, 36, 1.0, "color g"), (2, 52, 5.0, "range"),
+    ];
+    for (light, offset, expected, name) in floats {
+        if float(light, offset) != expected {
+            anyhow::bail!("light set: the {} of light {} at byte {} is {} instead of {}", name, light, offset, float(light, offset), expected);
+        }
+    }
+    // the kind is 0 for point and 1 for directional lights, the padding is zero
+    for (light, offset, expected, name) in [(0, 48, 0, "kind"), (1, 48, 1, "kind"), (2, 48, 0, "kind"), (0, 56, 0, "padding"), (1, 60, 0, "padding")] {
+        if uint(light, offset) != expected {
+            anyhow::bail!("light set: the {} of light {} at byte {} is {} instead of {}", name, light, offset, uint(light, offset), expected);
+        }
+    }
+    let count: [u32; 4] = bytemuck::pod_read_unaligned(&read_buffer(device, queue, &lights.count_buffer, 16));
+    if count != [3, 0, 0, 0] {
+        anyhow::bail!("light set: the count uniform holds {:?}", count);
+    }
+    println!("light set: ok (3 lights in {} bytes, the count uniform says {})", data.len(), count[0]);
+    Ok(())
+}
+
+/// The directional light as the GPU gets it has the std140 layout of the Light struct in
+/// lit_shader.wgsl: the vec3 direction at 0 with the ambient in the 4 bytes after it, the vec3
+/// color at 16 (its alignment) and the whole struct rounded up to 32 bytes.
+fn check_light_uniform(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let mut uniform = light::LightUniform::new(device);
+    uniform.ambient = 0.25;
+    uniform.update([1.0, -2.0, 3.0], [0.5, 0.75, 1.0], queue);
+    let size = uniform.gpu_buffer.size();
+    if size != 32 {
+        anyhow::bail!("light uniform: the buffer is {} bytes instead of 32", size);
+    }
+    let floats: [f32; 8] = bytemuck::pod_read_unaligned(&read_buffer(device, queue, &uniform.gpu_buffer, size));
+    let expected = [1.0, -2.0, 3.0, 0.25, 0.5, 0.75, 1.0, 0.0];
+    if floats != expected {
+        anyhow::bail!("light uniform: the buffer holds {:?} instead of {:?} (direction at 0, ambient at 12, color at 16)", floats, expected);
+    }
+    println!("light uniform: ok ({} bytes, the color starts at byte 16)", size);
+    Ok(())
+}
+
+/// A cube from -1 to 1 whose faces name their normals in the OBJ. Every corner of a face becomes
+/// a vertex of its own (24 in all), and the normal in its vertex buffer is the one of its face.
+async fn check_obj_normals(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    const CUBE: &str = "\
+v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1
+vn 1 0 0\nvn -1 0 0\nvn 0 1 0\nvn 0 -1 0\nvn 0 0 1\nvn 0 0 -1
+f 2//1 3//1 7//1\nf 2//1 7//1 6//1
+f 1//2 5//2 8//2\nf 1//2 8//2 4//2
+f 4//3 8//3 7//3\nf 4//3 7//3 3//3
+f 1//4 2//4 6//4\nf 1//4 6//4 5//4
+f 5//5 6//5 7//5\nf 5//5 7//5 8//5
+f 1//6 4//6 3//6\nf 1//6 3//6 2//6
+";
+    let path = std::env::temp_dir().join(format!("obj-normals-{}.obj", std::process::id()));
+    std::fs::write(&path, CUBE)?;
+    let cube = resources::load_model_from_path(&path, device, queue).await;
+    std::fs::remove_file(&path)?;
+    let cube = cube?;
+    let [mesh] = cube.meshes.as_slice() else {
+        anyhow::bail!("obj normals: {} meshes instead of 1", cube.meshes.len());
+    };
+    let bytes = read_buffer(device, queue, &mesh.vertex_buffer.buffer, mesh.vertex_buffer.size);
+    let vertices: &[model::RawVertex] = bytemuck::cast_slice(&bytes);
+    if vertices.len() != 24 {
+        anyhow::bail!("obj normals: {} vertices instead of 4 for each of the 6 faces", vertices.len());
+    }
+    for vertex in vertices {
+        // the normal points along one axis, and the vertex lies on the face at the end of it
+        let [x, y, z] = vertex.norm;
+        let on_face = vertex.pos[0] * x + vertex.pos[1] * y + vertex.pos[2] * z;
+        if (x * x + y * y + z * z - 1.0).abs() > 1e-6 || x * y + y * z + z * x != 0.0 || on_face != 1.0 {
+            anyhow::bail!("obj normals: the vertex at {:?} has the normal {:?}", vertex.pos, vertex.norm);
+        }
+    }
+    for axis in 0..3 {
+        for side in [-1.0, 1.0] {
+            let count = vertices.iter().filter(|v| v.norm[axis] == side).count();
+            if count != 4 {
+                anyhow::bail!("obj normals: {} vertices have the normal {} along axis {} instead of 4", count, side, axis);
+            }
+        }
+    }
+    println!("obj normals: ok (24 vertices, each with the normal of its face)");
+    Ok(())
+}
+
+/// The cube in `res/cube.stl` is 12 triangles, every corner of them gets its own vertex and
+/// index, with the normal of its face.
+async fn check_stl_cube(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let cube = resources::load_stl("cube.stl", device, queue).await?;
+    let [mesh] = cube.meshes.as_slice() else {
+        anyhow::bail!("stl cube: {} meshes instead of 1", cube.meshes.len());
+    };
+    if mesh.num_elements != 36 {
+        anyhow::bail!("stl cube: {} indices instead of 36 for 12 triangles", mesh.num_elements);
+    }
+    if mesh.aabb.min != cgmath::Point3::new(-1.0, -1.0, -1.0) || mesh.aabb.max != cgmath::Point3::new(1.0, 1.0, 1.0) {
+        anyhow::bail!("stl cube: the bounding box goes from {:?} to {:?}", mesh.aabb.min, mesh.aabb.max);
+    }
+    println!("stl cube: ok ({} triangles, {} indices)", mesh.num_elements / 3, mesh.num_elements);
+    Ok(())
+}
+
+/// The teapot built into the binary is the same as the one in the `res` folder, with the
+/// metadata of its sidecar
+async fn check_embedded_model(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let embedded = resources::load_embedded_model("teapot.obj", device, queue).await?;
+    let from_disk = resources::load_model("teapot.obj", device, queue).await?;
+    if embedded.source != Some(resources::Location::Embedded("teapot.obj".to_string())) {
+        anyhow::bail!("embedded model: the teapot was loaded from {:?}", embedded.source);
+    }
+    let shape = |object: &model::Object| object.meshes.iter()
+        .map(|mesh| (mesh.num_elements, mesh.aabb.min, mesh.aabb.max))
+        .collect::<Vec<_>>();
+    if shape(&embedded) != shape(&from_disk) {
+        anyhow::bail!("embedded model: the embedded teapot differs from the one in the res folder");
+    }
+    if embedded.metadata.is_empty() || embedded.metadata != from_disk.metadata {
+        anyhow::bail!("embedded model: the metadata of the embedded teapot is {:?}", embedded.metadata);
+    }
+    println!("embedded model: ok ({} meshes with {} indices, {} metadata entries)", embedded.meshes.len(), embedded.meshes.iter().map(|mesh| mesh.num_elements).sum::<u32>(), embedded.metadata.len());
+    Ok(())
+}
@@ -0,0 +1,261 @@
+/// Renderer for 2D quads in screen space (crosshairs, bars, image overlays) that are drawn on top
+/// of the finished scene. The positions are in pixels with the origin in the top left corner,
+/// an orthographic projection that matches the size of the screen maps them to clip space.
+///
+/// The quads are collected with `add_quad` during the frame, `prepare` writes them into an
+/// instance buffer and `render` draws them. Neighbouring quads with the same texture are drawn
+/// with a single instanced draw call, so the order of the quads is kept (later quads are on top).
+use std::mem;
+use std::sync::Arc;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use wgpu::util::DeviceExt;
+
+use crate::model;
+use crate::stats::RenderStats;
+
+/// A rectangle on the screen in pixels, `min` is the top left and `max` the bottom right corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HudRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl HudRect {
+    pub fn new(x0: f32, y0: f32, x1: f32, y1: f32) -> Self {
+        Self { min: [x0, y0], max: [x1, y1] }
+    }
+
+    /// a rectangle of the size with its center at the position
+    pub fn centered(center: [f32; 2], size: [f32; 2]) -> Self {
+        let half = [size[0] * 0.5, size[1] * 0.5];
+        Self::new(center[0] - half[0], center[1] - half[1], center[0] + half[0], center[1] + half[1])
+    }
+}
+
+// a quad in the instance buffer, this needs to match the QuadInput in hud.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawQuad {
+    rect: [f32; 4],
+    tint: [f32; 4],
+}
+
+impl RawQuad {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<RawQuad>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+// the quads in a row that use the same texture
+struct Batch {
+    texture: Arc<model::Texture>,
+    quads: std::ops::Range<u32>,
+}
+
+/// The matrix that maps pixels (origin top left, y down) to clip space
+pub fn pixel_projection(width: u32, height: u32) -> cgmath::Matrix4<f32> {
+    cgmath::ortho(0.0, width.max(1) as f32, height.max(1) as f32, 0.0, -1.0, 1.0)
+}
+
+/// A small cross for the center of the screen, white on transparent
+pub fn crosshair_image(size: u32) -> RgbaImage {
+    let center = size as f32 / 2.0;
+    let thickness = (size as f32 / 16.0).max(1.0);
+    RgbaImage::from_fn(size, size, |x, y| {
+        let dx = (x as f32 + 0.5 - center).abs();
+        let dy = (y as f32 + 0.5 - center).abs();
+        // the lines leave a gap in the middle so the target stays visible
+        let on_line = (dx <= thickness && dy > thickness * 2.0) || (dy <= thickness && dx > thickness * 2.0);
+        if on_line { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 0]) }
+    })
+}
+
+/// a texture with the crosshair for the HUD
+pub fn crosshair_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Arc<model::Texture>> {
+    let image = DynamicImage::ImageRgba8(crosshair_image(32));
+    let options = model::TextureOptions { premultiply_alpha: true, ..Default::default() };
+    Ok(Arc::new(model::Texture::from_images_with_options(device, queue, &[image], "crosshair", &options)?))
+}
+
+pub struct HudRenderer {
+    pipeline: wgpu::RenderPipeline,
+    projection_buffer: wgpu::Buffer,
+    projection_bind_group: wgpu::BindGroup,
+    instance_buffer: wgpu::Buffer,
+    // the number of quads that fit into the instance buffer
+    capacity: usize,
+    // the quads added since the last `prepare`
+    pending: Vec<(Arc<model::Texture>, RawQuad)>,
+    batches: Vec<Batch>,
+}
+
+impl HudRenderer {
+    pub fn new(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("HUD Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/hud.wgsl").into()),
+        });
+        let projection_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("HUD projection bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let texture_layout = model::Texture::create_layout(device);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("HUD pipeline layout"),
+            bind_group_layouts: &[&projection_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("HUD Renderer"),
+            layout: Some(&layout),
+            // the corners are generated in the shader, only the quads are in a buffer
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[RawQuad::desc()],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            // the HUD is always on top, there is no depth buffer
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let projection: [[f32; 4]; 4] = pixel_projection(surface_config.width, surface_config.height).into();
+        let projection_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("HUD projection uniform buffer"),
+            contents: bytemuck::cast_slice(&[projection]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let projection_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("HUD projection bind group"),
+            layout: &projection_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: projection_buffer.as_entire_binding(),
+            }],
+        });
+        let capacity = 16;
+        let instance_buffer = Self::create_instance_buffer(device, capacity);
+        Self {
+            pipeline,
+            projection_buffer,
+            projection_bind_group,
+            instance_buffer,
+            capacity,
+            pending: Vec::new(),
+            batches: Vec::new(),
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("HUD quad buffer"),
+            size: (capacity * mem::size_of::<RawQuad>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// the projection has to match the size of the screen, otherwise the quads are stretched
+    pub fn resize(&mut self, width: u32, height: u32, queue: &wgpu::Queue) {
+        let projection: [[f32; 4]; 4] = pixel_projection(width, height).into();
+        queue.write_buffer(&self.projection_buffer, 0, bytemuck::cast_slice(&[projection]));
+        RenderStats::record_buffer_write();
+    }
+
+    /// Draw the texture into the rectangle in this frame. The colors of the texture are
+    /// multiplied with the tint (rgba). The texture needs the bind group of the texture system
+    /// and should have premultiplied alpha.
+    pub fn add_quad(&mut self, rect: HudRect, texture: &Arc<model::Texture>, tint: [f32; 4]) {
+        let quad = RawQuad { rect: [rect.min[0], rect.min[1], rect.max[0], rect.max[1]], tint };
+        self.pending.push((texture.clone(), quad));
+    }
+
+    /// write the quads that were added in this frame to the GPU, they are drawn by the next
+    /// `render` and then forgotten
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.batches.clear();
+        if self.pending.is_empty() {
+            return;
+        }
+        if self.pending.len() > self.capacity {
+            self.capacity = self.pending.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        let quads = self.pending.iter().map(|(_, quad)| *quad).collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&quads));
+        RenderStats::record_buffer_write();
+        for (i, (texture, _)) in self.pending.drain(..).enumerate() {
+            let i = i as u32;
+            match self.batches.last_mut() {
+                Some(batch) if Arc::ptr_eq(&batch.texture, &texture) => batch.quads.end = i + 1,
+                _ => self.batches.push(Batch { texture, quads: i..i + 1 }),
+            }
+        }
+    }
+
+    /// draw the prepared quads on top of what is in the view
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        if self.batches.is_empty() {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("HUD render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.projection_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        for batch in self.batches.iter() {
+            let Some(bind_group) = &batch.texture.bind_group else {
+                continue;
+            };
+            render_pass.set_bind_group(1, bind_group, &[]);
+            RenderStats::record_bind_group_switch();
+            render_pass.draw(0..6, batch.quads.clone());
+            RenderStats::record_draw(2 * batch.quads.len() as u64);
+        }
+    }
+}
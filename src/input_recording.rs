@@ -0,0 +1,169 @@
+// Recording and replaying of the input. Starting the app with `--record input.jsonl` writes every
+// keyboard, mouse and resize event the app gets into the file, together with the time since the
+// recording started. `--replay input.jsonl` feeds them back to a fresh app at the same pace, so
+// a bug report can come with the exact input that leads to the bug.
+//
+// The events of winit can't be stored directly (they contain ids of the window and the device
+// that only make sense while the program is running, and some of them can't even be created
+// outside of winit), so only the parts of them that we care about are recorded as a
+// `RecordedEvent`. The file has one json object per line.
+//
+// Only the input that moves the camera and the size of the window are replayed, the ui doesn't
+// see the replayed events. While replaying, the real keyboard and mouse input is ignored.
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use web_time::Instant;
+use winit::event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+impl Mode {
+    pub fn from_args(args: &[String]) -> anyhow::Result<Option<Self>> {
+        for (i, arg) in args.iter().enumerate() {
+            let mode: fn(PathBuf) -> Mode = match arg.as_str() {
+                "--record" => Mode::Record,
+                "--replay" => Mode::Replay,
+                _ => continue,
+            };
+            let path = args.get(i + 1).ok_or_else(|| anyhow::anyhow!("{} needs the path of the input file", arg))?;
+            return Ok(Some(mode(PathBuf::from(path))));
+        }
+        Ok(None)
+    }
+}
+
+/// The part of a winit event that is recorded
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum RecordedEvent {
+    Key { code: KeyCode, state: ElementState },
+    MouseButton { button: MouseButton, state: ElementState },
+    /// a mouse wheel scrolls by lines
+    MouseWheelLines { x: f32, y: f32 },
+    /// a touchpad scrolls by pixels
+    MouseWheelPixels { x: f64, y: f64 },
+    /// the raw movement of the mouse (not the position of the cursor)
+    MouseMotion { dx: f64, dy: f64 },
+    Resized { width: u32, height: u32 },
+}
+
+impl RecordedEvent {
+    /// the recordable part of the event, None for all the events that are not recorded
+    pub fn from_event(event: &Event<()>) -> Option<Self> {
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::KeyboardInput { event, .. } => match event.physical_key {
+                    PhysicalKey::Code(code) => Some(RecordedEvent::Key { code, state: event.state }),
+                    PhysicalKey::Unidentified(_) => None,
+                },
+                WindowEvent::MouseInput { state, button, .. } => Some(RecordedEvent::MouseButton { button: *button, state: *state }),
+                WindowEvent::MouseWheel { delta, .. } => Some(match delta {
+                    MouseScrollDelta::LineDelta(x, y) => RecordedEvent::MouseWheelLines { x: *x, y: *y },
+                    MouseScrollDelta::PixelDelta(position) => RecordedEvent::MouseWheelPixels { x: position.x, y: position.y },
+                }),
+                WindowEvent::Resized(size) => Some(RecordedEvent::Resized { width: size.width, height: size.height }),
+                _ => None,
+            },
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
+                Some(RecordedEvent::MouseMotion { dx: delta.0, dy: delta.1 })
+            }
+            _ => None,
+        }
+    }
+
+    /// keyboard and mouse input, the events that are ignored from the real devices while replaying
+    pub fn is_input(&self) -> bool {
+        !matches!(self, RecordedEvent::Resized { .. })
+    }
+}
+
+/// a line of the input file
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TimedEvent {
+    /// the time since the recording started
+    pub time: Duration,
+    pub event: RecordedEvent,
+}
+
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path).map_err(|e| anyhow::anyhow!("could not create the input recording {:?}: {}", path, e))?;
+        Ok(Self { writer: BufWriter::new(file), start: Instant::now() })
+    }
+
+    /// write the event to the file if it is one of the recorded events
+    pub fn record(&mut self, event: &Event<()>) {
+        if let Event::LoopExiting = event {
+            if let Err(e) = self.writer.flush() {
+                log::error!("could not write the input recording: {}", e);
+            }
+        }
+        if let Some(recorded) = RecordedEvent::from_event(event) {
+            if let Err(e) = self.write(TimedEvent { time: self.start.elapsed(), event: recorded }) {
+                log::error!("could not record {:?}: {}", recorded, e);
+            }
+        }
+    }
+
+    pub fn write(&mut self, event: TimedEvent) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, &event)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+pub struct InputReplay {
+    events: VecDeque<TimedEvent>,
+    // the replay starts when the first event is asked for, not when the file was loaded
+    start: Option<Instant>,
+}
+
+impl InputReplay {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).map_err(|e| anyhow::anyhow!("could not open the input recording {:?}: {}", path, e))?;
+        let mut events = VecDeque::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = serde_json::from_str(&line)
+                .map_err(|e| anyhow::anyhow!("invalid event in line {} of {:?}: {}", number + 1, path, e))?;
+            events.push_back(event);
+        }
+        Ok(Self { events, start: None })
+    }
+
+    /// take the events that are due since the replay started
+    pub fn due(&mut self) -> Vec<RecordedEvent> {
+        let elapsed = self.start.get_or_insert_with(Instant::now).elapsed();
+        self.due_at(elapsed)
+    }
+
+    /// take the events that are due `elapsed` after the start of the recording, for a replay
+    /// that isn't paced by the clock on the wall
+    pub fn due_at(&mut self, elapsed: Duration) -> Vec<RecordedEvent> {
+        let mut due = Vec::new();
+        while self.events.front().is_some_and(|e| e.time <= elapsed) {
+            due.extend(self.events.pop_front().map(|e| e.event));
+        }
+        due
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
@@ -1,8 +1,14 @@
-use cgmath::{Vector3, Matrix4, Vector4, Quaternion};
+use cgmath::{Vector3, Matrix, Matrix3, Matrix4, Vector4, Quaternion, SquareMatrix, Point3, Transform, MetricSpace, InnerSpace, Euler, Deg};
+use std::collections::HashMap;
 use std::rc::{Rc, Weak};
+use std::sync::Arc;
 use std::mem;
+use std::ops::Range;
 use wgpu;
 
+use crate::upload;
+use crate::stats::RenderStats;
+
 /// The indexing that works for Vertices also kinda works for whole meshes.
 /// This allows us to easily (and while only using an additional 16 numbers) to
 /// create multiple copies (or instances) of the same mesh, without
@@ -27,13 +33,99 @@ pub struct Instance {
     pub scale: Vector3<f32>,
     /// for our colored mesh renderer, we need the color of the mesh
     pub color: Vector4<f32>,
+    /// the transform of the scene graph node the instance is attached to. The position,
+    /// rotation and scale are relative to this transform
+    pub parent_transform: Matrix4<f32>,
+    /// the layer of the texture array that is sampled for this instance, so instances of the
+    /// same mesh can show different textures. For animated textures this is the first frame
+    pub tex_layer: u32,
+    /// arbitrary information about the instance (sensor ids, measurements, ...) that is shown
+    /// when the instance is selected
+    pub metadata: HashMap<String, String>,
+    /// a hidden instance is not drawn, but it keeps its slot and its data so it can be
+    /// shown again right away. Like the other fields it takes effect with the next `update`.
+    /// This is the only switch of an instance: disabling an instance is hiding it, there is no
+    /// separate `enabled` flag that would have to agree with this one
+    pub visible: bool,
+    // if the position, rotation and scale are composed into the matrix of the instance, or if
+    // the matrix was given as a whole
+    local: LocalTransform,
+    // the rotation as the angles in degrees the ui shows, together with the rotation they
+    // belong to, see `build_ui`
+    ui_angles: Option<(Quaternion<f32>, [f32; 3])>,
     // we only store a reference to the index of the instance buffer here
     // as it is owned by the same struct that owns tis struct, so that
     // we can have proper lifetimes when we start to render things.
     pub buffer_index: Rc<usize>,
 }
 
-pub type RawInstance = [[f32;4];5];
+// The transform of an instance in its parent. A matrix that comes from somewhere else (like the
+// node of a scene file) can have shear, which the position, rotation and scale can't express,
+// and even without it the decomposition loses a little precision. So the matrix is kept and
+// used as is, the position, rotation and scale hold its decomposition. As soon as one of them
+// is changed the matrix is given up and the instance is composed from them again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LocalTransform {
+    Trs,
+    Matrix {
+        matrix: Matrix4<f32>,
+        // the position, rotation and scale the matrix was decomposed into
+        decomposed: (Vector3<f32>, Quaternion<f32>, Vector3<f32>),
+    },
+}
+
+/// Split a matrix into the translation, rotation and scale that are composed back into it by
+/// `Instance`. Shear can't be expressed with them and is lost. A mirroring matrix (with a
+/// negative determinant) gets a negative scale in x.
+pub fn decompose(matrix: Matrix4<f32>) -> (Vector3<f32>, Quaternion<f32>, Vector3<f32>) {
+    let translation = matrix.w.truncate();
+    let axes = Matrix3::from_cols(matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate());
+    let mut scale = Vector3::new(axes.x.magnitude(), axes.y.magnitude(), axes.z.magnitude());
+    if axes.determinant() < 0.0 {
+        scale.x = -scale.x;
+    }
+    // a matrix that flattens the mesh has no rotation that can be found from it
+    if scale.x == 0.0 || scale.y == 0.0 || scale.z == 0.0 {
+        return (translation, Quaternion::new(1.0, 0.0, 0.0, 0.0), scale);
+    }
+    let rotation = Matrix3::from_cols(axes.x / scale.x, axes.y / scale.y, axes.z / scale.z);
+    (translation, Quaternion::from(rotation).normalize(), scale)
+}
+
+/// The data of an instance as it is laid out in the instance buffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct RawInstance {
+    model: [[f32; 4]; 4],
+    color: [f32; 4],
+    tex_layer: u32,
+    // the matrix for the normals, see `Instance::normal_matrix`
+    normal: [[f32; 3]; 3],
+}
+
+impl RawInstance {
+    fn is_transparent(&self) -> bool {
+        self.color[3] < 1.0
+    }
+
+    // where the point of the mesh ends up in the world with this instance
+    fn world_point(&self, local: Point3<f32>) -> Point3<f32> {
+        Matrix4::from(self.model).transform_point(local)
+    }
+}
+
+/// Put the instances in the order they have to be blended in: the one farthest from the eye
+/// first. The distance is measured to `local_center` (usually the center of the bounding box of
+/// the mesh) placed by every instance. Instances at the same distance keep their order, so they
+/// don't flicker from one frame to the next. Returns false if the order was right already.
+pub fn sort_back_to_front(instances: &mut [RawInstance], local_center: Point3<f32>, eye: Point3<f32>) -> bool {
+    let distance = |instance: &RawInstance| instance.world_point(local_center).distance2(eye);
+    if instances.is_sorted_by(|a, b| distance(a) >= distance(b)) {
+        return false;
+    }
+    instances.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+    true
+}
 
 impl Instance {
     /// Create a new instance given a new instance buffer
@@ -43,6 +135,12 @@ impl Instance {
             rotation: Quaternion { v: Vector3::unit_z(), s: 0.0 },
             scale: Vector3 { x: 1.0, y: 1.0, z: 1.0 },
             color: Vector4 { x: 0.0, y: 1.0, z: 0.0, w: 1.0 },
+            parent_transform: Matrix4::identity(),
+            tex_layer: 0,
+            metadata: HashMap::new(),
+            visible: true,
+            local: LocalTransform::Trs,
+            ui_angles: None,
             buffer_index,
         }
     }
@@ -59,44 +157,110 @@ impl Instance {
             rotation,
             scale,
             color,
+            parent_transform: Matrix4::identity(),
+            tex_layer: 0,
+            metadata: HashMap::new(),
+            visible: true,
+            local: LocalTransform::Trs,
+            ui_angles: None,
             buffer_index,
         }
     }
 
+    /// An instance that is placed with the matrix instead of a position, rotation and scale.
+    /// The matrix ends up in the instance buffer exactly as it is, until the position, rotation
+    /// or scale are changed (see `translate`).
+    #[allow(dead_code)]
+    pub fn from_matrix(matrix: Matrix4<f32>, color: Vector4<f32>, buffer_index: Rc<usize>) -> Self {
+        let decomposed = decompose(matrix);
+        let (position, rotation, scale) = decomposed;
+        Self {
+            position,
+            rotation,
+            scale,
+            color,
+            local: LocalTransform::Matrix { matrix, decomposed },
+            ..Self::new(buffer_index)
+        }
+    }
+
+    // the matrix the instance was created with, as long as it still holds
+    fn given_matrix(&self) -> Option<Matrix4<f32>> {
+        match self.local {
+            LocalTransform::Matrix { matrix, decomposed } if decomposed == (self.position, self.rotation, self.scale) => Some(matrix),
+            _ => None,
+        }
+    }
+
     /// turn the data in our shader struct into a matrix in homogenious
     /// coordinates
-    fn compute_instance_matrix(&self) -> RawInstance {
-        let buffer_content: [[f32; 4]; 4] = (
-            Matrix4::<f32>::from_translation(self.position) *
-            Matrix4::<f32>::from(self.rotation) *
-            Matrix4::<f32>::new(
-                self.scale.x, 0.0, 0.0, 0.0,
-                0.0, self.scale.y, 0.0, 0.0,
-                0.0, 0.0, self.scale.z, 0.0,
-                0.0, 0.0,          0.0, 1.0)).into();
-        let color: [f32; 4] = self.color.into();
-        {
-            let mut whole = [[0.0; 4]; 5];
-            let (left, right) = whole.split_at_mut(buffer_content.len());
-            left.copy_from_slice(&buffer_content);
-            right.copy_from_slice(&[color]);
-            whole
+    /// The transform from the coordinates of the mesh into the world
+    pub fn world_matrix(&self) -> Matrix4<f32> {
+        if let Some(matrix) = self.given_matrix() {
+            return self.parent_transform * matrix;
+        }
+        self.parent_transform *
+        Matrix4::<f32>::from_translation(self.position) *
+        Matrix4::<f32>::from(self.rotation) *
+        Matrix4::<f32>::new(
+            self.scale.x, 0.0, 0.0, 0.0,
+            0.0, self.scale.y, 0.0, 0.0,
+            0.0, 0.0, self.scale.z, 0.0,
+            0.0, 0.0,          0.0, 1.0)
+    }
+
+    /// The matrix that turns the normals of the mesh into world space, the inverse transpose of
+    /// the rotation and scale in `world_matrix`. The normals can't be transformed with the
+    /// instance matrix itself when the scale is not the same in every direction, they would be
+    /// stretched along with the mesh and lean towards the stretched axis. For a rotation (and a
+    /// uniform scale, after the normal is normalized) the inverse transpose is the same as the
+    /// matrix, so nothing changes for those.
+    pub fn normal_matrix(&self) -> Matrix3<f32> {
+        let world = self.world_matrix();
+        let m = Matrix3::from_cols(world.x.truncate(), world.y.truncate(), world.z.truncate());
+        // a scale of 0 flattens the mesh and there is no inverse. The cofactor matrix is the
+        // inverse transpose times the determinant, the normals only need the direction of it
+        m.invert().map(|inverse| inverse.transpose()).unwrap_or_else(|| {
+            Matrix3::from_cols(m.y.cross(m.z), m.z.cross(m.x), m.x.cross(m.y))
+        })
+    }
+
+    /// the data of the instance as it goes into the instance buffer
+    pub fn compute_instance_matrix(&self) -> RawInstance {
+        RawInstance {
+            model: self.world_matrix().into(),
+            color: self.color.into(),
+            tex_layer: self.tex_layer,
+            normal: self.normal_matrix().into(),
         }
     }
 
     pub fn update(&mut self, gpu_buffer: &mut InstanceBuffer) {
         let im = self.compute_instance_matrix();
-        gpu_buffer.set_data(*self.buffer_index, im)
+        gpu_buffer.set_data(*self.buffer_index, im);
+        gpu_buffer.set_visible(*self.buffer_index, self.visible)
+    }
+
+    /// hide the instance or show it again, see `visible`
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
     }
 
-    /// rotate the instance by the given quaternion
+    /// rotate the instance by the given quaternion, an instance created with `from_matrix`
+    /// is composed from the decomposition of its matrix from now on
     pub fn rotate(&mut self, rotation: Quaternion<f32>) {
         self.rotation = self.rotation * rotation;
     }
 
-    /// translate the instance along the given vector
+    /// translate the instance along the given vector. The matrix of an instance created with
+    /// `from_matrix` is moved along with it, so it keeps its shear
     pub fn translate(&mut self, translation: Vector3<f32>) {
-        self.position += translation
+        let given = self.given_matrix().is_some();
+        self.position += translation;
+        if let (true, LocalTransform::Matrix { matrix, decomposed }) = (given, &mut self.local) {
+            *matrix = Matrix4::from_translation(translation) * *matrix;
+            decomposed.0 = self.position;
+        }
     }
     
     /// we need the buffer layout for this at one point so we encode it here
@@ -104,9 +268,9 @@ impl Instance {
     /// method)
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
-            // we know te size of the instance transform matrix, and then we add the size of the
-            // rgba color to the total size
-            array_stride: (mem::size_of::<[[f32; 4]; 4]>() + mem::size_of::<Vector4<u8>>()) as wgpu::BufferAddress,
+            // the transform matrix, the rgba color, the texture layer and the normal matrix,
+            // exactly as they are laid out in the RawInstance
+            array_stride: mem::size_of::<RawInstance>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Instance,
             // So the 4x4 matrix needs to be split into vectors (as we can't describe
             // matrices as vertex attributes, so we split the matrix into 4 vectors
@@ -133,61 +297,236 @@ impl Instance {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
-                // the color encoded as 4 integers in the CPU and coverted to 4 floats [0,1] (rgba)
-                // in the shader
+                // the rgba color of the instance as 4 floats in [0,1]
                 wgpu::VertexAttribute {
                     offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
                     shader_location: 9,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // the layer of the texture array, this is an integer in the shader as well
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                // the three columns of the matrix for the normals, right after the layer
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 21]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 24]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 27]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
 
+    /// Edit the position, rotation, scale and color of the instance. Any change is written to
+    /// `instance_buffer` right away (it still needs to be flushed).
+    pub fn build_ui(&mut self, ui: &mut egui::Ui, snap: &GridSnap, instance_buffer: &mut InstanceBuffer) {
+        let mut changed = ui.checkbox(&mut self.visible, "visible").changed();
+        changed |= ui.add(egui::Slider::new(&mut self.position.x, -5.0..=5.).text("position x")).changed();
+        changed |= ui.add(egui::Slider::new(&mut self.position.y, -5.0..=5.).text("position y")).changed();
+        changed |= ui.add(egui::Slider::new(&mut self.position.z, -5.0..=5.).text("position z")).changed();
+        // snap after the sliders so the value that ends up in the instance buffer is on the grid
+        let snapped = snap.snap(self.position);
+        changed |= snapped != self.position;
+        self.position = snapped;
+
+        // The quaternion is shown as euler angles. Going from the angles to the quaternion and
+        // back doesn't always give the same angles (near 90 degrees pitch there are many angles
+        // for the same rotation), so the angles would jump around while one of them is dragged.
+        // The ui keeps its own angles instead, and only takes them from the quaternion again
+        // when the rotation was changed somewhere else.
+        let mut angles = match self.ui_angles {
+            Some((rotation, angles)) if rotation == self.rotation => angles,
+            _ => {
+                let euler = Euler::from(self.rotation);
+                [Deg::from(euler.x).0, Deg::from(euler.y).0, Deg::from(euler.z).0]
+            }
+        };
+        let mut rotated = false;
+        for (angle, axis) in angles.iter_mut().zip(["x", "y", "z"]) {
+            rotated |= ui.add(egui::Slider::new(angle, -180.0..=180.0).suffix("°").text(format!("rotation {}", axis))).changed();
+        }
+        if rotated {
+            self.rotation = Quaternion::from(Euler::new(Deg(angles[0]), Deg(angles[1]), Deg(angles[2])));
+            changed = true;
+        }
+        self.ui_angles = Some((self.rotation, angles));
+
+        // the uniform scale scales all axes by the same factor, so the proportions stay
+        let old_uniform = (self.scale.x * self.scale.y * self.scale.z).abs().cbrt();
+        let mut uniform = old_uniform;
+        if ui.add(egui::DragValue::new(&mut uniform).speed(0.01).clamp_range(0.01..=100.0).prefix("uniform scale ")).changed() && old_uniform > 0.0 {
+            self.scale *= uniform / old_uniform;
+            changed = true;
+        }
+        ui.horizontal(|ui| {
+            for (value, axis) in [(&mut self.scale.x, "x"), (&mut self.scale.y, "y"), (&mut self.scale.z, "z")] {
+                changed |= ui.add(egui::DragValue::new(value).speed(0.01).prefix(format!("scale {} ", axis))).changed();
+            }
+        });
+
+        let mut color: [f32; 4] = self.color.into();
+        ui.horizontal(|ui| {
+            ui.label("color");
+            if ui.color_edit_button_rgba_unmultiplied(&mut color).changed() {
+                self.color = color.into();
+                changed = true;
+            }
+        });
+        changed |= ui.add(egui::DragValue::new(&mut self.tex_layer).prefix("texture layer ")).changed();
+        if changed {
+            self.update(instance_buffer);
+        }
+    }
+}
+
+/// Rounds positions to a grid so that instances placed with the sliders line up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridSnap {
+    pub enabled: bool,
+    /// the distance between two grid lines
+    pub step: f32,
+}
+
+impl Default for GridSnap {
+    fn default() -> Self {
+        Self { enabled: false, step: 0.25 }
+    }
+}
+
+impl GridSnap {
+    /// move the position to the closest point on the grid, if snapping is enabled
+    pub fn snap(&self, position: Vector3<f32>) -> Vector3<f32> {
+        if !self.enabled || self.step <= 0.0 {
+            return position;
+        }
+        position.map(|x| (x / self.step).round() * self.step)
+    }
+
     pub fn build_ui(&mut self, ui: &mut egui::Ui) {
-        ui.add(egui::Slider::new(&mut self.position.x, -5.0..=5.).text("position x"));
-        ui.add(egui::Slider::new(&mut self.position.y, -5.0..=5.).text("position y"));
-        ui.add(egui::Slider::new(&mut self.position.z, -5.0..=5.).text("position z"));
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.enabled, "snap to grid");
+            ui.add_enabled(self.enabled, egui::DragValue::new(&mut self.step).speed(0.01).clamp_range(0.01..=10.0));
+        });
     }
 }
 
+// the number of flushes in a row the instance buffer has to be mostly empty before it shrinks
+const SHRINK_AFTER_FLUSHES: u32 = 60;
 
 /// many instances share the same buffer the buffer will grow in powers o
 /// so instance buffers will not be terribly large so we can keep a copy on the cpu side
 pub struct InstanceBuffer {
     cpu_copy: Vec<RawInstance>,
-    pub gpu_buffer: wgpu::Buffer,
+    // if the instance in the slot is drawn, hidden slots keep their data in the cpu copy
+    visible: Vec<bool>,
+    // the buffer is shared with the upload thread
+    pub gpu_buffer: Arc<wgpu::Buffer>,
     gpu_buffer_size: usize,
+    // the size the buffer was created with, it never shrinks below that
+    initial_size: usize,
+    // the number of flushes in a row that used less than a quarter of the GPU buffer
+    low_usage_flushes: u32,
     handles: Vec<Weak<usize>>,
+    /// the number of instances at the start of the GPU buffer that are drawn, hidden instances
+    /// are not counted
     pub occupied_slots: u64,
-    changed: bool
+    // the drawn instances are split in two contiguous parts, first the opaque ones and then the
+    // transparent ones (see `opaque_instances`), this is the size of the first part
+    opaque_slots: u64,
+    // the slots have to be packed again, an instance was added, removed, shown or hidden
+    changed: bool,
+    // the lowest and highest slot (as a range) whose data changed since the last flush, as long
+    // as the packing stays the same only these are written to the GPU
+    dirty: Option<Range<usize>>,
+    // the occupied slots packed together for the upload. This is kept between flushes so
+    // that flushing doesn't allocate (and zero) a new buffer every time
+    scratch: Vec<RawInstance>,
+    // where the data of every slot is in `scratch` (and the GPU buffer), None for the slots
+    // that are not packed
+    packed_index: Vec<Option<usize>>,
+    // the bytes the last flush wrote to the GPU buffer
+    uploaded_bytes: usize,
+    // The buffer doubles whenever it is full, so a loop that spawns instances by accident can
+    // eat up all the GPU memory without anybody noticing. With a limit, no more slots are
+    // handed out once it is reached.
+    max_instances: Option<usize>,
+    // called with the limit when a slot is refused
+    on_limit_reached: Option<Box<dyn FnMut(usize)>>,
+    // the center of the mesh and the eye the transparent instances were last sorted for, the
+    // packing keeps them in this order (see `sort_transparent`)
+    sort_origin: Option<(Point3<f32>, Point3<f32>)>,
 }
 
 impl InstanceBuffer {
     pub fn new(device: &wgpu::Device, buffer_size_in_elems: usize) -> Self {
         InstanceBuffer {
             cpu_copy: Vec::new(),
+            visible: Vec::new(),
             handles: Vec::new(),
             gpu_buffer: Self::create_new_buffer_with_size(buffer_size_in_elems, device),
             gpu_buffer_size: buffer_size_in_elems,
+            initial_size: buffer_size_in_elems,
+            low_usage_flushes: 0,
             occupied_slots: 0,
+            opaque_slots: 0,
             changed: false,
+            dirty: None,
+            scratch: Vec::new(),
+            packed_index: Vec::new(),
+            uploaded_bytes: 0,
+            max_instances: None,
+            on_limit_reached: None,
+            sort_origin: None,
         }
     }
 
-    fn create_new_buffer_with_size(size: usize, device: &wgpu::Device) -> wgpu::Buffer {
-        device.create_buffer(
+    /// limit the number of instances that can be in the buffer at the same time, None (the
+    /// default) lets the buffer grow without limit
+    pub fn set_max_instances(&mut self, max_instances: Option<usize>) {
+        self.max_instances = max_instances;
+    }
+
+    pub fn max_instances(&self) -> Option<usize> {
+        self.max_instances
+    }
+
+    /// call `callback` with the limit every time a slot is refused because of it
+    pub fn on_limit_reached(&mut self, callback: impl FnMut(usize) + 'static) {
+        self.on_limit_reached = Some(Box::new(callback));
+    }
+
+    /// the number of instances that hold a slot right now
+    pub fn live_instances(&self) -> usize {
+        self.handles.iter().filter(|h| h.strong_count() > 0).count()
+    }
+
+    /// the number of instances the GPU buffer has room for
+    pub fn gpu_capacity(&self) -> usize {
+        self.gpu_buffer_size
+    }
+
+    fn create_new_buffer_with_size(size: usize, device: &wgpu::Device) -> Arc<wgpu::Buffer> {
+        Arc::new(device.create_buffer(
             &wgpu::BufferDescriptor {
                 label: Some("Instance Buffer on GPU"),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                // COPY_SRC to read the packed instances back and check them (see golden.rs)
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
                 mapped_at_creation: false,
                 size: (mem::size_of::<RawInstance>() as usize * size) as wgpu::BufferAddress
             }
-        )
-    }
-
-    fn get_occupied_slots(&self) -> Vec<usize> {
-        self.handles.iter().filter_map(|h| h.upgrade()).map(|h| *h).collect()
+        ))
     }
 
 
@@ -202,43 +541,319 @@ impl InstanceBuffer {
         free_slot
     }
 
-    pub fn get_instance_buffer_slot(&mut self) -> Rc<usize> {
+    /// Reserve a slot for an instance, the slot is free again when the returned handle is dropped.
+    /// Fails if the buffer already holds `max_instances` instances.
+    pub fn get_instance_buffer_slot(&mut self) -> anyhow::Result<Rc<usize>> {
+        if let Some(max_instances) = self.max_instances {
+            if self.live_instances() >= max_instances {
+                log::warn!("the instance buffer is full, it is limited to {} instances", max_instances);
+                if let Some(callback) = &mut self.on_limit_reached {
+                    callback(max_instances);
+                }
+                anyhow::bail!("no more than {} instances are allowed in the buffer", max_instances);
+            }
+        }
         let lowest_free_index = self.get_first_free_slot_idx();
         if lowest_free_index >= self.cpu_copy.len() {
             self.cpu_copy.push(RawInstance::default());
+            self.visible.push(true);
         }
+        // a reused slot may still be hidden from the instance that had it before
+        self.visible[lowest_free_index] = true;
         self.changed = true;
         let nbf = Rc::new(lowest_free_index);
-        self.handles.push(Rc::<usize>::downgrade(&nbf));
-        self.occupied_slots += 1;
-        nbf
+        // the handle of a slot is at the index of the slot, a freed slot gets the new handle in
+        // the place of the dropped one, otherwise the dropped handle would hand out the slot again
+        if lowest_free_index < self.handles.len() {
+            self.handles[lowest_free_index] = Rc::<usize>::downgrade(&nbf);
+        } else {
+            self.handles.push(Rc::<usize>::downgrade(&nbf));
+        }
+        // `occupied_slots` is not counted up here, the data of the new slot only reaches the GPU
+        // buffer with the next flush which counts the slots again
+        Ok(nbf)
     }
 
-    pub fn set_data(&mut self, index: usize, data: RawInstance) {
+    /// Give the slot back right away. Dropping the handle frees the slot as well, but the buffer
+    /// doesn't notice until something else changes and the instance is still drawn until then.
+    /// With this the next flush packs the buffer without it.
+    pub fn release_slot(&mut self, handle: Rc<usize>) {
+        drop(handle);
         self.changed = true;
+    }
+
+    /// the instances that are drawn, opaque and transparent ones
+    pub fn all_instances(&self) -> Range<u32> {
+        0..self.occupied_slots as u32
+    }
+
+    /// the instances with an alpha of 1, they are at the start of the buffer
+    pub fn opaque_instances(&self) -> Range<u32> {
+        0..self.opaque_slots as u32
+    }
+
+    /// the instances with an alpha below 1, they follow the opaque ones
+    pub fn transparent_instances(&self) -> Range<u32> {
+        self.opaque_slots as u32..self.occupied_slots as u32
+    }
+
+    /// Sort the transparent instances from back to front as seen from `eye` (see
+    /// `sort_back_to_front`), and write them to the GPU in the new order if it changed. The
+    /// order is kept when the buffer is packed again, until the next call. This is meant to be
+    /// called every frame, before the transparent instances are drawn.
+    pub fn sort_transparent(&mut self, local_center: Point3<f32>, eye: Point3<f32>, queue: &wgpu::Queue) {
+        self.sort_origin = Some((local_center, eye));
+        // the scratch buffer holds what is in the GPU buffer, unless some of it is about to be
+        // written anyway
+        if self.changed || self.dirty.is_some() {
+            return;
+        }
+        let transparent = self.opaque_slots as usize..self.occupied_slots as usize;
+        if sort_back_to_front(&mut self.scratch[transparent.clone()], local_center, eye) {
+            self.forget_transparent_positions();
+            let offset = (transparent.start * mem::size_of::<RawInstance>()) as wgpu::BufferAddress;
+            queue.write_buffer(&self.gpu_buffer, offset, bytemuck::cast_slice(&self.scratch[transparent]));
+            RenderStats::record_buffer_write();
+        }
+    }
+
+    // The sorting moves the transparent instances around in the packed buffer without knowing
+    // which slot they belong to. Their slots are packed again the next time they change.
+    fn forget_transparent_positions(&mut self) {
+        let opaque_slots = self.opaque_slots as usize;
+        for position in self.packed_index.iter_mut() {
+            if position.is_some_and(|p| p >= opaque_slots) {
+                *position = None;
+            }
+        }
+    }
+
+    pub fn set_data(&mut self, index: usize, data: RawInstance) {
+        // the instances are updated every frame whether they changed or not (the UI does that),
+        // the unchanged ones must not make the written range bigger
+        if bytemuck::bytes_of(&self.cpu_copy[index]) == bytemuck::bytes_of(&data) {
+            return;
+        }
+        let was_transparent = self.cpu_copy[index].is_transparent();
         self.cpu_copy[index] = data;
+        // a hidden instance is not in the GPU buffer, its data is packed when it is shown again
+        if !self.visible[index] {
+            return;
+        }
+        // an instance that moves to the other part of the buffer (see `prepare_flush`) or that
+        // doesn't have its place in the buffer yet needs the slots to be packed again, otherwise
+        // the new data can be written right where the old data was
+        let packed = self.packed_index.get(index).is_some_and(|p| p.is_some());
+        if !packed || was_transparent != data.is_transparent() {
+            self.changed = true;
+            return;
+        }
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(index)..dirty.end.max(index + 1),
+            None => index..index + 1,
+        });
+    }
+
+    /// the number of bytes the last flush wrote to the GPU buffer
+    pub fn uploaded_bytes(&self) -> usize {
+        self.uploaded_bytes
+    }
+
+    /// show or hide the instance in the slot, the data of a hidden instance stays in the slot
+    pub fn set_visible(&mut self, index: usize, visible: bool) {
+        if self.visible[index] != visible {
+            self.changed = true;
+            self.visible[index] = visible;
+        }
     }
 
     /// all the interaction between the cpu and gpu happens here, when the cpu managed buffer
     /// is flushed to the GPU
     pub fn flush(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
-        // if nothing has changed, do nothing
-        if !self.changed {
-            return
+        if let Some((range, _)) = self.prepare_flush(device) {
+            let offset = (range.start * mem::size_of::<RawInstance>()) as wgpu::BufferAddress;
+            queue.write_buffer(&self.gpu_buffer, offset, bytemuck::cast_slice(&self.scratch[range]));
+            RenderStats::record_buffer_write();
+        }
+    }
+
+    /// Same as `flush`, but the data is written to the GPU by the upload thread, so the render
+    /// thread doesn't have to wait for the copy. Only changed data goes that way: when the
+    /// buffer was reallocated or packed again, `occupied_slots` already counts the new layout,
+    /// so the data is written right away to be there for the next frame.
+    pub fn flush_async(&mut self, device: &wgpu::Device, uploader: &upload::Uploader) {
+        if let Some((range, packed)) = self.prepare_flush(device) {
+            let request = upload::UploadRequest {
+                buffer: self.gpu_buffer.clone(),
+                offset: (range.start * mem::size_of::<RawInstance>()) as wgpu::BufferAddress,
+                data: bytemuck::cast_slice(&self.scratch[range]).to_vec(),
+            };
+            if packed {
+                uploader.upload_now(request);
+            } else {
+                uploader.upload(request);
+            }
+            RenderStats::record_buffer_write();
         }
-        // if by any chance the CPU buffer is bigger than the GPU buffer, resize the GPU buffer
+    }
+
+    /// resize the GPU buffer if needed and pack the occupied slots into the scratch buffer that
+    /// is uploaded. Returns the part of the scratch buffer that has to be uploaded, None if
+    /// nothing has changed
+    // the range to write, and whether the buffer was packed again (which moves the slots)
+    fn prepare_flush(&mut self, device: &wgpu::Device) -> Option<(Range<usize>, bool)> {
+        self.uploaded_bytes = 0;
+        self.shrink_if_unused(device);
+        // if by any chance the CPU buffer is bigger than the GPU buffer, resize the GPU buffer.
+        // It doubles as often as needed, many instances may have been added since the last
+        // flush. The new buffer is empty, so everything is written again
         if self.cpu_copy.len() >= self.gpu_buffer_size {
-            self.gpu_buffer_size = self.gpu_buffer_size * 2;
-            self.gpu_buffer = Self::create_new_buffer_with_size(self.gpu_buffer_size, device) 
+            while self.cpu_copy.len() >= self.gpu_buffer_size {
+                self.gpu_buffer_size = (self.gpu_buffer_size * 2).max(1);
+            }
+            self.gpu_buffer = Self::create_new_buffer_with_size(self.gpu_buffer_size, device);
+            self.changed = true;
+        }
+        let packed = self.changed;
+        let range = if packed { self.pack() } else { self.update_dirty()? };
+        self.uploaded_bytes = range.len() * mem::size_of::<RawInstance>();
+        (!range.is_empty()).then_some((range, packed))
+    }
+
+    // The buffer doubles when it is full but a spike of instances would keep it big forever.
+    // When less than a quarter of it is drawn for `SHRINK_AFTER_FLUSHES` flushes in a row it is
+    // halved. Waiting for several flushes keeps it from shrinking and growing back every other
+    // frame when the number of instances goes up and down around the boundary.
+    fn shrink_if_unused(&mut self, device: &wgpu::Device) {
+        let half = self.gpu_buffer_size / 2;
+        if self.occupied_slots as usize * 4 >= self.gpu_buffer_size || half < self.initial_size {
+            self.low_usage_flushes = 0;
+            return;
+        }
+        self.low_usage_flushes += 1;
+        if self.low_usage_flushes < SHRINK_AFTER_FLUSHES {
+            return;
+        }
+        self.low_usage_flushes = 0;
+        // the slots can't move, the instances hold on to their index, but the free slots at the
+        // end are not needed anymore
+        while self.handles.last().is_some_and(|h| h.strong_count() == 0) {
+            self.handles.pop();
+            self.cpu_copy.pop();
+            self.visible.pop();
+        }
+        self.packed_index.truncate(self.cpu_copy.len());
+        // an instance in a slot at the end still needs the room (see the resize in `prepare_flush`)
+        if self.cpu_copy.len() >= half {
+            return;
+        }
+        self.gpu_buffer_size = half;
+        self.gpu_buffer = Self::create_new_buffer_with_size(self.gpu_buffer_size, device);
+        self.changed = true;
+    }
+
+    // Only the data of some instances changed and they are still in the same place in the
+    // packed buffer, so only those places are written. The changed slots are one range, so
+    // their places are one range as well, the unchanged slots in between are written again.
+    fn update_dirty(&mut self) -> Option<Range<usize>> {
+        let dirty = self.dirty.take()?;
+        let mut packed: Option<Range<usize>> = None;
+        for slot in dirty {
+            if let Some(position) = self.packed_index[slot] {
+                self.scratch[position] = self.cpu_copy[slot];
+                packed = Some(match packed {
+                    Some(packed) => packed.start.min(position)..packed.end.max(position + 1),
+                    None => position..position + 1,
+                });
+            }
         }
-        // get all the slots that actually have data and fill them into a contiguous buffer
-        let occupied_indices = self.get_occupied_slots();
-        self.occupied_slots = occupied_indices.len() as u64;
-        let mut contiguous_instance_buffer: Vec<RawInstance> = vec![RawInstance::default(); self.gpu_buffer_size];
-        for (i, &cpu_buf_idx) in  occupied_indices.iter().enumerate() {
-            contiguous_instance_buffer[i] = self.cpu_copy[cpu_buf_idx];
+        packed
+    }
+
+    // pack all the slots again, returns the whole packed range
+    fn pack(&mut self) -> Range<usize> {
+        // get all the slots that actually have data and fill them into a contiguous buffer.
+        // Only this occupied prefix is uploaded, the rest of the GPU buffer is never drawn
+        // (see `occupied_slots`) so it doesn't matter what is in there. The hidden instances
+        // are left out, so they are not drawn and `occupied_slots` only counts the visible ones.
+        // The opaque instances are packed first and the transparent ones after them, so both
+        // can be drawn as one range. As the packing only happens when something has changed,
+        // an instance only moves to the other part when its alpha crosses 1 (or the set of
+        // instances changes), not every frame.
+        self.scratch.clear();
+        self.packed_index.clear();
+        self.packed_index.resize(self.cpu_copy.len(), None);
+        for transparent in [false, true] {
+            for h in self.handles.iter().filter_map(|h| h.upgrade()) {
+                if self.visible[*h] && self.cpu_copy[*h].is_transparent() == transparent {
+                    self.packed_index[*h] = Some(self.scratch.len());
+                    self.scratch.push(self.cpu_copy[*h]);
+                }
+            }
+            if !transparent {
+                self.opaque_slots = self.scratch.len() as u64;
+            }
+        }
+        if let Some((local_center, eye)) = self.sort_origin {
+            if sort_back_to_front(&mut self.scratch[self.opaque_slots as usize..], local_center, eye) {
+                self.forget_transparent_positions();
+            }
         }
-        queue.write_buffer(&self.gpu_buffer, 0, bytemuck::cast_slice(&contiguous_instance_buffer));
+        self.occupied_slots = self.scratch.len() as u64;
         self.changed = false;
+        self.dirty = None;
+        0..self.scratch.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a mesh stretched to twice its height: the normal of a slope has to stay at a right angle to
+    // the (stretched) slope. Transforming it with the instance matrix leans it towards the
+    // stretched axis, with the normal matrix it doesn't
+    #[test]
+    fn normal_matrix_keeps_normals_upright() {
+        let mut instance = Instance::new(Rc::new(0));
+        instance.rotation = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+        instance.scale = Vector3::new(1.0, 2.0, 1.0);
+        let world = instance.world_matrix();
+        let normal = Vector3::new(1.0, 1.0, 0.0).normalize();
+        let stretched_slope = world.transform_vector(Vector3::new(1.0, -1.0, 0.0)).normalize();
+
+        let corrected = (instance.normal_matrix() * normal).normalize();
+        assert!(corrected.dot(stretched_slope).abs() <= 1e-5, "{:?} is not at a right angle to the slope", corrected);
+        let uncorrected = world.transform_vector(normal).normalize();
+        assert!(uncorrected.dot(stretched_slope).abs() >= 0.1);
+    }
+
+    #[test]
+    fn grid_snap_rounds_to_the_closest_line() {
+        let position = Vector3::new(0.3, -0.62, 1.125);
+        let snap = GridSnap { enabled: true, step: 0.25 };
+        assert_eq!(snap.snap(position), Vector3::new(0.25, -0.5, 1.25));
+        assert_eq!(GridSnap { enabled: false, ..snap }.snap(position), position);
+        assert_eq!(GridSnap { step: 0.0, ..snap }.snap(position), position);
+    }
+
+    // instances in front of an eye at the origin, told apart by their red. The center of the
+    // mesh is a unit in front of the instance, so the one at -4 that is scaled by 3 has its center
+    // at -7, behind the one at -5.5. The two at -10 are as far away and keep their order
+    #[test]
+    fn transparent_instances_are_sorted_back_to_front() {
+        let raw = |id: f32, z: f32, scale: f32| {
+            let mut instance = Instance::new(Rc::new(0));
+            instance.rotation = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+            instance.position = Vector3::new(0.0, 0.0, z);
+            instance.scale = Vector3::new(scale, scale, scale);
+            instance.color = Vector4::new(id, 0.0, 0.0, 0.5);
+            instance.compute_instance_matrix()
+        };
+        let mut instances = [raw(0.0, -1.0, 1.0), raw(1.0, -10.0, 1.0), raw(2.0, -4.0, 3.0), raw(3.0, -5.5, 1.0), raw(4.0, -10.0, 1.0)];
+        let (center, eye) = (Point3::new(0.0, 0.0, -1.0), Point3::new(0.0, 0.0, 0.0));
+        assert!(sort_back_to_front(&mut instances, center, eye));
+        assert_eq!(instances.map(|instance| instance.color[0]), [1.0, 4.0, 2.0, 3.0, 0.0]);
+        assert!(!sort_back_to_front(&mut instances, center, eye), "sorted instances were sorted again");
     }
 }
@@ -0,0 +1,342 @@
+// Lights for the lit shaders. All lights of the scene are kept in one storage buffer that the
+// fragment shader loops over, together with a small uniform that holds the number of lights in
+// use. The buffer is allocated for the maximum number of lights once, so adding and removing
+// lights only needs a write of the data and never a new bind group.
+//
+// The lit mesh renderer only knows a single directional light, which is a plain uniform of its
+// own (`LightUniform`).
+use cgmath::{InnerSpace, Point3, Vector3};
+use std::mem;
+
+use crate::stats::RenderStats;
+
+/// the number of lights the app reserves space for
+pub const DEFAULT_MAX_LIGHTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightKind {
+    /// light that comes from a position and gets weaker with the distance, up to `range`
+    Point,
+    /// light that comes from very far away (like the sun), it only has a direction
+    Directional,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// only used by point lights
+    pub position: Point3<f32>,
+    /// the direction the light travels in, only used by directional lights
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// the distance after which a point light has no effect anymore
+    pub range: f32,
+}
+
+impl Light {
+    fn to_raw(self) -> RawLight {
+        RawLight {
+            position: [self.position.x, self.position.y, self.position.z, 1.0],
+            direction: [self.direction.x, self.direction.y, self.direction.z, 0.0],
+            color: self.color,
+            intensity: self.intensity,
+            kind: match self.kind {
+                LightKind::Point => 0,
+                LightKind::Directional => 1,
+            },
+            range: self.range,
+            _padding: [0; 2],
+        }
+    }
+}
+
+// The layout of a light in the storage buffer. The vec3 color is followed by the intensity so
+// that it fills the 16 bytes the vec3 is aligned to, and the struct is padded to 64 bytes as
+// arrays in storage buffers use the alignment of the largest member (vec4, 16 bytes)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawLight {
+    position: [f32; 4],
+    direction: [f32; 4],
+    color: [f32; 3],
+    intensity: f32,
+    kind: u32,
+    range: f32,
+    _padding: [u32; 2],
+}
+
+// uniforms need to be at least 16 bytes
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawLightCount {
+    count: u32,
+    _padding: [u32; 3],
+}
+
+pub struct LightSet {
+    lights: Vec<Light>,
+    max_lights: usize,
+    pub gpu_buffer: wgpu::Buffer,
+    pub count_buffer: wgpu::Buffer,
+    // the lights are read by shader.wgsl at group 2, there is no pipeline that uses it yet
+    #[allow(dead_code)]
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    #[allow(dead_code)]
+    pub bind_group: wgpu::BindGroup,
+    changed: bool,
+}
+
+impl LightSet {
+    /// Create an empty light set with room for `max_lights` lights. The number is reduced if the
+    /// storage buffer would be larger than the device allows. Devices without storage buffers in
+    /// the fragment shader (WebGL) can't have a light set.
+    pub fn new(device: &wgpu::Device, limits: &wgpu::Limits, max_lights: usize) -> anyhow::Result<Self> {
+        if limits.max_storage_buffers_per_shader_stage == 0 {
+            anyhow::bail!("the device has no storage buffers, the lights can't be uploaded");
+        }
+        let fitting = limits.max_storage_buffer_binding_size as usize / mem::size_of::<RawLight>();
+        if fitting < max_lights {
+            log::warn!("only {} of {} lights fit into a storage buffer", fitting, max_lights);
+        }
+        let max_lights = max_lights.min(fitting).max(1);
+
+        let gpu_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light storage buffer"),
+            size: (max_lights * mem::size_of::<RawLight>()) as wgpu::BufferAddress,
+            // COPY_SRC so the golden checks can read the lights back
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light count uniform buffer"),
+            size: mem::size_of::<RawLightCount>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&Self::describe());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: gpu_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: count_buffer.as_entire_binding() },
+            ],
+        });
+        Ok(Self {
+            lights: Vec::new(),
+            max_lights,
+            gpu_buffer,
+            count_buffer,
+            bind_group_layout,
+            bind_group,
+            // the count buffer starts out uninitialized
+            changed: true,
+        })
+    }
+
+    pub fn describe() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Light bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        }
+    }
+
+    fn add(&mut self, light: Light) -> anyhow::Result<usize> {
+        if self.lights.len() >= self.max_lights {
+            anyhow::bail!("there are already {} lights, which is the maximum", self.max_lights);
+        }
+        self.lights.push(light);
+        self.changed = true;
+        Ok(self.lights.len() - 1)
+    }
+
+    /// add a point light and return its index
+    pub fn add_point_light(&mut self, position: Point3<f32>, color: [f32; 3], intensity: f32, range: f32) -> anyhow::Result<usize> {
+        self.add(Light {
+            kind: LightKind::Point,
+            position,
+            direction: Vector3::unit_y(),
+            color,
+            intensity,
+            range,
+        })
+    }
+
+    /// add a directional light and return its index
+    pub fn add_directional_light(&mut self, direction: Vector3<f32>, color: [f32; 3], intensity: f32) -> anyhow::Result<usize> {
+        self.add(Light {
+            kind: LightKind::Directional,
+            position: Point3::new(0.0, 0.0, 0.0),
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range: f32::INFINITY,
+        })
+    }
+
+    /// remove the light at the index, the lights after it move down by one
+    pub fn remove(&mut self, index: usize) -> Option<Light> {
+        if index >= self.lights.len() {
+            return None;
+        }
+        self.changed = true;
+        Some(self.lights.remove(index))
+    }
+
+    /// write the lights to the GPU if they changed since the last upload
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        if !self.changed {
+            return;
+        }
+        let raw = self.lights.iter().map(|l| l.to_raw()).collect::<Vec<_>>();
+        if !raw.is_empty() {
+            queue.write_buffer(&self.gpu_buffer, 0, bytemuck::cast_slice(&raw));
+            RenderStats::record_buffer_write();
+        }
+        let count = RawLightCount { count: raw.len() as u32, _padding: [0; 3] };
+        queue.write_buffer(&self.count_buffer, 0, bytemuck::cast_slice(&[count]));
+        RenderStats::record_buffer_write();
+        self.changed = false;
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label(format!("Lights ({} of {})", self.lights.len(), self.max_lights));
+        let mut remove = None;
+        for (i, light) in self.lights.iter_mut().enumerate() {
+            let before = *light;
+            ui.collapsing(format!("Light {} ({:?})", i, light.kind), |ui| {
+                match light.kind {
+                    LightKind::Point => {
+                        ui.add(egui::Slider::new(&mut light.position.x, -10.0..=10.0).text("x"));
+                        ui.add(egui::Slider::new(&mut light.position.y, -10.0..=10.0).text("y"));
+                        ui.add(egui::Slider::new(&mut light.position.z, -10.0..=10.0).text("z"));
+                        ui.add(egui::Slider::new(&mut light.range, 0.1..=100.0).text("range"));
+                    }
+                    LightKind::Directional => {
+                        ui.add(egui::Slider::new(&mut light.direction.x, -1.0..=1.0).text("direction x"));
+                        ui.add(egui::Slider::new(&mut light.direction.y, -1.0..=1.0).text("direction y"));
+                        ui.add(egui::Slider::new(&mut light.direction.z, -1.0..=1.0).text("direction z"));
+                    }
+                }
+                ui.color_edit_button_rgb(&mut light.color);
+                ui.add(egui::Slider::new(&mut light.intensity, 0.0..=10.0).text("intensity"));
+                if ui.button("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+            if *light != before {
+                self.changed = true;
+            }
+        }
+        if let Some(i) = remove {
+            self.remove(i);
+        }
+        ui.horizontal(|ui| {
+            let full = self.lights.len() >= self.max_lights;
+            if ui.add_enabled(!full, egui::Button::new("Add point light")).clicked() {
+                let _ = self.add_point_light(Point3::new(0.0, 2.0, 2.0), [1.0, 1.0, 1.0], 1.0, 10.0);
+            }
+            if ui.add_enabled(!full, egui::Button::new("Add directional light")).clicked() {
+                let _ = self.add_directional_light(Vector3::new(0.0, -1.0, 0.0), [1.0, 1.0, 1.0], 1.0);
+            }
+        });
+    }
+}
+
+// the layout of the directional light of the lit renderer in its uniform buffer. The ambient
+// fills up the 16 bytes the vec3 direction is aligned to, the color is padded the same way
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawDirectionalLight {
+    direction: [f32; 3],
+    ambient: f32,
+    color: [f32; 3],
+    _padding: f32,
+}
+
+// The Light struct in lit_shader.wgsl follows the std140 rules of uniforms: a vec3 is aligned to
+// 16 bytes, so the color starts at 16 and not right after the 12 bytes of the direction, and the
+// size of the struct is rounded up to 16. Anything else and the shader reads shifted values, so
+// the layout is checked when the crate is built.
+const _: () = {
+    assert!(mem::offset_of!(RawDirectionalLight, direction) == 0);
+    assert!(mem::offset_of!(RawDirectionalLight, ambient) == 12);
+    assert!(mem::offset_of!(RawDirectionalLight, color) == 16);
+    assert!(mem::size_of::<RawDirectionalLight>() == 32);
+};
+
+/// The single directional light the `LitMeshRenderer` shades with. It is a small uniform that
+/// lives in a bind group of its own, set up the same way as the `CameraUniform`.
+pub struct LightUniform {
+    pub gpu_buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    /// the share of the light color that reaches the surfaces that face away from the light,
+    /// it's written with the next `update`
+    pub ambient: f32,
+}
+
+impl LightUniform {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let gpu_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Directional light uniform buffer"),
+            size: mem::size_of::<RawDirectionalLight>() as wgpu::BufferAddress,
+            // COPY_SRC so the golden checks can read the layout back
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&Self::describe());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Directional light bind group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: gpu_buffer.as_entire_binding() }],
+        });
+        Self { gpu_buffer, bind_group_layout, bind_group, ambient: 0.1 }
+    }
+
+    /// send the light to the GPU, `direction` is the direction the light travels in (it doesn't
+    /// need to be normalized) and `color` is multiplied with the color of the surfaces
+    pub fn update(&mut self, direction: [f32; 3], color: [f32; 3], queue: &wgpu::Queue) {
+        let raw = RawDirectionalLight { direction, ambient: self.ambient, color, _padding: 0.0 };
+        queue.write_buffer(&self.gpu_buffer, 0, bytemuck::cast_slice(&[raw]));
+        RenderStats::record_buffer_write();
+    }
+
+    pub fn describe() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Directional light bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// The limits that are requested when creating the device. wgpu::Limits::default() is not
+// supported everywhere (WebGL2 and older GPUs support less), and requesting more than the
+// adapter supports makes `request_device` fail. So the limits are picked from what the adapter
+// can do and then checked against what our pipelines actually need.
+
+/// The resources that the pipelines of this crate need from the device
+#[derive(Debug, Clone, Copy)]
+pub struct Requirements {
+    /// camera + fog + clip planes, and the per object constants on devices without push
+    /// constants (+ textures for the textured shaders)
+    pub max_bind_groups: u32,
+    /// the vertex uses locations 0-2 and the instance 5-13 (the transform, the color, the
+    /// texture layer and the normal matrix), the locations need to be smaller than the limit
+    pub max_vertex_attributes: u32,
+    /// one buffer for the vertices and one for the instances
+    pub max_vertex_buffers: u32,
+    /// the largest uniform buffer we bind (the camera matrices)
+    pub max_uniform_buffer_binding_size: u32,
+}
+
+impl Default for Requirements {
+    fn default() -> Self {
+        Self {
+            max_bind_groups: 4,
+            max_vertex_attributes: 14,
+            max_vertex_buffers: 2,
+            max_uniform_buffer_binding_size: crate::camera::CameraUniform::SIZE as u32,
+        }
+    }
+}
+
+/// Pick the limits to request from the device. If the adapter supports the default limits
+/// they are used, otherwise we fall back to the WebGL2 limits (with the texture sizes the
+/// adapter supports). Returns an error describing every requirement the adapter can't meet.
+pub fn select_limits(adapter_limits: &wgpu::Limits, requirements: &Requirements) -> anyhow::Result<wgpu::Limits> {
+    let mut limits = if wgpu::Limits::default().check_limits(adapter_limits) {
+        wgpu::Limits::default()
+    } else {
+        log::info!("the adapter does not support the default limits, using the downlevel limits");
+        wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter_limits.clone())
+    };
+
+    let mut failures = Vec::new();
+    let mut require = |name: &str, required: u32, supported: u32, requested: &mut u32| {
+        if required > supported {
+            failures.push(format!("{}: need {}, the adapter supports {}", name, required, supported));
+        } else if required > *requested {
+            *requested = required;
+        }
+    };
+    require("max_bind_groups", requirements.max_bind_groups, adapter_limits.max_bind_groups, &mut limits.max_bind_groups);
+    require("max_vertex_attributes", requirements.max_vertex_attributes, adapter_limits.max_vertex_attributes, &mut limits.max_vertex_attributes);
+    require("max_vertex_buffers", requirements.max_vertex_buffers, adapter_limits.max_vertex_buffers, &mut limits.max_vertex_buffers);
+    require("max_uniform_buffer_binding_size", requirements.max_uniform_buffer_binding_size, adapter_limits.max_uniform_buffer_binding_size, &mut limits.max_uniform_buffer_binding_size);
+
+    if !failures.is_empty() {
+        anyhow::bail!("the graphics hardware can't support the render pipelines:\n  {}", failures.join("\n  "));
+    }
+    Ok(limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instance, model};
+
+    #[test]
+    fn vertex_attributes_fit_the_limit() {
+        let limit = Requirements::default().max_vertex_attributes;
+        let highest = [model::Vertex::desc(), instance::Instance::desc()].iter()
+            .flat_map(|layout| layout.attributes.iter())
+            .map(|attribute| attribute.shader_location)
+            .max()
+            .unwrap_or_default();
+        assert!(highest < limit, "location {} is used but only {} vertex attributes are required", highest, limit);
+    }
+}
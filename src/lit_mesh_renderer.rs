@@ -0,0 +1,211 @@
+/// The renderer that shades meshes with a single directional light (see `light::LightUniform`).
+/// The light is computed for every fragment with the Phong model: ambient, diffuse and a
+/// highlight that moves with the eye. The surfaces have the color of their instances, or the
+/// color of their texture if the renderer is created with the layout of the textures.
+use wgpu::util::DeviceExt;
+use wgpu::RenderPipelineDescriptor;
+
+use crate::{camera, instance, light, model, renderer};
+use crate::stats::RenderStats;
+use std::mem;
+
+impl <'a, 'b, 'c> model::DrawMesh<'a, 'b, 'c> for LitMeshRenderer {
+    fn draw_mesh (
+        render_pass: &'a mut wgpu::RenderPass<'b>,
+        mesh: &'c model::Surface,
+        camera_bind_group: &'c wgpu::BindGroup,
+    ) where 'b: 'a, 'c: 'b {
+        LitMeshRenderer::draw_mesh_instanced(render_pass, mesh, mesh.instance_buffer.all_instances(), camera_bind_group);
+    }
+
+    fn draw_mesh_instanced(
+        render_pass: &'a mut wgpu::RenderPass<'b>,
+        mesh: &'c model::Surface,
+        instances: std::ops::Range<u32>,
+        camera_bind_group: &'c wgpu::BindGroup,
+    ) where 'b: 'a, 'c: 'b {
+        // far away meshes are drawn with a simplified version of them, see `model::Lod`
+        let (indices, base_vertex) = mesh.lod_range();
+        LitMeshRenderer::draw_submesh(render_pass, mesh, indices, base_vertex, instances, camera_bind_group);
+    }
+
+    fn draw_submesh(
+        render_pass: &'a mut wgpu::RenderPass<'b>,
+        mesh: &'c model::Surface,
+        indices: std::ops::Range<u32>,
+        base_vertex: i32,
+        instances: std::ops::Range<u32>,
+        camera_bind_group: &'c wgpu::BindGroup,
+    ) where 'b: 'a, 'c: 'b {
+        let occ_slots = mesh.instance_buffer.occupied_slots;
+        // all instances are hidden (or gone), an empty buffer slice is not allowed
+        if occ_slots == 0 {
+            return;
+        }
+        // only the instances that are in the buffer can be drawn
+        let instances = instances.start.min(occ_slots as u32)..instances.end.min(occ_slots as u32);
+        if instances.is_empty() {
+            return;
+        }
+        // the buffers may be shared with other meshes, the whole buffers are bound and the mesh
+        // is found in them with its first index and base vertex
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.buffer.slice(..));
+        render_pass.set_vertex_buffer(1, mesh.instance_buffer.gpu_buffer.slice(..occ_slots * mem::size_of::<instance::RawInstance>() as u64));
+        render_pass.set_index_buffer(mesh.index_buffer.buffer.slice(..), wgpu::IndexFormat::Uint32);
+        // the light and the eye are the same for all meshes, they are bound once with `bind`.
+        // The texture of the textured pipeline is bound by the caller
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+        let instance_count = instances.len() as u64;
+        // the share of the triangles of the mesh that are in the index range
+        let triangle_count = mesh.triangle_count() * indices.len() as u64 / mesh.num_elements.max(1) as u64;
+        let first_index = mesh.first_index();
+        render_pass.draw_indexed(indices.start + first_index..indices.end + first_index, base_vertex + mesh.base_vertex(), instances);
+        RenderStats::record_draw(triangle_count * instance_count);
+    }
+}
+
+pub struct LitMeshRenderer {
+    pub pipeline: wgpu::RenderPipeline,
+    pub config: renderer::PipelineConfig,
+    // the position of the camera, the highlight depends on where the surface is seen from
+    eye_buffer: wgpu::Buffer,
+    eye_bind_group: wgpu::BindGroup,
+}
+
+impl LitMeshRenderer {
+    pub fn new(
+        // The device on which we create the render pipeline
+        device: &wgpu::Device,
+        // the camera the meshes are seen with, its bind group is set for every mesh
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        // the layout of the directional light the fragment shader reads
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        // with the layout of the textures (`model::Texture::create_layout`) the surfaces get
+        // the color of their texture, which the caller binds at group 3 for every mesh.
+        // Without it they get the color of their instances
+        texture_bind_group_layout: Option<&wgpu::BindGroupLayout>,
+        // the configuration of the texture the scene is rendered to
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        // the number of samples per pixel, needs to match the color and depth targets
+        sample_count: u32,
+    ) -> LitMeshRenderer {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Lit Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/lit_shader.wgsl").into()),
+        });
+        let eye_bind_group_layout = device.create_bind_group_layout(&Self::describe_eye());
+        let eye_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lit eye uniform buffer"),
+            contents: bytemuck::cast_slice(&[[0.0f32; 4]]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let eye_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Lit eye bind group"),
+            layout: &eye_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: eye_buffer.as_entire_binding(),
+            }],
+        });
+
+        // the texture comes last so the groups before it are the same with and without it
+        let mut bind_group_layouts = vec![camera_bind_group_layout, light_bind_group_layout, &eye_bind_group_layout];
+        bind_group_layouts.extend(texture_bind_group_layout);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Layout of the Lit Mesh Renderer"),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        let config = renderer::PipelineConfig {
+            label: if texture_bind_group_layout.is_some() { "Textured Lit Mesh Renderer" } else { "Lit Mesh Renderer" },
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            // not every model has a consistent winding, so both sides are drawn and the shader
+            // turns the normal towards the eye
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            depth_format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            color_format: surface_config.format,
+            blend: Some(wgpu::BlendState::REPLACE),
+            sample_count,
+            bind_group_layout_entries: [
+                camera::CameraUniform::describe().entries.to_vec(),
+                light::LightUniform::describe().entries.to_vec(),
+                Self::describe_eye().entries.to_vec(),
+            ].into_iter()
+                .chain(texture_bind_group_layout.map(|_| model::Texture::desc_layout().entries.to_vec()))
+                .collect(),
+            push_constant_ranges: Vec::new(),
+        };
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(config.label),
+            layout: Some(&layout),
+            // the same vertex and instance layout as the other mesh renderers
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::Vertex::desc(), instance::Instance::desc()],
+            },
+            primitive: config.primitive_state(),
+            depth_stencil: config.depth_stencil_state(),
+            multisample: config.multisample_state(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                // the untextured entry point doesn't touch the texture, so its pipeline doesn't
+                // need the group of the texture
+                entry_point: if texture_bind_group_layout.is_some() { "fs_textured" } else { "fs_main" },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: config.blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        LitMeshRenderer { pipeline, config, eye_buffer, eye_bind_group }
+    }
+
+    fn describe_eye() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Lit eye bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        }
+    }
+
+    /// write the position of the camera that the scene is rendered with
+    pub fn update_eye(&self, camera: &camera::Camera, queue: &wgpu::Queue) {
+        let eye = [camera.position.x, camera.position.y, camera.position.z, 1.0];
+        queue.write_buffer(&self.eye_buffer, 0, bytemuck::cast_slice(&[eye]));
+        RenderStats::record_buffer_write();
+    }
+
+    /// set the pipeline and bind the light and the eye, this is done once before the meshes
+    /// are drawn
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, light: &'a light::LightUniform) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, &light.bind_group, &[]);
+        render_pass.set_bind_group(2, &self.eye_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+        RenderStats::record_bind_group_switch();
+    }
+
+    /// Describe the effective state of the pipeline
+    pub fn describe_config(&self) -> String {
+        self.config.describe()
+    }
+}
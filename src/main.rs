@@ -1,37 +1,82 @@
-use std::time::{Instant, Duration};
+use std::time::Duration;
+// std::time::Instant is not available in the browser, web_time is the same as std on native
+use web_time::Instant;
+use std::collections::HashMap;
 use std::iter;
+use std::sync::Arc;
 
-use cgmath;
 use colored_mesh_renderer::ColoredMeshRenderer;
 use model::DrawMesh;
-use renderer::DescribeRenderPipeline;
+use renderer::{AntiAliasing, DescribeRenderPipeline, RenderMode};
 use winit::{
     event::*,
     event_loop::EventLoop,
     keyboard::PhysicalKey,
     window::{WindowBuilder, Window}, dpi::PhysicalSize, keyboard::KeyCode,
 };
-use egui_winit;
-use egui_wgpu;
-use egui;
 
+mod buffer_arena;
 mod camera;
+mod clock;
+mod config;
 mod model;
 mod renderer;
 mod instance;
+mod clip;
 mod colored_mesh_renderer;
+mod debug_lines;
+#[cfg(not(target_arch = "wasm32"))]
+mod file_watcher;
+mod fxaa_renderer;
+#[cfg(not(target_arch = "wasm32"))]
+mod golden;
+mod hud_renderer;
+#[cfg(not(target_arch = "wasm32"))]
+mod input_recording;
 mod resources;
+mod fog;
+mod light;
+mod lit_mesh_renderer;
+mod matcap_renderer;
+mod limits;
+mod object_constants;
+mod overlay;
+mod point_cloud_renderer;
+mod primitives;
+mod scene;
+mod skybox_renderer;
+mod stats;
+mod tonemap_renderer;
+mod ui;
+mod upload;
+mod wireframe_renderer;
+
+// how often the frame rate in the window title changes, see `App::update_title`
+const TITLE_FPS_INTERVAL: Duration = Duration::from_millis(500);
+// the models of the scene get two simplified versions for when they are far away, see `model::Lod`
+const SCENE_LOAD_OPTIONS: resources::LoadOptions = resources::LoadOptions { validate: true, repair: false, lod_levels: 2 };
 
 
 // We need a place to put the objects/data related to the global state into
+//
+// A note on the order of the fields: when a struct is dropped, Rust drops its fields in the order
+// they are declared (top to bottom), unlike local variables which are dropped in reverse order.
+// The surface is created with `unsafe { create_surface(&window) }`, which means we promised that
+// the window outlives the surface. The window used to be the first field, so it was destroyed
+// before the surface still pointing at it. It is now the last field, so all the GPU resources
+// (including the surface) are released while the window still exists.
+// New fields have to go above the window.
 struct App {
-    window: Window, // The winit Window
     // we need to keep the size here so that we can detect when the screen size
     // changes
     window_size: PhysicalSize<u32>,
-    // the connection of the gpu with the window so that the GPU can draw stuff
+    // the connection of the gpu with the window so that the GPU can draw stuff. A headless app
+    // (see `new_headless`) has no surface, the configuration describes its offscreen target then
     surface_config: wgpu::SurfaceConfiguration,
-    surface: wgpu::Surface, 
+    surface: Option<wgpu::Surface>,
+    // what a headless app renders into instead of the surface, it has the size and the format
+    // of `surface_config`
+    offscreen: Option<model::Texture>,
     // The representation of the GPU driver in our application
     instance: wgpu::Instance,
     // The physical card used for precessing the rendering commands
@@ -39,13 +84,36 @@ struct App {
     adapter: wgpu::Adapter,
     // the logical connection to the GPU
     device: wgpu::Device,
+    // the limits the device was created with, buffers and bind groups have to stay within these
+    limits: wgpu::Limits,
     // The command queue that provides the context for the rendering commands
     // we first create a sequence of commands and then have the GPU driver
     // execute those when we submit the queue to the GPU
-    queue: wgpu::Queue,
+    // The queue is shared with the upload thread
+    queue: Arc<wgpu::Queue>,
+    // writes data into the GPU buffers on a separate thread
+    uploader: upload::Uploader,
+
+    // our render pipelines by name, the wireframe (`renderer::WIREFRAME_PIPELINE`) and the
+    // same with filled triangles for the solid mode (`renderer::SOLID_PIPELINE`)
+    pipelines: renderer::PipelineController,
 
-    // our render pipeline
-    render_pipeline: ColoredMeshRenderer,
+    // shades the meshes with a matcap instead of the colored wireframe in the matcap mode
+    matcap: matcap_renderer::MatcapRenderer,
+    // shades the meshes with the directional light in the lit mode
+    lit: lit_mesh_renderer::LitMeshRenderer,
+    // draws the edges of the meshes with wide lines in the thick line mode
+    wireframe: wireframe_renderer::WireframeRenderer,
+    // the directional light of the lit mode, the direction and the color are edited in the ui
+    // and sent to the uniform when they change
+    light_direction: [f32; 3],
+    light_color: [f32; 3],
+    light_uniform: light::LightUniform,
+    // which of the pipelines draws the meshes, F3 switches to the next mode
+    render_mode: RenderMode,
+    // the side of the triangles the wireframe and the solid mode don't draw, C switches to the
+    // next one. The pipelines of all cull modes are created up front, see `renderer::CULL_MODES`
+    cull_mode: Option<wgpu::Face>,
 
     //camera structs 
     cameras: Vec<camera::Camera>,
@@ -53,42 +121,143 @@ struct App {
     // active camera
     active_camera: usize,
 
+    // when the active camera changes, the view moves over to the new camera in this time
+    // instead of jumping. While the transition runs the camera doesn't take input
+    camera_transition: Option<camera::CameraTransition>,
+    camera_transition_duration: Duration,
+
     // the depth texture for the render to the screen
     depth_texture: model::Texture,
+
+    // the color the scene is cleared to at the start of every frame. With an alpha below 1 the
+    // background of captured frames is (partly) transparent
+    background: wgpu::Color,
+    // distance fog, the settings are edited in the ui and sent to the uniform when they change
+    fog_settings: fog::FogSettings,
+    fog_uniform: fog::FogUniform,
+
+    // the section planes that cut away parts of the scene, handled the same way as the fog
+    clip_settings: clip::ClipSettings,
+    clip_uniform: clip::ClipUniform,
+
+    // the anti aliasing method that is currently used together with the targets it needs.
+    // With MSAA the scene is rendered into the multisampled texture and resolved into the
+    // surface, with FXAA it is rendered into the offscreen target of the FXAA renderer
+    anti_aliasing: AntiAliasing,
+    // the sample counts for MSAA that the adapter supports for our color and depth format
+    msaa_sample_counts: Vec<u32>,
+    msaa_texture: Option<model::Texture>,
+    fxaa: Option<fxaa_renderer::FxaaRenderer>,
+
+    // render the depth of the scene first so the color pass only shades visible fragments
+    depth_prepass: bool,
+
+    // With HDR the scene is rendered into the floating point target of the tone mapping
+    // renderer, so colors brighter than 1.0 survive until the tone mapping pass. The surface is
+    // switched to the HDR format if the surface has one, otherwise the tone mapping compresses
+    // the colors for the sRGB surface.
+    hdr: bool,
+    // the surface format without HDR, and the HDR format if the surface supports one
+    sdr_surface_format: wgpu::TextureFormat,
+    hdr_surface_format: Option<wgpu::TextureFormat>,
+    tonemap: Option<tonemap_renderer::TonemapRenderer>,
+
+    // hands the per object data (the color override) to the shaders, as push constants if the
+    // device has them
+    object_constants: object_constants::ObjectConstantBinding,
+
+    // the lights of the scene, this is None if the device has no storage buffers
+    lights: Option<light::LightSet>,
+
+    // lines for debugging and feedback that are drawn on top of the scene
+    debug_lines: debug_lines::DebugLineRenderer,
+
+    // 2D quads in screen space that are drawn over the scene (but under the ui)
+    hud: hud_renderer::HudRenderer,
+    crosshair: Arc<model::Texture>,
+    show_crosshair: bool,
+    // an object held in front of the camera that is drawn after the scene with its own camera
+    overlay: overlay::OverlayPass,
+    show_overlay: bool,
+    // points drawn as round splats, for now a generated cloud around the scene
+    point_cloud_renderer: point_cloud_renderer::PointCloudRenderer,
+    point_cloud: point_cloud_renderer::PointCloud,
+    point_settings: point_cloud_renderer::PointSettings,
+    show_point_cloud: bool,
+    // the cubemap behind the scene, instead of the background color
+    skybox: skybox_renderer::SkyboxRenderer,
+    show_skybox: bool,
     
     // This is where we store the objects that we want to render
     objects: Vec<model::Object>,
+    // the shared vertex and index buffers the meshes of the objects are loaded into, so the
+    // driver doesn't have to allocate two buffers for every mesh
+    mesh_arena: buffer_arena::MeshArena,
+
+    // decodes the textures of the objects in the background and swaps them in when they're done
+    texture_loader: resources::TextureLoader,
+    // debug builds rebuild the colored mesh pipelines when their shader is saved, None if the
+    // shader can't be watched
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    shader_watcher: Option<file_watcher::FileWatcher>,
+    // the files the objects were loaded from, an object is reloaded when its file is saved.
+    // None if the files can't be watched
+    #[cfg(not(target_arch = "wasm32"))]
+    object_watcher: Option<file_watcher::FileWatcher>,
+
+    // the hierarchy of transforms that places the instances of the objects in the world
+    scene: scene::SceneGraph,
 
-    // this is all the egui stuff we need to have a UI visible
-    ui_context: egui::Context,
-    ui_painter: egui_wgpu::renderer::Renderer,
-    ui_state: egui_winit::State,
-    ui_screen_descriptor: egui_wgpu::renderer::ScreenDescriptor,
+    // positions edited in the ui are rounded to this grid if it is enabled
+    grid_snap: instance::GridSnap,
+
+    // the instance that is currently selected, its bounding box is drawn around it
+    selected: Option<scene::InstanceRef>,
+
+    // if the depth buffer can be copied to the CPU, which `world_position_under_cursor` needs
+    depth_readback: bool,
+    // where the mouse cursor is in the window, in physical pixels, None if it left the window
+    cursor_position: Option<winit::dpi::PhysicalPosition<f64>>,
+    // the point of the scene that was under the cursor when P was pressed the last time
+    picked_position: Option<cgmath::Point3<f32>>,
+
+    // the time source for the camera and the animations
+    clock: clock::Clock,
+
+    // what the GPU was asked to do in the last frame
+    frame_stats: stats::RenderStats,
+    // how long the last frames took, for the frame rate and the graph in the ui
+    frame_times: stats::FrameStats,
+    // show the frame rate in the title of the window as well. The title is only changed every
+    // `TITLE_FPS_INTERVAL`, the number would be unreadable if it changed every frame
+    fps_in_title: bool,
+    window_title: String,
+    title_updated: Instant,
+
+    // this is all the egui stuff we need to have a UI visible, a headless app starts with the
+    // ui hidden
+    ui: ui::UI,
+
+    // The winit Window, this has to stay the last field, see the note above. None for a
+    // headless app
+    window: Option<Window>,
 }
 
 impl App {
-    async fn new(window: Window) -> Self {
+    async fn new(window: Window, app_config: &config::AppConfig) -> anyhow::Result<Self> {
         let window_size = window.inner_size();
         
         // Now that an event loop and a window have been generated/procured from the os
         // we procede to initialize the GPU driver/WGPU
         // First off is the instance, this is the object that represents the environment
         // on the current machine
-        // The pattern of 'descriptor structure' and create call is a typical pattern in
-        // Vulkan
-        let instance_descriptor = wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
-            flags: wgpu::InstanceFlags::all(),
-            gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
-        };
-        let instance = wgpu::Instance::new(instance_descriptor);
+        let instance = Self::create_instance();
 
         // this is the thing we use to render onto. It is created using
         // the window handle we get from winit
         // This is unsafe in the sense that we need to guarantee that the window
         // lives at least as long as the surface
-        let surface = unsafe { instance.create_surface(&window).unwrap() };
+        let surface = unsafe { instance.create_surface(&window)? };
 
         // A single Instance can manage multiple physical adapters (cards)
         // so now we need to describe and then request the graphics card we actually
@@ -100,36 +269,24 @@ impl App {
             force_fallback_adapter: false,
         };
         // wait for the gpu driver to set up everything so that we can talk to the GPU
-        let adapter = instance.request_adapter(
-            &adapter_descriptor)
-            .await.unwrap();
-        // A single physical card can be split into many logical devices. A device is
-        // the thing that performs the work while the queue is where the CPU prepares
-        // the commands in the order in which they shoud be executed. We could have
-        // multiple devices and queues in a single application that even run on the same
-        // physical card but represent different threads of operations, that are indipen
-        // dent of each other
-        let device_descriptor = wgpu::DeviceDescriptor {
-            label: Some("Main Device"), // we don't give this logical thread a name
-            features: wgpu::Features::POLYGON_MODE_LINE | wgpu::Features::POLYGON_MODE_POINT, // we
-            // need the line mode to draw the wireframes
-            limits: wgpu::Limits::default(),
-        };
-        let (device, queue) = adapter.request_device(&device_descriptor, None).await.unwrap();
-        
-        // now we that we have the window and the rendering device we
-        // need to configure the surface so that we can render to it properly
+        let adapter = instance.request_adapter(&adapter_descriptor).await
+            .ok_or_else(|| anyhow::anyhow!("no graphics adapter can draw into the window"))?;
+
+        // now we that we have the window and the graphics card we need to configure the
+        // surface so that we can render to it properly
         // first off, we find out what operations this surface actually supports
         //
         let surface_capabilities = surface.get_capabilities(&adapter);
-        // we want a surface with a srgb format, otherwise we panic
-        let surface_format = surface_capabilities.formats.iter()
-            .copied()
-            .filter(|f| f.is_srgb())
-            .next()
-            .unwrap(); 
-        // we now set up the surface configuration that we want and then configure
-        // the surface
+        // we want a surface with a srgb format, otherwise we give up. We also check if the
+        // surface can show HDR, the HDR format is only used once HDR is switched on (see
+        // `select_surface_format` for the formats that are tried)
+        let surface_format = tonemap_renderer::select_surface_format(&surface_capabilities.formats, false)
+            .ok_or_else(|| anyhow::anyhow!("the surface has no sRGB format"))?;
+        let hdr_surface_format = tonemap_renderer::select_surface_format(&surface_capabilities.formats, true)
+            .filter(|&format| format == tonemap_renderer::HDR_FORMAT);
+        log::info!("surface format {:?}, HDR format {:?}", surface_format, hdr_surface_format);
+        // we now set up the surface configuration that we want, the surface is configured
+        // once there is a device
         // The surface becomes a texture (in the context of wgpu). It is given to a
         // render pass as color attachement which is where the GPU ends up rendering
         // things to.
@@ -142,7 +299,97 @@ impl App {
             alpha_mode: surface_capabilities.alpha_modes[0],
             view_formats: vec![],
         };
-        surface.configure(&device, &config);
+        Self::build(instance, adapter, Some((window, surface)), config, hdr_surface_format, app_config).await
+    }
+
+    /// Set up the app without a window, for tests and for running without a display. It renders
+    /// into an offscreen texture of `width` x `height` pixels instead of a surface (see
+    /// `render` and `read_frame`), and the ui is hidden until `ui.visible` is set. Fails if there is
+    /// no graphics adapter.
+    #[allow(dead_code)]
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        let instance = Self::create_instance();
+        let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        }).await.ok_or_else(|| anyhow::anyhow!("no graphics adapter was found"))?;
+        // the offscreen target is read back as an image, so it needs COPY_SRC and a format
+        // `read_rgba` knows
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+        let mut app = Self::build(instance, adapter, None, config, None, &config::AppConfig::default()).await?;
+        app.ui.visible = false;
+        Ok(app)
+    }
+
+    // The pattern of 'descriptor structure' and create call is a typical pattern in
+    // Vulkan
+    fn create_instance() -> wgpu::Instance {
+        let instance_descriptor = wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
+            flags: wgpu::InstanceFlags::all(),
+            gles_minor_version: wgpu::Gles3MinorVersion::Automatic,
+        };
+        wgpu::Instance::new(instance_descriptor)
+    }
+
+    // everything after the adapter is the same with and without a window, `target` is the
+    // window with its surface or None for a headless app
+    async fn build(
+        instance: wgpu::Instance,
+        adapter: wgpu::Adapter,
+        target: Option<(Window, wgpu::Surface)>,
+        config: wgpu::SurfaceConfiguration,
+        hdr_surface_format: Option<wgpu::TextureFormat>,
+        app_config: &config::AppConfig,
+    ) -> anyhow::Result<Self> {
+        let window_size = PhysicalSize::new(config.width, config.height);
+        let surface_format = config.format;
+        // A single physical card can be split into many logical devices. A device is
+        // the thing that performs the work while the queue is where the CPU prepares
+        // the commands in the order in which they shoud be executed. We could have
+        // multiple devices and queues in a single application that even run on the same
+        // physical card but represent different threads of operations, that are indipen
+        // dent of each other
+        //
+        // The limits decide how many resources (bind groups, vertex attributes, buffer sizes)
+        // we can use. Not every adapter supports the default limits, so we ask the adapter
+        // what it can do and check that it is enough for our pipelines.
+        let mut limits = limits::select_limits(&adapter.limits(), &limits::Requirements::default())?;
+        //
+        // We need the line mode to draw the wireframes, but not every backend has it (WebGL
+        // doesn't), so we only ask for it if the adapter supports it.
+        let mut wanted_features = wgpu::Features::POLYGON_MODE_LINE | wgpu::Features::POLYGON_MODE_POINT;
+        // Push constants are only requested if the adapter has them and they are large enough
+        // for the per object data, the limit has to be requested as well as the default is 0.
+        // Without them the data goes through a uniform buffer, see object_constants.rs
+        if object_constants::push_constants_supported(&adapter) {
+            wanted_features |= wgpu::Features::PUSH_CONSTANTS;
+            limits.max_push_constant_size = object_constants::PUSH_CONSTANT_SIZE;
+        }
+        let device_descriptor = wgpu::DeviceDescriptor {
+            label: Some("Main Device"), // we don't give this logical thread a name
+            features: adapter.features() & wanted_features,
+            limits: limits.clone(),
+        };
+        let (device, queue) = adapter.request_device(&device_descriptor, app_config.device_trace_path()).await?;
+        let queue = Arc::new(queue);
+        let uploader = upload::Uploader::new(queue.clone());
+
+        let (window, surface) = target.unzip();
+        if let Some(surface) = &surface {
+            surface.configure(&device, &config);
+        }
+        let offscreen = surface.is_none().then(|| model::Texture::create_color_target(&device, &config, 1, "offscreen target"));
 
         // now that we have all the necessary resources to draw to a screen, we can start
         // to construct various render pipelines and then use those to render stuff to
@@ -170,9 +417,9 @@ impl App {
         // all kinds of different things and have it rendered. This means that this is simply the
         // rendering part of the app that needs to contain a modeling part as well.
         
-        // so we instaltiate a camera, the camera does not include the buffer in the GPU, that is
-        // the CameraUniform which is separate. We can however write the content to the Camera
-        // Uniform, this allows us to have multiple cameras, but only one buffer on the GPU.
+        // so we instaltiate a camera, every camera owns a CameraUniform with the buffer on the
+        // GPU. The render pass binds the uniform of the active camera, so switching cameras only
+        // has to make sure that this uniform holds the current view.
         let camera = camera::Camera::new(
             (1.0, 0.0, 0.0),
             cgmath::Deg(-20.0),
@@ -186,73 +433,708 @@ impl App {
             &queue
         );
 
+        // a second camera that looks at the scene from further above
+        let overview_camera = camera::Camera::new(
+            (0.0, 6.0, 8.0),
+            cgmath::Deg(-35.0),
+            cgmath::Deg(-90.0),
+            cgmath::Deg(45.0),
+            window_size.width,
+            window_size.height,
+            0.1,
+            100.0,
+            &device,
+            &queue
+        );
+
         // this texture holds the depth information that is used for the z-buffer algorithm.
-        let depth_texture = model::Texture::create_depth_texture(&device, &config, "depth texture");
+        let depth_texture = model::Texture::create_depth_texture(&device, &config, 1, "depth texture");
+
+        // find out which multisample counts we can use. Counts other than 1 and 4 are only
+        // guaranteed to work if the adapter specific format features are enabled. With HDR the
+        // scene is multisampled in the HDR format, so the count has to work for both formats.
+        let color_features = adapter.get_texture_format_features(config.format);
+        let hdr_features = adapter.get_texture_format_features(tonemap_renderer::HDR_FORMAT);
+        let depth_features = adapter.get_texture_format_features(model::Texture::DEPTH_FORMAT);
+        let msaa_sample_counts = [2, 4, 8, 16].into_iter()
+            .filter(|&count| color_features.flags.sample_count_supported(count)
+                && hdr_features.flags.sample_count_supported(count)
+                && depth_features.flags.sample_count_supported(count)
+                && (count == 4 || device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)))
+            .collect::<Vec<_>>();
+        // GL (and WebGL) can't copy depth textures into buffers, there the depth under the
+        // cursor can't be read back
+        let depth_readback = adapter.get_downlevel_capabilities().flags
+            .contains(wgpu::DownlevelFlags::DEPTH_TEXTURE_AND_BUFFER_COPIES);
+
+        // the fog fades into the background so it gets the clear color by default
+        let background = renderer::DEFAULT_CLEAR_COLOR;
+        let fog_settings = fog::FogSettings::new([background.r as f32, background.g as f32, background.b as f32]);
+        let mut fog_uniform = fog::FogUniform::new(&device);
+        fog_uniform.update(&fog_settings, &queue);
+        let clip_settings = clip::ClipSettings::default();
+        let mut clip_uniform = clip::ClipUniform::new(&device);
+        clip_uniform.update(&clip_settings, &queue);
+        let object_constants = object_constants::ObjectConstantBinding::new(&device);
 
-        // now we create the render pipeline and the pipeline controller, the pipeline controller
-        // won't be important right now, but we will use it when we have more than one pipeline.
-        let color_render_pipeline = colored_mesh_renderer::ColoredMeshRenderer::new(
+        // now we create the render pipelines and register them with the pipeline controller,
+        // where the render modes and the objects find them by name. There is a wireframe and a
+        // pipeline with filled triangles for the solid render mode for every cull mode.
+        // run with RUST_LOG=debug to see what the pipelines actually ended up doing
+        let mut pipelines = renderer::PipelineController::new();
+        for cull_mode in renderer::CULL_MODES {
+            for (name, polygon_mode) in [(renderer::WIREFRAME_PIPELINE, wgpu::PolygonMode::Line), (renderer::SOLID_PIPELINE, wgpu::PolygonMode::Fill)] {
+                let pipeline = colored_mesh_renderer::ColoredMeshRenderer::new(
+                    &device,
+                    &camera.uniform.lock().as_ref().unwrap().bind_group_layout,
+                    &fog_uniform.bind_group_layout,
+                    &clip_uniform.bind_group_layout,
+                    &object_constants,
+                    &config,
+                    Some(model::Texture::DEPTH_FORMAT),
+                    1,
+                    wgpu::PrimitiveTopology::TriangleList,
+                    false,
+                    polygon_mode,
+                    cull_mode,
+                );
+                log::debug!("created pipeline: {}", pipeline.describe_config());
+                pipelines.add_pipeline(&renderer::culled_pipeline_name(name, cull_mode), pipeline);
+            }
+        }
+        let matcap = matcap_renderer::MatcapRenderer::new(
             &device,
+            &queue,
             &camera.uniform.lock().as_ref().unwrap().bind_group_layout,
             &config,
             Some(model::Texture::DEPTH_FORMAT),
+            1,
+        )?;
+        log::debug!("created pipeline: {}", matcap.describe_config());
+        // the sun shines from the top front left onto the teapot
+        let light_direction = [0.4, -1.0, -0.6];
+        let light_color = [1.0, 1.0, 1.0];
+        let mut light_uniform = light::LightUniform::new(&device);
+        light_uniform.update(light_direction, light_color, &queue);
+        let lit = lit_mesh_renderer::LitMeshRenderer::new(
+            &device,
+            &camera.uniform.lock().as_ref().unwrap().bind_group_layout,
+            &light_uniform.bind_group_layout,
+            None,
+            &config,
+            Some(model::Texture::DEPTH_FORMAT),
+            1,
+        );
+        log::debug!("created pipeline: {}", lit.describe_config());
+        let wireframe = wireframe_renderer::WireframeRenderer::new(
+            &device,
+            &camera.uniform.lock().as_ref().unwrap().bind_group_layout,
+            &config,
+            Some(model::Texture::DEPTH_FORMAT),
+            1,
+        );
+        // start with a single light above the teapot
+        let lights = match light::LightSet::new(&device, &limits, light::DEFAULT_MAX_LIGHTS) {
+            Ok(mut lights) => {
+                lights.add_point_light(cgmath::Point3::new(2.0, 2.0, 2.0), [1.0, 1.0, 1.0], 1.0, 20.0)?;
+                lights.upload(&queue);
+                Some(lights)
+            }
+            Err(e) => {
+                log::warn!("{}", e);
+                None
+            }
+        };
+        let debug_lines = debug_lines::DebugLineRenderer::new(
+            &device,
+            &camera.uniform.lock().as_ref().unwrap().bind_group_layout,
+            &config,
+            Some(model::Texture::DEPTH_FORMAT),
+            1,
         );
 
+        let point_cloud_renderer = point_cloud_renderer::PointCloudRenderer::new(
+            &device,
+            &camera.uniform.lock().as_ref().unwrap().bind_group_layout,
+            &config,
+            Some(model::Texture::DEPTH_FORMAT),
+            1,
+        );
+        // a shell of points around the scene, colored by the direction from the center
+        let points = primitives::sphere_points(4000, 6.0).into_iter()
+            .map(|p| {
+                use cgmath::{EuclideanSpace, InnerSpace};
+                let color = (p.to_vec().normalize() + cgmath::Vector3::new(1.0, 1.0, 1.0)) * 0.5;
+                point_cloud_renderer::Point::new(p, [color.x, color.y, color.z, 1.0])
+            })
+            .collect::<Vec<_>>();
+        let point_cloud = point_cloud_renderer::PointCloud::new("sphere points".to_string(), &points, &device);
+
+        let skybox = skybox_renderer::SkyboxRenderer::new(
+            &device,
+            skybox_renderer::gradient_sky(&device, &queue)?,
+            &config,
+            Some(model::Texture::DEPTH_FORMAT),
+            1,
+        );
+
+        let hud = hud_renderer::HudRenderer::new(&device, &config);
+        // a small cube held at the lower right of the view, placed relative to the eye
+        let mut held_cube = resources::load_model("cube.obj", &device, &queue).await?;
+        held_cube.name = "held cube".to_string();
+        for mesh in held_cube.meshes.iter_mut() {
+            for instance in mesh.instances.iter_mut() {
+                instance.position = cgmath::Vector3::new(0.3, -0.22, -0.9);
+                instance.rotation = cgmath::Quaternion::from(cgmath::Euler::new(cgmath::Deg(20.0), cgmath::Deg(35.0), cgmath::Deg(0.0)));
+                instance.scale = cgmath::Vector3::new(0.1, 0.1, 0.1);
+                instance.color = cgmath::Vector4::new(1.0, 0.6, 0.1, 1.0);
+                instance.update(&mut mesh.instance_buffer);
+            }
+            mesh.instance_buffer.flush(&device, &queue);
+        }
+        let overlay = overlay::OverlayPass::new(&device, &queue, held_cube, &config, 1, &object_constants);
+        let crosshair = hud_renderer::crosshair_texture(&device, &queue)?;
+
         // now that we have set up our own pipeline, we need to set up the pipeline that draws to
         // to the ui to the screen this is somewhat important as we need the UI to do control the
         // rendering
-        let ui_context = egui::Context::default();
-        let viewport_id = ui_context.viewport_id();
-        let ui_state = egui_winit::State::new(
-            ui_context.clone(),
-            viewport_id,
-            &window,
-            Some(window.scale_factor() as f32),
-            None,
-        );
-        let ui_renderer = egui_wgpu::renderer::Renderer::new(&device, surface_format, Some(model::Texture::DEPTH_FORMAT), 1);
-        let ui_screen_descriptor = egui_wgpu::renderer::ScreenDescriptor{ size_in_pixels: [config.width, config.height], pixels_per_point: 2. };
+        let ui = ui::UI::new(&device, surface_format, config.width, config.height, window.as_ref());
 
-        let initial_object = resources::load_model("teapot.obj", &device, &queue).await.unwrap();
-        App {
+        let mut mesh_arena = buffer_arena::MeshArena::default();
+        let initial_object = resources::load_model_from(&resources::resource_location("teapot.obj"), &device, &queue, None, Some(&mut mesh_arena), SCENE_LOAD_OPTIONS).await?;
+        // the cube has a texture, it appears right away with the placeholder and the texture
+        // follows a few frames later
+        let mut texture_loader = resources::TextureLoader::new(&device, &queue)?;
+        let cube = resources::load_model_in_background("cube.obj", &device, &queue, &mut texture_loader, Some(&mut mesh_arena)).await?;
+
+        // each loaded object gets a node in the scene graph, moving the node moves all the
+        // instances of the object
+        let mut scene = scene::SceneGraph::new();
+        let objects = vec![initial_object, cube];
+        let placements = [cgmath::Matrix4::from_scale(1.0), cgmath::Matrix4::from_translation(cgmath::Vector3::new(4.0, 0.0, 0.0))];
+        for (object_idx, (object, placement)) in objects.iter().zip(placements).enumerate() {
+            let node = scene.add_node(&object.name, placement, None);
+            for (mesh_idx, mesh) in object.meshes.iter().enumerate() {
+                for instance_idx in 0..mesh.instances.len() {
+                    scene.attach_instance(node, scene::InstanceRef { object: object_idx, mesh: mesh_idx, instance: instance_idx }, &objects);
+                }
+            }
+        }
+        let window_title = window.as_ref().map(|window| window.title()).unwrap_or_default();
+        let mut app = App {
             window,
             window_size,
             surface,
+            offscreen,
             instance,
             adapter,
             device,
+            limits,
             queue,
+            uploader,
             depth_texture,
-            render_pipeline: color_render_pipeline,
-            cameras: vec![camera],
-            objects: vec![initial_object],
-            ui_context,
-            ui_painter: ui_renderer,
-            ui_screen_descriptor,
-            ui_state,
+            background,
+            fog_settings,
+            fog_uniform,
+            clip_settings,
+            clip_uniform,
+            anti_aliasing: AntiAliasing::None,
+            msaa_sample_counts,
+            msaa_texture: None,
+            fxaa: None,
+            depth_prepass: false,
+            hdr: false,
+            sdr_surface_format: surface_format,
+            hdr_surface_format,
+            tonemap: None,
+            object_constants,
+            lights,
+            debug_lines,
+            hud,
+            crosshair,
+            show_crosshair: false,
+            overlay,
+            show_overlay: false,
+            point_cloud_renderer,
+            point_cloud,
+            point_settings: point_cloud_renderer::PointSettings::default(),
+            show_point_cloud: false,
+            skybox,
+            show_skybox: false,
+            pipelines,
+            matcap,
+            lit,
+            wireframe,
+            light_direction,
+            light_color,
+            light_uniform,
+            render_mode: RenderMode::default(),
+            cull_mode: None,
+            cameras: vec![camera, overview_camera],
+            objects,
+            mesh_arena,
+            texture_loader,
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            shader_watcher: file_watcher::FileWatcher::new(&[std::path::Path::new(colored_mesh_renderer::SHADER_PATH)])
+                .map_err(|e| log::warn!("the shader is not reloaded when it changes: {}", e))
+                .ok(),
+            #[cfg(not(target_arch = "wasm32"))]
+            object_watcher: None,
+            scene,
+            selected: None,
+            depth_readback,
+            cursor_position: None,
+            picked_position: None,
+            grid_snap: instance::GridSnap::default(),
+            clock: clock::Clock::new(),
+            frame_stats: stats::RenderStats::default(),
+            frame_times: stats::FrameStats::default(),
+            fps_in_title: false,
+            window_title,
+            title_updated: Instant::now(),
+            ui,
             active_camera: 0,
+            camera_transition: None,
+            camera_transition_duration: Duration::from_millis(800),
             surface_config: config,
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        app.watch_object_files();
+        // this rebuilds the pipelines and the targets for the sample count, or warns and stays
+        // without MSAA if the adapter doesn't support the count
+        if app_config.msaa_samples > 1 {
+            app.set_anti_aliasing(AntiAliasing::Msaa(app_config.msaa_samples));
         }
+        Ok(app)
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             println!("Resize occurred: width {}, height {}", new_size.width, new_size.height);
+            // the surface and the depth texture can't be larger than the device allows
+            let max_dimension = self.limits.max_texture_dimension_2d;
+            if new_size.width > max_dimension || new_size.height > max_dimension {
+                log::warn!("the window is larger than the maximum texture size {}, the image is clamped", max_dimension);
+            }
+            let new_size = winit::dpi::PhysicalSize::new(new_size.width.min(max_dimension), new_size.height.min(max_dimension));
             self.surface_config.width  = new_size.width;
             self.surface_config.height = new_size.height;
-            self.surface.configure(&self.device, &self.surface_config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.surface_config);
+            }
+            // all cameras get the new aspect ratio, but only the active one is uploaded (the
+            // others are uploaded when they become active and are updated)
             for camera in self.cameras.iter_mut() {
-                camera.resize(new_size.width, new_size.height);
+                camera.resize_cpu_only(new_size.width, new_size.height);
+            }
+            self.cameras[self.active_camera].update_uniform(&self.queue);
+            self.create_render_targets();
+            self.hud.resize(new_size.width, new_size.height, &self.queue);
+            self.ui.resize(new_size.width, new_size.height);
+        }
+    }
+
+    /// (re)create the depth buffer and the targets the anti aliasing method needs for the current
+    /// size of the surface. The old textures are dropped when they are replaced.
+    fn create_render_targets(&mut self) {
+        let sample_count = self.anti_aliasing.sample_count();
+        if self.surface.is_none() {
+            self.offscreen = Some(model::Texture::create_color_target(&self.device, &self.surface_config, 1, "offscreen target"));
+        }
+        self.depth_texture = model::Texture::create_depth_texture(&self.device, &self.surface_config, sample_count, "depth texture");
+        self.overlay.resize(&self.device, &self.queue, &self.surface_config, sample_count);
+        self.msaa_texture = match self.anti_aliasing {
+            AntiAliasing::Msaa(_) => Some(model::Texture::create_color_target(&self.device, &self.scene_config(), sample_count, "msaa color target")),
+            _ => None,
+        };
+        match (self.anti_aliasing, &mut self.fxaa) {
+            (AntiAliasing::Fxaa, Some(fxaa)) => fxaa.resize(&self.device, &self.surface_config),
+            (AntiAliasing::Fxaa, None) => self.fxaa = Some(fxaa_renderer::FxaaRenderer::new(&self.device, &self.surface_config)),
+            _ => self.fxaa = None,
+        }
+        match (self.hdr, &mut self.tonemap) {
+            (true, Some(tonemap)) => tonemap.resize(&self.device, &self.surface_config),
+            (true, None) => self.tonemap = Some(tonemap_renderer::TonemapRenderer::new(&self.device, &self.surface_config, self.surface_config.format)),
+            (false, _) => self.tonemap = None,
+        }
+    }
+
+    /// the surface configuration the scene pipelines render for, it has the HDR format if HDR
+    /// is on and the surface format otherwise
+    fn scene_config(&self) -> wgpu::SurfaceConfiguration {
+        let format = if self.hdr { tonemap_renderer::HDR_FORMAT } else { self.surface_config.format };
+        wgpu::SurfaceConfiguration { format, ..self.surface_config.clone() }
+    }
+
+    /// Switch HDR rendering on or off. The surface is reconfigured with the HDR format if it has
+    /// one, everything that renders to the surface (the ui, FXAA and the tone mapping) is
+    /// recreated for the new format and the scene pipelines are rebuilt for the HDR target.
+    pub fn set_hdr(&mut self, hdr: bool) {
+        if hdr == self.hdr {
+            return;
+        }
+        self.hdr = hdr;
+        let format = match hdr {
+            true => self.hdr_surface_format.unwrap_or(self.sdr_surface_format),
+            false => self.sdr_surface_format,
+        };
+        if format != self.surface_config.format {
+            self.surface_config.format = format;
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.surface_config);
             }
-            self.depth_texture = model::Texture::create_depth_texture(&self.device, &self.surface_config, "depth texture");
-            self.ui_screen_descriptor = egui_wgpu::renderer::ScreenDescriptor{ size_in_pixels: [new_size.width, new_size.height], pixels_per_point: 2. };
+            self.ui.set_format(&self.device, &self.queue, format);
+            self.fxaa = None;
+            self.hud = hud_renderer::HudRenderer::new(&self.device, &self.surface_config);
         }
+        self.tonemap = None;
+        self.create_scene_pipelines();
+        self.create_render_targets();
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+    /// switch the anti aliasing method. The pipeline is rebuilt if the sample count changes.
+    /// Sample counts that are not supported by the adapter are rejected.
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) {
+        if anti_aliasing == self.anti_aliasing {
+            return;
+        }
+        if let AntiAliasing::Msaa(count) = anti_aliasing {
+            if !self.msaa_sample_counts.contains(&count) {
+                log::warn!("MSAA with {} samples is not supported by the adapter", count);
+                return;
+            }
+        }
+        let rebuild_pipeline = anti_aliasing.sample_count() != self.anti_aliasing.sample_count();
+        self.anti_aliasing = anti_aliasing;
+        if rebuild_pipeline {
+            self.create_scene_pipelines();
+        }
+        self.create_render_targets();
+    }
+
+    /// if the device can draw the meshes in the mode
+    fn render_mode_available(&self, mode: RenderMode) -> bool {
+        match mode {
+            RenderMode::HiddenLines => self.wireframe_pipeline().hidden_line.is_some(),
+            RenderMode::Wireframe | RenderMode::Solid | RenderMode::Matcap | RenderMode::Lit | RenderMode::ThickLines => true,
+        }
+    }
+
+    // the wireframe renderer is always registered, the hidden line mode needs its pipelines
+    fn wireframe_pipeline(&self) -> &ColoredMeshRenderer {
+        self.pipelines.get_as(renderer::WIREFRAME_PIPELINE).expect("the wireframe pipeline is registered in App::new")
+    }
+
+    /// switch to the next render mode the device can draw
+    pub fn cycle_render_mode(&mut self) {
+        let mut mode = self.render_mode.next();
+        while !self.render_mode_available(mode) {
+            mode = mode.next();
+        }
+        log::info!("render mode: {}", mode.name());
+        self.render_mode = mode;
+    }
+
+    /// Pause or resume the clock, the camera and the animations hold still while the frames are
+    /// still drawn (so the ui keeps working). The camera forgets the input it had.
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused {
+            self.clock.pause();
+            self.cameras[self.active_camera].controls.reset();
+        } else {
+            self.clock.resume();
+        }
+        log::info!("clock {}", if paused { "paused" } else { "running" });
+    }
+
+    /// switch to the next cull mode of the scene pipelines
+    pub fn cycle_cull_mode(&mut self) {
+        let index = renderer::CULL_MODES.iter().position(|&mode| mode == self.cull_mode).unwrap_or(0);
+        self.cull_mode = renderer::CULL_MODES[(index + 1) % renderer::CULL_MODES.len()];
+        log::info!("cull mode: {}", renderer::cull_mode_name(self.cull_mode));
+    }
+
+    /// switch the depth prepass on or off, the scene pipelines are rebuilt for it
+    pub fn set_depth_prepass(&mut self, depth_prepass: bool) {
+        if depth_prepass != self.depth_prepass {
+            self.depth_prepass = depth_prepass;
+            self.create_scene_pipelines();
+        }
+    }
+
+    /// (re)create the pipelines that draw into the scene pass for the current sample count,
+    /// depth prepass and HDR setting
+    fn create_scene_pipelines(&mut self) {
+        let sample_count = self.anti_aliasing.sample_count();
+        let scene_config = self.scene_config();
+        for cull_mode in renderer::CULL_MODES {
+            for (name, polygon_mode) in [(renderer::WIREFRAME_PIPELINE, wgpu::PolygonMode::Line), (renderer::SOLID_PIPELINE, wgpu::PolygonMode::Fill)] {
+                let pipeline = ColoredMeshRenderer::new(
+                    &self.device,
+                    &self.cameras[self.active_camera].uniform.lock().unwrap().bind_group_layout,
+                    &self.fog_uniform.bind_group_layout,
+                    &self.clip_uniform.bind_group_layout,
+                    &self.object_constants,
+                    &scene_config,
+                    Some(model::Texture::DEPTH_FORMAT),
+                    sample_count,
+                    wgpu::PrimitiveTopology::TriangleList,
+                    self.depth_prepass,
+                    polygon_mode,
+                    cull_mode,
+                );
+                log::debug!("created pipeline: {}", pipeline.describe_config());
+                self.pipelines.add_pipeline(&renderer::culled_pipeline_name(name, cull_mode), pipeline);
+            }
+        }
+        self.debug_lines = debug_lines::DebugLineRenderer::new(
+            &self.device,
+            &self.cameras[self.active_camera].uniform.lock().unwrap().bind_group_layout,
+            &scene_config,
+            Some(model::Texture::DEPTH_FORMAT),
+            sample_count,
+        );
+        self.point_cloud_renderer = point_cloud_renderer::PointCloudRenderer::new(
+            &self.device,
+            &self.cameras[self.active_camera].uniform.lock().unwrap().bind_group_layout,
+            &scene_config,
+            Some(model::Texture::DEPTH_FORMAT),
+            sample_count,
+        );
+        self.skybox.recreate_pipeline(&self.device, &scene_config, Some(model::Texture::DEPTH_FORMAT), sample_count);
+        self.wireframe.recreate_pipeline(
+            &self.device,
+            &self.cameras[self.active_camera].uniform.lock().unwrap().bind_group_layout,
+            &scene_config,
+            Some(model::Texture::DEPTH_FORMAT),
+            sample_count,
+        );
+        self.matcap.recreate_pipeline(
+            &self.device,
+            &self.cameras[self.active_camera].uniform.lock().unwrap().bind_group_layout,
+            &scene_config,
+            Some(model::Texture::DEPTH_FORMAT),
+            sample_count,
+        );
+        self.lit = lit_mesh_renderer::LitMeshRenderer::new(
+            &self.device,
+            &self.cameras[self.active_camera].uniform.lock().unwrap().bind_group_layout,
+            &self.light_uniform.bind_group_layout,
+            None,
+            &scene_config,
+            Some(model::Texture::DEPTH_FORMAT),
+            sample_count,
+        );
+        self.overlay.recreate_pipeline(&self.device, &scene_config, sample_count, &self.object_constants);
+    }
+
+    /// Load the object again from the file it came from and put the new meshes in place of the
+    /// old ones (see `Object::replace_meshes`). The rest of the object (name, render order, ...)
+    /// is kept. If the file can't be loaded the old object stays as it is.
+    /// This waits for the file to be read and parsed. The textures follow in the background,
+    /// unless `wait_for_textures` is set, then the new meshes are only swapped in with all their
+    /// textures and the object doesn't flicker through the placeholder.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_object(&mut self, index: usize, wait_for_textures: bool) -> anyhow::Result<()> {
+        let object = self.objects.get(index).ok_or_else(|| anyhow::anyhow!("there is no object {}", index))?;
+        let source = object.source.clone().ok_or_else(|| anyhow::anyhow!("{} was not loaded from a file", object.name))?;
+        let loader = (!wait_for_textures).then_some(&mut self.texture_loader);
+        let mut reloaded = pollster::block_on(resources::load_model_from(&source, &self.device, &self.queue, loader, Some(&mut self.mesh_arena), SCENE_LOAD_OPTIONS))?;
+        let object = &mut self.objects[index];
+        let (old_count, new_count) = (object.meshes.len(), reloaded.meshes.len());
+        if !object.replace_meshes(std::mem::take(&mut reloaded.meshes)) {
+            log::warn!("{} has {} meshes instead of {} now, the instances start over", source, new_count, old_count);
+            // the mesh and instance indices into the object mean something else now
+            self.scene.detach_object(index);
+            if self.selected.is_some_and(|selected| selected.object == index) {
+                self.selected = None;
+            }
+        }
+        log::info!("reloaded {} from {}", object.name, source);
+        Ok(())
+    }
+
+    /// Watch the files of all objects that were loaded from a file, see `reload_changed_objects`.
+    /// This has to be called again when objects are added.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch_object_files(&mut self) {
+        let files = self.objects.iter()
+            .filter_map(|object| object.source.as_ref()?.file_path())
+            .collect::<Vec<_>>();
+        let files = files.iter().map(|file| file.as_path()).collect::<Vec<_>>();
+        self.object_watcher = file_watcher::FileWatcher::new(&files)
+            .map_err(|e| log::warn!("the objects are not reloaded when their files change: {}", e))
+            .ok();
+    }
+
+    /// Reload the objects whose files were saved since the last call. The old meshes are drawn
+    /// until the new ones are loaded completely, a file that can't be loaded (like one that is
+    /// saved in the middle of an edit) keeps the old object.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_changed_objects(&mut self) {
+        let Some(watcher) = &self.object_watcher else { return };
+        let changed = watcher.changed();
+        if changed.is_empty() {
+            return;
+        }
+        for index in 0..self.objects.len() {
+            // the watcher reports the files with their absolute path
+            let Some(path) = self.objects[index].source.as_ref().and_then(|source| source.file_path()?.canonicalize().ok()) else {
+                continue;
+            };
+            if changed.contains(&path) {
+                if let Err(e) = self.reload_object(index, true) {
+                    log::warn!("could not reload {:?}, keeping the old object: {}", path, e);
+                }
+            }
+        }
+    }
+
+    /// Read the shader of the colored mesh pipelines from the source tree again and rebuild the
+    /// pipelines with it. A pipeline whose shader doesn't compile keeps the old one.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    fn reload_shaders(&mut self) {
+        let scene_config = self.scene_config();
+        for cull_mode in renderer::CULL_MODES {
+            for name in [renderer::WIREFRAME_PIPELINE, renderer::SOLID_PIPELINE] {
+                let name = renderer::culled_pipeline_name(name, cull_mode);
+                let Some(pipeline) = self.pipelines.get_as_mut::<ColoredMeshRenderer>(&name) else { continue };
+                match pipeline.reload_shader(&self.device, &self.object_constants, &scene_config, Some(model::Texture::DEPTH_FORMAT)) {
+                    Ok(()) => log::info!("reloaded the shader of {}", name),
+                    Err(e) => {
+                        // all pipelines share the shader, the others would fail the same way
+                        log::error!("{}", e);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Render a frame into an offscreen texture and save it as a PNG file. The frame is the same
+    /// as the one on the screen, with the ui, but it is not presented. This waits for the GPU to
+    /// finish the frame.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture_frame(&mut self, path: &std::path::Path) -> anyhow::Result<()> {
+        // the target has the size and format of the surface, so all the pipelines that draw
+        // into the surface can draw into it
+        let target = model::Texture::create_color_target(&self.device, &self.surface_config, 1, "frame capture target");
+        self.render_to(&target.view);
+        let image = target.read_rgba(&self.device, &self.queue)?;
+        image.save(path).map_err(|e| anyhow::anyhow!("could not write {:?}: {}", path, e))?;
+        log::info!("saved the frame to {:?}", path);
+        Ok(())
+    }
+
+    /// The bounding box of the selected instance in world coordinates. This is the box of the
+    /// mesh transformed with the current transform of the instance, so it follows the instance
+    /// when it moves.
+    fn selected_world_aabb(&self) -> Option<model::Aabb> {
+        let selected = self.selected?;
+        let mesh = self.objects.get(selected.object)?.meshes.get(selected.mesh)?;
+        let instance = mesh.instances.get(selected.instance)?;
+        Some(mesh.aabb.transform(&instance.world_matrix()))
+    }
+
+    /// The bounding box of all instances of all objects in world coordinates, None for an
+    /// empty scene
+    fn scene_world_aabb(&self) -> Option<model::Aabb> {
+        let boxes = self.objects.iter()
+            .flat_map(|object| object.meshes.iter())
+            .flat_map(|mesh| mesh.instances.iter().map(|instance| mesh.aabb.transform(&instance.world_matrix())))
+            .collect::<Vec<_>>();
+        (!boxes.is_empty()).then(|| model::Aabb::from_points(boxes.iter().flat_map(|aabb| [aabb.min, aabb.max])))
+    }
+
+    /// Move the active camera back until the selected instance fills the view, or the whole
+    /// scene if nothing is selected
+    pub fn frame_selection(&mut self) {
+        let Some(aabb) = self.selected_world_aabb().or_else(|| self.scene_world_aabb()) else {
+            return;
+        };
+        let camera = &mut self.cameras[self.active_camera];
+        camera.frame_object(aabb.min, aabb.max);
+        camera.update_uniform(&self.queue);
+    }
+
+    /// The point of the scene under the mouse cursor in world coordinates, found from the value
+    /// in the depth buffer of the last frame. None if the cursor is not in the window or over
+    /// the background. With MSAA or on devices that can't copy depth textures (GL) the depth
+    /// can't be read back, there it is None as well.
+    /// This waits for the GPU, so it's meant for clicks and key presses, not for every frame.
+    pub fn world_position_under_cursor(&self) -> Option<cgmath::Point3<f32>> {
+        let cursor = self.cursor_position?;
+        if !self.depth_readback {
+            log::warn!("the device can't read the depth buffer back");
+            return None;
+        }
+        if cursor.x < 0.0 || cursor.y < 0.0 {
+            return None;
+        }
+        let (x, y) = (cursor.x as u32, cursor.y as u32);
+        let depth = match self.depth_texture.read_depth_at(&self.device, &self.queue, x, y) {
+            Ok(depth) => depth,
+            Err(e) => {
+                log::warn!("could not read the depth under the cursor: {}", e);
+                return None;
+            }
+        };
+        // the depth buffer is cleared to the far plane, nothing was drawn there
+        if depth >= 1.0 {
+            return None;
+        }
+        // the center of the pixel in normalized device coordinates, y points up there
+        let (width, height) = (self.depth_texture.size.width as f32, self.depth_texture.size.height as f32);
+        let ndc = cgmath::Point3::new(
+            (x as f32 + 0.5) / width * 2.0 - 1.0,
+            1.0 - (y as f32 + 0.5) / height * 2.0,
+            depth,
+        );
+        self.cameras[self.active_camera].unproject(ndc)
+    }
+
+    /// make another camera the active one, the view moves over from the current camera
+    pub fn set_active_camera(&mut self, index: usize) {
+        if index == self.active_camera || index >= self.cameras.len() {
+            return;
+        }
+        let previous = &self.cameras[self.active_camera];
+        self.camera_transition = if self.camera_transition_duration.is_zero() {
+            None
+        } else {
+            Some(camera::CameraTransition::new(previous, self.camera_transition_duration))
+        };
+        self.active_camera = index;
+        // the render pass binds the uniform of the active camera, which was not written while
+        // the camera was inactive (a resize only uploads the active camera for example). So it
+        // gets the first view of the transition or the view of the camera right away
+        let camera = &self.cameras[self.active_camera];
+        match &mut self.camera_transition {
+            Some(transition) => { transition.step(Duration::ZERO, camera, &self.queue); }
+            None => camera.update_uniform(&self.queue),
+        }
+    }
+
+    /// add a camera with the current view of the active camera and switch to it, returns its index
+    pub fn add_camera_at_view(&mut self) -> usize {
+        let camera = self.cameras[self.active_camera].duplicate(&self.device, &self.queue);
+        self.cameras.push(camera);
+        let index = self.cameras.len() - 1;
+        self.set_active_camera(index);
+        index
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // a headless app draws into its offscreen texture, which is read with `read_frame`
+        let Some(surface) = &self.surface else {
+            let offscreen = self.offscreen.take().expect("a headless app has an offscreen target");
+            self.render_to(&offscreen.view);
+            self.offscreen = Some(offscreen);
+            return Ok(());
+        };
         // This is the texture we are going to render the output to. We get the texture from the
         // surface meaning it will be a texture that is part of the swapchain.
-        let output = match self.surface.get_current_texture() {
+        let output = match surface.get_current_texture() {
             Ok(frame) => frame,
             Err(wgpu::SurfaceError::Outdated) => {
                 // This error occurs when the app is minimized on Windows.
@@ -274,17 +1156,67 @@ impl App {
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+        self.render_to(&view);
+        output.present();
+        Ok(())
+    }
 
-        // this collects all the operations we want the GPU to perform. It is sent as a batch to
-        // the GPU to be processed
-        let depth_texture_view = &mut self.depth_texture.view;
-        let camera_uniform = self.cameras[self.active_camera].uniform.lock().unwrap();
-        let color_attachment = [ColoredMeshRenderer::describe_color_attachment(Some(&view))];
-        let depth_stencil_attachment = ColoredMeshRenderer::describe_depth_stencil(Some(depth_texture_view));
+    /// The last frame a headless app rendered, this waits for the GPU. An app with a window
+    /// shows its frames on the screen, use `capture_frame` for those.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_frame(&self) -> anyhow::Result<image::RgbaImage> {
+        let offscreen = self.offscreen.as_ref().ok_or_else(|| anyhow::anyhow!("the app renders to a window, not to a texture"))?;
+        offscreen.read_rgba(&self.device, &self.queue)
+    }
 
+    /// Draw a frame (the scene, the post processing and the ui) into `view`, which needs the
+    /// size and the format of the surface. This is the surface texture for the frames on the
+    /// screen and an offscreen texture for `capture_frame`.
+    fn render_to(&mut self, view: &wgpu::TextureView) {
         // process the ui specific things before starting with the render pass
-        let ui_input = self.ui_state.take_egui_input(&self.window);
-        let ui_output = self.ui_context.run(ui_input, |ctx| {
+        let mut anti_aliasing = self.anti_aliasing;
+        let mut depth_prepass = self.depth_prepass;
+        let mut render_mode = self.render_mode;
+        let mut cull_mode = self.cull_mode;
+        let available_modes = RenderMode::ALL.into_iter().filter(|&mode| self.render_mode_available(mode)).collect::<Vec<_>>();
+        let mut hdr = self.hdr;
+        let mut show_crosshair = self.show_crosshair;
+        let mut show_overlay = self.show_overlay;
+        let mut show_point_cloud = self.show_point_cloud;
+        let mut show_skybox = self.show_skybox;
+        let mut point_settings = self.point_settings;
+        let point_cloud_label = format!("Point cloud: {} ({} points)", self.point_cloud.name, self.point_cloud.point_count());
+        let mut matcap_choice = None;
+        let matcap_name = self.matcap.matcap_name.clone();
+        let mut light_direction = self.light_direction;
+        let mut line_width = self.wireframe.line_width();
+        let mut light_color = self.light_color;
+        let hdr_output = self.hdr_surface_format.is_some();
+        let mut background = [self.background.r, self.background.g, self.background.b, self.background.a].map(|c| c as f32);
+        let mut fog_settings = self.fog_settings;
+        let mut clip_settings = self.clip_settings;
+        let msaa_sample_counts = &self.msaa_sample_counts;
+        let pending_uploads = self.uploader.pending();
+        let pending_textures = self.texture_loader.pending();
+        let object_data_path = if self.object_constants.uses_push_constants() { "push constants" } else { "uniform buffer" };
+        let frame_stats = self.frame_stats;
+        let frame_times = &self.frame_times;
+        let mut fps_in_title = self.fps_in_title;
+        let lights = &mut self.lights;
+        let mut next_camera = None;
+        let mut add_camera = false;
+        let picked_position = self.picked_position;
+        let camera_count = self.cameras.len();
+        let active_camera = self.active_camera;
+        let mut transition_seconds = self.camera_transition_duration.as_secs_f32();
+        let mut min_height = self.cameras[self.active_camera].min_height;
+        let camera = &mut self.cameras[self.active_camera];
+        let mut camera_changed = false;
+        let clock = &mut self.clock;
+        let mut selected = self.selected;
+        let grid_snap = &mut self.grid_snap;
+        let pipeline_names = self.pipelines.names();
+        let ui_output = self.ui.generate_ui(self.window.as_ref(), |ctx| {
             egui::Window::new("Color Controls").show(&ctx, |ui| {
                 ui.label("Hello world!");
                 if ui.button("Change Color").clicked() {
@@ -302,58 +1234,594 @@ impl App {
                         }
                     }
                 }
-                for object in self.objects.iter_mut() {
+                grid_snap.build_ui(ui);
+                for (i, object) in self.objects.iter_mut().enumerate() {
+                    ui.add(egui::DragValue::new(&mut object.render_order).prefix(format!("{} render order ", object.name)));
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut object.auto_rotate.enabled, "auto rotate");
+                        // picking an axis starts the turntable about it
+                        for (label, axis) in [("x", cgmath::Vector3::unit_x()), ("y", cgmath::Vector3::unit_y()), ("z", cgmath::Vector3::unit_z())] {
+                            if ui.selectable_label(object.auto_rotate.enabled && object.auto_rotate.axis == axis, label).clicked() {
+                                object.set_auto_rotate(axis, object.auto_rotate.radians_per_sec);
+                            }
+                        }
+                        ui.add_enabled(object.auto_rotate.enabled,
+                            egui::Slider::new(&mut object.auto_rotate.radians_per_sec, -3.0..=3.0).text("rad/s"));
+                    });
+                    egui::ComboBox::new(("object pipeline", i), format!("{} pipeline", object.name))
+                        .selected_text(object.pipeline.as_deref().unwrap_or("render mode"))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut object.pipeline, None, "render mode");
+                            for name in pipeline_names.iter() {
+                                ui.selectable_value(&mut object.pipeline, Some(name.clone()), name.as_str());
+                            }
+                        });
                     for mesh in object.meshes.iter_mut() {
-                        mesh.build_ui(ui);
-                        mesh.instance_buffer.flush(&self.device, &self.queue)
+                        mesh.build_ui(ui, grid_snap);
+                        mesh.instance_buffer.flush_async(&self.device, &self.uploader)
+                    }
+                }
+            });
+            egui::Window::new("Selection").show(ctx, |ui| {
+                // the selection may point at something that was removed, like the other checks
+                // of the selection (`selected_world_aabb`) this doesn't index blindly
+                let name = |s: &scene::InstanceRef| match self.objects.get(s.object).and_then(|o| Some((o, o.meshes.get(s.mesh)?))) {
+                    Some((object, mesh)) => format!("{} / {} / {}", object.name, mesh.name, s.instance),
+                    None => "missing".to_string(),
+                };
+                egui::ComboBox::from_label("Selected instance")
+                    .selected_text(selected.as_ref().map(name).unwrap_or("None".to_string()))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut selected, None, "None");
+                        for (object_idx, object) in self.objects.iter().enumerate() {
+                            for (mesh_idx, mesh) in object.meshes.iter().enumerate() {
+                                for instance_idx in 0..mesh.instances.len() {
+                                    let s = scene::InstanceRef { object: object_idx, mesh: mesh_idx, instance: instance_idx };
+                                    ui.selectable_value(&mut selected, Some(s), name(&s));
+                                }
+                            }
+                        }
+                    });
+                // the inspector for the metadata of the selection
+                if let Some((s, object)) = selected.and_then(|s| Some((s, self.objects.get(s.object)?))) {
+                    ui.separator();
+                    metadata_grid(ui, "Object", &object.metadata);
+                    if let Some(instance) = object.meshes.get(s.mesh).and_then(|mesh| mesh.instances.get(s.instance)) {
+                        metadata_grid(ui, "Instance", &instance.metadata);
                     }
                 }
             });
+            egui::Window::new("Camera").show(ctx, |ui| {
+                camera_changed = camera.build_ui(ui);
+            });
+            egui::Window::new("Render Settings").show(&ctx, |ui| {
+                egui::ComboBox::from_label("Anti aliasing")
+                    .selected_text(anti_aliasing.name())
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut anti_aliasing, AntiAliasing::None, AntiAliasing::None.name());
+                        for &count in msaa_sample_counts.iter() {
+                            let msaa = AntiAliasing::Msaa(count);
+                            ui.selectable_value(&mut anti_aliasing, msaa, msaa.name());
+                        }
+                        ui.selectable_value(&mut anti_aliasing, AntiAliasing::Fxaa, AntiAliasing::Fxaa.name());
+                    });
+                ui.checkbox(&mut depth_prepass, "Depth prepass");
+                egui::ComboBox::from_label("Render mode (F3)")
+                    .selected_text(render_mode.name())
+                    .show_ui(ui, |ui| {
+                        for &mode in available_modes.iter() {
+                            ui.selectable_value(&mut render_mode, mode, mode.name());
+                        }
+                    });
+                // the matcap and the lit mode draw both sides, the culling is for the wireframe
+                // and the solid mode
+                egui::ComboBox::from_label("Culling (C)")
+                    .selected_text(renderer::cull_mode_name(cull_mode))
+                    .show_ui(ui, |ui| {
+                        for mode in renderer::CULL_MODES {
+                            ui.selectable_value(&mut cull_mode, mode, renderer::cull_mode_name(mode));
+                        }
+                    });
+                ui.checkbox(&mut show_crosshair, "Crosshair");
+                ui.checkbox(&mut show_overlay, "Held object");
+                ui.checkbox(&mut show_skybox, "Skybox");
+                ui.checkbox(&mut show_point_cloud, point_cloud_label);
+                if show_point_cloud {
+                    point_settings.build_ui(ui);
+                }
+                if render_mode == RenderMode::Matcap {
+                    egui::ComboBox::from_label("Matcap")
+                        .selected_text(matcap_name.as_str())
+                        .show_ui(ui, |ui| {
+                            for matcap in matcap_renderer::BuiltinMatcap::ALL {
+                                if ui.selectable_label(matcap_name == matcap.name(), matcap.name()).clicked() {
+                                    matcap_choice = Some(matcap);
+                                }
+                            }
+                        });
+                }
+                if render_mode == RenderMode::ThickLines {
+                    ui.add(egui::Slider::new(&mut line_width, 1.0..=16.0).text("line width (px)"));
+                }
+                if render_mode == RenderMode::Lit {
+                    // the direction the light travels in, it is normalized in the shader
+                    ui.horizontal(|ui| {
+                        ui.label("Light direction");
+                        for component in light_direction.iter_mut() {
+                            ui.add(egui::DragValue::new(component).speed(0.01).clamp_range(-1.0..=1.0));
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Light color");
+                        ui.color_edit_button_rgb(&mut light_color);
+                    });
+                }
+                ui.checkbox(&mut hdr, "HDR")
+                    .on_hover_text(if hdr_output { "the display gets the HDR colors" } else { "the display has no HDR format, the colors are tone mapped to sRGB" });
+                ui.horizontal(|ui| {
+                    ui.label("Background");
+                    ui.color_edit_button_rgba_unmultiplied(&mut background);
+                });
+                ui.separator();
+                fog_settings.build_ui(ui);
+                ui.separator();
+                clip_settings.build_ui(ui);
+                ui.separator();
+                match lights {
+                    Some(lights) => lights.build_ui(ui),
+                    None => { ui.label("Lights are not supported on this device"); }
+                }
+                ui.separator();
+                clock.build_ui(ui);
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let mut chosen = active_camera;
+                    egui::ComboBox::from_label("active camera")
+                        .selected_text(format!("Camera {}", active_camera + 1))
+                        .show_ui(ui, |ui| {
+                            for index in 0..camera_count {
+                                ui.selectable_value(&mut chosen, index, format!("Camera {}", index + 1));
+                            }
+                        });
+                    if chosen != active_camera {
+                        next_camera = Some(chosen);
+                    }
+                    if ui.button("Next camera").clicked() {
+                        next_camera = Some((active_camera + 1) % camera_count);
+                    }
+                });
+                if ui.button("New camera at this view").clicked() {
+                    add_camera = true;
+                }
+                ui.add(egui::Slider::new(&mut transition_seconds, 0.0..=3.0).text("camera transition (s)"));
+                let mut ground_clamp = min_height.is_some();
+                ui.checkbox(&mut ground_clamp, "Keep the camera above the ground");
+                min_height = match (ground_clamp, min_height) {
+                    (true, Some(mut height)) => {
+                        ui.add(egui::Slider::new(&mut height, -10.0..=10.0).text("minimal height"));
+                        Some(height)
+                    }
+                    (true, None) => Some(1.0),
+                    (false, _) => None,
+                };
+                ui.label(match picked_position {
+                    Some(p) => format!("Picked with P: ({:.2}, {:.2}, {:.2})", p.x, p.y, p.z),
+                    None => "Picked with P: nothing".to_string(),
+                });
+                ui.separator();
+                ui.label(format!("Uploads in flight: {}", pending_uploads));
+                ui.label(format!("Textures loading: {}", pending_textures));
+                ui.label(format!("Per object data: {}", object_data_path));
+                ui.separator();
+                frame_times.build_ui(ui);
+                ui.checkbox(&mut fps_in_title, "FPS in the window title");
+                // the counts are from the previous frame, the ui is not counted
+                frame_stats.build_ui(ui);
+            });
         });
-        self.ui_state.handle_platform_output(&self.window, ui_output.platform_output);
-        let ui_primitives = self.ui_context.tessellate(ui_output.shapes, ui_output.pixels_per_point);
+        // switching the anti aliasing method has to happen before we pick the targets of the
+        // render pass
+        self.set_anti_aliasing(anti_aliasing);
+        self.set_depth_prepass(depth_prepass);
+        self.fps_in_title = fps_in_title;
+        self.set_hdr(hdr);
+        self.render_mode = render_mode;
+        self.cull_mode = cull_mode;
+        self.wireframe.set_line_width(line_width);
+        self.show_crosshair = show_crosshair;
+        let [r, g, b, a] = background.map(|c| c as f64);
+        self.background = wgpu::Color { r, g, b, a };
+        self.show_overlay = show_overlay;
+        self.show_point_cloud = show_point_cloud;
+        self.show_skybox = show_skybox;
+        self.point_settings = point_settings;
+        if let Some(matcap) = matcap_choice {
+            if let Err(e) = self.matcap.set_builtin(&self.device, &self.queue, matcap) {
+                log::error!("could not create the {} matcap: {}", matcap.name(), e);
+            }
+        }
+        self.camera_transition_duration = Duration::from_secs_f32(transition_seconds);
+        self.cameras[self.active_camera].min_height = min_height;
+        if camera_changed {
+            self.cameras[self.active_camera].update_uniform(&self.queue);
+        }
+        if let Some(index) = next_camera {
+            self.set_active_camera(index);
+        }
+        if add_camera {
+            self.add_camera_at_view();
+        }
+        self.selected = selected;
+        if let Some(lights) = &mut self.lights {
+            lights.upload(&self.queue);
+        }
+        if clip_settings != self.clip_settings {
+            self.clip_settings = clip_settings;
+            self.clip_uniform.update(&self.clip_settings, &self.queue);
+        }
+        if fog_settings != self.fog_settings {
+            self.fog_settings = fog_settings;
+            self.fog_uniform.update(&self.fog_settings, &self.queue);
+        }
+        // a direction of zero length can't be normalized, the light keeps the last one
+        if light_direction == [0.0; 3] {
+            light_direction = self.light_direction;
+        }
+        if light_direction != self.light_direction || light_color != self.light_color {
+            self.light_direction = light_direction;
+            self.light_color = light_color;
+            self.light_uniform.update(self.light_direction, self.light_color, &self.queue);
+        }
 
-        // prepare all the buffers and such
-        for (id, image_delta) in &ui_output.textures_delta.set {
-            self.ui_painter.update_texture(&self.device, &self.queue, *id, &image_delta);
+        // the box around the selected instance is rebuilt every frame so it follows the instance
+        if let Some(aabb) = self.selected_world_aabb() {
+            self.debug_lines.aabb(&aabb, [1.0, 0.8, 0.0, 1.0]);
+        }
+        self.debug_lines.prepare(&self.device, &self.queue);
+        if self.show_point_cloud {
+            self.point_cloud_renderer.prepare(&self.point_settings, self.surface_config.width, self.surface_config.height, &self.queue);
+        }
+        if self.render_mode == RenderMode::ThickLines {
+            self.wireframe.prepare(self.surface_config.width, self.surface_config.height, &self.queue);
+        }
+        if self.show_skybox {
+            self.skybox.prepare(&self.cameras[self.active_camera], &self.queue);
+        }
+        if self.show_crosshair {
+            let center = [self.surface_config.width as f32 / 2.0, self.surface_config.height as f32 / 2.0];
+            self.hud.add_quad(hud_renderer::HudRect::centered(center, [32.0, 32.0]), &self.crosshair, [1.0, 1.0, 1.0, 0.8]);
+        }
+        self.hud.prepare(&self.device, &self.queue);
+        // the transparent instances are blended from back to front, the order depends on where
+        // the camera is so they are sorted again every frame. The level of detail depends on
+        // the distance to the camera as well
+        let eye = self.cameras[self.active_camera].position;
+        for mesh in self.objects.iter_mut().flat_map(|object| object.meshes.iter_mut()) {
+            mesh.select_lod(eye);
+            if !mesh.instance_buffer.transparent_instances().is_empty() {
+                mesh.sort_transparent_instances(eye, &self.queue);
+            }
+        }
+        // the objects are sorted by their render order, the meshes are drawn in this order in
+        // both passes and the index of a mesh selects its constants
+        let draw_order = model::draw_order(&self.objects);
+        let objects = &self.objects;
+        let object_meshes = || draw_order.iter().flat_map(|&i| objects[i].meshes.iter().map(move |mesh| (&objects[i], mesh)));
+        let meshes = || object_meshes().map(|(_, mesh)| mesh);
+        // the meshes of the overlay get the constants after the ones of the scene
+        let scene_mesh_count = meshes().count();
+        let overlay_meshes = self.show_overlay.then_some(self.overlay.object.meshes.iter()).into_iter().flatten();
+        let constants = meshes()
+            .chain(overlay_meshes)
+            .map(|mesh| mesh.object_constants())
+            .collect();
+        self.object_constants.prepare(&self.device, &self.queue, constants);
+        if self.render_mode == RenderMode::Matcap {
+            self.matcap.update_view(&self.cameras[self.active_camera], &self.queue);
         }
+        if self.render_mode == RenderMode::Lit {
+            self.lit.update_eye(&self.cameras[self.active_camera], &self.queue);
+        }
+        // the matcap, the lit and the thick line pipeline have their own vertex shader, so they
+        // can't use the depth of the prepass
+        // (see ColoredMeshRenderer::new), the prepass is skipped while it is on. For the hidden
+        // line removal the depth pass fills the surface that hides the lines behind it.
+        let pipelines = &self.pipelines;
+        let mesh_renderer = pipelines.get(&renderer::culled_pipeline_name(self.render_mode.pipeline_name(), self.cull_mode))
+            .expect("the pipelines of the render modes are registered in App::new");
+        let hidden_line = pipelines.get_as::<ColoredMeshRenderer>(&renderer::culled_pipeline_name(renderer::WIREFRAME_PIPELINE, self.cull_mode))
+            .and_then(|wireframe| wireframe.hidden_line.as_ref());
+        // the transparent instances are left out of the opaque passes and blended at the end,
+        // unless the pipeline of the render mode has no pipeline for them
+        let transparent_pipeline = mesh_renderer.transparent_pipeline();
+        let opaque_instances = |mesh: &model::Surface| match transparent_pipeline {
+            Some(_) => mesh.instance_buffer.opaque_instances(),
+            None => mesh.instance_buffer.all_instances(),
+        };
+        let (prepass_pipeline, scene_pipeline) = match (self.render_mode, hidden_line) {
+            (RenderMode::Matcap | RenderMode::Lit | RenderMode::ThickLines, _) => (None, mesh_renderer.pipeline()),
+            (RenderMode::HiddenLines, Some(hidden_line)) => (Some(&hidden_line.surface_pipeline), &hidden_line.line_pipeline),
+            _ => (mesh_renderer.prepass_pipeline(), mesh_renderer.pipeline()),
+        };
+
+        // this collects all the operations we want the GPU to perform. It is sent as a batch to
+        // the GPU to be processed
+        let depth_texture_view = &self.depth_texture.view;
+        let camera_uniform = self.cameras[self.active_camera].uniform.lock().unwrap();
+        // pick the texture the scene is rendered into depending on the anti aliasing method.
+        // With HDR the scene ends up in the target of the tone mapping, which writes to where the
+        // scene would go without HDR
+        let post_view = match &self.fxaa {
+            Some(fxaa) => &fxaa.target.view,
+            None => view,
+        };
+        let scene_output = match &self.tonemap {
+            Some(tonemap) => &tonemap.target.view,
+            None => post_view,
+        };
+        let (scene_view, resolve_target) = match &self.msaa_texture {
+            Some(msaa_texture) => (&msaa_texture.view, Some(scene_output)),
+            None => (scene_output, None),
+        };
+        let color_attachment = [ColoredMeshRenderer::describe_color_attachment(Some(scene_view), resolve_target, self.background)];
+        // after a prepass the depth buffer holds the depth of the scene, it must not be cleared
+        let scene_depth_load = if prepass_pipeline.is_some() { wgpu::LoadOp::Load } else { renderer::DEPTH_CLEAR };
+        let depth_stencil_attachment = ColoredMeshRenderer::describe_depth_stencil(Some(depth_texture_view), scene_depth_load);
 
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Main render encoder"),
             });
-        self.ui_painter.update_buffers(&self.device, &self.queue, &mut encoder, &ui_primitives, &self.ui_screen_descriptor);
+        let ui_frame = self.ui.prepare(&self.device, &self.queue, &mut encoder, self.window.as_ref(), ui_output);
+        if let Some(prepass_pipeline) = prepass_pipeline {
+            // the depth only pass, there is no color attachment
+            let mut depth_pass = encoder.begin_render_pass(&ColoredMeshRenderer::describe_render_pass(
+                &[],
+                ColoredMeshRenderer::describe_depth_stencil(Some(depth_texture_view), renderer::DEPTH_CLEAR),
+            ));
+            depth_pass.set_pipeline(prepass_pipeline);
+            depth_pass.set_bind_group(1, &self.fog_uniform.bind_group, &[]);
+            depth_pass.set_bind_group(2, &self.clip_uniform.bind_group, &[]);
+            stats::RenderStats::record_bind_group_switch();
+            stats::RenderStats::record_bind_group_switch();
+            // the decals are not in the depth, they are pulled in front of it in the color pass.
+            // Neither are the transparent instances, they must not hide what is behind them
+            for (i, mesh) in meshes().enumerate().filter(|(_, mesh)| !mesh.is_decal()) {
+                self.object_constants.apply(&mut depth_pass, i);
+                ColoredMeshRenderer::draw_mesh_instanced(&mut depth_pass, mesh, opaque_instances(mesh), &camera_uniform.bind_group);
+            }
+        }
         {
             let mut render_pass = encoder.begin_render_pass(&ColoredMeshRenderer::describe_render_pass(&color_attachment, depth_stencil_attachment));
-            render_pass.set_pipeline(&self.render_pipeline.pipeline);
-            for obj in self.objects.iter() {
-                for mesh in obj.meshes.iter() {
-                    ColoredMeshRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group);
+            // the sky goes first, everything else is drawn over it
+            if self.show_skybox {
+                self.skybox.draw(&mut render_pass);
+            }
+            if self.render_mode == RenderMode::Matcap {
+                self.matcap.bind(&mut render_pass);
+                // the matcap renderer has no decal pipelines, decals are drawn like the other
+                // surfaces and can flicker through the surface they are on
+                for mesh in meshes() {
+                    matcap_renderer::MatcapRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group);
+                }
+            } else if self.render_mode == RenderMode::Lit {
+                // like the matcap, the lit renderer draws the decals with the other surfaces
+                self.lit.bind(&mut render_pass, &self.light_uniform);
+                for mesh in meshes() {
+                    lit_mesh_renderer::LitMeshRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group);
                 }
+            } else if self.render_mode == RenderMode::ThickLines {
+                // the lines of the decals are drawn like all the others
+                self.wireframe.bind(&mut render_pass);
+                for mesh in meshes() {
+                    wireframe_renderer::WireframeRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group);
+                }
+            } else {
+                render_pass.set_pipeline(scene_pipeline);
+                render_pass.set_bind_group(1, &self.fog_uniform.bind_group, &[]);
+                render_pass.set_bind_group(2, &self.clip_uniform.bind_group, &[]);
+                stats::RenderStats::record_bind_group_switch();
+                stats::RenderStats::record_bind_group_switch();
+                // the objects that picked a pipeline of their own are drawn with it, the pipeline
+                // is only switched when it changes from one object to the next
+                let mut bound_pipeline = scene_pipeline;
+                for (i, (object, mesh)) in object_meshes().enumerate().filter(|(_, (_, mesh))| !mesh.is_decal()) {
+                    let own_pipeline = object.pipeline.as_deref().and_then(|name| pipelines.get(name));
+                    let pipeline = own_pipeline.map_or(scene_pipeline, |own| own.pipeline());
+                    if !std::ptr::eq(pipeline, bound_pipeline) {
+                        render_pass.set_pipeline(pipeline);
+                        bound_pipeline = pipeline;
+                    }
+                    self.object_constants.apply(&mut render_pass, i);
+                    match own_pipeline {
+                        Some(own) => own.draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group),
+                        None => ColoredMeshRenderer::draw_mesh_instanced(&mut render_pass, mesh, opaque_instances(mesh), &camera_uniform.bind_group),
+                    }
+                }
+                // the decals come after all the surfaces they can lie on, the lowest level first
+                for level in 1..=model::DECAL_LEVELS {
+                    let Some(decal_pipeline) = mesh_renderer.decal_pipeline(level) else { break };
+                    render_pass.set_pipeline(decal_pipeline);
+                    let decals = meshes().enumerate().filter(|(_, mesh)| mesh.decal_level.min(model::DECAL_LEVELS) == level);
+                    for (i, mesh) in decals {
+                        self.object_constants.apply(&mut render_pass, i);
+                        ColoredMeshRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group);
+                    }
+                }
+                // the transparent instances come last, when everything that can be seen through
+                // them is drawn. The objects with a pipeline of their own drew all their
+                // instances already
+                if let Some(transparent_pipeline) = transparent_pipeline {
+                    render_pass.set_pipeline(transparent_pipeline);
+                    let transparent = object_meshes().enumerate()
+                        .filter(|(_, (object, mesh))| !mesh.is_decal() && object.pipeline.as_deref().and_then(|name| pipelines.get(name)).is_none());
+                    for (i, (_, mesh)) in transparent {
+                        let instances = mesh.instance_buffer.transparent_instances();
+                        if instances.is_empty() {
+                            continue;
+                        }
+                        self.object_constants.apply(&mut render_pass, i);
+                        ColoredMeshRenderer::draw_mesh_instanced(&mut render_pass, mesh, instances, &camera_uniform.bind_group);
+                    }
+                }
+            }
+            if self.show_point_cloud {
+                self.point_cloud_renderer.draw(&mut render_pass, &self.point_cloud, &camera_uniform.bind_group);
             }
-            self.ui_painter.render(&mut render_pass, &ui_primitives, &self.ui_screen_descriptor);
+            self.debug_lines.draw(&mut render_pass, &camera_uniform.bind_group);
         }
-        for id in &ui_output.textures_delta.free {
-            self.ui_painter.free_texture(id);
+        if self.show_overlay {
+            // drawn into the same target as the scene, with MSAA it is resolved once more
+            self.overlay.render(&mut encoder, scene_view, resolve_target, &self.object_constants, scene_mesh_count);
         }
+        if let Some(tonemap) = &self.tonemap {
+            tonemap.render(&mut encoder, post_view);
+        }
+        if let Some(fxaa) = &self.fxaa {
+            fxaa.render(&mut encoder, view);
+        }
+        self.hud.render(&mut encoder, view);
+        // the ui is drawn on top of the finished scene
+        self.ui.render(&mut encoder, view, ui_frame);
         self.queue.submit(iter::once(encoder.finish()));
-        output.present();
-        Ok(())
+        // this also resets the counters for the next frame
+        self.frame_stats = stats::RenderStats::take();
     }
 
-    pub fn update(&mut self, dt: Duration) {
-        self.cameras[self.active_camera].update(dt);
-        self.cameras[self.active_camera].update_uniform(&self.queue);
+    pub fn update(&mut self, real_dt: Duration) {
+        self.frame_times.push(real_dt);
+        self.update_title();
+        // everything that moves is driven by the simulated time of the clock
+        let dt = self.clock.tick(real_dt);
+        // the transition between cameras is part of the ui, so it runs in real time even
+        // when the clock is paused
+        let camera = &mut self.cameras[self.active_camera];
+        // switching between perspective and orthographic is part of the ui as well, the new
+        // projection is written with the view below
+        camera.animate_projection(real_dt);
+        match &mut self.camera_transition {
+            Some(transition) => {
+                if !transition.step(real_dt, camera, &self.queue) {
+                    self.camera_transition = None;
+                    // input that came in before the transition doesn't move the camera now
+                    camera.controls.reset();
+                }
+            }
+            // nothing moves while the clock is paused, and the input that comes in meanwhile is
+            // dropped so the camera doesn't jump when the clock runs again
+            None if self.clock.is_paused() && dt.is_zero() => {
+                camera.controls.reset();
+                camera.update_uniform(&self.queue);
+            }
+            None => {
+                camera.update(dt);
+                camera.update_uniform(&self.queue);
+            }
+        }
+
+        // swap in the textures that finished loading
+        self.texture_loader.poll(&self.device, &self.queue);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_changed_objects();
+
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        if self.shader_watcher.as_ref().is_some_and(|watcher| !watcher.changed().is_empty()) {
+            self.reload_shaders();
+        }
+
+        // the auto rotation moves the instances relative to their scene graph node
+        for object in self.objects.iter_mut() {
+            object.update(dt, &self.device, &self.uploader);
+        }
+
+        // place the instances of the nodes that moved according to the scene graph
+        self.scene.resolve_world_transforms(&mut self.objects);
+
+        // step the animated textures to the frame for the current time
+        for object in self.objects.iter() {
+            for mesh in object.meshes.iter() {
+                if let Some(material) = &mesh.material {
+                    material.texture().animate(self.clock.elapsed(), &self.queue);
+                }
+            }
+        }
     }
     
+    // show the frame rate in the title (or take it out again)
+    fn update_title(&mut self) {
+        let Some(window) = &self.window else {
+            return;
+        };
+        let now = Instant::now();
+        if now - self.title_updated < TITLE_FPS_INTERVAL {
+            return;
+        }
+        self.title_updated = now;
+        match (self.fps_in_title, self.frame_times.average_fps()) {
+            (true, Some(fps)) => window.set_title(&format!("{} ({:.0} FPS)", self.window_title, fps)),
+            _ if window.title() != self.window_title => window.set_title(&self.window_title),
+            _ => {}
+        }
+    }
+
+    /// Apply an event from an input recording. This does what `on_event` does for the real
+    /// events, but the ui doesn't see the event.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn on_recorded_event(&mut self, event: &input_recording::RecordedEvent, ewlt: &winit::event_loop::EventLoopWindowTarget<()>) {
+        use input_recording::RecordedEvent;
+        if let RecordedEvent::Key { code: KeyCode::Escape, state: ElementState::Pressed } = event {
+            ewlt.exit();
+            return;
+        }
+        if let RecordedEvent::Resized { width, height } = *event {
+            // the surface has to match the window, so the window is resized. If that happens
+            // right away there is no Resized event from winit
+            if let Some(size) = self.window.as_ref().and_then(|window| window.request_inner_size(PhysicalSize::new(width, height))) {
+                self.resize(size);
+            }
+            return;
+        }
+        self.apply_recorded_input(event);
+    }
+
+    /// hand the keyboard and mouse input of a recording to the controls of the camera, the
+    /// part of `on_recorded_event` that doesn't need the event loop
+    #[cfg(not(target_arch = "wasm32"))]
+    fn apply_recorded_input(&mut self, event: &input_recording::RecordedEvent) {
+        use input_recording::RecordedEvent;
+        if self.camera_transition.is_some() {
+            return;
+        }
+        let controls = &mut self.cameras[self.active_camera].controls;
+        match *event {
+            RecordedEvent::Key { code, state } => _ = controls.on_key(code, state),
+            RecordedEvent::MouseButton { button, state } => _ = controls.on_mouse_button_input(&state, &button),
+            RecordedEvent::MouseWheelLines { x, y } => _ = controls.on_mouse_wheel(&MouseScrollDelta::LineDelta(x, y)),
+            RecordedEvent::MouseWheelPixels { x, y } => {
+                _ = controls.on_mouse_wheel(&MouseScrollDelta::PixelDelta(winit::dpi::PhysicalPosition::new(x, y)))
+            }
+            RecordedEvent::MouseMotion { dx, dy } => _ = controls.on_cursor_moved(&(dx, dy)),
+            RecordedEvent::Resized { .. } => {}
+        }
+    }
+
     fn on_event(&mut self, event: &Event<()>, ewlt: &winit::event_loop::EventLoopWindowTarget<()>, last_render_time: &mut Instant) {
         match event {
-            Event::WindowEvent { window_id, event, .. } if *window_id == self.window.id() => {
-                // let the ui handle the input
-                let resp = self.ui_state.on_window_event(&self.window, event);
+            Event::WindowEvent { window_id, event, .. } if self.window.as_ref().is_some_and(|window| window.id() == *window_id) => {
+                // let the ui handle the input, there is a ui state whenever there is a window
+                let resp = match &self.window {
+                    Some(window) => self.ui.on_window_event(window, event),
+                    None => egui_winit::EventResponse { consumed: false, repaint: false },
+                };
+                match event {
+                    WindowEvent::CursorMoved { position, .. } => self.cursor_position = Some(*position),
+                    WindowEvent::CursorLeft { .. } => self.cursor_position = None,
+                    _ => {}
+                }
                 // pass the input to the camera for it to process stuff
-                let processed = if !resp.consumed {
+                let processed = if !resp.consumed && self.camera_transition.is_none() {
                     self.cameras[self.active_camera].controls.on_window_event(event)
                 } else {
                     false
@@ -370,12 +1838,145 @@ impl App {
                                 },
                             ..
                         } => ewlt.exit(),
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } if !resp.consumed => {
+                            self.picked_position = self.world_position_under_cursor();
+                        }
+                        // F3 switches to the next render mode
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F3),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } if !resp.consumed => {
+                            self.cycle_render_mode();
+                        }
+                        // F6 pauses and resumes the clock, F7 advances the paused clock by
+                        // one frame (Space already moves the camera up)
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F6),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } if !resp.consumed => {
+                            self.set_paused(!self.clock.is_paused());
+                        }
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F7),
+                                    ..
+                                },
+                            ..
+                        } if !resp.consumed => {
+                            self.clock.step();
+                        }
+                        // O switches the active camera between perspective and orthographic
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyO),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } if !resp.consumed => {
+                            let camera = &mut self.cameras[self.active_camera];
+                            camera.set_orthographic(!camera.is_orthographic());
+                        }
+                        // C switches to the next cull mode
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } if !resp.consumed => {
+                            self.cycle_cull_mode();
+                        }
+                        // F moves the camera back until the selection (or everything) is in view
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } if !resp.consumed => {
+                            self.frame_selection();
+                        }
+                        // F5 reloads the object of the selected instance from its file, or all
+                        // objects if nothing is selected
+                        #[cfg(not(target_arch = "wasm32"))]
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F5),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } if !resp.consumed => {
+                            let reload = match self.selected {
+                                Some(selected) => vec![selected.object],
+                                None => (0..self.objects.len()).filter(|&i| self.objects[i].source.is_some()).collect(),
+                            };
+                            for index in reload {
+                                if let Err(e) = self.reload_object(index, false) {
+                                    log::error!("could not reload object {}: {}", index, e);
+                                }
+                            }
+                        }
+                        // F12 saves the frame into the working directory
+                        #[cfg(not(target_arch = "wasm32"))]
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    physical_key: PhysicalKey::Code(KeyCode::F12),
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } if !resp.consumed => {
+                            let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+                            let path = std::path::PathBuf::from(format!("frame-{}.png", time.as_millis()));
+                            if let Err(e) = self.capture_frame(&path) {
+                                log::error!("could not capture the frame: {}", e);
+                            }
+                        }
                         WindowEvent::Resized(physical_size) => {
                             self.resize(*physical_size);
                         }
                         WindowEvent::ScaleFactorChanged { .. } => {
                             // new_inner_size is &mut so w have to dereference it twice
-                            self.resize(self.window.inner_size());
+                            if let Some(size) = self.window.as_ref().map(|window| window.inner_size()) {
+                                self.resize(size);
+                            }
                         }
                         WindowEvent::RedrawRequested => {
                             let now = Instant::now();
@@ -396,36 +1997,175 @@ impl App {
                     }
                 }
             },
-            Event::DeviceEvent { event, .. } => {
+            Event::DeviceEvent { event, .. } if self.camera_transition.is_none() => {
                 _ = self.cameras[self.active_camera].controls.on_device_event(&event);
             },
             Event::AboutToWait => {
                 // RedrawRequested will only trigger once, unless we manually
                 // request it.
-                self.window.request_redraw();
+                if let Some(window) = &self.window {
+                    window.request_redraw();
+                }
             }
             _ => {}
         }
     }
 }
 
+/// show the key value pairs of the metadata in a table, sorted by key so the order stays the
+/// same from frame to frame
+fn metadata_grid(ui: &mut egui::Ui, title: &str, metadata: &HashMap<String, String>) {
+    ui.label(format!("{} metadata", title));
+    if metadata.is_empty() {
+        ui.label("(none)");
+        return;
+    }
+    let mut entries = metadata.iter().collect::<Vec<_>>();
+    entries.sort();
+    egui::Grid::new(title).striped(true).show(ui, |ui| {
+        for (key, value) in entries {
+            ui.label(key);
+            ui.label(value);
+            ui.end_row();
+        }
+    });
+}
+
+// Building for the browser
+// ------------------------
+// The app also runs in the browser with WebGL2. Build it for the wasm target and generate the
+// javascript bindings, then serve the output directory together with the `res` folder:
+//
+//     cargo build --release --target wasm32-unknown-unknown
+//     wasm-bindgen --target web --out-dir web target/wasm32-unknown-unknown/release/learn-graphics-with-rust.wasm
+//     cp -r res web/res
+//
+// and load `learn_graphics_with_rust.js` from an index.html in the web folder with
+// `import init from "./learn_graphics_with_rust.js"; init();`. The resources are fetched from
+// `res/` relative to the page (see resources.rs), so the web folder can be served from any path.
+
 async fn run() {
-    // This sets up a logger so that we can track what we are doing
+    // This sets up a logger so that we can track what we are doing, in the browser the log
+    // goes to the developer console
+    #[cfg(not(target_arch = "wasm32"))]
     env_logger::init();
+    #[cfg(target_arch = "wasm32")]
+    {
+        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+        console_log::init_with_level(log::Level::Warn).expect("could not initialize the logger");
+    }
+
+    // `--golden` and `--update-golden` render the golden images instead of opening a window
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(mode) = golden::Mode::from_args(&std::env::args().collect::<Vec<_>>()) {
+        if let Err(e) = golden::run(mode).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     
+    // `--record <file>` writes the input into the file, `--replay <file>` plays it back
+    #[cfg(not(target_arch = "wasm32"))]
+    let (mut recorder, mut replay) = {
+        let input_mode = input_recording::Mode::from_args(&std::env::args().collect::<Vec<_>>());
+        let opened = input_mode.and_then(|mode| match mode {
+            Some(input_recording::Mode::Record(path)) => Ok((Some(input_recording::InputRecorder::create(&path)?), None)),
+            Some(input_recording::Mode::Replay(path)) => Ok((None, Some(input_recording::InputReplay::load(&path)?))),
+            None => Ok((None, None)),
+        });
+        match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    // `--trace <dir>` writes a wgpu API trace, see config.rs
+    #[cfg(not(target_arch = "wasm32"))]
+    let config = match config::AppConfig::from_args(&std::env::args().collect::<Vec<_>>()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    #[cfg(target_arch = "wasm32")]
+    let config = config::AppConfig::default();
+
     // first of all we create the event loop that gathers the events
     // like button presses and mouse movements/clicks from the window,
     // as well as provide us with a mechanism to draw the our output on
     // the screen
     let event_loop = EventLoop::new().unwrap();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
-    let mut app = App::new(window).await;
+
+    // in the browser the window is a canvas that needs to be added to the page
+    #[cfg(target_arch = "wasm32")]
+    {
+        use winit::platform::web::WindowExtWebSys;
+        let _ = window.request_inner_size(PhysicalSize::new(800, 600));
+        web_sys::window()
+            .and_then(|win| win.document())
+            .and_then(|doc| {
+                let body = doc.body()?;
+                let canvas = web_sys::Element::from(window.canvas()?);
+                body.append_child(&canvas).ok()?;
+                Some(())
+            })
+            .expect("could not add the canvas to the document");
+    }
+
+    let mut app = match App::new(window, &config).await {
+        Ok(app) => app,
+        Err(e) => {
+            log::error!("{}", e);
+            panic!("{}", e);
+        }
+    };
     let mut now = Instant::now();
-    let _ = event_loop.run(move |event, ewlt| {
-        app.on_event(&event, ewlt, &mut now)
-    });
+
+    // the browser owns the event loop, so it can't block here. Instead the event loop is
+    // handed to the browser and run gets to return
+    #[cfg(target_arch = "wasm32")]
+    {
+        use winit::platform::web::EventLoopExtWebSys;
+        event_loop.spawn(move |event, ewlt| {
+            app.on_event(&event, ewlt, &mut now)
+        });
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = event_loop.run(move |event, ewlt| {
+            if let Some(replay) = &mut replay {
+                // the recorded events are applied once per frame, just before the redraw
+                if let Event::AboutToWait = event {
+                    let was_finished = replay.is_finished();
+                    for recorded in replay.due() {
+                        app.on_recorded_event(&recorded, ewlt);
+                    }
+                    if replay.is_finished() && !was_finished {
+                        log::info!("the input replay is finished");
+                    }
+                }
+                // while replaying, the real input would only get in the way
+                if input_recording::RecordedEvent::from_event(&event).is_some_and(|e| e.is_input()) {
+                    return;
+                }
+            }
+            if let Some(recorder) = &mut recorder {
+                recorder.record(&event);
+            }
+            app.on_event(&event, ewlt, &mut now)
+        });
+    }
 }
 
 fn main() {
+    #[cfg(target_arch = "wasm32")]
+    wasm_bindgen_futures::spawn_local(run());
+    #[cfg(not(target_arch = "wasm32"))]
     pollster::block_on(run());
 }
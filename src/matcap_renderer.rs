@@ -0,0 +1,356 @@
+/// The renderer that shades meshes with a matcap (material capture), an image of a lit sphere
+/// that is looked up with the normal of the surface as seen from the camera. It needs no lights
+/// and shows the shape of a mesh very clearly, which makes it good for inspecting meshes.
+use image::{Rgba, RgbaImage};
+use wgpu::util::DeviceExt;
+use wgpu::RenderPipelineDescriptor;
+
+use crate::{camera, instance, model, renderer, resources};
+use crate::stats::RenderStats;
+use std::mem;
+
+/// the size of the generated built in matcaps in pixels
+const BUILTIN_SIZE: u32 = 256;
+
+/// The matcaps that come with the app, they are generated instead of loaded from files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinMatcap {
+    /// a soft, warm diffuse material that shows the shape without distractions
+    Clay,
+    /// a mirror that reflects a bright sky and a dark ground, shows the smoothness of a surface
+    Chrome,
+    /// a saturated red with a sharp highlight and a bright rim
+    RedWax,
+    /// the view space normal as a color
+    Normals,
+}
+
+impl BuiltinMatcap {
+    pub const ALL: [BuiltinMatcap; 4] = [BuiltinMatcap::Clay, BuiltinMatcap::Chrome, BuiltinMatcap::RedWax, BuiltinMatcap::Normals];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuiltinMatcap::Clay => "Clay",
+            BuiltinMatcap::Chrome => "Chrome",
+            BuiltinMatcap::RedWax => "Red wax",
+            BuiltinMatcap::Normals => "Normals",
+        }
+    }
+
+    // the color of the sphere at the normal (which points towards the camera for z = 1)
+    fn shade(&self, normal: [f32; 3]) -> [f32; 3] {
+        let [x, y, z] = normal;
+        // the light comes from the top left, a bit in front of the sphere
+        let light = normalize([-0.4, 0.6, 0.7]);
+        let diffuse = dot(normal, light).max(0.0);
+        // the reflection of the view direction (0, 0, -1) at the normal
+        let reflected = [2.0 * z * x, 2.0 * z * y, 2.0 * z * z - 1.0];
+        let highlight = |sharpness: i32| dot(reflected, light).max(0.0).powi(sharpness);
+        match self {
+            BuiltinMatcap::Clay => {
+                let shade = 0.25 + 0.75 * diffuse;
+                let base = [0.78, 0.62, 0.52];
+                base.map(|c| c * shade + 0.1 * highlight(8))
+            }
+            BuiltinMatcap::Chrome => {
+                let up = reflected[1];
+                let environment = if up > 0.0 {
+                    // the sky gets brighter towards the top
+                    mix([0.55, 0.6, 0.65], [0.95, 0.97, 1.0], up.powf(0.5))
+                } else {
+                    // the ground gets darker towards the bottom, with a bright horizon line
+                    mix([0.35, 0.3, 0.25], [0.08, 0.07, 0.06], (-up).powf(0.3))
+                };
+                environment.map(|c| c + highlight(64))
+            }
+            BuiltinMatcap::RedWax => {
+                let rim = (1.0 - z).powi(3);
+                let base = [0.6, 0.05, 0.04];
+                let shade = 0.15 + 0.85 * diffuse;
+                base.map(|c| c * shade + 0.6 * highlight(32) + 0.35 * rim)
+            }
+            BuiltinMatcap::Normals => normal.map(|c| c * 0.5 + 0.5),
+        }
+    }
+
+    /// Draw the sphere. The pixels outside of the sphere get the color of a point just inside of
+    /// its edge, so the filtering at the edge doesn't pull in a background color (right on the
+    /// edge the reflection flips between the sky and the ground from rounding).
+    pub fn image(&self, size: u32) -> RgbaImage {
+        RgbaImage::from_fn(size, size, |px, py| {
+            let x = (px as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let y = 1.0 - (py as f32 + 0.5) / size as f32 * 2.0;
+            let length = (x * x + y * y).sqrt();
+            let edge = 0.999;
+            let (x, y) = if length > edge { (x / length * edge, y / length * edge) } else { (x, y) };
+            let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+            let color = self.shade([x, y, z]);
+            let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+            Rgba([r, g, b, 255])
+        })
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = dot(v, v).sqrt();
+    v.map(|c| c / length)
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+impl <'a, 'b, 'c> model::DrawMesh<'a, 'b, 'c> for MatcapRenderer {
+    fn draw_mesh (
+        render_pass: &'a mut wgpu::RenderPass<'b>,
+        mesh: &'c model::Surface,
+        camera_bind_group: &'c wgpu::BindGroup,
+    ) where 'b: 'a, 'c: 'b {
+        MatcapRenderer::draw_mesh_instanced(render_pass, mesh, mesh.instance_buffer.all_instances(), camera_bind_group);
+    }
+
+    fn draw_mesh_instanced(
+        render_pass: &'a mut wgpu::RenderPass<'b>,
+        mesh: &'c model::Surface,
+        instances: std::ops::Range<u32>,
+        camera_bind_group: &'c wgpu::BindGroup,
+    ) where 'b: 'a, 'c: 'b {
+        // far away meshes are drawn with a simplified version of them, see `model::Lod`
+        let (indices, base_vertex) = mesh.lod_range();
+        MatcapRenderer::draw_submesh(render_pass, mesh, indices, base_vertex, instances, camera_bind_group);
+    }
+
+    fn draw_submesh(
+        render_pass: &'a mut wgpu::RenderPass<'b>,
+        mesh: &'c model::Surface,
+        indices: std::ops::Range<u32>,
+        base_vertex: i32,
+        instances: std::ops::Range<u32>,
+        camera_bind_group: &'c wgpu::BindGroup,
+    ) where 'b: 'a, 'c: 'b {
+        let occ_slots = mesh.instance_buffer.occupied_slots;
+        // all instances are hidden (or gone), an empty buffer slice is not allowed
+        if occ_slots == 0 {
+            return;
+        }
+        // only the instances that are in the buffer can be drawn
+        let instances = instances.start.min(occ_slots as u32)..instances.end.min(occ_slots as u32);
+        if instances.is_empty() {
+            return;
+        }
+        // the buffers may be shared with other meshes, the whole buffers are bound and the mesh
+        // is found in them with its first index and base vertex
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.buffer.slice(..));
+        render_pass.set_vertex_buffer(1, mesh.instance_buffer.gpu_buffer.slice(..occ_slots * mem::size_of::<instance::RawInstance>() as u64));
+        render_pass.set_index_buffer(mesh.index_buffer.buffer.slice(..), wgpu::IndexFormat::Uint32);
+        // the matcap is the same for all meshes, it is bound once with `bind`
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+        let instance_count = instances.len() as u64;
+        // the share of the triangles of the mesh that are in the index range
+        let triangle_count = mesh.triangle_count() * indices.len() as u64 / mesh.num_elements.max(1) as u64;
+        let first_index = mesh.first_index();
+        render_pass.draw_indexed(indices.start + first_index..indices.end + first_index, base_vertex + mesh.base_vertex(), instances);
+        RenderStats::record_draw(triangle_count * instance_count);
+    }
+}
+
+pub struct MatcapRenderer {
+    pub pipeline: wgpu::RenderPipeline,
+    pub config: renderer::PipelineConfig,
+    // the matcap image, it's a texture of the texture system so it has its own bind group
+    matcap: model::Texture,
+    /// the name of the built in matcap or the file of the custom one
+    pub matcap_name: String,
+    // the view matrix of the camera, the normals are turned into view space with it
+    view_buffer: wgpu::Buffer,
+    view_bind_group: wgpu::BindGroup,
+}
+
+impl MatcapRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        // the configuration of the texture the scene is rendered to
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) -> anyhow::Result<MatcapRenderer> {
+        let (pipeline, config) = Self::create_pipeline(device, camera_bind_group_layout, surface_config, depth_format, sample_count);
+        let view_bind_group_layout = device.create_bind_group_layout(&Self::describe_view());
+        let view_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Matcap view uniform buffer"),
+            contents: bytemuck::cast_slice(&[[[0.0f32; 4]; 4]]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let view_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Matcap view bind group"),
+            layout: &view_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: view_buffer.as_entire_binding(),
+            }],
+        });
+        let default = BuiltinMatcap::Clay;
+        let matcap = Self::create_builtin(device, queue, default)?;
+        Ok(MatcapRenderer { pipeline, config, matcap, matcap_name: default.name().to_string(), view_buffer, view_bind_group })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) -> (wgpu::RenderPipeline, renderer::PipelineConfig) {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Matcap Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/matcap.wgsl").into()),
+        });
+        let texture_bind_group_layout = model::Texture::create_layout(device);
+        let view_bind_group_layout = device.create_bind_group_layout(&Self::describe_view());
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Layout of the Matcap Renderer"),
+            bind_group_layouts: &[camera_bind_group_layout, &texture_bind_group_layout, &view_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let config = renderer::PipelineConfig {
+            label: "Matcap Renderer",
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            // not every model has a consistent winding, so both sides are drawn
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            depth_format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            color_format: surface_config.format,
+            blend: Some(wgpu::BlendState::REPLACE),
+            sample_count,
+            bind_group_layout_entries: vec![
+                camera::CameraUniform::describe().entries.to_vec(),
+                model::Texture::desc_layout().entries.to_vec(),
+                Self::describe_view().entries.to_vec(),
+            ],
+            push_constant_ranges: Vec::new(),
+        };
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(config.label),
+            layout: Some(&layout),
+            // the same vertex and instance layout as the other mesh renderers, the matcap
+            // doesn't use the texture coordinates and the color of the instances
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::Vertex::desc(), instance::Instance::desc()],
+            },
+            primitive: config.primitive_state(),
+            depth_stencil: config.depth_stencil_state(),
+            multisample: config.multisample_state(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.color_format,
+                    blend: config.blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        (pipeline, config)
+    }
+
+    /// Create the pipeline again for other targets, the matcap stays the same
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) {
+        (self.pipeline, self.config) = Self::create_pipeline(device, camera_bind_group_layout, surface_config, depth_format, sample_count);
+    }
+
+    fn describe_view() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Matcap view bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        }
+    }
+
+    fn create_builtin(device: &wgpu::Device, queue: &wgpu::Queue, matcap: BuiltinMatcap) -> anyhow::Result<model::Texture> {
+        // the pixels are made right here, there is no image file to decode
+        let image = matcap.image(BUILTIN_SIZE);
+        model::Texture::from_raw_rgba(device, queue, image.as_raw(), BUILTIN_SIZE, BUILTIN_SIZE, &format!("{} matcap", matcap.name()))
+    }
+
+    /// switch to one of the built in matcaps
+    pub fn set_builtin(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, matcap: BuiltinMatcap) -> anyhow::Result<()> {
+        self.set_texture(Self::create_builtin(device, queue, matcap)?, matcap.name());
+        Ok(())
+    }
+
+    /// Use a texture as the matcap. The texture should show a sphere that fills the whole image,
+    /// it needs the bind group of the texture system (all textures created by `model::Texture`
+    /// from images have one).
+    pub fn set_texture(&mut self, texture: model::Texture, name: &str) {
+        self.matcap = texture;
+        self.matcap_name = name.to_string();
+    }
+
+    /// the texture of the matcap that is used
+    pub fn texture(&self) -> &model::Texture {
+        &self.matcap
+    }
+
+    /// load a custom matcap from the resources, the old matcap stays if the image can't be loaded
+    #[allow(dead_code)] // there is no file picker in the ui yet
+    pub async fn load(&mut self, file_name: &str, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+        let texture = resources::load_texture(file_name, device, queue, &model::TextureOptions::default()).await?;
+        self.set_texture(texture, file_name);
+        Ok(())
+    }
+
+    /// write the view matrix of the camera that the scene is rendered with
+    pub fn update_view(&self, camera: &camera::Camera, queue: &wgpu::Queue) {
+        let view: [[f32; 4]; 4] = camera.compute_view_matrix().into();
+        queue.write_buffer(&self.view_buffer, 0, bytemuck::cast_slice(&[view]));
+        RenderStats::record_buffer_write();
+    }
+
+    /// set the pipeline and bind the matcap and the view, this is done once before the meshes
+    /// are drawn
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        if let Some(bind_group) = &self.matcap.bind_group {
+            render_pass.set_bind_group(1, bind_group, &[]);
+            RenderStats::record_bind_group_switch();
+        }
+        render_pass.set_bind_group(2, &self.view_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+    }
+
+    /// Describe the effective state of the pipeline
+    pub fn describe_config(&self) -> String {
+        self.config.describe()
+    }
+}
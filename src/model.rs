@@ -1,4 +1,6 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use core::ops::Range;
 use wgpu::util::DeviceExt;
 
@@ -7,7 +9,9 @@ use wgpu::util::DeviceExt;
 use image::{GenericImageView, Rgba, ImageBuffer};
 use cgmath::*;
 
-use crate::instance;
+use crate::{instance, object_constants, primitives, upload, wireframe_renderer};
+use crate::buffer_arena::{BufferRange, MeshArena};
+use crate::stats::RenderStats;
 
 /// The vertex is the thing that is a node in our mesh. It's what we build
 /// meshes out of. In this case the Vertex is simple and it's only job is
@@ -99,6 +103,64 @@ impl Vertex {
     }
 }
 
+/// An axis aligned bounding box, the smallest box along the coordinate axes that contains
+/// all the vertices of a mesh.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Self {
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for p in points {
+            min = Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        Self { min, max }
+    }
+
+    pub fn from_vertices(vertices: &[RawVertex]) -> Self {
+        Self::from_points(vertices.iter().map(|v| Point3::from(v.pos)))
+    }
+
+    /// The 8 corners of the box, the bits of the index select min (0) or max (1) for x, y and z
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let mut corners = [self.min; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            corner.x = if i & 1 == 0 { self.min.x } else { self.max.x };
+            corner.y = if i & 2 == 0 { self.min.y } else { self.max.y };
+            corner.z = if i & 4 == 0 { self.min.z } else { self.max.z };
+        }
+        corners
+    }
+
+    /// The 12 edges of the box as pairs of corners, connecting the corners that differ in
+    /// exactly one coordinate
+    pub fn edges(&self) -> [(Point3<f32>, Point3<f32>); 12] {
+        let corners = self.corners();
+        let mut edges = [(self.min, self.min); 12];
+        let mut n = 0;
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    edges[n] = (corners[i], corners[i | axis]);
+                    n += 1;
+                }
+            }
+        }
+        edges
+    }
+
+    /// The box that contains this box after it has been transformed, as the box is rotated
+    /// the result can be larger than the transformed box itself.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        Self::from_points(self.corners().iter().map(|c| matrix.transform_point(*c)))
+    }
+}
+
 
 /// The core data structure that defines the geometry of the 3D model is the Surface
 /// also known as Mesh. A Surface consists of a list of vertices together with other vertex
@@ -106,58 +168,219 @@ impl Vertex {
 /// 
 pub struct Surface {
     pub name: String,
-    /// This is where the data for the vertices is stored
-    pub vertex_buffer: wgpu::Buffer,
+    /// This is where the data for the vertices is stored, either in a buffer of its own or
+    /// in a range of a shared buffer (see buffer_arena.rs)
+    pub vertex_buffer: BufferRange,
     /// Many vertices are used multiple times in different triangles
     /// so to save memory the vertices with the attributes are stored
     /// only once and when building the triangles GPU iterates through
     /// the index buffer using the vertices referenced by the index in
     /// the index buffer.
-    pub index_buffer: wgpu::Buffer,
+    pub index_buffer: BufferRange,
+    /// the edges of the triangles as quads for the thick lines of the `WireframeRenderer`,
+    /// None if the indices draw no triangles
+    pub edges: Option<wireframe_renderer::EdgeMesh>,
     pub num_elements: u32,
+    /// how the indices form the triangles, the pipeline that draws the surface has to be created
+    /// with the same topology
+    pub topology: wgpu::PrimitiveTopology,
+    // the number of triangles the indices draw, for the statistics
+    num_triangles: u64,
+    /// the bounding box of the vertices in the coordinates of the mesh
+    pub aabb: Aabb,
     pub fallback_color: Vector4<f32>,
+    /// replaces the colors of all instances when set, this is sent to the shader as a push
+    /// constant (see object_constants.rs)
+    pub color_override: Option<[f32; 3]>,
     pub instances: Vec<instance::Instance>,
     pub instance_buffer: instance::InstanceBuffer,
     // this is the index of a material used for this mesh
-    pub material: Option<Arc<Texture>>,
+    pub material: Option<Arc<Material>>,
+    /// A surface that lies on another one, like a sticker on a wall, has the same depth as the
+    /// surface below it and flickers through it. Decals (level 1 and up) are drawn after the
+    /// other surfaces and are pulled towards the camera with a depth bias, higher levels lie on
+    /// top of the lower ones. 0 is a normal surface, levels above `DECAL_LEVELS` are drawn as
+    /// the highest level.
+    pub decal_level: u8,
+    /// the simplified versions of the mesh for when it is far away, see `Lod`
+    pub lods: Vec<Lod>,
+    /// the level that is drawn, 0 is the full mesh and 1 the first of the `lods`. It is picked
+    /// by `select_lod`
+    pub lod: usize,
 }
 
+/// A simplified version of a surface (see `simplify`) that is drawn instead of the full mesh
+/// when the surface is far away from the camera. Its vertices and indices are stored in the
+/// buffers of the surface behind the ones of the full mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lod {
+    /// the distance from the camera from which on the level is drawn
+    pub distance: f32,
+    /// the indices of the level in the index range of the surface
+    pub indices: Range<u32>,
+    /// the first vertex of the level, the indices of every level start at 0
+    pub base_vertex: i32,
+}
+
+/// Every level of detail has half the triangles of the level before it and is drawn from a
+/// distance of `level * LOD_SPACING` times the size (the diagonal of the bounding box) of the mesh
+pub const LOD_SPACING: f32 = 10.0;
+
+/// the number of decal levels that can be stacked on top of a surface, see `Surface::decal_level`
+pub const DECAL_LEVELS: u8 = 3;
+
 impl Surface {
+    /// sort the transparent instances from back to front as seen from `eye`, measured to the
+    /// center of the bounding box of the mesh
+    pub fn sort_transparent_instances(&mut self, eye: Point3<f32>, queue: &wgpu::Queue) {
+        let center = self.aabb.min.midpoint(self.aabb.max);
+        self.instance_buffer.sort_transparent(center, eye, queue);
+    }
+
+    /// a surface with a triangle list, like the ones loaded from files
     pub fn new(
         name: String,
         vertices: &[RawVertex],
         indices: &[u32],
-        material: Option<Arc<Texture>>,
+        material: Option<Arc<Material>>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        Self::new_with_topology(name, vertices, indices, wgpu::PrimitiveTopology::TriangleList, material, device, queue)
+    }
+
+    /// A surface with a triangle list and `lod_levels` simplified versions of it (see `Lod`). The
+    /// vertices and indices are stored in the shared buffers of the arena if there is one,
+    /// otherwise in buffers of its own
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_lods(
+        name: String,
+        vertices: &[RawVertex],
+        indices: &[u32],
+        lod_levels: u8,
+        material: Option<Arc<Material>>,
+        arena: Option<&mut MeshArena>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        let mut all_vertices = vertices.to_vec();
+        let mut all_indices = indices.to_vec();
+        let aabb = Aabb::from_vertices(vertices);
+        let size = (aabb.max - aabb.min).magnitude();
+        let mut lods = Vec::with_capacity(lod_levels as usize);
+        for level in 1..=lod_levels as i32 {
+            let (lod_vertices, lod_indices) = simplify(vertices, indices, 0.5f32.powi(level));
+            lods.push(Lod {
+                distance: level as f32 * LOD_SPACING * size,
+                indices: all_indices.len() as u32..(all_indices.len() + lod_indices.len()) as u32,
+                base_vertex: all_vertices.len() as i32,
+            });
+            all_vertices.extend(lod_vertices);
+            all_indices.extend(lod_indices);
+        }
+        let (vertex_buffer, index_buffer) = match arena {
+            Some(arena) => (
+                arena.vertices.allocate(bytemuck::cast_slice(&all_vertices), device, queue),
+                arena.indices.allocate(bytemuck::cast_slice(&all_indices), device, queue),
+            ),
+            None => Self::dedicated_buffers(&name, &all_vertices, &all_indices, device),
+        };
+        // the rest of the surface (the bounding box, the edges, the statistics) is about the full mesh
+        let mut surface = Self::from_buffers(name, vertex_buffer, index_buffer, vertices, indices, wgpu::PrimitiveTopology::TriangleList, material, device, queue);
+        surface.lods = lods;
+        surface
+    }
+
+    /// a surface whose indices are for the topology, strips are separated with
+    /// `primitives::PRIMITIVE_RESTART`
+    pub fn new_with_topology(
+        name: String,
+        vertices: &[RawVertex],
+        indices: &[u32],
+        topology: wgpu::PrimitiveTopology,
+        material: Option<Arc<Material>>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        let (vertex_buffer, index_buffer) = Self::dedicated_buffers(&name, vertices, indices, device);
+        Self::from_buffers(name, vertex_buffer, index_buffer, vertices, indices, topology, material, device, queue)
+    }
+
+    // the vertex and index buffers of a surface that doesn't share them
+    fn dedicated_buffers(name: &str, vertices: &[RawVertex], indices: &[u32], device: &wgpu::Device) -> (BufferRange, BufferRange) {
+        let vertex_buffer = BufferRange::dedicated(
+            &format!("{:?} Vertex Buffer", name),
+            bytemuck::cast_slice(vertices),
+            // COPY_SRC to read the vertices of a loaded model back and check them (see golden.rs)
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+            device,
+        );
+        let index_buffer = BufferRange::dedicated(
+            &format!("{:?} Index Buffer", name),
+            bytemuck::cast_slice(indices),
+            wgpu::BufferUsages::INDEX,
+            device,
+        );
+        (vertex_buffer, index_buffer)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn from_buffers(
+        name: String,
+        vertex_buffer: BufferRange,
+        index_buffer: BufferRange,
+        vertices: &[RawVertex],
+        indices: &[u32],
+        topology: wgpu::PrimitiveTopology,
+        material: Option<Arc<Material>>,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) -> Self {
         let mut instbuf = instance::InstanceBuffer::new(&device, 5);
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor{
-            label: Some(&format!("{:?} Vertex Buffer", name)),
-            contents: bytemuck::cast_slice(vertices),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{:?} Index Buffer", name)),
-            contents: bytemuck::cast_slice(indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-        let mut first_instance = instance::Instance::new(instbuf.get_instance_buffer_slot());
+        let mut first_instance = instance::Instance::new(instbuf.get_instance_buffer_slot()
+            .expect("a new instance buffer has no limit"));
         first_instance.update(&mut instbuf);
         instbuf.flush(device, queue);
         let instances = vec![first_instance];
+        let edges = wireframe_renderer::EdgeMesh::new(&name, vertices, indices, topology, device);
         Self {
             name,
             vertex_buffer,
             index_buffer,
+            edges,
             num_elements: indices.len() as u32,
+            topology,
+            num_triangles: primitives::triangle_count(indices, topology),
+            aabb: Aabb::from_vertices(vertices),
             material,
             fallback_color: [0., 1., 0., 1.].into(),
+            color_override: None,
+            decal_level: 0,
+            lods: Vec::new(),
+            lod: 0,
             instance_buffer: instbuf,
             instances
         }
     }
 
+    /// Pick the level of detail for the distance of the closest instance to the eye. All
+    /// instances are drawn with the same level.
+    pub fn select_lod(&mut self, eye: Point3<f32>) {
+        let center = self.aabb.min.midpoint(self.aabb.max);
+        let distance = self.instances.iter()
+            .map(|instance| instance.world_matrix().transform_point(center).distance(eye))
+            .fold(f32::INFINITY, f32::min);
+        self.lod = self.lods.iter().take_while(|lod| distance >= lod.distance).count();
+    }
+
+    /// the indices and the base vertex of the level of detail that is drawn
+    pub fn lod_range(&self) -> (Range<u32>, i32) {
+        match self.lod.checked_sub(1).and_then(|level| self.lods.get(level)) {
+            Some(lod) => (lod.indices.clone(), lod.base_vertex),
+            None => (0..self.num_elements, 0),
+        }
+    }
+
     pub fn create_instance(
         &mut self,
         position: Vector3<f32>, 
@@ -165,34 +388,343 @@ impl Surface {
         scale: Vector3<f32>,
         // todo change to proper color space definition
         color: Vector4<f32>,
-    ) {
+    ) -> anyhow::Result<()> {
         self.instances.push(
             instance::Instance::init(
                 position,
                 rotation,
                 scale,
                 color,
-                self.instance_buffer.get_instance_buffer_slot(),
+                self.instance_buffer.get_instance_buffer_slot()?,
             )
         );
+        Ok(())
+    }
+
+    /// Remove the instance at the index of `instances`, the instances after it move down by one.
+    /// Its slot in the instance buffer is free again right away and the next flush packs the
+    /// buffer without it. Returns false if there is no instance at the index.
+    pub fn remove_instance(&mut self, index: usize) -> bool {
+        if index >= self.instances.len() {
+            return false;
+        }
+        let instance = self.instances.remove(index);
+        self.instance_buffer.release_slot(instance.buffer_index);
+        true
     }
 
-    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui, snap: &instance::GridSnap) {
         ui.label(format!("Surface Properties: {}", self.name));
+        if let Some(material) = &self.material {
+            ui.label(if material.is_loaded() { "Texture: loaded" } else { "Texture: loading" });
+        }
+        ui.horizontal(|ui| {
+            let mut max_instances = self.instance_buffer.max_instances();
+            let mut limited = max_instances.is_some();
+            ui.checkbox(&mut limited, "Limit instances");
+            max_instances = match (limited, max_instances) {
+                (true, Some(mut max)) => {
+                    ui.add(egui::DragValue::new(&mut max).clamp_range(1..=1_000_000));
+                    Some(max)
+                }
+                (true, None) => Some(self.instance_buffer.live_instances().max(1)),
+                (false, _) => None,
+            };
+            self.instance_buffer.set_max_instances(max_instances);
+        });
+        ui.add(egui::Slider::new(&mut self.decal_level, 0..=DECAL_LEVELS).text("decal level"));
+        ui.horizontal(|ui| {
+            let mut enabled = self.color_override.is_some();
+            ui.checkbox(&mut enabled, "Override color");
+            match (enabled, &mut self.color_override) {
+                (true, Some(color)) => { ui.color_edit_button_rgb(color); }
+                (true, None) => self.color_override = Some([1.0, 0.5, 0.0]),
+                (false, _) => self.color_override = None,
+            }
+        });
         for (i, instance) in self.instances.iter_mut().enumerate() {
             ui.collapsing(format!("Instance {} of {}", i, self.name), |ui| {
-                instance.build_ui(ui);
+                instance.build_ui(ui, snap, &mut self.instance_buffer);
             });
-            instance.update(&mut self.instance_buffer)
+        }
+    }
+
+    /// the number of triangles of a single instance
+    pub fn is_decal(&self) -> bool {
+        self.decal_level > 0
+    }
+
+    pub fn triangle_count(&self) -> u64 {
+        self.num_triangles
+    }
+
+    /// the index of the first vertex of the surface in its vertex buffer, 0 unless the buffer
+    /// is shared with other surfaces
+    pub fn base_vertex(&self) -> i32 {
+        (self.vertex_buffer.offset / std::mem::size_of::<RawVertex>() as wgpu::BufferAddress) as i32
+    }
+
+    /// the position of the first index of the surface in its index buffer
+    pub fn first_index(&self) -> u32 {
+        (self.index_buffer.offset / std::mem::size_of::<u32>() as wgpu::BufferAddress) as u32
+    }
+
+    /// the per object data of the surface for the shaders
+    pub fn object_constants(&self) -> object_constants::ObjectConstants {
+        object_constants::ObjectConstants {
+            color_override: match self.color_override {
+                Some([r, g, b]) => [r, g, b, 1.0],
+                None => [0.0; 4],
+            },
         }
     }
 
     pub fn update_vertex_buffer(&mut self, vertices: &[RawVertex], queue: &wgpu::Queue) {
-        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+        self.aabb = Aabb::from_vertices(vertices);
+        self.vertex_buffer.write(bytemuck::cast_slice(vertices), queue);
     }
 
     pub fn update_index_buffer(&mut self, indices: &[usize], queue: &wgpu::Queue) {
-        queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(indices));
+        self.index_buffer.write(bytemuck::cast_slice(indices), queue);
+    }
+}
+
+/// Reduce the number of triangles of a mesh to roughly `target_ratio` times the original
+/// count, to generate cheaper versions of a mesh for objects that are far away.
+///
+/// This is a simple edge collapse: the shortest edges are collapsed first by merging their two
+/// vertices into one at the midpoint. Triangles that lose an edge that way disappear. Vertices on
+/// the bounding box of the mesh are never moved, so the simplified mesh keeps its extent.
+pub fn simplify(vertices: &[RawVertex], indices: &[u32], target_ratio: f32) -> (Vec<RawVertex>, Vec<u32>) {
+    let triangle_count = indices.len() / 3;
+    let target = ((triangle_count as f32) * target_ratio.clamp(0.0, 1.0)).ceil() as usize;
+    let mut merged: Vec<RawVertex> = vertices.to_vec();
+
+    // find the vertices on the bounding box, those must stay where they are
+    let mut bbox_min = [f32::MAX; 3];
+    let mut bbox_max = [f32::MIN; 3];
+    for v in vertices.iter() {
+        for axis in 0..3 {
+            bbox_min[axis] = bbox_min[axis].min(v.pos[axis]);
+            bbox_max[axis] = bbox_max[axis].max(v.pos[axis]);
+        }
+    }
+    // flat meshes have no extent along one axis, every vertex would be on the box there
+    let on_bbox = |v: &RawVertex| (0..3).any(|axis| bbox_max[axis] > bbox_min[axis]
+        && (v.pos[axis] == bbox_min[axis] || v.pos[axis] == bbox_max[axis]));
+
+    // every vertex points to the vertex it has been merged into (union find)
+    let mut parent: Vec<usize> = (0..vertices.len()).collect();
+    fn find(parent: &mut [usize], mut v: usize) -> usize {
+        while parent[v] != v {
+            parent[v] = parent[parent[v]];
+            v = parent[v];
+        }
+        v
+    }
+
+    // the triangles each vertex is a part of, so that we only need to check those after a collapse
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertices.len()];
+    let mut alive = vec![true; triangle_count];
+    let mut edges = Vec::with_capacity(indices.len());
+    for (t, tri) in indices.chunks_exact(3).enumerate() {
+        for k in 0..3 {
+            let (a, b) = (tri[k] as usize, tri[(k + 1) % 3] as usize);
+            vertex_triangles[a].push(t);
+            if a < b {
+                edges.push((a, b));
+            } else {
+                edges.push((b, a));
+            }
+        }
+    }
+    edges.sort_unstable();
+    edges.dedup();
+    let length = |(a, b): (usize, usize)| {
+        let (pa, pb) = (Vector3::from(vertices[a].pos), Vector3::from(vertices[b].pos));
+        (pa - pb).magnitude2()
+    };
+    edges.sort_by(|&e1, &e2| length(e1).total_cmp(&length(e2)));
+
+    let mut live_triangles = triangle_count;
+    for (a, b) in edges {
+        if live_triangles <= target {
+            break;
+        }
+        let (ra, rb) = (find(&mut parent, a), find(&mut parent, b));
+        if ra == rb {
+            continue;
+        }
+        let (va, vb) = (merged[ra], merged[rb]);
+        // collapsing two bounding box vertices would change the extent of the mesh
+        if on_bbox(&va) && on_bbox(&vb) {
+            continue;
+        }
+        let average = |x: &[f32], y: &[f32]| x.iter().zip(y).map(|(x, y)| (x + y) * 0.5).collect::<Vec<_>>();
+        let mut collapsed = if on_bbox(&va) {
+            va
+        } else if on_bbox(&vb) {
+            vb
+        } else {
+            let mut midpoint = va;
+            midpoint.pos.copy_from_slice(&average(&va.pos, &vb.pos));
+            midpoint.tex_ccord.copy_from_slice(&average(&va.tex_ccord, &vb.tex_ccord));
+            midpoint
+        };
+        let normal = Vector3::from(va.norm) + Vector3::from(vb.norm);
+        if normal.magnitude2() > 0.0 {
+            collapsed.norm = normal.normalize().into();
+        }
+        merged[ra] = collapsed;
+        parent[rb] = ra;
+        let moved = std::mem::take(&mut vertex_triangles[rb]);
+        vertex_triangles[ra].extend(moved);
+
+        // the triangles that had both vertices of the edge are now degenerate
+        for &t in vertex_triangles[ra].iter() {
+            if !alive[t] {
+                continue;
+            }
+            let roots = [
+                find(&mut parent, indices[t * 3] as usize),
+                find(&mut parent, indices[t * 3 + 1] as usize),
+                find(&mut parent, indices[t * 3 + 2] as usize),
+            ];
+            if roots[0] == roots[1] || roots[1] == roots[2] || roots[0] == roots[2] {
+                alive[t] = false;
+                live_triangles -= 1;
+            }
+        }
+    }
+
+    // build the new, compact vertex and index buffers from the surviving triangles
+    let mut new_index: Vec<Option<u32>> = vec![None; vertices.len()];
+    let mut out_vertices = Vec::new();
+    let mut out_indices = Vec::with_capacity(live_triangles * 3);
+    for (t, tri) in indices.chunks_exact(3).enumerate() {
+        if !alive[t] {
+            continue;
+        }
+        for &v in tri {
+            let root = find(&mut parent, v as usize);
+            let idx = *new_index[root].get_or_insert_with(|| {
+                out_vertices.push(merged[root]);
+                (out_vertices.len() - 1) as u32
+            });
+            out_indices.push(idx);
+        }
+    }
+    (out_vertices, out_indices)
+}
+
+/// The problems `validate` found in a triangle list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeshReport {
+    /// triangles without area, two of their corners are the same or all three are on a line
+    pub degenerate_triangles: usize,
+    /// indices that point past the end of the vertices
+    pub out_of_range_indices: usize,
+    /// vertices that no index points to
+    pub unreferenced_vertices: usize,
+}
+
+impl MeshReport {
+    pub fn is_clean(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// A triangle is degenerate if its area is tiny compared to its longest edge. The cross product
+// of two edges is twice the area, it is compared with the squared longest edge so the test
+// doesn't depend on the size of the mesh.
+fn is_degenerate(a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> bool {
+    let (ab, ac, bc) = (b - a, c - a, c - b);
+    let longest = ab.magnitude2().max(ac.magnitude2()).max(bc.magnitude2());
+    ab.cross(ac).magnitude() <= longest * 1e-6
+}
+
+/// Look for triangles that can't be drawn or don't show up and for vertices that only take up
+/// memory. The indices are a triangle list, a trailing incomplete triangle is ignored. Triangles
+/// with an index out of range are only counted for that index, their area is not checked.
+pub fn validate(vertices: &[RawVertex], indices: &[u32]) -> MeshReport {
+    let mut report = MeshReport::default();
+    let mut referenced = vec![false; vertices.len()];
+    for tri in indices.chunks_exact(3) {
+        let in_range = tri.iter().filter(|&&i| (i as usize) < vertices.len()).count();
+        report.out_of_range_indices += 3 - in_range;
+        for &i in tri.iter().filter(|&&i| (i as usize) < vertices.len()) {
+            referenced[i as usize] = true;
+        }
+        if in_range == 3 {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| Vector3::from(vertices[i as usize].pos));
+            if is_degenerate(a, b, c) {
+                report.degenerate_triangles += 1;
+            }
+        }
+    }
+    report.unreferenced_vertices = referenced.iter().filter(|&&r| !r).count();
+    report
+}
+
+/// Remove what `validate` reports: the triangles with an index out of range, the degenerate
+/// triangles and the vertices that are not used by the remaining triangles (so a vertex that
+/// only a removed triangle used goes as well). The order of the rest stays the same. Returns
+/// the report of the mesh before the repair.
+pub fn repair(vertices: &[RawVertex], indices: &[u32]) -> (Vec<RawVertex>, Vec<u32>, MeshReport) {
+    let report = validate(vertices, indices);
+    if report.is_clean() {
+        return (vertices.to_vec(), indices.to_vec(), report);
+    }
+    let kept = indices.chunks_exact(3)
+        .filter(|tri| tri.iter().all(|&i| (i as usize) < vertices.len()))
+        .filter(|tri| !is_degenerate(
+            Vector3::from(vertices[tri[0] as usize].pos),
+            Vector3::from(vertices[tri[1] as usize].pos),
+            Vector3::from(vertices[tri[2] as usize].pos),
+        ));
+    // the vertices get new indices in the order they had before
+    let mut used = vec![false; vertices.len()];
+    for tri in kept.clone() {
+        for &i in tri {
+            used[i as usize] = true;
+        }
+    }
+    let mut new_index = vec![0u32; vertices.len()];
+    let mut out_vertices = Vec::with_capacity(vertices.len() - report.unreferenced_vertices);
+    for (i, v) in vertices.iter().enumerate().filter(|(i, _)| used[*i]) {
+        new_index[i] = out_vertices.len() as u32;
+        out_vertices.push(*v);
+    }
+    let out_indices = kept.flatten().map(|&i| new_index[i as usize]).collect();
+    (out_vertices, out_indices, report)
+}
+
+/// Give every vertex the mean of the normals of the triangles around it, weighted by their
+/// area, for meshes that come without normals. The cross product of two edges of a triangle is
+/// as long as twice its area, so the sum of the unnormalized cross products is already weighted.
+/// Only vertices that share an index are smoothed, a vertex that was split at a seam of the
+/// texture gets the normal of its side. Degenerate triangles and triangles with an index out of
+/// range are skipped, a vertex without any other triangle keeps its normal.
+pub fn compute_normals(vertices: &mut [RawVertex], indices: &[u32]) {
+    let mut sums = vec![Vector3::<f32>::zero(); vertices.len()];
+    for tri in indices.chunks_exact(3) {
+        if tri.iter().any(|&i| (i as usize) >= vertices.len()) {
+            continue;
+        }
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| Vector3::from(vertices[i as usize].pos));
+        if is_degenerate(a, b, c) {
+            continue;
+        }
+        // counter clockwise triangles face the viewer, like the front face of the pipelines
+        let normal = (b - a).cross(c - a);
+        for &i in tri {
+            sums[i as usize] += normal;
+        }
+    }
+    for (vertex, sum) in vertices.iter_mut().zip(sums) {
+        if sum.magnitude2() > 0.0 {
+            vertex.norm = sum.normalize().into();
+        }
     }
 }
 
@@ -208,8 +740,192 @@ pub trait DrawMesh<'a, 'b, 'c> {
         instances: Range<u32>,
         camera_bind_group: &'c wgpu::BindGroup,
     ) where 'b: 'a, 'c: 'b;
+    /// Draw a part of the index buffer of the mesh. When several meshes share one vertex and
+    /// index buffer, the indices of each one start at 0 for its own vertices, `base_vertex` is
+    /// added to every index to find them in the shared buffer.
+    fn draw_submesh(
+        render_pass: &'a mut wgpu::RenderPass<'b>,
+        mesh: &'c Surface,
+        indices: Range<u32>,
+        base_vertex: i32,
+        instances: Range<u32>,
+        camera_bind_group: &'c wgpu::BindGroup,
+    ) where 'b: 'a, 'c: 'b;
+}
+
+
+/// Options that change how the image data of a texture is prepared before it is uploaded
+#[derive(Debug, Clone, Copy)]
+pub struct TextureOptions {
+    /// Convert the colors to premultiplied alpha (rgb * a) on upload. Pipelines that blend
+    /// textures expect premultiplied colors (`wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING`),
+    /// straight alpha images need this flag to avoid dark fringes around transparent edges.
+    /// Images that are already premultiplied (like the ones egui produces) must not set it.
+    pub premultiply_alpha: bool,
+    /// Build the full chain of mip levels down to 1x1 (see `downsample`). Without them a
+    /// texture that is far away shimmers, as the sampler skips over most of its pixels.
+    pub mipmaps: bool,
+    /// how the values of the image are meant, see `ColorSpace`
+    pub color_space: ColorSpace,
+    /// the level of detail settings of the sampler
+    pub sampler: SamplerOptions,
+    /// the frames per second of a flipbook animation (a texture with more than one frame), see
+    /// `Texture::frame_rate`
+    pub frame_rate: f32,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self { premultiply_alpha: false, mipmaps: true, color_space: ColorSpace::Srgb, sampler: SamplerOptions::default(), frame_rate: 8.0 }
+    }
+}
+
+/// What the 8 bit values of a texture stand for. Colors (albedo, diffuse) are stored in sRGB,
+/// which spends more of the values on the dark colors, and the sampler turns them back into
+/// linear colors for the shader. Data that isn't a color (normal maps, roughness) has to reach
+/// the shader unchanged, the conversion would bend a normal of (0.5, 0.5, 1) into (0.21, 0.21, 1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    /// the format of a texture with the values in this color space
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+
+    // the value in linear space, where averaging and multiplying works
+    fn decode(self, c: f32) -> f32 {
+        match self {
+            ColorSpace::Srgb => srgb_to_linear(c),
+            ColorSpace::Linear => c,
+        }
+    }
+
+    fn encode(self, c: f32) -> f32 {
+        match self {
+            ColorSpace::Srgb => linear_to_srgb(c),
+            ColorSpace::Linear => c,
+        }
+    }
 }
 
+/// Which mip levels the sampler of a texture may pick. The level that the GPU picks from the
+/// size of the texture on the screen is shifted by `lod_bias` (positive values are blurrier)
+/// and then clamped to `lod_min_clamp..=lod_max_clamp`. A texture with a single mip level
+/// ignores all of this, there is nothing else to pick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerOptions {
+    pub lod_min_clamp: f32,
+    pub lod_max_clamp: f32,
+    /// wgpu samplers have no bias, it is stored next to the frame index and the shaders of the
+    /// texture system hand it to `textureSampleBias`
+    pub lod_bias: f32,
+}
+
+impl Default for SamplerOptions {
+    // the same clamps as the default of wgpu, which don't limit any texture
+    fn default() -> Self {
+        Self { lod_min_clamp: 0.0, lod_max_clamp: 32.0, lod_bias: 0.0 }
+    }
+}
+
+/// Multiply the color channels of the image with the alpha channel. An sRGB texture is
+/// stored in sRGB, but the blending happens on linear colors after the sampler decoded them,
+/// so the multiplication has to happen in linear space as well.
+pub fn premultiply_alpha(img: &mut image::RgbaImage, color_space: ColorSpace) {
+    for pixel in img.pixels_mut() {
+        let alpha = pixel[3] as f32 / 255.0;
+        for channel in pixel.0.iter_mut().take(3) {
+            let linear = color_space.decode(*channel as f32 / 255.0) * alpha;
+            *channel = (color_space.encode(linear) * 255.0).round() as u8;
+        }
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Shrink the image to the size of the next mip level (half the size, rounded down, but at
+/// least 1) with a box filter: every pixel is the average of the pixels it covers, which is 2x2
+/// pixels or 3 in a row for the last pixel of an odd side. Like in `premultiply_alpha` the colors
+/// are averaged in linear space, the average of sRGB values would make the small levels darker.
+pub fn downsample(img: &image::RgbaImage, color_space: ColorSpace) -> image::RgbaImage {
+    let (width, height) = img.dimensions();
+    let (new_width, new_height) = ((width / 2).max(1), (height / 2).max(1));
+    // there are only 256 values to decode
+    let decode: Vec<f32> = (0..=255).map(|c| color_space.decode(c as f32 / 255.0)).collect();
+    image::RgbaImage::from_fn(new_width, new_height, |x, y| {
+        let xs = x * width / new_width..(x + 1) * width / new_width;
+        let ys = y * height / new_height..(y + 1) * height / new_height;
+        let mut sum = [0.0f32; 4];
+        for sy in ys.clone() {
+            for sx in xs.clone() {
+                let pixel = img.get_pixel(sx, sy);
+                for c in 0..3 {
+                    sum[c] += decode[pixel[c] as usize];
+                }
+                sum[3] += pixel[3] as f32 / 255.0;
+            }
+        }
+        let count = (xs.len() * ys.len()) as f32;
+        let mut out = [0u8; 4];
+        for c in 0..3 {
+            out[c] = (color_space.encode(sum[c] / count) * 255.0).round() as u8;
+        }
+        // alpha is linear already
+        out[3] = (sum[3] / count * 255.0).round() as u8;
+        Rgba(out)
+    })
+}
+
+/// The texture of a surface. The texture can be replaced while the surface is being rendered (a
+/// placeholder is shown until the real texture has been loaded in the background), so it is kept
+/// behind a lock. A renderer takes the current texture with `texture()` and holds on to the Arc
+/// while it records the frame, so replacing the texture never pulls it away from a frame that
+/// is half done. The new texture is used from the next frame on.
+pub struct Material {
+    texture: RwLock<Arc<Texture>>,
+    loaded: AtomicBool,
+}
+
+impl Material {
+    /// a material with its final texture
+    pub fn new(texture: Arc<Texture>) -> Self {
+        Self { texture: RwLock::new(texture), loaded: AtomicBool::new(true) }
+    }
+
+    /// a material that shows the placeholder until `replace` is called with the real texture
+    pub fn placeholder(texture: Arc<Texture>) -> Self {
+        Self { texture: RwLock::new(texture), loaded: AtomicBool::new(false) }
+    }
+
+    pub fn texture(&self) -> Arc<Texture> {
+        self.texture.read().unwrap().clone()
+    }
+
+    /// false as long as the placeholder is shown
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.load(Ordering::Acquire)
+    }
+
+    /// swap in the real texture, all surfaces that share the material see it from the next frame
+    pub fn replace(&self, texture: Arc<Texture>) {
+        *self.texture.write().unwrap() = texture;
+        self.loaded.store(true, Ordering::Release);
+    }
+}
 
 /**
 To be able to render meshes with fancy images on their surface, we need a texture
@@ -229,6 +945,13 @@ pub struct Texture {
     pub size: wgpu::Extent3d,
     pub bind_group_layout: Option<wgpu::BindGroupLayout>,
     pub bind_group: Option<wgpu::BindGroup>,
+    /// the number of frames per second the layers of the texture are cycled through
+    /// if the texture is a flipbook animation
+    pub frame_rate: f32,
+    // The index of the layer that should be sampled lives on the GPU, so that the shader
+    // can pick the frame of the animation. Textures that don't get sampled (depth) don't
+    // have one.
+    frame_buffer: Option<wgpu::Buffer>,
 }
 
 impl Texture {
@@ -236,7 +959,7 @@ impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
     /// Provide the Description of the texture on the GPU
-    fn desc_layout() -> wgpu::BindGroupLayoutDescriptor<'static> {
+    pub fn desc_layout() -> wgpu::BindGroupLayoutDescriptor<'static> {
         wgpu::BindGroupLayoutDescriptor {
             label: Some("Texture Bind Group Layout"),
             entries: &[
@@ -246,7 +969,10 @@ impl Texture {
                     count: None,
                     ty: wgpu::BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                        // every texture is an array texture, a normal image is simply an array
+                        // with a single layer. This way animated and still textures can be
+                        // used with the same shaders.
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
                         multisampled: false,
                     }
                 },
@@ -255,23 +981,45 @@ impl Texture {
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     count: None,
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                },
+                // the layer of the texture array that is currently displayed
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
                 }
             ]
         }
     }
 
-    fn desc(label: Option<&str>, size: wgpu::Extent3d) -> wgpu::TextureDescriptor {
+    fn desc(label: Option<&str>, size: wgpu::Extent3d, mip_level_count: u32, format: wgpu::TextureFormat) -> wgpu::TextureDescriptor {
         wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format,
+            // COPY_SRC to read the texels back and check them (see golden.rs)
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         }
     }
+    /// The number of mip levels from the full size down to 1x1, `floor(log2(max(w, h))) + 1`.
+    /// Depth textures always get a single level: the average of the depths of neighbouring
+    /// pixels is a depth that no surface has, and the depth comparison needs the exact values.
+    pub fn full_mip_level_count(size: wgpu::Extent3d, format: wgpu::TextureFormat) -> u32 {
+        if format.has_depth_aspect() {
+            return 1;
+        }
+        size.max_mips(wgpu::TextureDimension::D2)
+    }
+
     /// Create the bind group layout on the GPU. The layout needs to be known to the GPU driver
     ///
     /// Notes
@@ -288,7 +1036,7 @@ impl Texture {
     /// -----
     /// The caller must make sure that this function is called with the bind group layout
     /// acquired by calling the create layout 
-    pub fn create_bind_group(name: &str, view: &wgpu::TextureView, sampler: &wgpu::Sampler, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> wgpu::BindGroup {
+    pub fn create_bind_group(name: &str, view: &wgpu::TextureView, sampler: &wgpu::Sampler, frame_buffer: &wgpu::Buffer, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> wgpu::BindGroup {
             device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some(&(name.to_owned() + "bind Group")),
             layout,
@@ -301,11 +1049,54 @@ impl Texture {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(sampler)
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: frame_buffer.as_entire_binding(),
+                },
             ]
         })
     }
 
+    // create the uniform that holds the index of the layer to sample, the lod bias and the
+    // number of layers (textureNumLayers can't be translated to GLSL ES, the shaders that wrap
+    // a layer around read the count from here). A uniform needs to be at least 16 bytes large
+    // so the values are padded
+    fn create_frame_buffer(name: &str, lod_bias: f32, layer_count: u32, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&(name.to_owned() + " frame uniform")),
+            contents: bytemuck::cast_slice(&[0, lod_bias.to_bits(), layer_count, 0]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    /// the number of layers (frames) in the texture
+    pub fn frame_count(&self) -> u32 {
+        self.size.depth_or_array_layers
+    }
+
+    /// select the layer of the texture array that the shader samples
+    pub fn set_frame(&self, frame: u32, queue: &wgpu::Queue) {
+        if let Some(frame_buffer) = &self.frame_buffer {
+            let frame = frame % self.frame_count();
+            // only the frame is written, the lod bias behind it stays as it is
+            queue.write_buffer(frame_buffer, 0, bytemuck::cast_slice(&[frame]));
+            RenderStats::record_buffer_write();
+        }
+    }
+
+    /// advance the flipbook animation to the frame that should be visible after `elapsed` time
+    /// has passed since the start of the animation. Still images only have one frame so they
+    /// are not touched.
+    pub fn animate(&self, elapsed: std::time::Duration, queue: &wgpu::Queue) {
+        if self.frame_count() > 1 {
+            let frame = (elapsed.as_secs_f32() * self.frame_rate) as u32;
+            self.set_frame(frame, queue);
+        }
+    }
+
     pub fn update_gpu_texture(&self, queue: &wgpu::Queue, data: &ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        // only the first layer is updated, the texture needs to be the size of a single frame
+        let size = wgpu::Extent3d { depth_or_array_layers: 1, ..self.size };
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
@@ -319,13 +1110,14 @@ impl Texture {
                 bytes_per_row: Some(4 * self.size.width),
                 rows_per_image: Some(self.size.height),
             },
-            self.size,
+            size,
         );
     }
     
     pub fn add_bind_group(&mut self, device: &wgpu::Device) {
         let layout = device.create_bind_group_layout(&Texture::desc_layout());
-        let bind_group = Texture::create_bind_group(&self.name, &self.view, &self.sampler, device, &layout);
+        let frame_buffer = self.frame_buffer.get_or_insert_with(|| Texture::create_frame_buffer(&self.name, 0.0, self.size.depth_or_array_layers, device));
+        let bind_group = Texture::create_bind_group(&self.name, &self.view, &self.sampler, frame_buffer, device, &layout);
         self.bind_group = Some(bind_group);
         self.bind_group_layout = Some(layout);
 
@@ -338,9 +1130,10 @@ impl Texture {
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
+        options: &TextureOptions,
         ) -> anyhow::Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, label)
+        Self::from_images_with_options(device, queue, std::slice::from_ref(&img), label, options)
     }
 
     /// Load a texture from an image 
@@ -350,55 +1143,273 @@ impl Texture {
         img: &image::DynamicImage,
         label: &str
     ) -> anyhow::Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        Self::from_images(device, queue, std::slice::from_ref(img), label)
+    }
+
+    /// Create a texture from pixels that are already in memory, 4 bytes (RGBA, sRGB encoded)
+    /// per pixel, row by row from the top. This is for textures that are generated in code,
+    /// which would otherwise have to be encoded into an image file just to be decoded again.
+    pub fn from_raw_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> anyhow::Result<Self> {
+        if width == 0 || height == 0 {
+            anyhow::bail!("texture {} needs at least one pixel, it is {}x{}", label, width, height);
+        }
+        let expected = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(4));
+        if expected != Some(pixels.len()) {
+            anyhow::bail!("texture {} is {}x{} pixels, which needs {} bytes of RGBA but there are {}",
+                label, width, height, 4 * width as u64 * height as u64, pixels.len());
+        }
+        let img = image::RgbaImage::from_raw(width, height, pixels.to_vec())
+            .ok_or_else(|| anyhow::anyhow!("the pixels of texture {} don't fit its size", label))?;
+        Self::from_image(device, queue, &image::DynamicImage::ImageRgba8(img), label)
+    }
+
+    /// Load a texture array from a list of images. Every image becomes a layer of the
+    /// texture, this is used for flipbook animations (or sprite sheets that have been split
+    /// into frames). All frames need to have the same dimensions.
+    pub fn from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frames: &[image::DynamicImage],
+        label: &str
+    ) -> anyhow::Result<Self> {
+        Self::from_images_with_options(device, queue, frames, label, &TextureOptions::default())
+    }
+
+    /// Same as `from_images` but the image data is prepared according to the options
+    pub fn from_images_with_options(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frames: &[image::DynamicImage],
+        label: &str,
+        options: &TextureOptions,
+    ) -> anyhow::Result<Self> {
+        let lod = options.sampler;
+        if lod.lod_min_clamp < 0.0 || lod.lod_max_clamp < lod.lod_min_clamp {
+            anyhow::bail!("texture {} has an invalid lod range {}..={}", label, lod.lod_min_clamp, lod.lod_max_clamp);
+        }
+        if !options.frame_rate.is_finite() || options.frame_rate < 0.0 {
+            anyhow::bail!("texture {} has an invalid frame rate of {} frames per second", label, options.frame_rate);
+        }
+        let first = frames.first().ok_or_else(|| anyhow::anyhow!("texture {} needs at least one frame", label))?;
+        let dimensions = first.dimensions();
+        if frames.iter().any(|f| f.dimensions() != dimensions) {
+            anyhow::bail!("all frames of texture {} need to have the same size", label);
+        }
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
-            depth_or_array_layers: 1,
+            depth_or_array_layers: frames.len() as u32,
         };
 
         // create the texture and the sampler
+        let format = options.color_space.format();
+        let mip_level_count = if options.mipmaps {
+            Texture::full_mip_level_count(size, format)
+        } else {
+            1
+        };
+        // the OpenGL backend (WebGL2 and the golden checks) makes a texture with a single layer a
+        // plain 2D texture, which the array view of the shaders can't sample. Still images get a
+        // second layer that is never shown (the frame wraps around at `frame_count`)
         let texture = device.create_texture(
             &Texture::desc(
                 Some(label),
-                size.clone()
+                wgpu::Extent3d { depth_or_array_layers: size.depth_or_array_layers.max(2), ..size },
+                mip_level_count,
+                format,
             )
         );
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // every frame is uploaded into its own layer of the texture, followed by its smaller
+        // mip levels which are shrunk on the CPU
+        for (layer, frame) in frames.iter().enumerate() {
+            let mut rgba = frame.to_rgba8();
+            if options.premultiply_alpha {
+                premultiply_alpha(&mut rgba, options.color_space);
+            }
+            Texture::write_layer(queue, &texture, layer as u32, rgba, mip_level_count, options.color_space);
+        }
+        // the view needs to be an array view even if there is only one layer, otherwise the
+        // wgpu would pick a plain 2D view for single layer textures
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            // blend between the pixels of the two closest mip levels, so there is no visible
+            // seam where the sampler switches from one level to the next
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            lod_min_clamp: lod.lod_min_clamp,
+            lod_max_clamp: lod.lod_max_clamp,
             ..Default::default()
         });
-        let layout = Texture::create_layout(&device);
-        let bind_group = Some(Texture::create_bind_group(label, &view, &sampler, device, &layout));
-        Ok(Self{ size, name: label.to_string(), texture, view, sampler, bind_group_layout: Some(layout), bind_group})
+        let frame_buffer = Texture::create_frame_buffer(label, lod.lod_bias, size.depth_or_array_layers, device);
+        let layout = Texture::create_layout(device);
+        let bind_group = Some(Texture::create_bind_group(label, &view, &sampler, &frame_buffer, device, &layout));
+        Ok(Self{
+            size,
+            name: label.to_string(),
+            texture,
+            view,
+            sampler,
+            bind_group_layout: Some(layout),
+            bind_group,
+            frame_rate: options.frame_rate,
+            frame_buffer: Some(frame_buffer),
+        })
     }
     
-    /// create a depth texture
+    // upload the image into a layer of the texture and fill the mip levels below it with
+    // smaller and smaller copies
+    fn write_layer(queue: &wgpu::Queue, texture: &wgpu::Texture, layer: u32, mut rgba: image::RgbaImage, mip_level_count: u32, color_space: ColorSpace) {
+        for mip_level in 0..mip_level_count {
+            if mip_level > 0 {
+                rgba = downsample(&rgba, color_space);
+            }
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture,
+                    mip_level,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+                },
+                &rgba,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * rgba.width()),
+                    rows_per_image: Some(rgba.height()),
+                },
+                wgpu::Extent3d { width: rgba.width(), height: rgba.height(), depth_or_array_layers: 1 },
+            );
+        }
+    }
+
+    /// The layout of the bind group of a cubemap (`from_cube_faces`): the cube texture and its
+    /// sampler. Unlike the 2D textures it has no frame uniform, a cube has no animation.
+    pub fn desc_layout_cube() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cube Texture Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    }
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                },
+            ]
+        }
+    }
+
+    /// Load a cubemap from six encoded images (png, jpg, ...) of its faces, in the order of the
+    /// layers of a wgpu cube: +X, -X, +Y, -Y, +Z, -Z. The faces need to be squares of the same
+    /// size. The texture is sampled with a direction instead of texture coordinates, which
+    /// makes it the background of a scene (a skybox) or the reflection of the environment.
+    pub fn from_cube_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: [&[u8]; 6],
+        label: &str,
+    ) -> anyhow::Result<Self> {
+        let faces = faces.iter().enumerate()
+            .map(|(i, bytes)| image::load_from_memory(bytes)
+                .map_err(|e| anyhow::anyhow!("face {} of cubemap {} can't be decoded: {}", i, label, e)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let faces: [image::DynamicImage; 6] = faces.try_into().expect("there are six faces");
+        Self::from_cube_images(device, queue, &faces, label)
+    }
+
+    /// Same as `from_cube_faces` with faces that are already decoded, or generated in code
+    pub fn from_cube_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: &str,
+    ) -> anyhow::Result<Self> {
+        let (width, height) = faces[0].dimensions();
+        if width != height {
+            anyhow::bail!("the faces of cubemap {} need to be square, the first one is {}x{}", label, width, height);
+        }
+        if let Some(i) = faces.iter().position(|face| face.dimensions() != (width, height)) {
+            let (w, h) = faces[i].dimensions();
+            anyhow::bail!("face {} of cubemap {} is {}x{}, but the first one is {}x{}", i, label, w, h, width, height);
+        }
+        let size = wgpu::Extent3d { width, height, depth_or_array_layers: 6 };
+        let format = ColorSpace::Srgb.format();
+        let mip_level_count = Texture::full_mip_level_count(size, format);
+        let texture = device.create_texture(&Texture::desc(Some(label), size, mip_level_count, format));
+        for (layer, face) in faces.iter().enumerate() {
+            Texture::write_layer(queue, &texture, layer as u32, face.to_rgba8(), mip_level_count, ColorSpace::Srgb);
+        }
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        // the sampler crosses over to the neighbouring face at the edges by itself, the
+        // address mode only matters inside of a face
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let layout = device.create_bind_group_layout(&Texture::desc_layout_cube());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&(label.to_owned() + " bind group")),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ]
+        });
+        Ok(Self {
+            size,
+            name: label.to_string(),
+            texture,
+            view,
+            sampler,
+            bind_group_layout: Some(layout),
+            bind_group: Some(bind_group),
+            frame_rate: 0.0,
+            frame_buffer: None,
+        })
+    }
+
+    /// create a depth texture, the sample count needs to match the one of the color target
+    /// it is used together with
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
         label: &str
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -410,10 +1421,12 @@ impl Texture {
             label: Some(label),
             size, 
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            // TEXTURE_BINDING is for the shaders that sample the depth. That isn't enough to read
+            // it back on the CPU (see `read_depth_at`), the copy out of the texture needs COPY_SRC
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         };
         let texture = device.create_texture(&desc);
@@ -432,7 +1445,183 @@ impl Texture {
                 ..Default::default()
             }
         );
-        Self { size, name: label.to_string(), texture, view, sampler, bind_group_layout: None, bind_group: None}
+        Self { size, name: label.to_string(), texture, view, sampler, bind_group_layout: None, bind_group: None, frame_rate: 0.0, frame_buffer: None}
+    }
+
+    /// Read the value of a single pixel of a depth texture back to the CPU, 1.0 is the far plane
+    /// (what the depth buffer is cleared to). This waits until the GPU has finished all the work
+    /// that was submitted, so it's meant for the occasional click, not for every frame.
+    /// Multisampled textures can't be copied, they give an error. The device needs the
+    /// `DEPTH_TEXTURE_AND_BUFFER_COPIES` downlevel flag, which GL doesn't have.
+    pub fn read_depth_at(&self, device: &wgpu::Device, queue: &wgpu::Queue, x: u32, y: u32) -> anyhow::Result<f32> {
+        if self.texture.format() != Self::DEPTH_FORMAT {
+            anyhow::bail!("{} is not a depth texture", self.name);
+        }
+        if self.texture.sample_count() > 1 {
+            anyhow::bail!("the depth of the multisampled texture {} can't be read back", self.name);
+        }
+        if x >= self.size.width || y >= self.size.height {
+            anyhow::bail!("pixel ({}, {}) is outside of {} with the size {}x{}", x, y, self.name, self.size.width, self.size.height);
+        }
+        // wgpu only copies depth textures as a whole, so everything is read back and the pixel
+        // is picked out on the CPU. The rows of the copy have to be aligned to 256 bytes.
+        let texel_size = std::mem::size_of::<f32>() as u32;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = (self.size.width * texel_size).div_ceil(align) * align;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth readback"),
+            size: (padded_row * self.size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Depth readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::DepthOnly,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(self.size.height),
+                },
+            },
+            wgpu::Extent3d { depth_or_array_layers: 1, ..self.size },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| _ = sender.send(result));
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+        let offset = (y * padded_row + x * texel_size) as usize;
+        let depth = bytemuck::pod_read_unaligned::<f32>(&slice.get_mapped_range()[offset..offset + texel_size as usize]);
+        readback.unmap();
+        Ok(depth)
+    }
+
+    /// Read the whole texture back to the CPU as an image, for the 8 bit RGBA and BGRA formats
+    /// (the formats of the surfaces). This waits for the GPU like `read_depth_at`. The texture
+    /// needs the COPY_SRC usage, which the single sampled color targets have.
+    pub fn read_rgba(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<image::RgbaImage> {
+        self.read_rgba_level(device, queue, 0)
+    }
+
+    /// `read_rgba` for a mip level of the first layer, every level is half as big as the one
+    /// above it (but at least a pixel)
+    pub fn read_rgba_level(&self, device: &wgpu::Device, queue: &wgpu::Queue, mip_level: u32) -> anyhow::Result<image::RgbaImage> {
+        if mip_level >= self.texture.mip_level_count() {
+            anyhow::bail!("{} has {} mip levels, there is no level {}", self.name, self.texture.mip_level_count(), mip_level);
+        }
+        let size = wgpu::Extent3d {
+            width: (self.size.width >> mip_level).max(1),
+            height: (self.size.height >> mip_level).max(1),
+            depth_or_array_layers: 1,
+        };
+        let bgra = match self.texture.format() {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            format => anyhow::bail!("{} has the format {:?}, which can't be read back as an image", self.name, format),
+        };
+        if !self.texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            anyhow::bail!("{} can't be copied, it doesn't have the COPY_SRC usage", self.name);
+        }
+        // the rows of the copy have to be aligned to 256 bytes, which the rows of the image
+        // usually aren't. The padding is cut off again below
+        let unpadded_row = size.width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = unpadded_row.div_ceil(align) * align;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Color readback"),
+            size: (padded_row * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Color readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture { mip_level, ..self.texture.as_image_copy() },
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| _ = sender.send(result));
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+        let mut pixels = Vec::with_capacity((unpadded_row * size.height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_row as usize]);
+        }
+        readback.unmap();
+        if bgra {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(size.width, size.height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("the readback of {} doesn't have the size of the texture", self.name))
+    }
+
+    /// create a texture with the size and format of the surface that can be rendered into.
+    /// With a sample count > 1 this is the multisampled target that is resolved into the
+    /// surface, otherwise it is an offscreen target that can be sampled by a later pass (or
+    /// read back with `read_rgba`).
+    pub fn create_color_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        label: &str
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        // multisampled textures can't be sampled with a normal sampler, they are only resolved
+        let usage = if sample_count > 1 {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self { size, name: label.to_string(), texture, view, sampler, bind_group_layout: None, bind_group: None, frame_rate: 0.0, frame_buffer: None}
     }
 }
 
@@ -442,6 +1631,37 @@ impl Texture {
 pub struct Object {
     pub name: String,
     pub meshes: Vec<Surface>,
+    /// arbitrary information about the object, it can be loaded from a sidecar file next to
+    /// the model (see `resources::MetadataSidecar`)
+    pub metadata: HashMap<String, String>,
+    /// Objects are drawn from low to high render order, so an overlay with a higher value ends
+    /// up on top of the objects it covers. Objects with the same order are drawn in the order
+    /// they were added, with the opaque ones before the transparent ones (see `draw_order`).
+    pub render_order: i32,
+    /// turntable rotation of the whole object, applied by `update`
+    pub auto_rotate: AutoRotate,
+    /// the file the object was loaded from, None if it was built in code
+    pub source: Option<crate::resources::Location>,
+    /// the name of the pipeline in the `renderer::PipelineController` the object is drawn with,
+    /// None (or a name that is not registered) uses the pipeline of the render mode. The matcap
+    /// mode draws every object with the matcap
+    pub pipeline: Option<String>,
+}
+
+/// Rotates all instances of an object at a constant speed about an axis through the center of
+/// the object, for turntable previews.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoRotate {
+    pub enabled: bool,
+    /// the axis of the rotation, in the space of the parent transform of the instances
+    pub axis: Vector3<f32>,
+    pub radians_per_sec: f32,
+}
+
+impl Default for AutoRotate {
+    fn default() -> Self {
+        Self { enabled: false, axis: Vector3::unit_y(), radians_per_sec: 0.5 }
+    }
 }
 
 impl Object {
@@ -449,9 +1669,81 @@ impl Object {
         Self {
             name,
             meshes: Vec::new(),
+            metadata: HashMap::new(),
+            render_order: 0,
+            auto_rotate: AutoRotate::default(),
+            source: None,
+            pipeline: None,
         }
     }
 
+    /// Start rotating the object about `axis` by `radians_per_sec` in every `update`. An axis
+    /// of length zero stops the rotation.
+    pub fn set_auto_rotate(&mut self, axis: Vector3<f32>, radians_per_sec: f32) {
+        if axis.magnitude2() == 0.0 {
+            log::warn!("auto rotate of {} needs an axis that is not zero", self.name);
+            self.auto_rotate.enabled = false;
+            return;
+        }
+        self.auto_rotate = AutoRotate { enabled: true, axis: axis.normalize(), radians_per_sec };
+    }
+
+    /// The center the object rotates about, the mean position of all its instances. Rotating
+    /// the instances about it doesn't move it, so the object doesn't drift over time.
+    pub fn center(&self) -> Point3<f32> {
+        let positions = self.meshes.iter().flat_map(|mesh| mesh.instances.iter()).map(|i| i.position);
+        let (sum, count) = positions.fold((Vector3::zero(), 0), |(sum, count), p| (sum + p, count + 1));
+        if count == 0 {
+            return Point3::origin();
+        }
+        Point3::from_vec(sum / count as f32)
+    }
+
+    /// Advance the animations of the object by `dt` and upload the instances that changed.
+    pub fn update(&mut self, dt: std::time::Duration, device: &wgpu::Device, uploader: &upload::Uploader) {
+        if !self.auto_rotate.enabled || dt.is_zero() {
+            return;
+        }
+        let angle = Rad(self.auto_rotate.radians_per_sec * dt.as_secs_f32());
+        let rotation = Quaternion::from_axis_angle(self.auto_rotate.axis.normalize(), angle);
+        let center = self.center().to_vec();
+        for mesh in self.meshes.iter_mut() {
+            for instance in mesh.instances.iter_mut() {
+                instance.position = center + rotation.rotate_vector(instance.position - center);
+                instance.rotation = (rotation * instance.rotation).normalize();
+                instance.update(&mut mesh.instance_buffer);
+            }
+            mesh.instance_buffer.flush_async(device, uploader);
+        }
+    }
+
+    /// Put new meshes in place of the current ones, like the meshes of the same file after it
+    /// was edited. If there are as many new meshes as old ones, every new mesh takes over the
+    /// instances of the old one in the same place (with their transforms and the slots the
+    /// scene graph refers to) and its settings, and this returns true. Otherwise every new
+    /// mesh starts with its default instance.
+    pub fn replace_meshes(&mut self, mut meshes: Vec<Surface>) -> bool {
+        let keep_instances = meshes.len() == self.meshes.len();
+        if keep_instances {
+            for (new, old) in meshes.iter_mut().zip(self.meshes.iter_mut()) {
+                std::mem::swap(&mut new.instances, &mut old.instances);
+                std::mem::swap(&mut new.instance_buffer, &mut old.instance_buffer);
+                new.color_override = old.color_override;
+                new.decal_level = old.decal_level;
+            }
+        }
+        self.meshes = meshes;
+        keep_instances
+    }
+
+    /// an object counts as transparent if one of the instances that are drawn is not fully
+    /// opaque, the blending needs the things behind it to be drawn first
+    pub fn is_transparent(&self) -> bool {
+        self.meshes.iter()
+            .flat_map(|mesh| mesh.instances.iter())
+            .any(|instance| instance.visible && instance.color.w < 1.0)
+    }
+
     pub fn translate(&mut self, dx: Vector3<f32>) {
 
     }
@@ -459,3 +1751,99 @@ impl Object {
     pub fn move_instance(&mut self, dx: Vector3<f32>, id: usize) {
     }
 }
+
+/// The indices of the objects in the order they are drawn: by render order first and within the
+/// same render order the opaque objects before the transparent ones. The sort is stable, so
+/// objects that are otherwise equal keep the order they have in the slice. The objects
+/// themselves are not moved, the selection and the scene graph refer to them by index.
+pub fn draw_order(objects: &[Object]) -> Vec<usize> {
+    let mut order = (0..objects.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| (objects[i].render_order, objects[i].is_transparent()));
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simplify_halves_a_plane() {
+        let (vertices, indices) = primitives::grid_plane(8, 8, 2.0, primitives::IndexLayout::TriangleList);
+        let (simple_vertices, simple_indices) = simplify(&vertices, &indices, 0.5);
+        assert_eq!(simple_indices.len(), indices.len() / 2);
+        assert!(simple_vertices.len() < vertices.len());
+        assert!(simple_indices.iter().all(|&i| (i as usize) < simple_vertices.len()));
+        assert!(validate(&simple_vertices, &simple_indices).degenerate_triangles == 0);
+        assert_eq!(Aabb::from_vertices(&simple_vertices), Aabb::from_vertices(&vertices));
+    }
+
+    // The corners of a unit cube that share their vertices get normals that point straight out
+    // of the corner, along the diagonal. The normals are weighted by area, so each face is split
+    // along the diagonal between the corners of one tetrahedron (0, 3, 5 and 6): every corner
+    // then gets the same weight from its three faces.
+    #[test]
+    fn cube_corner_normals_point_along_the_diagonals() {
+        // corner i has x, y and z set by its bits 1, 2 and 4
+        let mut vertices = (0..8u32).map(|i| RawVertex {
+            pos: [0, 1, 2].map(|bit| if i & (1 << bit) == 0 { -0.5 } else { 0.5 }),
+            tex_ccord: [0.0, 0.0],
+            norm: [0.0, 0.0, 0.0],
+        }).collect::<Vec<_>>();
+        // counter clockwise seen from the outside, starting at a corner of the tetrahedron
+        let faces: [[u32; 4]; 6] = [[0, 2, 3, 1], [5, 7, 6, 4], [0, 4, 6, 2], [3, 7, 5, 1], [0, 1, 5, 4], [6, 7, 3, 2]];
+        let indices = faces.iter().flat_map(|[a, b, c, d]| [*a, *b, *c, *a, *c, *d]).collect::<Vec<_>>();
+        compute_normals(&mut vertices, &indices);
+        for vertex in &vertices {
+            let expected = Vector3::from(vertex.pos).normalize();
+            let normal = Vector3::from(vertex.norm);
+            assert!((normal - expected).magnitude() <= 1e-6, "the corner {:?} has the normal {:?}", vertex.pos, normal);
+        }
+    }
+
+    // a unit cube turned by 45 degrees about y, stretched to twice its height and moved along x.
+    // Worked out by hand: the corners now reach sqrt(0.5) = 0.7071 out along x and z
+    #[test]
+    fn world_box_of_a_turned_cube() {
+        let cube = Aabb { min: Point3::new(-0.5, -0.5, -0.5), max: Point3::new(0.5, 0.5, 0.5) };
+        let mut instance = instance::Instance::new(std::rc::Rc::new(0));
+        instance.position = Vector3::new(1.0, 0.0, 0.0);
+        instance.rotation = Quaternion::from_angle_y(Deg(45.0));
+        instance.scale = Vector3::new(1.0, 2.0, 1.0);
+        let world = cube.transform(&instance.world_matrix());
+        let half = 0.5f32.sqrt();
+        let expected = Aabb { min: Point3::new(1.0 - half, -1.0, -half), max: Point3::new(1.0 + half, 1.0, half) };
+        assert!((world.min - expected.min).magnitude() <= 1e-5 && (world.max - expected.max).magnitude() <= 1e-5,
+            "the box is {:?} instead of {:?}", world, expected);
+    }
+
+    // the render loop draws the objects in this order, the lower render order first and the
+    // objects with the same render order in the order of the scene
+    #[test]
+    fn objects_are_drawn_by_render_order() {
+        let objects = [("overlay", 2), ("ground", -1), ("teapot", 0), ("cup", 0)].map(|(name, render_order)| {
+            let mut object = Object::new(name.to_string());
+            object.render_order = render_order;
+            object
+        });
+        let names = draw_order(&objects).into_iter().map(|i| objects[i].name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["ground", "teapot", "cup", "overlay"]);
+    }
+
+    // two good triangles of a square, one with an index past the end, one with two indices past
+    // the end, one with a corner twice and one with its corners on a line. Vertex 4 is only
+    // used by the flat triangle, vertex 5 by nothing, the two indices at the end are no triangle
+    #[test]
+    fn repair_keeps_the_good_triangles() {
+        let vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [2.0, 0.0, 0.0], [5.0, 5.0, 5.0]]
+            .map(|pos| RawVertex { pos, tex_ccord: [0.0, 0.0], norm: [0.0, 0.0, 1.0] });
+        let indices = [0, 1, 2, 1, 3, 2, 0, 1, 9, 7, 8, 3, 2, 2, 3, 0, 1, 4, 0, 1];
+        let expected = MeshReport { degenerate_triangles: 2, out_of_range_indices: 3, unreferenced_vertices: 1 };
+        assert_eq!(validate(&vertices, &indices), expected);
+
+        let (repaired_vertices, repaired_indices, report) = repair(&vertices, &indices);
+        assert_eq!(report, expected);
+        assert_eq!(repaired_vertices.iter().map(|v| v.pos).collect::<Vec<_>>(), vertices[..4].iter().map(|v| v.pos).collect::<Vec<_>>());
+        assert_eq!(repaired_indices, [0, 1, 2, 1, 3, 2]);
+        assert!(validate(&repaired_vertices, &repaired_indices).is_clean());
+    }
+}
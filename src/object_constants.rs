@@ -0,0 +1,192 @@
+// Small data that changes from object to object (like a tint color) doesn't need its own uniform
+// buffer that is written before every draw. Push constants are a few bytes that are recorded
+// straight into the render pass, right before the draw call that uses them.
+//
+// Not every device has push constants (WebGL doesn't, and on others `max_push_constant_size` can
+// be too small), so there is a fallback: before the render pass the values of all objects are
+// written into one uniform buffer, one slot per object. Every draw then binds that buffer at
+// group 3 with a dynamic offset that points to the slot of the object. The shaders are written
+// for push constants, for the fallback the declaration of the `object` variable is swapped for a
+// uniform (see `shader_source`), so the rest of the shader doesn't know the difference.
+use std::mem;
+
+use crate::stats::RenderStats;
+
+/// the bind group the fallback uniform is bound to
+pub const FALLBACK_BIND_GROUP: u32 = 3;
+
+const PUSH_CONSTANT_DECLARATION: &str = "var<push_constant> object: ObjectConstants;";
+const UNIFORM_DECLARATION: &str = "@group(3) @binding(0)\nvar<uniform> object: ObjectConstants;";
+
+// this needs to match the ObjectConstants struct in the shaders
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ObjectConstants {
+    /// the rgb color replaces the instance color by the amount in a, so all zeros (which is also
+    /// what unset push constants are) keeps the instance colors
+    pub color_override: [f32; 4],
+}
+
+/// the number of bytes of push constants that are needed
+pub const PUSH_CONSTANT_SIZE: u32 = mem::size_of::<ObjectConstants>() as u32;
+
+fn has_push_constants(features: wgpu::Features, limits: &wgpu::Limits) -> bool {
+    features.contains(wgpu::Features::PUSH_CONSTANTS) && limits.max_push_constant_size >= PUSH_CONSTANT_SIZE
+}
+
+/// Check if the adapter can give us the push constants, this decides if they are requested for
+/// the device. The GL backend only emulates them with ordinary uniforms, so nothing is gained
+/// there (and the emulation in wgpu 0.18 reads the data unaligned), it gets the fallback.
+pub fn push_constants_supported(adapter: &wgpu::Adapter) -> bool {
+    adapter.get_info().backend != wgpu::Backend::Gl && has_push_constants(adapter.features(), &adapter.limits())
+}
+
+// the uniform buffer that holds the constants of all objects on devices without push constants
+struct UniformFallback {
+    gpu_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    // the distance between two slots, the dynamic offsets need to be aligned
+    stride: u64,
+    // the number of slots that fit into the gpu buffer
+    capacity: usize,
+}
+
+/// Hands the per object constants to the shaders, either as push constants or through the
+/// fallback uniform. The values are set for all objects with `prepare` before the render pass
+/// and then `apply` is called with the index of the object before each of its draws.
+pub struct ObjectConstantBinding {
+    // None if the push constants are used
+    fallback: Option<UniformFallback>,
+    values: Vec<ObjectConstants>,
+}
+
+impl ObjectConstantBinding {
+    /// uses push constants if the device was created with them, otherwise the uniform fallback
+    pub fn new(device: &wgpu::Device) -> Self {
+        let fallback = if has_push_constants(device.features(), &device.limits()) {
+            None
+        } else {
+            log::info!("the device has no push constants, the per object data is put into a uniform buffer");
+            let bind_group_layout = device.create_bind_group_layout(&Self::describe());
+            let stride = PUSH_CONSTANT_SIZE.max(device.limits().min_uniform_buffer_offset_alignment) as u64;
+            let capacity = 16;
+            let (gpu_buffer, bind_group) = Self::create_uniform(device, &bind_group_layout, stride, capacity);
+            Some(UniformFallback { gpu_buffer, bind_group_layout, bind_group, stride, capacity })
+        };
+        Self { fallback, values: Vec::new() }
+    }
+
+    pub fn uses_push_constants(&self) -> bool {
+        self.fallback.is_none()
+    }
+
+    fn create_uniform(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        stride: u64,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let gpu_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Object constants uniform buffer"),
+            size: stride * capacity as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Object constants bind group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                // the binding only covers one slot, the dynamic offset selects which one
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &gpu_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(PUSH_CONSTANT_SIZE as u64),
+                }),
+            }],
+        });
+        (gpu_buffer, bind_group)
+    }
+
+    pub fn describe() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Object constants bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        }
+    }
+
+    /// the push constant ranges for the pipeline layout, empty for the fallback
+    pub fn push_constant_ranges(&self) -> Vec<wgpu::PushConstantRange> {
+        match self.fallback {
+            None => vec![wgpu::PushConstantRange {
+                stages: wgpu::ShaderStages::FRAGMENT,
+                range: 0..PUSH_CONSTANT_SIZE,
+            }],
+            Some(_) => Vec::new(),
+        }
+    }
+
+    /// the layout that has to be added to the pipeline layout at `FALLBACK_BIND_GROUP`, only
+    /// needed for the fallback
+    pub fn bind_group_layout(&self) -> Option<&wgpu::BindGroupLayout> {
+        self.fallback.as_ref().map(|fallback| &fallback.bind_group_layout)
+    }
+
+    /// The shader source for this device. The shaders declare `object` as a push constant, for
+    /// the fallback the declaration is replaced by the uniform binding.
+    pub fn shader_source(&self, source: &str) -> String {
+        assert!(source.contains(PUSH_CONSTANT_DECLARATION), "the shader does not declare the object constants");
+        match self.fallback {
+            None => source.to_string(),
+            Some(_) => source.replace(PUSH_CONSTANT_DECLARATION, UNIFORM_DECLARATION),
+        }
+    }
+
+    /// set the constants of all objects for the next render pass, for the fallback they are
+    /// written to the uniform buffer (which grows if they don't fit)
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, values: Vec<ObjectConstants>) {
+        if let Some(UniformFallback { gpu_buffer, bind_group_layout, bind_group, stride, capacity }) = &mut self.fallback {
+            if values.len() > *capacity {
+                *capacity = values.len().next_power_of_two();
+                (*gpu_buffer, *bind_group) = Self::create_uniform(device, bind_group_layout, *stride, *capacity);
+            }
+            if !values.is_empty() {
+                let mut data = vec![0u8; *stride as usize * values.len()];
+                for (slot, value) in data.chunks_mut(*stride as usize).zip(values.iter()) {
+                    slot[..PUSH_CONSTANT_SIZE as usize].copy_from_slice(bytemuck::bytes_of(value));
+                }
+                queue.write_buffer(gpu_buffer, 0, &data);
+                RenderStats::record_buffer_write();
+            }
+        }
+        self.values = values;
+    }
+
+    /// make the constants of the object at `index` (in the order they were given to `prepare`)
+    /// visible to the following draw calls
+    pub fn apply<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: usize) {
+        match &self.fallback {
+            None => {
+                let value = self.values.get(index).copied().unwrap_or_default();
+                render_pass.set_push_constants(wgpu::ShaderStages::FRAGMENT, 0, bytemuck::bytes_of(&value));
+            }
+            Some(UniformFallback { bind_group, stride, capacity, .. }) => {
+                // the offset has to stay inside the buffer, an object that was not prepared reads
+                // whatever is left in the slot
+                let slot = index.min(capacity - 1) as u64;
+                render_pass.set_bind_group(FALLBACK_BIND_GROUP, bind_group, &[(slot * stride) as wgpu::DynamicOffset]);
+                RenderStats::record_bind_group_switch();
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+// The overlay is an object that is drawn on top of the scene with its own camera, like the tool
+// that is held in a first person game. If it was part of the scene it would poke into walls
+// when the camera gets close to them, and it would need a near plane so close that the depth of
+// the world loses its precision. Instead it gets its own pass after the scene: the depth buffer
+// of the overlay is cleared, so the world can't hide it, and the overlay camera has a small
+// field of view and near and far planes that fit the object.
+//
+// The overlay camera never moves, its position is the eye of the main camera. The instances of
+// the overlay object are placed relative to it (x to the right, y up and -z forward), so they
+// stay at the same place on the screen however the main camera moves.
+use crate::colored_mesh_renderer::ColoredMeshRenderer;
+use crate::model::DrawMesh;
+use crate::renderer::{self, DescribeRenderPipeline};
+use crate::{camera, clip, fog, model, object_constants};
+
+/// the field of view of the overlay camera in degrees
+pub const OVERLAY_FIELD_OF_VIEW: f32 = 40.0;
+/// the near and far planes of the overlay camera, the object has to be between them
+pub const OVERLAY_ZNEAR: f32 = 0.01;
+pub const OVERLAY_ZFAR: f32 = 10.0;
+
+pub struct OverlayPass {
+    pub camera: camera::Camera,
+    pub object: model::Object,
+    renderer: ColoredMeshRenderer,
+    // the overlay is not in the world, so the fog and the section planes of the world don't
+    // apply to it. Both stay switched off.
+    fog_uniform: fog::FogUniform,
+    clip_uniform: clip::ClipUniform,
+    // the overlay has its own depth buffer so the depth of the world stays available after the
+    // frame (see `App::world_position_under_cursor`)
+    depth_texture: model::Texture,
+}
+
+impl OverlayPass {
+    /// The overlay draws `object` for the scene target that `scene_config` describes. The
+    /// instances of the object have to be placed in front of the camera already.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        object: model::Object,
+        scene_config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        object_constants: &object_constants::ObjectConstantBinding,
+    ) -> Self {
+        let mut camera = camera::Camera::new(
+            (0.0, 0.0, 0.0),
+            cgmath::Deg(0.0),
+            cgmath::Deg(-90.0),
+            cgmath::Deg(OVERLAY_FIELD_OF_VIEW),
+            scene_config.width,
+            scene_config.height,
+            OVERLAY_ZNEAR,
+            OVERLAY_ZFAR,
+            device,
+            queue,
+        );
+        camera.set_perspective(cgmath::Deg(OVERLAY_FIELD_OF_VIEW), camera.aspect_ratio, OVERLAY_ZNEAR, OVERLAY_ZFAR);
+        camera.update_uniform(queue);
+        let mut fog_uniform = fog::FogUniform::new(device);
+        fog_uniform.update(&fog::FogSettings::new([0.0; 3]), queue);
+        let mut clip_uniform = clip::ClipUniform::new(device);
+        clip_uniform.update(&clip::ClipSettings::default(), queue);
+        let renderer = Self::create_renderer(device, &camera, &fog_uniform, &clip_uniform, scene_config, sample_count, object_constants);
+        let depth_texture = model::Texture::create_depth_texture(device, scene_config, sample_count, "overlay depth texture");
+        Self { camera, object, renderer, fog_uniform, clip_uniform, depth_texture }
+    }
+
+    // the overlay is always drawn with the colored mesh renderer and without a depth prepass,
+    // whatever the scene uses
+    fn create_renderer(
+        device: &wgpu::Device,
+        camera: &camera::Camera,
+        fog_uniform: &fog::FogUniform,
+        clip_uniform: &clip::ClipUniform,
+        scene_config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        object_constants: &object_constants::ObjectConstantBinding,
+    ) -> ColoredMeshRenderer {
+        ColoredMeshRenderer::new(
+            device,
+            &camera.uniform.lock().unwrap().bind_group_layout,
+            &fog_uniform.bind_group_layout,
+            &clip_uniform.bind_group_layout,
+            object_constants,
+            scene_config,
+            Some(model::Texture::DEPTH_FORMAT),
+            sample_count,
+            wgpu::PrimitiveTopology::TriangleList,
+            false,
+            wgpu::PolygonMode::Line,
+            None,
+        )
+    }
+
+    /// rebuild the pipeline when the format or the sample count of the scene target changes
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        scene_config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        object_constants: &object_constants::ObjectConstantBinding,
+    ) {
+        self.renderer = Self::create_renderer(device, &self.camera, &self.fog_uniform, &self.clip_uniform, scene_config, sample_count, object_constants);
+    }
+
+    /// adapt the camera and the depth buffer to the new size or sample count of the scene target
+    pub fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scene_config: &wgpu::SurfaceConfiguration, sample_count: u32) {
+        self.camera.resize_cpu_only(scene_config.width, scene_config.height);
+        self.camera.update_uniform(queue);
+        self.depth_texture = model::Texture::create_depth_texture(device, scene_config, sample_count, "overlay depth texture");
+    }
+
+    /// Draw the overlay on top of what is in the scene target. `view` and `resolve_target` are
+    /// the same as for the scene pass, the colors are kept and only the depth is cleared. The
+    /// object constants of the overlay meshes follow the ones of the scene, starting at
+    /// `first_constant`.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        object_constants: &object_constants::ObjectConstantBinding,
+        first_constant: usize,
+    ) {
+        let color_attachment = [Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+        })];
+        let depth_stencil_attachment = ColoredMeshRenderer::describe_depth_stencil(Some(&self.depth_texture.view), renderer::DEPTH_CLEAR);
+        let camera_uniform = self.camera.uniform.lock().unwrap();
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overlay render pass"),
+            ..ColoredMeshRenderer::describe_render_pass(&color_attachment, depth_stencil_attachment)
+        });
+        render_pass.set_pipeline(&self.renderer.pipeline);
+        render_pass.set_bind_group(1, &self.fog_uniform.bind_group, &[]);
+        render_pass.set_bind_group(2, &self.clip_uniform.bind_group, &[]);
+        for (i, mesh) in self.object.meshes.iter().enumerate() {
+            object_constants.apply(&mut render_pass, first_constant + i);
+            ColoredMeshRenderer::draw_mesh(&mut render_pass, mesh, &camera_uniform.bind_group);
+        }
+    }
+}
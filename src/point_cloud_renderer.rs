@@ -0,0 +1,236 @@
+// Renderer for point clouds, like the ones that come out of a 3D scanner. A single pixel per
+// point is hard to see and flickers when the camera moves, so every point is drawn as a small
+// round splat instead: a quad that faces the camera with a circle cut out of it in the fragment
+// shader. The edge of the circle fades out over a pixel, which smooths it without MSAA.
+//
+// The points are the instances of the quad, the quad itself has no vertex buffer, its corners
+// come from the vertex index. The size of a splat is given in pixels and shrinks with the
+// distance to the camera (see point_cloud.wgsl), so the cloud keeps its depth.
+use cgmath::Point3;
+use std::mem;
+
+use crate::stats::RenderStats;
+
+/// A point of a point cloud as it is laid out in the vertex buffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Point {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl Point {
+    pub fn new(position: Point3<f32>, color: [f32; 4]) -> Self {
+        Self { position: position.into(), color }
+    }
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Point>() as wgpu::BufferAddress,
+            // every point is an instance of the quad
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// The points of a cloud in a vertex buffer, the positions are in world coordinates
+pub struct PointCloud {
+    pub name: String,
+    gpu_buffer: wgpu::Buffer,
+    count: u32,
+}
+
+impl PointCloud {
+    pub fn new(name: String, points: &[Point], device: &wgpu::Device) -> Self {
+        use wgpu::util::DeviceExt;
+        let gpu_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} point buffer", name)),
+            contents: bytemuck::cast_slice(points),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        Self { name, gpu_buffer, count: points.len() as u32 }
+    }
+
+    pub fn point_count(&self) -> u32 {
+        self.count
+    }
+}
+
+/// How big the points are drawn. The diameter in pixels is
+/// `max(size / (1 + attenuation * distance), min_size)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointSettings {
+    /// the diameter in pixels of a point right in front of the camera
+    pub size: f32,
+    /// how fast the points shrink with the distance, 0 draws all points with the same size
+    pub attenuation: f32,
+    /// the smallest diameter in pixels, so far away points don't disappear
+    pub min_size: f32,
+}
+
+impl Default for PointSettings {
+    fn default() -> Self {
+        Self { size: 24.0, attenuation: 0.2, min_size: 1.0 }
+    }
+}
+
+impl PointSettings {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.size, 1.0..=64.0).text("point size (px)"));
+        ui.add(egui::Slider::new(&mut self.attenuation, 0.0..=2.0).text("point attenuation"));
+        ui.add(egui::Slider::new(&mut self.min_size, 0.5..=8.0).text("minimal point size (px)"));
+    }
+}
+
+// this needs to match the PointSettings struct in the shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawPointSettings {
+    viewport: [f32; 2],
+    size: f32,
+    attenuation: f32,
+    min_size: f32,
+    _padding: [f32; 3],
+}
+
+pub struct PointCloudRenderer {
+    pub pipeline: wgpu::RenderPipeline,
+    settings_buffer: wgpu::Buffer,
+    settings_bind_group: wgpu::BindGroup,
+    // what is in the settings buffer, it's only written when something changes
+    uploaded: Option<RawPointSettings>,
+}
+
+impl PointCloudRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        // has to match the sample count of the scene render pass the points are drawn in
+        sample_count: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Point Cloud Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/point_cloud.wgsl").into()),
+        });
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Point settings uniform buffer"),
+            size: mem::size_of::<RawPointSettings>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let settings_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Point settings bind group"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &settings_bind_group_layout,
+            label: Some("Point settings bind group"),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: settings_buffer.as_entire_binding(),
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Point cloud pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &settings_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Point Cloud Renderer"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Point::desc()],
+            },
+            // the quads are built facing the camera, there is no back side to cull
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            // the points hide each other and the geometry behind them. The faded edge writes
+            // the depth as well, which can leave a thin seam where a point covers another one
+            // that is drawn after it, but sorting millions of points is not worth it
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        Self { pipeline, settings_buffer, settings_bind_group, uploaded: None }
+    }
+
+    /// send the settings to the GPU if they changed, `width` and `height` are the size of the
+    /// target the points are drawn into
+    pub fn prepare(&mut self, settings: &PointSettings, width: u32, height: u32, queue: &wgpu::Queue) {
+        let raw = RawPointSettings {
+            viewport: [width as f32, height as f32],
+            size: settings.size,
+            attenuation: settings.attenuation,
+            min_size: settings.min_size,
+            _padding: [0.0; 3],
+        };
+        if self.uploaded != Some(raw) {
+            queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[raw]));
+            RenderStats::record_buffer_write();
+            self.uploaded = Some(raw);
+        }
+    }
+
+    /// draw the points of the cloud, this has to be called inside the scene render pass as it
+    /// shares the depth buffer with the meshes
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, cloud: &'a PointCloud, camera_bind_group: &'a wgpu::BindGroup) {
+        if cloud.count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.settings_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+        RenderStats::record_bind_group_switch();
+        render_pass.set_vertex_buffer(0, cloud.gpu_buffer.slice(..));
+        render_pass.draw(0..4, 0..cloud.count);
+        // two triangles per point
+        RenderStats::record_draw(2 * cloud.count as u64);
+    }
+}
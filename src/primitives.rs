@@ -0,0 +1,133 @@
+// Meshes that are generated instead of loaded from a file. The generators return the vertices
+// and indices, which are turned into a `model::Surface` with the topology the indices are for.
+//
+// Large regular meshes like terrain grids can use triangle strips instead of a triangle list. In a
+// strip every new index forms a triangle with the two indices before it, so a row of a grid with
+// n quads needs 2n + 2 indices instead of 6n. The rows are separated with the primitive restart
+// index, which starts a new strip without drawing a triangle.
+use crate::model::RawVertex;
+
+/// the index that ends a strip and starts the next one. wgpu always uses the largest value of the
+/// index format, the meshes have u32 indices.
+pub const PRIMITIVE_RESTART: u32 = u32::MAX;
+
+/// How the generated indices form the triangles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexLayout {
+    /// three indices for every triangle
+    TriangleList,
+    /// one strip per row of the mesh, separated by `PRIMITIVE_RESTART`
+    TriangleStrip,
+}
+
+impl IndexLayout {
+    /// the topology the pipeline that draws the mesh needs
+    pub fn topology(&self) -> wgpu::PrimitiveTopology {
+        match self {
+            IndexLayout::TriangleList => wgpu::PrimitiveTopology::TriangleList,
+            IndexLayout::TriangleStrip => wgpu::PrimitiveTopology::TriangleStrip,
+        }
+    }
+}
+
+/// The number of triangles the indices draw with the topology, strips lose two indices at the
+/// start of every strip. Topologies that draw no triangles give 0.
+pub fn triangle_count(indices: &[u32], topology: wgpu::PrimitiveTopology) -> u64 {
+    match topology {
+        wgpu::PrimitiveTopology::TriangleList => indices.len() as u64 / 3,
+        wgpu::PrimitiveTopology::TriangleStrip => indices
+            .split(|&i| i == PRIMITIVE_RESTART)
+            .map(|strip| strip.len().saturating_sub(2) as u64)
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// The edges of the triangles the indices draw with the topology, every edge only once no
+/// matter how many triangles share it. The smaller index comes first and the edges are sorted,
+/// so the same mesh always gives the same edges. Topologies that draw no triangles have none.
+pub fn edges(indices: &[u32], topology: wgpu::PrimitiveTopology) -> Vec<[u32; 2]> {
+    let triangles: Vec<[u32; 3]> = match topology {
+        wgpu::PrimitiveTopology::TriangleList => indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect(),
+        wgpu::PrimitiveTopology::TriangleStrip => indices
+            .split(|&i| i == PRIMITIVE_RESTART)
+            .flat_map(|strip| strip.windows(3).map(|t| [t[0], t[1], t[2]]))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let mut edges = std::collections::BTreeSet::new();
+    for [a, b, c] in triangles {
+        for (x, y) in [(a, b), (b, c), (c, a)] {
+            // degenerate triangles (like the ones that join strips) have edges of length zero
+            if x != y {
+                edges.insert([x.min(y), x.max(y)]);
+            }
+        }
+    }
+    edges.into_iter().collect()
+}
+
+/// A flat grid in the xz plane, centered at the origin with `size` as the length of its sides.
+/// It has `columns` x `rows` quads that are split into two triangles each, the normals point up.
+/// The triangles are the same for both index layouts (including the winding as the GPU sees it),
+/// so the two layouts render the same image.
+pub fn grid_plane(columns: u32, rows: u32, size: f32, layout: IndexLayout) -> (Vec<RawVertex>, Vec<u32>) {
+    let columns = columns.max(1);
+    let rows = rows.max(1);
+    let mut vertices = Vec::with_capacity(((columns + 1) * (rows + 1)) as usize);
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let u = column as f32 / columns as f32;
+            let v = row as f32 / rows as f32;
+            vertices.push(RawVertex {
+                pos: [(u - 0.5) * size, 0.0, (v - 0.5) * size],
+                tex_ccord: [u, v],
+                norm: [0.0, 1.0, 0.0],
+            });
+        }
+    }
+    // the vertex in the column of this row and the one below it
+    let index = |column: u32, row: u32| row * (columns + 1) + column;
+    let mut indices = Vec::new();
+    match layout {
+        IndexLayout::TriangleList => {
+            for row in 0..rows {
+                for column in 0..columns {
+                    let (top_left, bottom_left) = (index(column, row), index(column, row + 1));
+                    let (top_right, bottom_right) = (index(column + 1, row), index(column + 1, row + 1));
+                    // the same triangles as the strip, in a strip every second triangle has its
+                    // first two vertices swapped to keep the winding
+                    indices.extend([top_left, bottom_left, top_right]);
+                    indices.extend([top_right, bottom_left, bottom_right]);
+                }
+            }
+        }
+        IndexLayout::TriangleStrip => {
+            for row in 0..rows {
+                if row > 0 {
+                    indices.push(PRIMITIVE_RESTART);
+                }
+                for column in 0..=columns {
+                    indices.extend([index(column, row), index(column, row + 1)]);
+                }
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+/// `count` points spread evenly over a sphere around the origin, on a spiral from the bottom to
+/// the top (the Fibonacci sphere). Neighbouring points are about the same distance apart
+/// everywhere, there is no bunching at the poles like with a grid of angles.
+pub fn sphere_points(count: u32, radius: f32) -> Vec<cgmath::Point3<f32>> {
+    // every point turns further around the axis by the golden angle
+    let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+    (0..count)
+        .map(|i| {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let ring = (1.0 - y * y).sqrt();
+            let angle = golden_angle * i as f32;
+            cgmath::Point3::new(ring * angle.cos(), y, ring * angle.sin()) * radius
+        })
+        .collect()
+}
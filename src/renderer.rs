@@ -1,16 +1,267 @@
-/// A render pipeline manager that keeps track of different render pipelines in the same place.
+// The pipelines that draw the meshes of the scene are kept in the same place, the
+// PipelineController, where they are registered by their name. The app registers the wireframe
+// and the solid pipeline, an object can pick one of them by name (see `model::Object::pipeline`)
+// and the others are drawn with the pipeline of the render mode.
+use std::any::Any;
+use std::collections::HashMap;
 
+use crate::model;
 
-/// The actual rendering engine. At it's core will be the PipelineDescriptor that will be added to
-/// the PipelineController. A pipeline is priciply coupled to a shader, so the base Type will be a
-/// type called PipelineWithSource. The pipeline with source will be generic over the Type of the
-/// pipeline as we can describe both a compute and a render pipeline
+/// The first pass that uses a depth buffer clears it to the far plane (1.0). Passes after it
+/// that have to test against the depth of the earlier passes load it with `LoadOp::Load`.
+pub const DEPTH_CLEAR: wgpu::LoadOp<f32> = wgpu::LoadOp::Clear(1.0);
+
+/// The background of the scene unless the app is told otherwise, almost black. The color is
+/// linear, the sRGB targets convert it when they are cleared.
+pub const DEFAULT_CLEAR_COLOR: wgpu::Color = wgpu::Color { r: 0.001, g: 0.001, b: 0.001, a: 1.0 };
 
 pub trait DescribeRenderPipeline {
-    fn describe_color_attachment(view: Option<&wgpu::TextureView>) -> Option<wgpu::RenderPassColorAttachment>;
-    fn describe_depth_stencil(view: Option<&wgpu::TextureView>) -> Option<wgpu::RenderPassDepthStencilAttachment>;
+    /// the color attachment of a pass that starts with clearing the view to `clear_color`
+    fn describe_color_attachment<'a>(view: Option<&'a wgpu::TextureView>, resolve_target: Option<&'a wgpu::TextureView>, clear_color: wgpu::Color) -> Option<wgpu::RenderPassColorAttachment<'a>>;
+    /// the depth attachment of a pass, `load` decides if the depth starts over (`DEPTH_CLEAR`)
+    /// or if the pass continues with the depth of the passes before it (`LoadOp::Load`)
+    fn describe_depth_stencil<'a>(view: Option<&'a wgpu::TextureView>, load: wgpu::LoadOp<f32>) -> Option<wgpu::RenderPassDepthStencilAttachment<'a>>;
     fn describe_render_pass<'att_list, 'attachment> (
         color_attachment_views: &'att_list [Option<wgpu::RenderPassColorAttachment<'attachment>>],
         depth_stencil_view: Option<wgpu::RenderPassDepthStencilAttachment<'attachment>>,
     ) -> wgpu::RenderPassDescriptor<'att_list, 'attachment> where 'att_list: 'attachment ;
 }
+
+/// The name of the colored wireframe pipeline in the `PipelineController` of the app
+pub const WIREFRAME_PIPELINE: &str = "wireframe";
+/// The name of the pipeline that fills the triangles with the color of the instances
+pub const SOLID_PIPELINE: &str = "solid";
+
+/// The culling of the scene pipelines, in the order C cycles through them. The culling is part
+/// of the pipeline, so the wireframe and the solid pipeline are registered once for every mode
+/// (see `culled_pipeline_name`) and switching only picks another one.
+pub const CULL_MODES: [Option<wgpu::Face>; 3] = [None, Some(wgpu::Face::Back), Some(wgpu::Face::Front)];
+
+pub fn cull_mode_name(cull_mode: Option<wgpu::Face>) -> &'static str {
+    match cull_mode {
+        None => "No culling",
+        Some(wgpu::Face::Back) => "Cull back faces",
+        Some(wgpu::Face::Front) => "Cull front faces",
+    }
+}
+
+/// The name the pipeline `name` is registered under when it culls with `cull_mode`. Without
+/// culling it is the plain name, that is what the objects with a pipeline of their own get.
+pub fn culled_pipeline_name(name: &str, cull_mode: Option<wgpu::Face>) -> String {
+    match cull_mode {
+        None => name.to_string(),
+        Some(wgpu::Face::Back) => format!("{} (cull back)", name),
+        Some(wgpu::Face::Front) => format!("{} (cull front)", name),
+    }
+}
+
+/// A renderer that draws the meshes of the scene and can be stored in the `PipelineController`.
+/// `DescribeRenderPipeline` and `model::DrawMesh` only have static functions, so renderers can
+/// be used without an instance, but that also means they can't be trait objects. This trait
+/// takes `&self` instead and the renderers implement it by forwarding to their static functions.
+pub trait ScenePipeline: Any {
+    /// the pipeline that draws the meshes
+    fn pipeline(&self) -> &wgpu::RenderPipeline;
+    /// the pipeline that only writes the depth, if there is a depth prepass
+    fn prepass_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        None
+    }
+    /// the pipeline for the surfaces with the decal level (see `model::Surface::decal_level`)
+    fn decal_pipeline(&self, _level: u8) -> Option<&wgpu::RenderPipeline> {
+        None
+    }
+    /// the pipeline that blends the instances with an alpha below 1, None if the pipeline
+    /// draws them like the opaque ones
+    fn transparent_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        None
+    }
+    /// draw all instances of the mesh, the pipeline has to be set on the render pass already
+    fn draw_mesh<'a>(&self, render_pass: &mut wgpu::RenderPass<'a>, mesh: &'a model::Surface, camera_bind_group: &'a wgpu::BindGroup);
+    /// to get back the renderer behind the trait object, see `PipelineController::get_as`
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// The scene pipelines by their name. The pipelines are switched in the middle of the scene
+/// pass, so they all have to use the bind group layouts of the colored mesh renderer (camera,
+/// fog, clip planes and the object constants).
+#[derive(Default)]
+pub struct PipelineController {
+    pipelines: HashMap<String, Box<dyn ScenePipeline>>,
+}
+
+impl PipelineController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the pipeline under the name. A pipeline with the same name is replaced, which is
+    /// how the pipelines are rebuilt when the sample count of the scene changes.
+    pub fn add_pipeline(&mut self, name: &str, pipeline: impl ScenePipeline) {
+        log::debug!("registered pipeline {:?}", name);
+        self.pipelines.insert(name.to_string(), Box::new(pipeline));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn ScenePipeline> {
+        self.pipelines.get(name).map(|pipeline| pipeline.as_ref())
+    }
+
+    /// the pipeline with the name if it is a `T`, for the parts of a renderer that are not in
+    /// `ScenePipeline` (like the hidden line pipelines of the colored mesh renderer)
+    pub fn get_as<T: ScenePipeline>(&self, name: &str) -> Option<&T> {
+        self.get(name)?.as_any().downcast_ref()
+    }
+
+    /// like `get_as`, to change the pipeline in place (like reloading its shader)
+    #[allow(dead_code)] // only used by the shader reloading of debug builds
+    pub fn get_as_mut<T: ScenePipeline>(&mut self, name: &str) -> Option<&mut T> {
+        self.pipelines.get_mut(name)?.as_any_mut().downcast_mut()
+    }
+
+    /// the names of all pipelines in alphabetical order
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.pipelines.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+/// The effective state of a render pipeline. The pipelines build their descriptors from this
+/// struct so that what is logged/displayed is what the GPU actually does.
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    pub label: &'static str,
+    pub topology: wgpu::PrimitiveTopology,
+    pub strip_index_format: Option<wgpu::IndexFormat>,
+    pub front_face: wgpu::FrontFace,
+    pub cull_mode: Option<wgpu::Face>,
+    pub polygon_mode: wgpu::PolygonMode,
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub depth_write_enabled: bool,
+    pub depth_compare: wgpu::CompareFunction,
+    pub color_format: wgpu::TextureFormat,
+    pub blend: Option<wgpu::BlendState>,
+    pub sample_count: u32,
+    /// the entries of every bind group layout in the order of the bind group index
+    #[allow(dead_code)] // only read through the Debug output
+    pub bind_group_layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    #[allow(dead_code)] // only read through the Debug output
+    pub push_constant_ranges: Vec<wgpu::PushConstantRange>,
+}
+
+impl PipelineConfig {
+    pub fn primitive_state(&self) -> wgpu::PrimitiveState {
+        wgpu::PrimitiveState {
+            topology: self.topology,
+            strip_index_format: self.strip_index_format,
+            front_face: self.front_face,
+            cull_mode: self.cull_mode,
+            unclipped_depth: false,
+            polygon_mode: self.polygon_mode,
+            conservative: false,
+        }
+    }
+
+    pub fn depth_stencil_state(&self) -> Option<wgpu::DepthStencilState> {
+        self.depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: self.depth_write_enabled,
+            depth_compare: self.depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        })
+    }
+
+    pub fn multisample_state(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: self.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        }
+    }
+
+    /// Pretty print the configuration, this does not touch the GPU
+    pub fn describe(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+/// How the meshes of the scene are drawn, F3 cycles through the modes. The pipelines of all
+/// modes are created with the scene pipelines, switching only picks another one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    /// the colored wireframe of the meshes
+    #[default]
+    Wireframe,
+    /// the meshes filled with their color
+    Solid,
+    /// the wireframe without the edges that are hidden behind the surface of the mesh, it needs
+    /// the line mode of the device
+    HiddenLines,
+    /// the meshes are shaded with the matcap
+    Matcap,
+    /// the meshes are lit by the directional light
+    Lit,
+    /// the edges of the meshes as lines of the width set in the ui, drawn by the
+    /// `WireframeRenderer`
+    ThickLines,
+}
+
+impl RenderMode {
+    /// the name of the pipeline in the `PipelineController` the mode draws the meshes with,
+    /// the matcap, the lit and the thick line mode have a renderer of their own
+    pub fn pipeline_name(&self) -> &'static str {
+        match self {
+            RenderMode::Solid => SOLID_PIPELINE,
+            RenderMode::Wireframe | RenderMode::HiddenLines | RenderMode::Matcap | RenderMode::Lit | RenderMode::ThickLines => WIREFRAME_PIPELINE,
+        }
+    }
+
+    /// all the modes in the order F3 cycles through them
+    pub const ALL: [RenderMode; 6] = [RenderMode::Wireframe, RenderMode::Solid, RenderMode::HiddenLines, RenderMode::Matcap, RenderMode::Lit, RenderMode::ThickLines];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RenderMode::Wireframe => "Wireframe",
+            RenderMode::Solid => "Solid",
+            RenderMode::HiddenLines => "Hidden lines",
+            RenderMode::Matcap => "Matcap",
+            RenderMode::Lit => "Lit",
+            RenderMode::ThickLines => "Thick lines",
+        }
+    }
+
+    /// the mode after this one, the last one is followed by the first one
+    pub fn next(&self) -> RenderMode {
+        let index = Self::ALL.iter().position(|mode| mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// The method used to smooth the jagged edges of the rendered geometry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AntiAliasing {
+    None,
+    /// render every pixel with the given number of samples and resolve them into the screen
+    Msaa(u32),
+    /// render the scene to an offscreen texture and smooth the edges in a post processing pass
+    Fxaa,
+}
+
+impl AntiAliasing {
+    /// the sample count the pipelines and the depth buffer for the scene need to be created with
+    pub fn sample_count(&self) -> u32 {
+        match self {
+            AntiAliasing::Msaa(samples) => *samples,
+            AntiAliasing::None | AntiAliasing::Fxaa => 1,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            AntiAliasing::None => "None".to_string(),
+            AntiAliasing::Msaa(samples) => format!("MSAA x{}", samples),
+            AntiAliasing::Fxaa => "FXAA".to_string(),
+        }
+    }
+}
@@ -1,43 +1,516 @@
+use std::collections::HashMap;
 use std::io::{BufReader, Cursor};
 
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
 
-use crate::model;
+use crate::{buffer_arena, model};
 
+// In the browser there is no file system, the resources are fetched from the web server that
+// serves the page. The `res` folder needs to be served next to the page (see the notes on
+// building for the browser in main.rs). The functions have the same signatures as the native
+// ones, so everything that loads resources works the same in both.
+#[cfg(target_arch = "wasm32")]
+async fn fetch(file_name: &str) -> anyhow::Result<web_sys::Response> {
+    use wasm_bindgen::JsCast;
+    let window = web_sys::window().ok_or_else(|| anyhow::anyhow!("there is no browser window"))?;
+    let url = resource_url(&window, file_name)?;
+    let response = wasm_bindgen_futures::JsFuture::from(window.fetch_with_str(&url))
+        .await
+        .map_err(|e| anyhow::anyhow!("could not fetch {}: {:?}", url, e))?;
+    let response: web_sys::Response = response.dyn_into().map_err(|e| anyhow::anyhow!("{:?}", e))?;
+    if !response.ok() {
+        anyhow::bail!("could not fetch {}: status {}", url, response.status());
+    }
+    Ok(response)
+}
+
+// The url of a resource relative to the base of the document, that is the folder of the page
+// unless it has a <base> element. This way the page doesn't have to be at the root of the server.
+#[cfg(target_arch = "wasm32")]
+fn resource_url(window: &web_sys::Window, file_name: &str) -> anyhow::Result<String> {
+    let document = window.document().ok_or_else(|| anyhow::anyhow!("the window has no document"))?;
+    let base = document.base_uri()
+        .map_err(|e| anyhow::anyhow!("{:?}", e))?
+        .ok_or_else(|| anyhow::anyhow!("the document has no base url"))?;
+    let url = web_sys::Url::new_with_base(&format!("res/{}", file_name), &base)
+        .map_err(|e| anyhow::anyhow!("{} is not a valid resource name: {:?}", file_name, e))?;
+    Ok(url.href())
+}
+
+#[cfg(target_arch = "wasm32")]
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
-    println!("file_name: {:?}", path);
-    let txt = std::fs::read_to_string(path)?;
-    Ok(txt)
+    let response = fetch(file_name).await?;
+    let text = response.text().map_err(|e| anyhow::anyhow!("{:?}", e))?;
+    let text = wasm_bindgen_futures::JsFuture::from(text).await.map_err(|e| anyhow::anyhow!("{:?}", e))?;
+    text.as_string().ok_or_else(|| anyhow::anyhow!("{} is not a text file", file_name))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
+    let response = fetch(file_name).await?;
+    let buffer = response.array_buffer().map_err(|e| anyhow::anyhow!("{:?}", e))?;
+    let buffer = wasm_bindgen_futures::JsFuture::from(buffer).await.map_err(|e| anyhow::anyhow!("{:?}", e))?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+// build.rs copies the resources next to the build output. While an asset is being edited that
+// copy is out of date until the next build, so the file in the source tree is read when it is
+// there. This way reloading an object (see `App::reload_object`) picks up the edit right away.
+#[cfg(not(target_arch = "wasm32"))]
+fn resource_path(file_name: &str) -> std::path::PathBuf {
+    let source = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res").join(file_name);
+    if source.exists() {
+        return source;
+    }
+    std::path::Path::new(env!("OUT_DIR")).join("res").join(file_name)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
+    load_string_from_path(&resource_path(file_name)).await
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(file_name);
+    load_binary_from_path(&resource_path(file_name)).await
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn load_string_from_path(path: &std::path::Path) -> anyhow::Result<String> {
+    println!("file_name: {:?}", path);
+    std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("could not read {:?}: {}", path, e))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn load_binary_from_path(path: &std::path::Path) -> anyhow::Result<Vec<u8>> {
     println!("binary file_name: {:?}", path);
-    let data = std::fs::read(path)?;
-    Ok(data)
+    std::fs::read(path).map_err(|e| anyhow::anyhow!("could not read {:?}: {}", path, e))
+}
+
+// The resources that are built into the binary, so that it runs without the `res` folder, from
+// any working directory and on any machine. Adding a resource is one more name in the list, the
+// names are relative to the `res` folder.
+macro_rules! embedded_resources {
+    ($($name:literal),* $(,)?) => {
+        const EMBEDDED_RESOURCES: &[(&str, &[u8])] = &[
+            $(($name, include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/res/", $name)))),*
+        ];
+    };
+}
+
+embedded_resources![
+    "teapot.obj",
+    "teapot.mtl",
+    "teapot.obj.meta.json",
+];
+
+/// the content of the resource if it is built into the binary
+pub fn embedded(file_name: &str) -> Option<&'static [u8]> {
+    EMBEDDED_RESOURCES.iter().find(|(name, _)| *name == file_name).map(|(_, data)| *data)
+}
+
+/// The location of a resource. The files in the `res` folder come first, so that edits show up
+/// when an object is reloaded, the copy in the binary is used when the file is not there. In
+/// the browser the copy in the binary saves the request to the server.
+pub fn resource_location(file_name: &str) -> Location {
+    #[cfg(not(target_arch = "wasm32"))]
+    let on_disk = resource_path(file_name).exists();
+    #[cfg(target_arch = "wasm32")]
+    let on_disk = false;
+    if !on_disk && embedded(file_name).is_some() {
+        return Location::Embedded(file_name.to_string());
+    }
+    Location::Resource(file_name.to_string())
+}
+
+/// Where a file is read from. A resource is looked up in the `res` folder (see `resource_path`,
+/// in the browser it is fetched from the web server), an embedded resource is built into the
+/// binary (see `embedded`) and a path is read from the file system as it is, so it can be any
+/// file the user picked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Resource(String),
+    Embedded(String),
+    #[cfg(not(target_arch = "wasm32"))]
+    Path(std::path::PathBuf),
+}
+
+impl Location {
+    /// The location of a file that the file at this location refers to, like the MTL of an OBJ
+    /// or the textures of an MTL. Resources refer to other resources, a file on disk to files
+    /// in its folder.
+    pub fn sibling(&self, file_name: &str) -> Location {
+        match self {
+            Location::Resource(_) => Location::Resource(file_name.to_string()),
+            Location::Embedded(_) => Location::Embedded(file_name.to_string()),
+            #[cfg(not(target_arch = "wasm32"))]
+            Location::Path(path) => Location::Path(path.parent().unwrap_or(std::path::Path::new("")).join(file_name)),
+        }
+    }
+
+    /// the location of the file with `suffix` appended to the name of this one
+    fn with_suffix(&self, suffix: &str) -> Location {
+        match self {
+            Location::Resource(name) => Location::Resource(format!("{}{}", name, suffix)),
+            Location::Embedded(name) => Location::Embedded(format!("{}{}", name, suffix)),
+            #[cfg(not(target_arch = "wasm32"))]
+            Location::Path(path) => {
+                let mut path = path.clone().into_os_string();
+                path.push(suffix);
+                Location::Path(path.into())
+            }
+        }
+    }
+
+    /// the file on disk that is read for this location, None for an embedded resource
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn file_path(&self) -> Option<std::path::PathBuf> {
+        match self {
+            Location::Resource(name) => Some(resource_path(name)),
+            Location::Embedded(_) => None,
+            Location::Path(path) => Some(path.clone()),
+        }
+    }
+
+    fn embedded(name: &str) -> anyhow::Result<&'static [u8]> {
+        embedded(name).ok_or_else(|| anyhow::anyhow!("{} is not built into the binary", name))
+    }
+
+    pub async fn load_string(&self) -> anyhow::Result<String> {
+        match self {
+            Location::Resource(name) => load_string(name).await,
+            Location::Embedded(name) => Ok(std::str::from_utf8(Self::embedded(name)?)?.to_string()),
+            #[cfg(not(target_arch = "wasm32"))]
+            Location::Path(path) => load_string_from_path(path).await,
+        }
+    }
+
+    pub async fn load_binary(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Location::Resource(name) => load_binary(name).await,
+            Location::Embedded(name) => Ok(Self::embedded(name)?.to_vec()),
+            #[cfg(not(target_arch = "wasm32"))]
+            Location::Path(path) => load_binary_from_path(path).await,
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Resource(name) => write!(f, "{}", name),
+            Location::Embedded(name) => write!(f, "{} (embedded)", name),
+            #[cfg(not(target_arch = "wasm32"))]
+            Location::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+
+// a texture that was read and decoded by the loading thread, together with the id of the request
+type DecodedTexture = (usize, anyhow::Result<image::DynamicImage>);
+
+struct PendingTexture {
+    name: String,
+    material: Arc<model::Material>,
+    options: model::TextureOptions,
+    // the number of polls (frames) the texture has been waiting for
+    polls: u32,
 }
 
+/// Loads textures without blocking the render thread. Reading the file and decoding the image
+/// is the slow part, that happens on a thread of its own (in the browser it is a future that
+/// runs while the page is idle). The decoded image is sent back through a channel, and `poll`
+/// creates the texture on the render thread and swaps it into the material. Until then the
+/// material shows a placeholder that is shared by everything that is still loading.
+pub struct TextureLoader {
+    placeholder: Arc<model::Texture>,
+    sender: mpsc::Sender<DecodedTexture>,
+    receiver: mpsc::Receiver<DecodedTexture>,
+    pending: HashMap<usize, PendingTexture>,
+    next_id: usize,
+}
+
+impl TextureLoader {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Self> {
+        // a grey and magenta checker board, so it's obvious that something is missing
+        let checker = image::RgbaImage::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 { image::Rgba([128, 128, 128, 255]) } else { image::Rgba([255, 0, 255, 255]) }
+        });
+        let placeholder = model::Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(checker), "placeholder texture")?;
+        let (sender, receiver) = mpsc::channel();
+        Ok(Self {
+            placeholder: Arc::new(placeholder),
+            sender,
+            receiver,
+            pending: HashMap::new(),
+            next_id: 0,
+        })
+    }
+
+    /// Start loading the texture, the returned material shows the placeholder until the texture
+    /// is done
+    #[allow(dead_code)]
+    pub fn request(&mut self, file_name: &str, options: model::TextureOptions) -> Arc<model::Material> {
+        self.request_from(resource_location(file_name), options)
+    }
+
+    /// like `request` for a texture that can also be somewhere else than in the resources
+    pub fn request_from(&mut self, location: Location, options: model::TextureOptions) -> Arc<model::Material> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let material = Arc::new(model::Material::placeholder(self.placeholder.clone()));
+        let file_name = location.to_string();
+        self.pending.insert(id, PendingTexture {
+            name: file_name.clone(),
+            material: material.clone(),
+            options,
+            polls: 0,
+        });
+
+        let sender = self.sender.clone();
+        let decode = async move {
+            let image = location.load_binary().await
+                .and_then(|data| Ok(image::load_from_memory(&data)?));
+            // the loader may be gone already, then nobody is interested in the texture
+            let _ = sender.send((id, image));
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::Builder::new()
+            .name(format!("load {}", file_name))
+            .spawn(move || pollster::block_on(decode))
+            .expect("could not spawn a texture loading thread");
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(decode);
+        material
+    }
+
+    /// the number of textures that are still loading
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Upload the textures that finished decoding since the last call and swap them into their
+    /// materials. This is called once per frame on the render thread. Textures that failed to
+    /// load keep the placeholder.
+    pub fn poll(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        for pending in self.pending.values_mut() {
+            pending.polls += 1;
+        }
+        for (id, image) in self.receiver.try_iter() {
+            let Some(pending) = self.pending.remove(&id) else { continue };
+            let texture = image.and_then(|image| {
+                model::Texture::from_images_with_options(device, queue, std::slice::from_ref(&image), &pending.name, &pending.options)
+            });
+            match texture {
+                Ok(texture) => {
+                    log::info!("texture {} is ready after {} frames", pending.name, pending.polls);
+                    pending.material.replace(Arc::new(texture));
+                }
+                Err(e) => log::error!("could not load the texture {}: {}", pending.name, e),
+            }
+        }
+    }
+}
 
 pub async fn load_texture (
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
+    options: &model::TextureOptions,
 ) -> anyhow::Result<model::Texture> {
-    let data = load_binary(file_name).await?;
-    model::Texture::from_bytes(device, queue, &data, file_name)
+    load_texture_from(&resource_location(file_name), device, queue, options).await
+}
+
+/// Load a texture from a file anywhere on disk, relative paths are relative to the working
+/// directory
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(dead_code)]
+pub async fn load_texture_from_path(
+    path: &std::path::Path,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    options: &model::TextureOptions,
+) -> anyhow::Result<model::Texture> {
+    load_texture_from(&Location::Path(path.to_path_buf()), device, queue, options).await
+}
+
+async fn load_texture_from(
+    location: &Location,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    options: &model::TextureOptions,
+) -> anyhow::Result<model::Texture> {
+    let data = location.load_binary().await?;
+    model::Texture::from_bytes(device, queue, &data, &location.to_string(), options)
 }
 
+/// Metadata for an object and its instances that is stored in a json file next to the model.
+/// For `teapot.obj` the sidecar is `teapot.obj.meta.json` and looks like this:
+///
+/// ```json
+/// {
+///     "object": { "sensor": "A-17" },
+///     "instances": [[{ "temperature": "21.5" }]]
+/// }
+/// ```
+///
+/// The instances are addressed by the index of the mesh and then the index of the instance in
+/// the mesh, both fields can be left out.
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MetadataSidecar {
+    #[serde(default)]
+    pub object: HashMap<String, String>,
+    #[serde(default)]
+    pub instances: Vec<Vec<HashMap<String, String>>>,
+}
+
+impl MetadataSidecar {
+    /// appended to the name of the model file to get the name of the sidecar
+    pub const SUFFIX: &'static str = ".meta.json";
+
+    #[allow(dead_code)]
+    pub fn sidecar_name(model_file_name: &str) -> String {
+        format!("{}{}", model_file_name, Self::SUFFIX)
+    }
+
+    /// collect the metadata of the object and its instances so that it can be written to a
+    /// sidecar file
+    pub fn from_object(object: &model::Object) -> Self {
+        Self {
+            object: object.metadata.clone(),
+            instances: object.meshes.iter()
+                .map(|mesh| mesh.instances.iter().map(|i| i.metadata.clone()).collect())
+                .collect(),
+        }
+    }
+
+    /// copy the metadata onto the object, entries for meshes or instances that the object
+    /// doesn't have are ignored with a warning
+    pub fn apply(self, object: &mut model::Object) {
+        object.metadata.extend(self.object);
+        for (mesh_idx, instances) in self.instances.into_iter().enumerate() {
+            for (instance_idx, metadata) in instances.into_iter().enumerate() {
+                match object.meshes.get_mut(mesh_idx).and_then(|m| m.instances.get_mut(instance_idx)) {
+                    Some(instance) => instance.metadata.extend(metadata),
+                    None => log::warn!("{} has no instance {} in mesh {}, ignoring its metadata", object.name, instance_idx, mesh_idx),
+                }
+            }
+        }
+    }
+}
+
+/// Load the metadata sidecar of a model. Models don't need to have a sidecar, so if the file
+/// can't be loaded there is no metadata. A sidecar that exists but isn't valid is an error.
+pub async fn load_metadata(model: &Location) -> anyhow::Result<Option<MetadataSidecar>> {
+    let sidecar_name = model.with_suffix(MetadataSidecar::SUFFIX);
+    let text = match sidecar_name.load_string().await {
+        Ok(text) => text,
+        Err(e) => {
+            log::debug!("no metadata for {}: {}", model, e);
+            return Ok(None);
+        }
+    };
+    let sidecar = serde_json::from_str(&text)
+        .map_err(|e| anyhow::anyhow!("invalid metadata in {}: {}", sidecar_name, e))?;
+    Ok(Some(sidecar))
+}
+
+/// What `load_model` does with the meshes of the file before they are uploaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// check the meshes with `model::validate` and log a warning for every mesh with problems
+    pub validate: bool,
+    /// remove the problems `model::validate` finds, see `model::repair`
+    pub repair: bool,
+    /// the number of simplified versions that are made of every mesh for when it is far away,
+    /// see `model::Lod`
+    pub lod_levels: u8,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        // repairing changes the indices of the vertices, it's only done when asked for
+        Self { validate: true, repair: false, lod_levels: 0 }
+    }
+}
+
+/// Load a model together with its textures, the model is returned once everything is loaded
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<model::Object> {
-    let obj_text = load_string(file_name).await?;
+    load_model_from(&resource_location(file_name), device, queue, None, None, LoadOptions::default()).await
+}
+
+/// Load a model that is built into the binary (see `embedded`), with its MTL and textures which
+/// have to be built in as well. This never touches the file system.
+#[allow(dead_code)]
+pub async fn load_embedded_model(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<model::Object> {
+    if embedded(file_name).is_none() {
+        anyhow::bail!("{} is not built into the binary", file_name);
+    }
+    load_model_from(&Location::Embedded(file_name.to_string()), device, queue, None, None, LoadOptions::default()).await
+}
+
+/// Load a model from a file anywhere on disk, like one picked in a file dialog. Relative paths
+/// are relative to the working directory. The MTL and the textures it refers to are read from
+/// the folder of the model, a texture that is missing is logged and the mesh is drawn with its
+/// `fallback_color` instead.
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(dead_code)]
+pub async fn load_model_from_path(
+    path: &std::path::Path,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<model::Object> {
+    load_model_from(&Location::Path(path.to_path_buf()), device, queue, None, None, LoadOptions::default()).await
+}
+
+/// Like `load_model`, with a choice of what is done with broken meshes
+#[allow(dead_code)]
+pub async fn load_model_with_options(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    options: LoadOptions,
+) -> anyhow::Result<model::Object> {
+    load_model_from(&resource_location(file_name), device, queue, None, None, options).await
+}
+
+/// Load a model without waiting for its textures. The meshes show the placeholder texture of the
+/// loader until the textures have been read and decoded in the background, they are swapped in
+/// by `TextureLoader::poll`. The meshes are put into the `arena` if there is one.
+pub async fn load_model_in_background(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    loader: &mut TextureLoader,
+    arena: Option<&mut buffer_arena::MeshArena>,
+) -> anyhow::Result<model::Object> {
+    load_model_from(&resource_location(file_name), device, queue, Some(loader), arena, LoadOptions::default()).await
+}
+
+/// Load an OBJ model from any location, the textures are loaded in the background if there is a
+/// `loader`. The MTL and the textures are siblings of the model (see `Location::sibling`).
+/// Missing materials don't stop the model from loading, the meshes without one are drawn with
+/// their `fallback_color`. With an `arena` the vertices and indices of the meshes go into its
+/// shared buffers (see `Surface::new_with_lods`), otherwise every mesh gets buffers of its own.
+pub async fn load_model_from(
+    location: &Location,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    loader: Option<&mut TextureLoader>,
+    mut arena: Option<&mut buffer_arena::MeshArena>,
+    options: LoadOptions,
+) -> anyhow::Result<model::Object> {
+    let file_name = location.to_string();
+    let obj_text = location.load_string().await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
@@ -48,22 +521,50 @@ pub async fn load_model(
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| {
+            let mtl = location.sibling(&p);
+            async move {
+                match mtl.load_string().await {
+                    Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                    Err(e) => {
+                        log::warn!("{}", e);
+                        Err(tobj::LoadError::OpenFileFailed)
+                    }
+                }
+            }
         },
     ).await?;
 
+    // one entry for every material of the MTL, so that the material ids of the meshes index it.
+    // Materials without a texture (or with one that can't be loaded) are None
     let mut materials = Vec::new();
-    if let Ok(obj_materials) = obj_materials {
-        for m in obj_materials.iter() {
+    let mut loader = loader;
+    match obj_materials {
+        Ok(obj_materials) => for m in obj_materials.iter() {
             // get the texture for that material
-            if let Some(diffuse_texture) = &m.diffuse_texture {
-                let mut diffuse_texture = load_texture(diffuse_texture, device, queue).await?;
-                diffuse_texture.add_bind_group(device);
-                materials.push(Arc::new(diffuse_texture))
-            }
-        }
+            let Some(diffuse_texture) = &m.diffuse_texture else {
+                materials.push(None);
+                continue;
+            };
+            let texture_location = location.sibling(diffuse_texture);
+            // the textures of OBJ materials use straight alpha
+            let options = model::TextureOptions::default();
+            let material = match loader.as_deref_mut() {
+                Some(loader) => Some(loader.request_from(texture_location, options)),
+                None => match load_texture_from(&texture_location, device, queue, &options).await {
+                    Ok(mut diffuse_texture) => {
+                        diffuse_texture.add_bind_group(device);
+                        Some(Arc::new(model::Material::new(Arc::new(diffuse_texture))))
+                    }
+                    Err(e) => {
+                        log::error!("the material {} of {} has no texture, using the fallback color: {}", m.name, file_name, e);
+                        None
+                    }
+                },
+            };
+            materials.push(material)
+        },
+        Err(e) => log::warn!("{} has no materials: {}", file_name, e),
     }
 
     let meshes = models.into_iter().enumerate().map(|(o, m)| {
@@ -77,34 +578,145 @@ pub async fn load_model(
             tex_ccord: [0.0, 0.0],
             norm: [0.0, 0.0, 0.0],
         }).collect::<Vec<_>>();
-        if m.mesh.texcoords.len() / 2 == m.mesh.positions.len() {
+        // the attributes are only used if every vertex has them, there are two floats per
+        // texture coordinate and three per position and normal
+        if m.mesh.texcoords.len() / 2 == m.mesh.positions.len() / 3 {
             for (i, v) in vertices.iter_mut().enumerate() {
                 v.tex_ccord = [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]];
             }
         }
-        if m.mesh.normals.len() / 3 == m.mesh.positions.len() {
+        if m.mesh.normals.len() == m.mesh.positions.len() {
             for (i, v) in vertices.iter_mut().enumerate() {
-                v.norm = [m.mesh.normals[i * 3], m.mesh.normals[i * 3 + 1], m.mesh.normals[i * 3 + 1]];
+                v.norm = [m.mesh.normals[i * 3], m.mesh.normals[i * 3 + 1], m.mesh.normals[i * 3 + 2]];
             }
         }
-
-
-        let mesh_material = match m.mesh.material_id {
-            Some(id) => {
-                if materials.len() > id {
-                    Some(materials[id].clone())
-                } else {
-                    None
-                }
+        let mut indices = m.mesh.indices;
+        if options.repair {
+            let (repaired_vertices, repaired_indices, report) = model::repair(&vertices, &indices);
+            if !report.is_clean() {
+                log::warn!("repaired mesh {:?} of {}: {:?}", m.name, file_name, report);
             }
-            None => None,
-        };
+            vertices = repaired_vertices;
+            indices = repaired_indices;
+        } else if options.validate {
+            let report = model::validate(&vertices, &indices);
+            if !report.is_clean() {
+                log::warn!("mesh {:?} of {} has problems: {:?}", m.name, file_name, report);
+            }
+        }
+        // without normals the lighting turns the mesh black, so they are made up from the faces
+        if m.mesh.normals.is_empty() {
+            model::compute_normals(&mut vertices, &indices);
+        }
+
+        let mesh_material = m.mesh.material_id.and_then(|id| materials.get(id).cloned().flatten());
 
-        model::Surface::new(format!("{} surface no {}", file_name.to_string(), o), &vertices, &m.mesh.indices[..], mesh_material, device, queue)
+        let name = format!("{} surface no {}", file_name, o);
+        model::Surface::new_with_lods(name, &vertices, &indices[..], options.lod_levels, mesh_material, arena.as_deref_mut(), device, queue)
     }).collect::<Vec<_>>();
-    Ok(model::Object { 
+    let mut object = model::Object { 
         name: "SomeObject".to_string(),
         meshes,
-    })
+        metadata: HashMap::new(),
+        render_order: 0,
+        auto_rotate: Default::default(),
+        source: Some(location.clone()),
+        pipeline: None,
+    };
+    if let Some(sidecar) = load_metadata(location).await? {
+        sidecar.apply(&mut object);
+    }
+    Ok(object)
+}
+
+
+/// Load a binary or ASCII STL file, the format of most 3D printable parts. STL is a plain list
+/// of triangles with the normal of every face and nothing else: the vertices are not shared, so
+/// every corner gets the normal of its face (the parts look faceted, which suits machined parts)
+/// and the indices just count up. There are no texture coordinates, they are all zero.
+pub async fn load_stl(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<model::Object> {
+    let data = load_binary(file_name).await?;
+    let vertices = parse_stl(&data).map_err(|e| anyhow::anyhow!("could not read {}: {}", file_name, e))?;
+    let indices = (0..vertices.len() as u32).collect::<Vec<_>>();
+    let report = model::validate(&vertices, &indices);
+    if !report.is_clean() {
+        log::warn!("{} has problems: {:?}", file_name, report);
+    }
+    let mut object = model::Object::new(file_name.to_string());
+    object.meshes.push(model::Surface::new(format!("{} surface", file_name), &vertices, &indices, None, device, queue));
+    let location = Location::Resource(file_name.to_string());
+    if let Some(sidecar) = load_metadata(&location).await? {
+        sidecar.apply(&mut object);
+    }
+    object.source = Some(location);
+    Ok(object)
 }
 
+// the normal and the three corners of a triangle of an STL file
+type StlTriangle = ([f32; 3], [[f32; 3]; 3]);
+
+// A binary STL has an 80 byte header, the number of triangles and then 50 bytes per triangle:
+// the normal, the three corners (12 floats in little endian) and two unused bytes. ASCII files
+// start with "solid", but so do the headers of some binary files, so the size decides.
+fn parse_stl(data: &[u8]) -> anyhow::Result<Vec<model::RawVertex>> {
+    let binary_size = |count: u32| 84 + 50 * count as usize;
+    let count = data.get(80..84).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    let triangles = match count {
+        Some(count) if data.len() == binary_size(count) => data[84..].chunks_exact(50).map(|triangle| {
+            let float = |i: usize| f32::from_le_bytes([triangle[i * 4], triangle[i * 4 + 1], triangle[i * 4 + 2], triangle[i * 4 + 3]]);
+            let vector = |i: usize| [float(i * 3), float(i * 3 + 1), float(i * 3 + 2)];
+            (vector(0), [vector(1), vector(2), vector(3)])
+        }).collect::<Vec<_>>(),
+        _ if data.starts_with(b"solid") => parse_ascii_stl(std::str::from_utf8(data)?)?,
+        Some(count) => anyhow::bail!("the file has {} bytes, a binary STL with {} triangles has {}", data.len(), count, binary_size(count)),
+        None => anyhow::bail!("the file is too short to be an STL"),
+    };
+    Ok(triangles.into_iter().flat_map(|(normal, corners)| {
+        // many exporters leave the normal at zero, then it comes from the winding of the corners
+        let normal = if normal == [0.0; 3] { face_normal(corners) } else { normal };
+        corners.map(|pos| model::RawVertex { pos, tex_ccord: [0.0, 0.0], norm: normal })
+    }).collect())
+}
+
+// facet normal nx ny nz / outer loop / vertex x y z (three times) / endloop / endfacet
+fn parse_ascii_stl(text: &str) -> anyhow::Result<Vec<StlTriangle>> {
+    let mut triangles = Vec::new();
+    let mut normal = [0.0; 3];
+    let mut corners = Vec::with_capacity(3);
+    let mut words = text.split_whitespace();
+    let vector = |words: &mut std::str::SplitWhitespace| -> anyhow::Result<[f32; 3]> {
+        let mut v = [0.0; 3];
+        for c in v.iter_mut() {
+            let word = words.next().ok_or_else(|| anyhow::anyhow!("the file ends in the middle of a vector"))?;
+            *c = word.parse().map_err(|e| anyhow::anyhow!("{:?} is not a number: {}", word, e))?;
+        }
+        Ok(v)
+    };
+    while let Some(word) = words.next() {
+        match word {
+            // the name after "solid" can be anything, so only the normal of a facet is read
+            "facet" if words.next() == Some("normal") => normal = vector(&mut words)?,
+            "vertex" => corners.push(vector(&mut words)?),
+            "endfacet" => {
+                let [a, b, c] = corners[..] else {
+                    anyhow::bail!("a facet has {} vertices instead of 3", corners.len());
+                };
+                triangles.push((normal, [a, b, c]));
+                corners.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
+
+fn face_normal([a, b, c]: [[f32; 3]; 3]) -> [f32; 3] {
+    use cgmath::InnerSpace;
+    let (a, b, c) = (cgmath::Vector3::from(a), cgmath::Vector3::from(b), cgmath::Vector3::from(c));
+    let normal = (b - a).cross(c - a);
+    if normal.magnitude2() > 0.0 { normal.normalize().into() } else { [0.0; 3] }
+}
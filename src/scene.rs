@@ -0,0 +1,182 @@
+use std::rc::{Rc, Weak};
+
+use cgmath::{Matrix4, SquareMatrix};
+
+use crate::model;
+
+// The instances of the meshes are placed in the world independently of each other. To be able
+// to attach one object to another (a wheel to a car) we need a hierarchy of transforms. Every
+// node in the scene graph has a transform relative to its parent, the transform of the node in
+// the world is found by walking up the tree and multiplying all transforms on the way.
+//
+// The instances that are attached to a node are placed relative to the node, so the position,
+// rotation and scale of the instance are applied first and then the world transform of the node.
+//
+// Only the nodes whose transform changed (or one of their parents) are written into their
+// instances, the instances keep the transform of their node until it changes again.
+
+/// The nodes are referenced by their index in the scene graph
+pub type NodeId = usize;
+
+/// Addresses a single instance of a mesh of an object in the list of objects of the app
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceRef {
+    pub object: usize,
+    pub mesh: usize,
+    pub instance: usize,
+}
+
+/// An instance that is attached to a node. The index of an instance in its mesh changes when an
+/// instance before it is removed, so the attachment holds on to the slot of the instance in the
+/// instance buffer, like the instance itself does. Once the instance is gone the slot is gone
+/// too and the attachment is dropped.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub object: usize,
+    pub mesh: usize,
+    // where the instance was found the last time, it is searched for when it isn't there anymore
+    index: usize,
+    slot: Weak<usize>,
+}
+
+impl Attachment {
+    /// the instance this attachment points to, None if it was removed
+    fn find<'a>(&mut self, objects: &'a mut [model::Object]) -> Option<&'a mut model::Surface> {
+        let slot = self.slot.upgrade()?;
+        let mesh = objects.get_mut(self.object)?.meshes.get_mut(self.mesh)?;
+        let is_slot = |instance: &crate::instance::Instance| Rc::ptr_eq(&instance.buffer_index, &slot);
+        if !mesh.instances.get(self.index).is_some_and(is_slot) {
+            self.index = mesh.instances.iter().position(is_slot)?;
+        }
+        Some(mesh)
+    }
+}
+
+#[derive(Debug)]
+pub struct SceneNode {
+    pub name: String,
+    /// the transform of this node relative to the parent (or the world if there is no parent),
+    /// see `SceneGraph::set_local`
+    local: Matrix4<f32>,
+    pub parent: Option<NodeId>,
+    /// the instances that move with this node
+    pub instances: Vec<Attachment>,
+    // the world transform has to be computed again and written into the instances
+    dirty: bool,
+}
+
+impl SceneNode {
+    /// the transform relative to the parent
+    pub fn local(&self) -> Matrix4<f32> {
+        self.local
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SceneGraph {
+    nodes: Vec<SceneNode>,
+    // the world transform of every node as of the last `resolve_world_transforms`
+    world: Vec<Matrix4<f32>>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self { nodes: Vec::new(), world: Vec::new() }
+    }
+
+    /// Add a node to the graph. The parent has to be added before the child, this keeps the nodes
+    /// sorted so that the world transforms can be computed in a single pass.
+    pub fn add_node(&mut self, name: &str, local: Matrix4<f32>, parent: Option<NodeId>) -> NodeId {
+        if let Some(parent) = parent {
+            assert!(parent < self.nodes.len(), "the parent node {} does not exist", parent);
+        }
+        self.nodes.push(SceneNode {
+            name: name.to_string(),
+            local,
+            parent,
+            instances: Vec::new(),
+            dirty: true,
+        });
+        self.world.push(Matrix4::identity());
+        self.nodes.len() - 1
+    }
+
+    /// Attach an instance to a node, so that it follows the node around. Returns false if there
+    /// is no such instance.
+    pub fn attach_instance(&mut self, node: NodeId, instance: InstanceRef, objects: &[model::Object]) -> bool {
+        let Some(slot) = objects.get(instance.object)
+            .and_then(|object| object.meshes.get(instance.mesh))
+            .and_then(|mesh| mesh.instances.get(instance.instance))
+            .map(|found| Rc::downgrade(&found.buffer_index)) else {
+            log::warn!("can't attach {:?} to the scene node {}, there is no such instance", instance, self.nodes[node].name);
+            return false;
+        };
+        let node = &mut self.nodes[node];
+        node.instances.push(Attachment { object: instance.object, mesh: instance.mesh, index: instance.instance, slot });
+        node.dirty = true;
+        true
+    }
+
+    /// Forget the instances of an object, for when they were replaced by new ones (see
+    /// `App::reload_object`). The references into the other objects stay as they are.
+    pub fn detach_object(&mut self, object: usize) {
+        for node in self.nodes.iter_mut() {
+            node.instances.retain(|attachment| attachment.object != object);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn node(&self, id: NodeId) -> &SceneNode {
+        &self.nodes[id]
+    }
+
+    /// move the node relative to its parent, the node and its children are placed again with
+    /// the next `resolve_world_transforms`
+    pub fn set_local(&mut self, id: NodeId, local: Matrix4<f32>) {
+        let node = &mut self.nodes[id];
+        node.local = local;
+        node.dirty = true;
+    }
+
+    /// The transform from the node space to the world space, as of the last
+    /// `resolve_world_transforms`
+    pub fn world_transform(&self, id: NodeId) -> Matrix4<f32> {
+        self.world[id]
+    }
+
+    /// Compute the world transforms of the nodes that moved and of their children, and write
+    /// them into the instances that are attached to them. As parents always come before their
+    /// children the world transform of the parent is already known when we get to the child.
+    /// The instance buffers are updated but not flushed to the GPU. Returns the number of
+    /// instances that were placed.
+    pub fn resolve_world_transforms(&mut self, objects: &mut [model::Object]) -> usize {
+        let mut moved = vec![false; self.nodes.len()];
+        let mut placed = 0;
+        for id in 0..self.nodes.len() {
+            let node = &mut self.nodes[id];
+            if !(node.dirty || node.parent.is_some_and(|parent| moved[parent])) {
+                continue;
+            }
+            node.dirty = false;
+            moved[id] = true;
+            let parent_world = match node.parent {
+                Some(parent) => self.world[parent],
+                None => Matrix4::identity(),
+            };
+            let transform = parent_world * node.local;
+            self.world[id] = transform;
+            node.instances.retain_mut(|attachment| {
+                let Some(mesh) = attachment.find(objects) else {
+                    log::info!("an instance of the scene node {} is gone, it is detached", node.name);
+                    return false;
+                };
+                let instance = &mut mesh.instances[attachment.index];
+                instance.parent_transform = transform;
+                instance.update(&mut mesh.instance_buffer);
+                placed += 1;
+                true
+            });
+        }
+        placed
+    }
+}
@@ -3,6 +3,8 @@
 // 3D version of the OpenGL triangle kind of shader, that support Instances and a moving camera in 3D
 struct Camera {
     view_proj: mat4x4<f32>,
+    // the world to view space part of view_proj
+    view: mat4x4<f32>,
 };
  
 @group(0) @binding(0)
@@ -13,10 +15,45 @@ struct VertexInput {
 };
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // invariant so the depth prepass and the color pass compute exactly the same depth
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) color: vec4<f32>,
+    // the distance from the camera along the view direction, needed for the fog
+    @location(1) view_depth: f32,
+    // the position in world space, needed for the clip planes
+    @location(2) world_position: vec3<f32>,
 };
 
+// mode 0 is no fog, 1 is linear fog between start and end and 2 is exp2 fog
+struct Fog {
+    color: vec4<f32>,
+    start: f32,
+    end: f32,
+    density: f32,
+    mode: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> fog: Fog;
+
+// a point p is cut away by a plane if dot(p, plane.xyz) + plane.w > 0, the planes that are
+// switched off are (0, 0, 0, -1) so they never cut anything
+struct ClipPlanes {
+    planes: array<vec4<f32>, 4>,
+};
+
+@group(2) @binding(0)
+var<uniform> clip: ClipPlanes;
+
+// the color of the instances is replaced by the rgb color by the amount in a
+struct ObjectConstants {
+    color_override: vec4<f32>,
+};
+
+// devices without push constants get this as a uniform at group 3, the declaration is replaced
+// when the shader is loaded (see object_constants.rs)
+var<push_constant> object: ObjectConstants;
+
 struct InstanceInput {
     @location(5) transform_matrix_0: vec4<f32>,
     @location(6) transform_matrix_1: vec4<f32>,
@@ -46,13 +83,68 @@ fn vs_main(
 
     // this is the thing that really matters to the clipping and rasterization process
     out.clip_position = camera.view_proj * instanced_position;
+    // the camera looks down -z in view space. The w of the clip position is the same for a
+    // perspective projection, but not for an orthographic one (or a blend of the two)
+    out.view_depth = -(camera.view * instanced_position).z;
     out.color = instance.color;
+    out.world_position = instanced_position.xyz;
     return out;
 }
 
+// the amount of the original color that is left after the fog has been applied
+fn fog_factor(depth: f32) -> f32 {
+    if fog.mode == 1u {
+        return clamp((fog.end - depth) / (fog.end - fog.start), 0.0, 1.0);
+    } else if fog.mode == 2u {
+        let d = fog.density * depth;
+        return clamp(exp(-d * d), 0.0, 1.0);
+    }
+    return 1.0;
+}
+
+fn is_clipped(world_position: vec3<f32>) -> bool {
+    for (var i = 0; i < 4; i++) {
+        let plane = clip.planes[i];
+        if dot(world_position, plane.xyz) + plane.w > 0.0 {
+            return true;
+        }
+    }
+    return false;
+}
+
+// the fragment shader of the depth prepass, there is no color output. Only the depth of the
+// fragments that are not clipped is written
+@fragment
+fn fs_depth(in: VertexOutput) {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+}
+
+// the color of the fragment with the color override and the fog
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let object_color = mix(in.color.rgb, object.color_override.rgb, object.color_override.a);
+    let color = mix(fog.color.rgb, object_color, fog_factor(in.view_depth));
+    return vec4<f32>(color, in.color.a);
+}
+
 // The fragment shader is really straight forward, as we essentially do no light calculations what so ever
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     //return vec4<f32>(0., 1., 1., 1.);
-    return in.color;
+    if is_clipped(in.world_position) {
+        discard;
+    }
+    return shade(in);
+}
+
+// the fragment shader of the instances with an alpha below 1. They are blended with the
+// premultiplied alpha blend state, so the color is multiplied with the alpha here
+@fragment
+fn fs_transparent(in: VertexOutput) -> @location(0) vec4<f32> {
+    if is_clipped(in.world_position) {
+        discard;
+    }
+    let color = shade(in);
+    return vec4<f32>(color.rgb * color.a, color.a);
 }
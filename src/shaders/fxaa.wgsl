@@ -0,0 +1,76 @@
+// Fast approximate anti aliasing. The scene is rendered into an offscreen texture and this
+// shader runs over the whole screen. It finds edges by looking at the brightness (luma) of the
+// neighbouring pixels and blurs along the edge. This is a lot cheaper than MSAA but also blurs
+// the picture a bit.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// A single triangle that covers the whole screen, the vertices are generated from the index
+// so no vertex buffer is needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    // texture coordinates go from top to bottom, clip space from bottom to top
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+
+const FXAA_SPAN_MAX: f32 = 8.0;
+const FXAA_REDUCE_MUL: f32 = 0.125;
+const FXAA_REDUCE_MIN: f32 = 0.0078125;
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_scene));
+
+    // sample the pixel and its diagonal neighbours
+    let rgb_nw = textureSample(t_scene, s_scene, in.uv + vec2<f32>(-1.0, -1.0) * texel).rgb;
+    let rgb_ne = textureSample(t_scene, s_scene, in.uv + vec2<f32>(1.0, -1.0) * texel).rgb;
+    let rgb_sw = textureSample(t_scene, s_scene, in.uv + vec2<f32>(-1.0, 1.0) * texel).rgb;
+    let rgb_se = textureSample(t_scene, s_scene, in.uv + vec2<f32>(1.0, 1.0) * texel).rgb;
+    let center = textureSample(t_scene, s_scene, in.uv);
+
+    let luma_nw = luma(rgb_nw);
+    let luma_ne = luma(rgb_ne);
+    let luma_sw = luma(rgb_sw);
+    let luma_se = luma(rgb_se);
+    let luma_m = luma(center.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // the direction of the edge is perpendicular to the luma gradient
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * texel;
+
+    // blur along the edge with two different widths
+    let rgb_a = 0.5 * (
+        textureSample(t_scene, s_scene, in.uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        textureSample(t_scene, s_scene, in.uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    let rgb_b = rgb_a * 0.5 + 0.25 * (
+        textureSample(t_scene, s_scene, in.uv + dir * -0.5).rgb +
+        textureSample(t_scene, s_scene, in.uv + dir * 0.5).rgb);
+
+    // if the wide blur picked up colors from outside of the local range it went too far
+    let luma_b = luma(rgb_b);
+    let outside = luma_b < luma_min || luma_b > luma_max;
+    return vec4<f32>(select(rgb_b, rgb_a, outside), center.a);
+}
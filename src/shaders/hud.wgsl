@@ -0,0 +1,62 @@
+// Textured quads in screen space for the HUD. The quads are given in pixels with the origin in
+// the top left corner of the screen, the projection turns the pixels into clip space. Every quad
+// is an instance, its corners are generated from the vertex index so there is no vertex buffer.
+struct Projection {
+    matrix: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> projection: Projection;
+
+// the quads sample textures of the texture system, which are all array textures
+struct Frame {
+    layer: u32,
+    // shifts the mip level the sampler picks, see SamplerOptions
+    lod_bias: f32,
+}
+
+@group(1) @binding(0)
+var t_quad: texture_2d_array<f32>;
+@group(1) @binding(1)
+var s_quad: sampler;
+@group(1) @binding(2)
+var<uniform> frame: Frame;
+
+struct QuadInput {
+    // the top left (xy) and bottom right (zw) corner in pixels
+    @location(0) rect: vec4<f32>,
+    @location(1) tint: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) tint: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, quad: QuadInput) -> VertexOutput {
+    // two triangles, the corners as a fraction of the quad
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[index];
+    let position = mix(quad.rect.xy, quad.rect.zw, corner);
+    var out: VertexOutput;
+    out.clip_position = projection.matrix * vec4<f32>(position, 0.0, 1.0);
+    out.uv = corner;
+    out.tint = quad.tint;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // the textures have premultiplied alpha, so the tint is premultiplied as well
+    let color = textureSampleBias(t_quad, s_quad, in.uv, frame.layer, frame.lod_bias);
+    return color * vec4<f32>(in.tint.rgb * in.tint.a, in.tint.a);
+}
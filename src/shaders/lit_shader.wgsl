@@ -0,0 +1,140 @@
+// Phong shading with a single directional light, computed for every fragment. The light has
+// three parts: the ambient light that reaches every surface, the diffuse light that depends on
+// the angle between the surface and the light, and the highlight (specular light) that is the
+// reflection of the light towards the eye.
+struct Camera {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// the layout matches RawDirectionalLight in light.rs, the direction is the one the light
+// travels in
+struct Light {
+    direction: vec3<f32>,
+    ambient: f32,
+    color: vec3<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+// the camera uniform only has the combined matrix, the highlight needs the position of the eye
+struct Eye {
+    position: vec4<f32>,
+};
+
+@group(2) @binding(0)
+var<uniform> eye: Eye;
+
+// the texture is only bound for the textured pipeline (fs_textured). The frame uniform selects
+// the layer of the array texture, every instance starts at a layer of its own (like in
+// shader.wgsl)
+struct Frame {
+    layer: u32,
+    // shifts the mip level the sampler picks, see SamplerOptions
+    lod_bias: f32,
+    // the number of layers, textureNumLayers can't be translated to GLSL ES
+    layer_count: u32,
+}
+
+@group(3) @binding(0)
+var t_diffuse: texture_2d_array<f32>;
+@group(3) @binding(1)
+var s_diffuse: sampler;
+@group(3) @binding(2)
+var<uniform> frame: Frame;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) transform_matrix_0: vec4<f32>,
+    @location(6) transform_matrix_1: vec4<f32>,
+    @location(7) transform_matrix_2: vec4<f32>,
+    @location(8) transform_matrix_3: vec4<f32>,
+    @location(9) color: vec4<f32>,
+    @location(10) tex_layer: u32,
+    // the inverse transpose of the instance matrix, for the normals (see Instance::normal_matrix)
+    @location(11) normal_matrix_0: vec3<f32>,
+    @location(12) normal_matrix_1: vec3<f32>,
+    @location(13) normal_matrix_2: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    // the normal and the position in world space, the light is computed in world space
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
+    // the texture layer of the instance, integers can't be interpolated
+    @location(4) @interpolate(flat) tex_layer: u32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let instance_transform = mat4x4<f32>(
+        instance.transform_matrix_0,
+        instance.transform_matrix_1,
+        instance.transform_matrix_2,
+        instance.transform_matrix_3,
+    );
+    let world_position = instance_transform * vec4<f32>(model.position, 1.0);
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.color = instance.color;
+    out.tex_coords = model.tex_coords;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_position = world_position.xyz;
+    out.tex_layer = instance.tex_layer;
+    return out;
+}
+
+// the color of the surface with the base color under the light
+fn shade(in: VertexOutput, base_color: vec3<f32>) -> vec3<f32> {
+    let to_eye = normalize(eye.position.xyz - in.world_position);
+    // meshes without normals get the normal of the triangle, like in matcap.wgsl. The meshes
+    // are not culled, so the normal is turned towards the eye to light the inside of open
+    // meshes as well
+    var normal = normalize(cross(dpdx(in.world_position), dpdy(in.world_position)));
+    if dot(in.world_normal, in.world_normal) > 0.0 {
+        normal = normalize(in.world_normal);
+    }
+    if dot(normal, to_eye) < 0.0 {
+        normal = -normal;
+    }
+    let to_light = -normalize(light.direction);
+    let diffuse = max(dot(normal, to_light), 0.0);
+    let reflected = reflect(-to_light, normal);
+    let shininess = 32.0;
+    let specular = 0.5 * pow(max(dot(reflected, to_eye), 0.0), shininess);
+    return (light.ambient + diffuse) * light.color * base_color + specular * light.color;
+}
+
+// the surfaces have the color of their instance
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(shade(in, in.color.rgb), in.color.a);
+}
+
+// the surfaces have the color of their texture
+@fragment
+fn fs_textured(in: VertexOutput) -> @location(0) vec4<f32> {
+    let layer = (in.tex_layer + frame.layer) % max(frame.layer_count, 1u);
+    let object_color = textureSampleBias(t_diffuse, s_diffuse, in.tex_coords, layer, frame.lod_bias);
+    return vec4<f32>(shade(in, object_color.rgb), object_color.a);
+}
@@ -0,0 +1,104 @@
+// Matcap (material capture) shading. The matcap is the image of a lit sphere, every point of the
+// sphere shows how the material looks for the normal at that point. So instead of computing any
+// light, the normal of the fragment (as seen from the camera) picks the pixel of the sphere that
+// has the same normal. This looks good from every side because the light moves with the camera.
+struct Camera {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// the layer of the matcap texture, the texture system uses array textures for everything
+struct Frame {
+    layer: u32,
+    // shifts the mip level the sampler picks, see SamplerOptions
+    lod_bias: f32,
+}
+
+@group(1) @binding(0)
+var t_matcap: texture_2d_array<f32>;
+@group(1) @binding(1)
+var s_matcap: sampler;
+@group(1) @binding(2)
+var<uniform> frame: Frame;
+
+// the camera uniform only has the combined matrix, the normals need the view matrix alone
+struct MatcapView {
+    view: mat4x4<f32>,
+};
+
+@group(2) @binding(0)
+var<uniform> matcap_view: MatcapView;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) transform_matrix_0: vec4<f32>,
+    @location(6) transform_matrix_1: vec4<f32>,
+    @location(7) transform_matrix_2: vec4<f32>,
+    @location(8) transform_matrix_3: vec4<f32>,
+    // the inverse transpose of the instance matrix, for the normals (see Instance::normal_matrix)
+    @location(11) normal_matrix_0: vec3<f32>,
+    @location(12) normal_matrix_1: vec3<f32>,
+    @location(13) normal_matrix_2: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // the normal and the position in view space
+    @location(0) view_normal: vec3<f32>,
+    @location(1) view_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let instance_transform = mat4x4<f32>(
+        instance.transform_matrix_0,
+        instance.transform_matrix_1,
+        instance.transform_matrix_2,
+        instance.transform_matrix_3,
+    );
+    let world_position = instance_transform * vec4<f32>(model.position, 1.0);
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_normal = normal_matrix * model.normal;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.view_normal = (matcap_view.view * vec4<f32>(world_normal, 0.0)).xyz;
+    out.view_position = (matcap_view.view * world_position).xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // meshes without normals get the normal of the triangle, the derivatives have to be taken
+    // outside of the branch. The direction of the screen y (and with it the sign of the cross
+    // product) depends on the backend, so the normal is turned towards the camera (which is at
+    // the origin of view space)
+    var face_normal = normalize(cross(dpdx(in.view_position), dpdy(in.view_position)));
+    if dot(face_normal, in.view_position) > 0.0 {
+        face_normal = -face_normal;
+    }
+    var normal = face_normal;
+    if dot(in.view_normal, in.view_normal) > 0.0 {
+        normal = normalize(in.view_normal);
+    }
+    // the x and y of the normal are the position on the sphere, the image goes from top to bottom
+    let uv = vec2<f32>(normal.x, -normal.y) * 0.5 + 0.5;
+    // the frame is already kept below the number of layers by the texture (textureNumLayers
+    // can't be translated to GLSL ES)
+    let color = textureSampleBias(t_matcap, s_matcap, uv, frame.layer, frame.lod_bias);
+    return vec4<f32>(color.rgb, 1.0);
+}
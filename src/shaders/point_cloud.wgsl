@@ -0,0 +1,68 @@
+// Draws every point of a point cloud as a round splat. The points are the instances of a quad
+// that always faces the camera, it is built in screen space around the projected point so its
+// size is given in pixels. The size shrinks with the distance to the camera, like
+// the point size attenuation of OpenGL.
+struct Camera {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct PointSettings {
+    // the size of the target in pixels
+    viewport: vec2<f32>,
+    // the diameter in pixels of a point right in front of the camera
+    size: f32,
+    // how fast the points shrink with the distance, 0 keeps the size the same
+    attenuation: f32,
+    // far away points don't get smaller than this so they don't disappear
+    min_size: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> settings: PointSettings;
+
+struct PointInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    // the position in the quad in pixels from the center of the point
+    @location(1) offset: vec2<f32>,
+    @location(2) radius: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, point: PointInput) -> VertexOutput {
+    // the corners of the quad as a triangle strip: (-1,-1), (1,-1), (-1,1), (1,1)
+    let corner = vec2<f32>(f32(vertex_index & 1u), f32(vertex_index >> 1u)) * 2.0 - 1.0;
+    let center = camera.view_proj * vec4<f32>(point.position, 1.0);
+    // w is the distance of the point along the view direction
+    let diameter = max(settings.size / (1.0 + settings.attenuation * center.w), settings.min_size);
+    let radius = diameter * 0.5;
+    // the quad is a pixel larger than the circle so the smooth edge fits in
+    let offset = corner * (radius + 1.0);
+    // pixels to normalized device coordinates, multiplied by w as the GPU divides by it
+    let clip_offset = offset * 2.0 / settings.viewport * center.w;
+
+    var out: VertexOutput;
+    out.clip_position = center + vec4<f32>(clip_offset, 0.0, 0.0);
+    out.color = point.color;
+    out.offset = offset;
+    out.radius = radius;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // how much of the pixel the circle covers, it fades out over one pixel at the edge
+    let coverage = clamp(in.radius - length(in.offset) + 0.5, 0.0, 1.0);
+    if coverage <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
@@ -20,6 +20,8 @@ struct VertexOutput {
     @location(1) world_normal: vec3<f32>,
     // the location of the vertex in the world reference frame
     @location(2) position: vec3<f32>,
+    // the texture layer of the instance, integers can't be interpolated
+    @location(3) @interpolate(flat) tex_layer: u32,
 };
 
 struct InstanceInput {
@@ -28,11 +30,21 @@ struct InstanceInput {
     @location(7) transform_matrix_2: vec4<f32>,
     @location(8) transform_matrix_3: vec4<f32>,
     @location(9) scale: vec4<f32>,
+    @location(10) tex_layer: u32,
 };
 
+// kind 0 is a point light, 1 a directional light. The layout matches RawLight in light.rs
 struct Light {
-    position: vec3<f32>,
+    position: vec4<f32>,
+    direction: vec4<f32>,
     color: vec3<f32>,
+    intensity: f32,
+    kind: u32,
+    range: f32,
+}
+
+struct LightCount {
+    count: u32,
 }
 
 // Here the vertex shader is doing pretty boring stuff, it simply maps the points into the view volume
@@ -56,6 +68,7 @@ fn vs_main(
     );
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    out.tex_layer = instance.tex_layer;
     
     // translate the 3d vectors for position and normal to homogenious coordinates
     // also calculate the vectors in the "world coordinate system"
@@ -71,25 +84,60 @@ fn vs_main(
 
 // Fragment shader
 
+// the diffuse texture is an array texture, for animated textures every layer is a frame
+// of the animation, the frame uniform selects the layer that is shown. Every instance can
+// start at a different layer, so instances of the same mesh can show different textures
+struct Frame {
+    layer: u32,
+    // shifts the mip level the sampler picks, see SamplerOptions
+    lod_bias: f32,
+    // the number of layers, textureNumLayers can't be translated to GLSL ES
+    layer_count: u32,
+}
+
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_diffuse: texture_2d_array<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(2)
+var<uniform> frame: Frame;
 @group(2) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: array<Light>;
+@group(2) @binding(1)
+var<uniform> light_count: LightCount;
+
+// the diffuse light that arrives at the position from a single light
+fn diffuse_light(light: Light, position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var light_dir: vec3<f32>;
+    var attenuation = 1.0;
+    if light.kind == 0u {
+        let to_light = light.position.xyz - position;
+        let light_distance = length(to_light);
+        light_dir = to_light / light_distance;
+        // the light falls off with the square of the distance and is gone at the range
+        let range_factor = clamp(1.0 - light_distance / light.range, 0.0, 1.0);
+        attenuation = range_factor / (light_distance * light_distance);
+    } else {
+        light_dir = -normalize(light.direction.xyz);
+    }
+    let diffuse_strength = 3.0 * max(dot(normal, light_dir), 0.0) * attenuation;
+    return light.color * light.intensity * diffuse_strength;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    
-    let light_dir = normalize(light.position - in.position);
-    let light_distance = length(light.position - in.position);
-    let distance_factor = (1.0/(light_distance*light_distance));
+    let layer = (in.tex_layer + frame.layer) % max(frame.layer_count, 1u);
+    let object_color: vec4<f32> = textureSampleBias(t_diffuse, s_diffuse, in.tex_coords, layer, frame.lod_bias);
     
-    let diffuse_strength = 3.0 * max(dot(in.world_normal, light_dir), 0.0) * distance_factor;
-    let diffuse_color = light.color * diffuse_strength;
+    let normal = normalize(in.world_normal);
+    var diffuse_color = vec3<f32>(0.0);
+    var ambient_color = vec3<f32>(0.0);
     let ambient_strength = 0.001;
-    let ambient_color = light.color * ambient_strength;
+    for (var i = 0u; i < light_count.count; i++) {
+        let light = lights[i];
+        diffuse_color += diffuse_light(light, in.position, normal);
+        ambient_color += light.color * light.intensity * ambient_strength;
+    }
 
     let result = (ambient_color + diffuse_color) * object_color.xyz;
     return vec4<f32>(result, object_color.a);
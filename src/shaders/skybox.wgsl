@@ -0,0 +1,47 @@
+// The background of the scene from a cubemap. A single triangle covers the whole screen, every
+// pixel turns its position back into the ray from the camera through it and looks up the cube
+// in the direction of the ray. The sky is infinitely far away, so only the direction matters and
+// moving the camera doesn't move the sky.
+
+// the camera uniform only has the view projection and WGSL can't invert a matrix, so the
+// inverse is computed on the CPU (see SkyboxRenderer::prepare)
+struct Sky {
+    inverse_view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> sky: Sky;
+
+@group(1) @binding(0)
+var t_sky: texture_cube<f32>;
+@group(1) @binding(1)
+var s_sky: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // the position on the screen in normalized device coordinates
+    @location(0) ndc: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // the corners (-1, -1), (3, -1) and (-1, 3), the parts of the triangle outside of the
+    // screen are clipped away
+    let x = f32(i32(index & 1u) * 4 - 1);
+    let y = f32(i32(index >> 1u) * 4 - 1);
+    var out: VertexOutput;
+    // right on the far plane, so everything that is drawn is in front of the sky
+    out.clip_position = vec4<f32>(x, y, 1.0, 1.0);
+    out.ndc = vec2<f32>(x, y);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // the points on the near and the far plane behind the pixel, the ray goes from one to the
+    // other. This works for the orthographic projection as well, where all rays are parallel
+    let near = sky.inverse_view_proj * vec4<f32>(in.ndc, 0.0, 1.0);
+    let far = sky.inverse_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = far.xyz / far.w - near.xyz / near.w;
+    return textureSample(t_sky, s_sky, direction);
+}
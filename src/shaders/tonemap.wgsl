@@ -0,0 +1,53 @@
+// Tone mapping. The scene is rendered into a floating point texture where colors can be brighter
+// than 1.0 (a highlight, a strong light). This shader maps them onto what the output can show.
+// An SDR output can only show 0 to 1, so the bright colors are compressed with a filmic curve
+// instead of being cut off. An HDR output (extended linear sRGB) can show values above 1.0, there
+// the colors are passed through and only limited to what a typical HDR display can show.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// A single triangle that covers the whole screen, the vertices are generated from the index
+// so no vertex buffer is needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    // texture coordinates go from top to bottom, clip space from bottom to top
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+
+// the brightest value passed to an HDR output, 1.0 is the white of SDR content
+const HDR_MAX: f32 = 8.0;
+
+// the ACES filmic curve fitted by Krzysztof Narkowicz, it maps 0..infinity to 0..1 with a soft
+// shoulder for the highlights
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_sdr(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_scene, s_scene, in.uv);
+    return vec4<f32>(aces(max(color.rgb, vec3<f32>(0.0))), color.a);
+}
+
+@fragment
+fn fs_hdr(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_scene, s_scene, in.uv);
+    return vec4<f32>(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(HDR_MAX)), color.a);
+}
@@ -0,0 +1,75 @@
+// Draws the edges of the meshes as lines that are a given number of pixels wide. The line mode of
+// the GPU only draws lines one pixel wide, so every edge is a quad instead: both ends of the edge
+// are projected onto the screen and moved apart sideways by half the width, at a right angle to
+// the edge as it is seen on the screen. The quad always faces the camera.
+struct Camera {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct LineSettings {
+    // multiplied with the color of the instance
+    color: vec4<f32>,
+    // the size of the target in pixels
+    viewport: vec2<f32>,
+    // the width of the lines in pixels
+    width: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> settings: LineSettings;
+
+// every corner of the quad knows both ends of its edge, `side` says which side of the edge it is on
+struct EdgeInput {
+    @location(0) position: vec3<f32>,
+    @location(1) other: vec3<f32>,
+    @location(2) side: f32,
+};
+
+struct InstanceInput {
+    @location(5) transform_matrix_0: vec4<f32>,
+    @location(6) transform_matrix_1: vec4<f32>,
+    @location(7) transform_matrix_2: vec4<f32>,
+    @location(8) transform_matrix_3: vec4<f32>,
+    @location(9) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(edge: EdgeInput, instance: InstanceInput) -> VertexOutput {
+    let instance_transform = mat4x4<f32>(
+        instance.transform_matrix_0,
+        instance.transform_matrix_1,
+        instance.transform_matrix_2,
+        instance.transform_matrix_3,
+    );
+    let view_proj = camera.view_proj * instance_transform;
+    let this_end = view_proj * vec4<f32>(edge.position, 1.0);
+    let other_end = view_proj * vec4<f32>(edge.other, 1.0);
+    // the direction of the edge on the screen in pixels. The corners at the other end see the
+    // edge the other way around, so they have the opposite side to end up on the same side
+    let along = (other_end.xy / other_end.w - this_end.xy / this_end.w) * settings.viewport;
+    var direction = vec2<f32>(1.0, 0.0);
+    if dot(along, along) > 0.0 {
+        direction = normalize(along);
+    }
+    let offset = vec2<f32>(-direction.y, direction.x) * edge.side * settings.width * 0.5;
+    // pixels to normalized device coordinates, multiplied by w as the GPU divides by it
+    let clip_offset = offset * 2.0 / settings.viewport * this_end.w;
+
+    var out: VertexOutput;
+    out.clip_position = this_end + vec4<f32>(clip_offset, 0.0, 0.0);
+    out.color = instance.color * settings.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,192 @@
+// Draws a cubemap as the background of the scene (see skybox.wgsl). The sky is drawn first in
+// the scene pass, on the far plane, with the depth test on but without writing the depth. The
+// meshes that follow are all in front of it and simply cover it, where nothing is drawn the sky
+// takes the place of the clear color.
+use image::{DynamicImage, Rgba, RgbaImage};
+use wgpu::util::DeviceExt;
+
+use crate::{camera, model};
+use crate::stats::RenderStats;
+
+/// the size of a face of the generated sky in pixels
+const GRADIENT_SKY_SIZE: u32 = 128;
+
+pub struct SkyboxRenderer {
+    pipeline: wgpu::RenderPipeline,
+    // the inverse view projection of the camera the scene is rendered with
+    sky_buffer: wgpu::Buffer,
+    sky_bind_group: wgpu::BindGroup,
+    /// the cubemap that is drawn, created with `model::Texture::from_cube_faces`
+    pub sky: model::Texture,
+}
+
+impl SkyboxRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        sky: model::Texture,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        // has to match the sample count of the scene render pass the sky is drawn in
+        sample_count: u32,
+    ) -> Self {
+        let sky_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox uniform buffer"),
+            contents: bytemuck::cast_slice(&[[[0.0f32; 4]; 4]]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sky_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Skybox bind group"),
+            layout: &device.create_bind_group_layout(&Self::describe_sky()),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: sky_buffer.as_entire_binding(),
+            }],
+        });
+        let pipeline = Self::create_pipeline(device, surface_config, depth_format, sample_count);
+        Self { pipeline, sky_buffer, sky_bind_group, sky }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/skybox.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox pipeline layout"),
+            bind_group_layouts: &[
+                &device.create_bind_group_layout(&Self::describe_sky()),
+                &device.create_bind_group_layout(&model::Texture::desc_layout_cube()),
+            ],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Renderer"),
+            layout: Some(&layout),
+            // the triangle has no vertex buffer, its corners come from the vertex index
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            // the sky is on the far plane, where the depth buffer is cleared to. LessEqual lets
+            // it through there, and it doesn't write the depth so the meshes are drawn over it
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+    }
+
+    /// Create the pipeline again for other targets, the sky stays the same
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(device, surface_config, depth_format, sample_count);
+    }
+
+    fn describe_sky() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skybox bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        }
+    }
+
+    /// Write the inverse view projection of the camera the scene is rendered with. It changes
+    /// with the aspect ratio, so this is done every frame and the sky follows a resize of the
+    /// window. A camera that can't be inverted keeps the sky of the last frame.
+    pub fn prepare(&self, camera: &camera::Camera, queue: &wgpu::Queue) {
+        if let Some(inverse) = camera.inverse_view_projection() {
+            let inverse: [[f32; 4]; 4] = inverse.into();
+            queue.write_buffer(&self.sky_buffer, 0, bytemuck::cast_slice(&[inverse]));
+            RenderStats::record_buffer_write();
+        }
+    }
+
+    /// draw the sky, this has to be the first thing in the scene render pass
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        let Some(sky_bind_group) = &self.sky.bind_group else {
+            return;
+        };
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.sky_bind_group, &[]);
+        render_pass.set_bind_group(1, sky_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+        RenderStats::record_bind_group_switch();
+        render_pass.draw(0..3, 0..1);
+        RenderStats::record_draw(1);
+    }
+}
+
+/// A sky that is generated instead of loaded: blue at the top that gets lighter towards the
+/// horizon, and a brown ground below it that gets darker towards the bottom
+pub fn gradient_sky(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<model::Texture> {
+    fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+        [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+    }
+    // the direction of the pixel (u, v) in -1..1 on the face, the faces are in the order of the
+    // layers of a cube and look at the cube from the inside
+    let direction = |face: usize, u: f32, v: f32| match face {
+        0 => [1.0, -v, -u],
+        1 => [-1.0, -v, u],
+        2 => [u, 1.0, v],
+        3 => [u, -1.0, -v],
+        4 => [u, -v, 1.0],
+        _ => [-u, -v, -1.0],
+    };
+    let faces = [0, 1, 2, 3, 4, 5].map(|face| {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(GRADIENT_SKY_SIZE, GRADIENT_SKY_SIZE, |px, py| {
+            let u = 2.0 * (px as f32 + 0.5) / GRADIENT_SKY_SIZE as f32 - 1.0;
+            let v = 2.0 * (py as f32 + 0.5) / GRADIENT_SKY_SIZE as f32 - 1.0;
+            let [x, y, z] = direction(face, u, v);
+            let up = y / (x * x + y * y + z * z).sqrt();
+            let color = if up > 0.0 {
+                mix([0.75, 0.85, 0.95], [0.25, 0.45, 0.8], up.powf(0.5))
+            } else {
+                mix([0.4, 0.36, 0.32], [0.12, 0.1, 0.09], (-up).powf(0.3))
+            };
+            let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+            Rgba([r, g, b, 255])
+        }))
+    });
+    model::Texture::from_cube_images(device, queue, &faces, "gradient sky")
+}
@@ -0,0 +1,162 @@
+use std::cell::Cell;
+
+// Counters for the work the renderer hands to the GPU in a frame. The places that issue the
+// commands are spread over the renderers and the buffers, so instead of passing a counter to
+// all of them the counts are collected in a thread local. Everything is recorded on the render
+// thread (the upload thread only executes the writes that were counted when they were queued).
+
+thread_local! {
+    static CURRENT_FRAME: Cell<RenderStats> = Cell::new(RenderStats::default());
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    /// the number of triangles of all draw calls, every instance counts separately
+    pub triangles: u64,
+    /// `queue.write_buffer` calls, including the ones handed to the upload thread
+    pub buffer_writes: u32,
+    pub bind_group_switches: u32,
+}
+
+impl RenderStats {
+    fn modify(f: impl FnOnce(&mut RenderStats)) {
+        CURRENT_FRAME.with(|current| {
+            let mut stats = current.get();
+            f(&mut stats);
+            current.set(stats);
+        });
+    }
+
+    pub fn record_draw(triangles: u64) {
+        Self::modify(|stats| {
+            stats.draw_calls += 1;
+            stats.triangles += triangles;
+        });
+    }
+
+    pub fn record_buffer_write() {
+        Self::modify(|stats| stats.buffer_writes += 1);
+    }
+
+    pub fn record_bind_group_switch() {
+        Self::modify(|stats| stats.bind_group_switches += 1);
+    }
+
+    /// return the counts since the last call and start counting from zero, this is called once
+    /// at the end of every frame
+    pub fn take() -> RenderStats {
+        CURRENT_FRAME.with(|current| current.take())
+    }
+
+    pub fn build_ui(&self, ui: &mut egui::Ui) {
+        ui.label(format!("Draw calls: {}", self.draw_calls));
+        ui.label(format!("Triangles: {}", self.triangles));
+        ui.label(format!("Buffer writes: {}", self.buffer_writes));
+        ui.label(format!("Bind group switches: {}", self.bind_group_switches));
+    }
+}
+
+/// the number of frames `FrameStats` keeps, two seconds at 60 frames per second
+pub const FRAME_HISTORY: usize = 120;
+
+/// The times of the last `FRAME_HISTORY` frames, for the frame rate and the frame time graph in
+/// the ui. The times are kept in a ring buffer of a fixed size, so collecting them doesn't
+/// allocate (an allocation in every frame could cause the spikes the graph is meant to show).
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    // in seconds, `next` is the oldest one once the buffer is full
+    times: [f32; FRAME_HISTORY],
+    next: usize,
+    count: usize,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self { times: [0.0; FRAME_HISTORY], next: 0, count: 0 }
+    }
+}
+
+impl FrameStats {
+    /// add the time of a frame, the oldest one is dropped when the buffer is full
+    pub fn push(&mut self, dt: std::time::Duration) {
+        self.times[self.next] = dt.as_secs_f32();
+        self.next = (self.next + 1) % FRAME_HISTORY;
+        self.count = (self.count + 1).min(FRAME_HISTORY);
+    }
+
+    /// the frame times from the oldest to the newest
+    fn times(&self) -> impl Iterator<Item = f32> + '_ {
+        let start = if self.count < FRAME_HISTORY { 0 } else { self.next };
+        (0..self.count).map(move |i| self.times[(start + i) % FRAME_HISTORY])
+    }
+
+    /// the frames per second over all the frames in the buffer, None before the first frame
+    pub fn average_fps(&self) -> Option<f32> {
+        let total: f32 = self.times().sum();
+        (total > 0.0).then(|| self.count as f32 / total)
+    }
+
+    /// The frames per second of the slowest 1% of the frames, at least the slowest frame. This
+    /// shows the stutter that the average hides.
+    pub fn one_percent_low_fps(&self) -> Option<f32> {
+        if self.count == 0 {
+            return None;
+        }
+        // sorted in a copy on the stack, no allocation here either
+        let mut sorted = [0.0; FRAME_HISTORY];
+        for (slot, time) in sorted.iter_mut().zip(self.times()) {
+            *slot = time;
+        }
+        let sorted = &mut sorted[..self.count];
+        sorted.sort_by(|a, b| b.total_cmp(a));
+        let slowest = (self.count / 100).max(1);
+        let total: f32 = sorted[..slowest].iter().sum();
+        (total > 0.0).then(|| slowest as f32 / total)
+    }
+
+    /// the frame rate and a bar for every frame time, the line is at 60 frames per second
+    pub fn build_ui(&self, ui: &mut egui::Ui) {
+        let fps = |fps: Option<f32>| fps.map(|fps| format!("{:.0}", fps)).unwrap_or("-".to_string());
+        ui.label(format!("FPS: {} (1% low: {})", fps(self.average_fps()), fps(self.one_percent_low_fps())));
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width().max(FRAME_HISTORY as f32), 60.0), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(96));
+        // the graph goes up to 1/30 of a second, longer frames are cut off at the top
+        let max_time = 1.0 / 30.0;
+        let to_y = |time: f32| rect.bottom() - (time / max_time).min(1.0) * rect.height();
+        let bar_width = rect.width() / FRAME_HISTORY as f32;
+        for (i, time) in self.times().enumerate() {
+            let x = rect.left() + i as f32 * bar_width;
+            let color = if time > 1.0 / 55.0 { egui::Color32::LIGHT_RED } else { egui::Color32::LIGHT_GREEN };
+            painter.rect_filled(egui::Rect::from_min_max(egui::pos2(x, to_y(time)), egui::pos2(x + bar_width, rect.bottom())), 0.0, color);
+        }
+        let target = to_y(1.0 / 60.0);
+        painter.hline(rect.x_range(), target, egui::Stroke::new(1.0, egui::Color32::WHITE));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // older frames drop out of the ring buffer, and one slow frame among the rest shows up in
+    // the 1% low but barely in the average
+    #[test]
+    fn frame_rate_of_the_last_frames() {
+        let mut frames = FrameStats::default();
+        // slow frames that are pushed out of the buffer by the ones below
+        for _ in 0..50 {
+            frames.push(Duration::from_millis(500));
+        }
+        for _ in 0..FRAME_HISTORY - 1 {
+            frames.push(Duration::from_secs_f32(1.0 / 60.0));
+        }
+        frames.push(Duration::from_millis(100));
+        let average = frames.average_fps().unwrap();
+        let expected_average = FRAME_HISTORY as f32 / ((FRAME_HISTORY - 1) as f32 / 60.0 + 0.1);
+        assert!((average - expected_average).abs() <= 0.001 * expected_average, "{} FPS instead of {}", average, expected_average);
+        assert!((frames.one_percent_low_fps().unwrap() - 10.0).abs() <= 0.01);
+    }
+}
@@ -0,0 +1,150 @@
+/// Post processing pass that maps the HDR scene onto the output. The scene is rendered into the
+/// floating point target owned by this renderer, which is then drawn to the next target (the
+/// screen or the FXAA target) with the tone mapping shader
+use crate::model;
+use crate::stats::RenderStats;
+
+/// the format the scene is rendered in when HDR is on, it can hold values above 1.0
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Pick the format of the surface. With `hdr` the formats are tried in this order:
+///
+/// 1. `Rgba16Float`, which the platforms treat as extended linear sRGB (scRGB): 0 to 1 is the
+///    same as SDR and values above 1 are brighter than SDR white.
+/// 2. the first sRGB format, the same as without HDR.
+///
+/// `Rgb10a2Unorm` is not tried, it is used for HDR10 but that also needs the PQ color space,
+/// which can't be chosen through wgpu yet.
+///
+/// Without `hdr` (or if none of the formats above is supported) the first sRGB format is used,
+/// None if the surface has no sRGB format either.
+pub fn select_surface_format(formats: &[wgpu::TextureFormat], hdr: bool) -> Option<wgpu::TextureFormat> {
+    let srgb = formats.iter().copied().find(|f| f.is_srgb());
+    if hdr && formats.contains(&HDR_FORMAT) {
+        return Some(HDR_FORMAT);
+    }
+    srgb
+}
+
+pub struct TonemapRenderer {
+    pub pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// the HDR texture that the scene is rendered into
+    pub target: model::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl TonemapRenderer {
+    /// `output_format` is the format of the texture the tone mapped image is written to, if it
+    /// is the HDR format the bright colors are kept, otherwise they are compressed to 0..1
+    pub fn new(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration, output_format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tone mapping Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/tonemap.wgsl").into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tone mapping bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tone mapping pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tone mapping Renderer"),
+            layout: Some(&layout),
+            // there is no vertex buffer, the fullscreen triangle is generated in the shader
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: if output_format == HDR_FORMAT { "fs_hdr" } else { "fs_sdr" },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let target = Self::create_target(device, surface_config);
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &target);
+        Self { pipeline, bind_group_layout, target, bind_group }
+    }
+
+    fn create_target(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) -> model::Texture {
+        let config = wgpu::SurfaceConfiguration { format: HDR_FORMAT, ..surface_config.clone() };
+        model::Texture::create_color_target(device, &config, 1, "HDR scene target")
+    }
+
+    fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, target: &model::Texture) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tone mapping bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&target.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&target.sampler),
+                },
+            ],
+        })
+    }
+
+    /// the HDR target needs to have the size of the screen
+    pub fn resize(&mut self, device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration) {
+        self.target = Self::create_target(device, surface_config);
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.target);
+    }
+
+    /// run the tone mapping pass, reading from the HDR target and writing to the `view`
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tone mapping render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // every pixel is overwritten by the fullscreen triangle
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+        render_pass.draw(0..3, 0..1);
+        RenderStats::record_draw(1);
+    }
+}
@@ -0,0 +1,142 @@
+// Everything egui needs to show the ui on top of the frame. The ui is built in immediate mode:
+// every frame `generate_ui` runs the closure that lays out the windows (the app does that in
+// `render_to`, as the closure needs most of the app), egui gives back the shapes and the texture
+// changes, `prepare` turns them into triangles in the GPU buffers and `render` draws them in a
+// pass of their own after the scene. The ui doesn't need a depth buffer and doesn't care about
+// the anti aliasing method of the scene.
+//
+// The input comes from the window through the egui-winit state. A headless app has no window,
+// its ui gets no input and only knows the size of the target.
+use winit::window::Window;
+
+// the ui is drawn at twice the size of a pixel
+const PIXELS_PER_POINT: f32 = 2.0;
+
+/// What `prepare` uploaded for a frame, `render` draws it and frees the textures egui is done with
+pub struct UiFrame {
+    primitives: Vec<egui::ClippedPrimitive>,
+    free_textures: Vec<egui::TextureId>,
+}
+
+pub struct UI {
+    context: egui::Context,
+    painter: egui_wgpu::renderer::Renderer,
+    // the input state of the window, None without a window
+    state: Option<egui_winit::State>,
+    screen_descriptor: egui_wgpu::renderer::ScreenDescriptor,
+    /// if the ui is built and drawn, without it the frame only shows the scene
+    pub visible: bool,
+}
+
+impl UI {
+    /// the ui for a target of `width` x `height` pixels in `format`, with the input of `window`
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, width: u32, height: u32, window: Option<&Window>) -> Self {
+        let context = egui::Context::default();
+        let viewport_id = context.viewport_id();
+        let state = window.map(|window| egui_winit::State::new(
+            context.clone(),
+            viewport_id,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+        ));
+        Self {
+            painter: egui_wgpu::renderer::Renderer::new(device, format, None, 1),
+            context,
+            state,
+            screen_descriptor: egui_wgpu::renderer::ScreenDescriptor { size_in_pixels: [width, height], pixels_per_point: PIXELS_PER_POINT },
+            visible: true,
+        }
+    }
+
+    /// let the ui see the event of the window first, the response says if it used the event
+    pub fn on_window_event(&mut self, window: &Window, event: &winit::event::WindowEvent) -> egui_winit::EventResponse {
+        match &mut self.state {
+            Some(state) => state.on_window_event(window, event),
+            None => egui_winit::EventResponse { consumed: false, repaint: false },
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.screen_descriptor = egui_wgpu::renderer::ScreenDescriptor { size_in_pixels: [width, height], pixels_per_point: PIXELS_PER_POINT };
+    }
+
+    /// Draw into targets with another format from now on. egui picks its shader by the format, so
+    /// it needs a new painter. The new painter doesn't have the textures of the old one, the font
+    /// atlas is uploaded again (the app has no other egui textures). On the Rgba16Float surface
+    /// egui writes its colors in gamma space (that's what it does for every non sRGB format), so
+    /// the ui looks a bit washed out.
+    pub fn set_format(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat) {
+        self.painter = egui_wgpu::renderer::Renderer::new(device, format, None, 1);
+        let font_image = self.context.fonts(|fonts| fonts.image());
+        let font_delta = egui::epaint::ImageDelta::full(font_image, egui::epaint::TextureAtlas::texture_options());
+        self.painter.update_texture(device, queue, egui::TextureId::default(), &font_delta);
+    }
+
+    /// Run one frame of the ui: `build` lays out the windows with the input of `window`. It is
+    /// not called while the ui is hidden.
+    pub fn generate_ui(&mut self, window: Option<&Window>, build: impl FnOnce(&egui::Context)) -> egui::FullOutput {
+        let input = match (&mut self.state, window) {
+            (Some(state), Some(window)) => state.take_egui_input(window),
+            // without a window there is no input, the ui only needs to know how big it is
+            _ => {
+                let [width, height] = self.screen_descriptor.size_in_pixels;
+                egui::RawInput {
+                    screen_rect: Some(egui::Rect::from_min_size(
+                        egui::Pos2::ZERO,
+                        egui::vec2(width as f32, height as f32) / self.screen_descriptor.pixels_per_point,
+                    )),
+                    ..Default::default()
+                }
+            }
+        };
+        let visible = self.visible;
+        self.context.run(input, |ctx| {
+            if visible {
+                build(ctx);
+            }
+        })
+    }
+
+    /// Hand the cursor and the clipboard changes back to the window and upload the textures and
+    /// the triangles of the ui, before `render` draws them with the same encoder.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        window: Option<&Window>,
+        output: egui::FullOutput,
+    ) -> UiFrame {
+        if let (Some(state), Some(window)) = (&mut self.state, window) {
+            state.handle_platform_output(window, output.platform_output);
+        }
+        let primitives = self.context.tessellate(output.shapes, output.pixels_per_point);
+        for (id, image_delta) in &output.textures_delta.set {
+            self.painter.update_texture(device, queue, *id, image_delta);
+        }
+        self.painter.update_buffers(device, queue, encoder, &primitives, &self.screen_descriptor);
+        UiFrame { primitives, free_textures: output.textures_delta.free }
+    }
+
+    /// draw the ui on top of what is already in `view`
+    pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, frame: UiFrame) {
+        {
+            let mut ui_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("UI render pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            self.painter.render(&mut ui_pass, &frame.primitives, &self.screen_descriptor);
+        }
+        for id in &frame.free_textures {
+            self.painter.free_texture(id);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+
+// Writing data into GPU buffers (queue.write_buffer) copies the data into a staging buffer, for
+// large scenes this can take a noticable amount of time on the render thread. The Uploader moves
+// this work to its own thread. Data is sent to the thread through a channel, the thread writes
+// it into the buffers and submits the writes to the GPU.
+//
+// wgpu::Queue is Send + Sync so it can be shared between the threads. Writes issued through the
+// queue are executed before any command buffer that is submitted afterwards, so the render thread
+// sees the new data in the first frame that is submitted after the upload thread has submitted
+// its writes. `pending` tells the render thread if there are uploads that have not finished yet.
+//
+// The render thread can't wait for the upload thread, so a frame may still draw the old data of
+// a buffer. That is fine for data that only changed (it's one frame late), but not for a buffer
+// whose layout changed, like an instance buffer that was reallocated or packed again: the draw
+// calls already count the new slots. Such data is written with `upload_now` instead, on the
+// calling thread and before the frame is submitted. It first waits until the upload thread has
+// handed everything it got so far to the queue (that's only the copy, not the GPU work), the
+// older data for the same buffer would overwrite it otherwise.
+//
+// In the browser there are no threads, there the data is written to the queue right away.
+
+/// A chunk of data that should end up in `buffer` at `offset`
+pub struct UploadRequest {
+    pub buffer: Arc<wgpu::Buffer>,
+    pub offset: wgpu::BufferAddress,
+    pub data: Vec<u8>,
+}
+
+pub struct Uploader {
+    // this is an option so that it can be dropped before joining the thread, which closes the
+    // channel and ends the loop of the thread
+    #[cfg(not(target_arch = "wasm32"))]
+    sender: Option<mpsc::Sender<UploadRequest>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    worker: Option<thread::JoinHandle<()>>,
+    queue: Arc<wgpu::Queue>,
+    requested: AtomicU64,
+    // the uploads the thread has written to the queue, but that may not be on the GPU yet
+    #[cfg(not(target_arch = "wasm32"))]
+    written: Arc<AtomicU64>,
+    completed: Arc<AtomicU64>,
+}
+
+impl Uploader {
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(queue: Arc<wgpu::Queue>) -> Self {
+        Self { queue, requested: AtomicU64::new(0), completed: Arc::new(AtomicU64::new(0)) }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn upload(&self, request: UploadRequest) {
+        self.queue.write_buffer(&request.buffer, request.offset, &request.data);
+        self.requested.fetch_add(1, Ordering::Relaxed);
+        self.completed.fetch_add(1, Ordering::Release);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(queue: Arc<wgpu::Queue>) -> Self {
+        let (sender, receiver) = mpsc::channel::<UploadRequest>();
+        let completed = Arc::new(AtomicU64::new(0));
+        let worker_completed = completed.clone();
+        let written = Arc::new(AtomicU64::new(0));
+        let worker_written = written.clone();
+        let worker_queue = queue.clone();
+        let worker = thread::Builder::new()
+            .name("gpu upload".to_string())
+            .spawn(move || {
+                let queue = worker_queue;
+                // block until there is something to do, then take everything that piled up in
+                // the meantime so that it can be submitted in one go
+                while let Ok(first) = receiver.recv() {
+                    let batch = std::iter::once(first).chain(receiver.try_iter()).collect::<Vec<_>>();
+                    for request in batch.iter() {
+                        queue.write_buffer(&request.buffer, request.offset, &request.data);
+                    }
+                    worker_written.fetch_add(batch.len() as u64, Ordering::Release);
+                    // an empty submit flushes the staged writes to the GPU
+                    queue.submit(std::iter::empty());
+                    let completed = worker_completed.clone();
+                    let count = batch.len() as u64;
+                    queue.on_submitted_work_done(move || {
+                        completed.fetch_add(count, Ordering::Release);
+                    });
+                }
+            })
+            .expect("could not spawn the upload thread");
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+            queue,
+            requested: AtomicU64::new(0),
+            written,
+            completed,
+        }
+    }
+
+    /// queue the data for upload, this returns immediately
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn upload(&self, request: UploadRequest) {
+        if let Some(sender) = &self.sender {
+            // counted before it is sent, the thread may be done with it before `send` returns
+            self.requested.fetch_add(1, Ordering::Relaxed);
+            if sender.send(request).is_err() {
+                self.requested.fetch_sub(1, Ordering::Relaxed);
+                log::error!("the upload thread is gone, the upload is dropped");
+            }
+        }
+    }
+
+    /// Write the data on this thread, it is in the buffer for the next submit of the render
+    /// thread. This is for data the next frame depends on, see the note at the top.
+    pub fn upload_now(&self, request: UploadRequest) {
+        #[cfg(not(target_arch = "wasm32"))]
+        while self.written.load(Ordering::Acquire) < self.requested.load(Ordering::Relaxed) {
+            if self.worker.as_ref().is_none_or(|worker| worker.is_finished()) {
+                break;
+            }
+            thread::yield_now();
+        }
+        self.queue.write_buffer(&request.buffer, request.offset, &request.data);
+    }
+
+    /// the number of uploads that have been requested but are not executed on the GPU yet
+    pub fn pending(&self) -> u64 {
+        // `completed` is read first, so it can't have counted an upload that `requested`
+        // doesn't know about yet
+        let completed = self.completed.load(Ordering::Acquire);
+        self.requested.load(Ordering::Relaxed).saturating_sub(completed)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        // closing the channel ends the thread after it has processed the remaining requests
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
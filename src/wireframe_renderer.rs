@@ -0,0 +1,296 @@
+// Renderer for wireframes with lines wider than a pixel. The line polygon mode of the colored
+// mesh renderer always draws lines a pixel wide, which are hard to see on screens with a high
+// pixel density, and wgpu has no way to set the width of lines. Instead every edge of a mesh is
+// drawn as a quad that is built in screen space around the edge (see wireframe.wgsl), so its
+// width is given in pixels.
+//
+// The quads need both ends of their edge in every vertex, which the vertices of the mesh don't
+// have. So every surface gets an `EdgeMesh` next to its vertex and index buffer, with four
+// vertices for each edge of its triangles. The instances are the same as for the surface.
+use std::mem;
+
+use wgpu::util::DeviceExt;
+
+use crate::model::{self, RawVertex};
+use crate::stats::RenderStats;
+use crate::{instance, primitives};
+
+/// A corner of the quad of an edge as it is laid out in the vertex buffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct EdgeVertex {
+    // the end of the edge the corner is at
+    position: [f32; 3],
+    // the other end of the edge
+    other: [f32; 3],
+    // which side of the edge the corner is on, 1 or -1
+    side: f32,
+}
+
+impl EdgeVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<EdgeVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+/// The edges of the triangles of a surface as quads, for the `WireframeRenderer`
+pub struct EdgeMesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+}
+
+impl EdgeMesh {
+    /// the quads of the edges of the triangles the indices draw with the topology, None if
+    /// there are no edges (the topology draws no triangles)
+    pub fn new(name: &str, vertices: &[RawVertex], indices: &[u32], topology: wgpu::PrimitiveTopology, device: &wgpu::Device) -> Option<Self> {
+        let edges = primitives::edges(indices, topology);
+        if edges.is_empty() {
+            return None;
+        }
+        let mut edge_vertices = Vec::with_capacity(4 * edges.len());
+        let mut edge_indices = Vec::with_capacity(6 * edges.len());
+        for [a, b] in edges {
+            let (a, b) = (vertices[a as usize].pos, vertices[b as usize].pos);
+            let first = edge_vertices.len() as u32;
+            // seen from b the edge points the other way, so the sides at b are swapped to put
+            // the corners 0 and 2 on one side of the edge and 1 and 3 on the other
+            edge_vertices.extend([
+                EdgeVertex { position: a, other: b, side: 1.0 },
+                EdgeVertex { position: a, other: b, side: -1.0 },
+                EdgeVertex { position: b, other: a, side: -1.0 },
+                EdgeVertex { position: b, other: a, side: 1.0 },
+            ]);
+            edge_indices.extend([0, 1, 3, 0, 3, 2].map(|i| first + i));
+        }
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Edge Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(&edge_vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Edge Index Buffer", name)),
+            contents: bytemuck::cast_slice(&edge_indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        Some(Self { vertex_buffer, index_buffer, num_indices: edge_indices.len() as u32 })
+    }
+}
+
+// this needs to match the LineSettings struct in the shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawLineSettings {
+    color: [f32; 4],
+    viewport: [f32; 2],
+    width: f32,
+    _padding: f32,
+}
+
+pub struct WireframeRenderer {
+    pipeline: wgpu::RenderPipeline,
+    settings_buffer: wgpu::Buffer,
+    settings_bind_group: wgpu::BindGroup,
+    // the width of the lines in pixels
+    line_width: f32,
+    /// multiplied with the color of the instances, white keeps the colors of the instances
+    pub color: [f32; 4],
+    // what is in the settings buffer, it's only written when something changes
+    uploaded: Option<RawLineSettings>,
+}
+
+impl WireframeRenderer {
+    /// the width of the lines of a new renderer in pixels
+    pub const DEFAULT_LINE_WIDTH: f32 = 2.0;
+
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        // has to match the sample count of the scene render pass the lines are drawn in
+        sample_count: u32,
+    ) -> Self {
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Line settings uniform buffer"),
+            size: mem::size_of::<RawLineSettings>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &device.create_bind_group_layout(&Self::describe_settings()),
+            label: Some("Line settings bind group"),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: settings_buffer.as_entire_binding(),
+            }],
+        });
+        let pipeline = Self::create_pipeline(device, camera_bind_group_layout, surface_config, depth_format, sample_count);
+        Self {
+            pipeline,
+            settings_buffer,
+            settings_bind_group,
+            line_width: Self::DEFAULT_LINE_WIDTH,
+            color: [1.0; 4],
+            uploaded: None,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Wireframe Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./shaders/wireframe.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Wireframe pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &device.create_bind_group_layout(&Self::describe_settings())],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Wireframe Renderer"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[EdgeVertex::desc(), instance::Instance::desc()],
+            },
+            // the quads are built facing the camera, but their winding depends on the direction
+            // of the edge on the screen, so none of them are culled
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            // the lines hide each other, the ones at the back of a mesh are still drawn as there
+            // is no surface in the depth buffer to hide them
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+    }
+
+    /// Create the pipeline again for other targets, the settings stay the same
+    pub fn recreate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        surface_config: &wgpu::SurfaceConfiguration,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(device, camera_bind_group_layout, surface_config, depth_format, sample_count);
+    }
+
+    fn describe_settings() -> wgpu::BindGroupLayoutDescriptor<'static> {
+        wgpu::BindGroupLayoutDescriptor {
+            label: Some("Line settings bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        }
+    }
+
+    pub fn line_width(&self) -> f32 {
+        self.line_width
+    }
+
+    /// Set the width of the lines in pixels, it is sent to the GPU with the next `prepare`.
+    /// Widths below a pixel let the lines break up, they are drawn a pixel wide.
+    pub fn set_line_width(&mut self, line_width: f32) {
+        self.line_width = line_width.max(1.0);
+    }
+
+    /// send the settings to the GPU if they changed, `width` and `height` are the size of the
+    /// target the lines are drawn into
+    pub fn prepare(&mut self, width: u32, height: u32, queue: &wgpu::Queue) {
+        let raw = RawLineSettings {
+            color: self.color,
+            viewport: [width as f32, height as f32],
+            width: self.line_width,
+            _padding: 0.0,
+        };
+        if self.uploaded != Some(raw) {
+            queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[raw]));
+            RenderStats::record_buffer_write();
+            self.uploaded = Some(raw);
+        }
+    }
+
+    /// set the pipeline and bind the settings, this is done once before the meshes are drawn
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, &self.settings_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+    }
+
+    /// draw the edges of all instances of the mesh, `bind` has to be called before
+    pub fn draw_mesh<'a>(render_pass: &mut wgpu::RenderPass<'a>, mesh: &'a model::Surface, camera_bind_group: &'a wgpu::BindGroup) {
+        let Some(edges) = &mesh.edges else {
+            return;
+        };
+        let occ_slots = mesh.instance_buffer.occupied_slots;
+        // all instances are hidden (or gone), an empty buffer slice is not allowed
+        if occ_slots == 0 {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, edges.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, mesh.instance_buffer.gpu_buffer.slice(..occ_slots * mem::size_of::<instance::RawInstance>() as u64));
+        render_pass.set_index_buffer(edges.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        RenderStats::record_bind_group_switch();
+        render_pass.draw_indexed(0..edges.num_indices, 0, 0..occ_slots as u32);
+        // two triangles per edge
+        RenderStats::record_draw(edges.num_indices as u64 / 3 * occ_slots);
+    }
+}